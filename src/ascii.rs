@@ -4,9 +4,10 @@ pub mod ascii {
 		once_cell::sync::Lazy
 	};
 	
-	pub const ENCODER: Lazy<HashMap<u8, char>> = Lazy::new(|| HashMap::from([
+	pub static ENCODER: Lazy<HashMap<u8, char>> = Lazy::new(|| HashMap::from([
 		(0x00, '\0'),
 		
+		(0x08, '\x08'),
 		(0x09, '\t'),
 		(0x0A, '\n'),
 		
@@ -109,9 +110,10 @@ pub mod ascii {
 		(0x7E, '~')
 	]));
 	
-	pub const DECODER: Lazy<HashMap<char, u8>> = Lazy::new(|| HashMap::from([
+	pub static DECODER: Lazy<HashMap<char, u8>> = Lazy::new(|| HashMap::from([
 		('\0', 0x00),
 		
+		('\x08', 0x08),
 		('\t', 0x09),
 		('\n', 0x0A),
 		
@@ -0,0 +1,570 @@
+use {
+	crate::hardware::cpu::{AddrMode, CpuModel, Mnemonic, Opcode},
+	std::{
+		collections::HashMap,
+		fmt::{self, Display, Formatter}
+	}
+};
+
+/**What went wrong on a line of assembly.*/
+#[derive(Debug, PartialEq, Clone)]
+pub enum AsmErrorKind {
+	///The line isn't a label, directive, or instruction
+	Syntax(String),
+	///The mnemonic isn't an instruction on the CPU model being assembled for
+	UnknownInstruction(String),
+	///The instruction doesn't have the addressing mode its operand is written in
+	AddressingMode(String),
+	UnknownDirective(String),
+	///The operand isn't a number or a label
+	BadValue(String),
+	///The value doesn't fit in the bytes the addressing mode has for it
+	OutOfRange {value: u16},
+	UnknownLabel(String),
+	DuplicateLabel(String),
+	///The branch target is further than a signed byte can reach
+	BranchRange {offset: i32},
+	///Bytes were already assembled at the address by an earlier line
+	Overlap {address: u16},
+	///The bytes would go past $FFFF
+	AddressSpace
+}
+
+impl Display for AsmErrorKind {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			AsmErrorKind::Syntax(expected) => {write!(f, "expected {}", expected)}
+			AsmErrorKind::UnknownInstruction(mnemonic) => {write!(f, "unknown instruction {}", mnemonic)}
+			AsmErrorKind::AddressingMode(mnemonic) => {write!(f, "{} can't be used with this addressing mode", mnemonic)}
+			AsmErrorKind::UnknownDirective(directive) => {write!(f, "unknown directive {}", directive)}
+			AsmErrorKind::BadValue(text) => {write!(f, "{} is not a number or a label", text)}
+			AsmErrorKind::OutOfRange {value} => {write!(f, "${:X} doesn't fit in the operand", value)}
+			AsmErrorKind::UnknownLabel(label) => {write!(f, "label {} is never defined", label)}
+			AsmErrorKind::DuplicateLabel(label) => {write!(f, "label {} is already defined", label)}
+			AsmErrorKind::BranchRange {offset} => {write!(f, "branch target is {} bytes away, but a branch can only reach -128 to 127", offset)}
+			AsmErrorKind::Overlap {address} => {write!(f, "${:04X} was already assembled by an earlier line", address)}
+			AsmErrorKind::AddressSpace => {write!(f, "the program goes past $FFFF")}
+		}
+	}
+}
+
+/**An error in the source, at a line and column counted from 1. Tabs count as one column.*/
+#[derive(Debug, PartialEq, Clone)]
+pub struct AsmError {
+	pub line: usize,
+	pub column: usize,
+	pub kind: AsmErrorKind
+}
+
+impl Display for AsmError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(f, "line {}, column {}: {}", self.line, self.column, self.kind)
+	}
+}
+
+impl std::error::Error for AsmError {}
+
+/**A line of source with the address it was assembled at and the bytes it made.*/
+#[derive(Debug, PartialEq, Clone)]
+pub struct ListingLine {
+	pub address: u16,
+	pub bytes: Vec<u8>,
+	pub source: String,
+	///Set when the bytes came from DAT, so they aren't code
+	pub data: bool
+}
+
+/**An assembled program, ready for System::load_main_program.*/
+#[derive(Debug, PartialEq, Clone)]
+pub struct Assembly {
+	///Address of the first byte
+	pub origin: u16,
+	///Every byte from the origin to the last byte assembled. Gaps between .ORG sections are filled with $00.
+	pub bytes: Vec<u8>,
+	///One line for every line of source up to .END
+	pub listing: Vec<ListingLine>,
+	///Labels written as `name:` and the addresses they point at, in address order. Labels with an offset point into
+	///operands, so they're left out.
+	pub labels: Vec<(String, u16)>
+}
+
+impl Assembly {
+	///Bytes shown next to the source in the listing. The rest go on the lines after it.
+	const LISTING_BYTES: usize = 3;
+	
+	/**The listing as text, one line per source line with the address in hex, then the bytes, then the source.*/
+	pub fn listing_text(&self) -> String {
+		let mut text: String = String::new();
+		for line in &self.listing {
+			let mut chunks = line.bytes.chunks(Self::LISTING_BYTES);
+			text.push_str(format!("{:04X}  {:<8}  {}\n", line.address, hex_string(chunks.next().unwrap_or(&[])), line.source).as_str());
+			for (i, chunk) in chunks.enumerate() {
+				text.push_str(format!("{:04X}  {}\n", line.address.wrapping_add(((i + 1) * Self::LISTING_BYTES) as u16), hex_string(chunk)).as_str());
+			}
+		}
+		return text;
+	}
+}
+
+///Column and kind of an error, before the line number is added
+type ParseError = (usize, AsmErrorKind);
+
+///A number or label written in the source, and the column it starts at
+#[derive(Debug, Clone)]
+struct Expr<'a> {
+	value: Value<'a>,
+	column: usize
+}
+
+#[derive(Debug, Clone)]
+enum Value<'a> {
+	///Hex with 2 digits or less, or a number below 256, is a zero page address
+	Number {value: u16, zero_page: bool},
+	Label(&'a str)
+}
+
+///How an operand is written
+#[derive(Debug, Clone)]
+enum Operand<'a> {
+	None,
+	///A
+	Accumulator,
+	///#value
+	Immediate(Expr<'a>),
+	///value
+	Direct(Expr<'a>),
+	///value,X
+	IndexedX(Expr<'a>),
+	///value,Y
+	IndexedY(Expr<'a>),
+	///(value)
+	Indirect(Expr<'a>),
+	///(value,X)
+	IndirectX(Expr<'a>),
+	///(value),Y
+	IndirectY(Expr<'a>)
+}
+
+///A DAT value. Hex literals are stored in the order they're written, and labels as little-endian addresses.
+#[derive(Debug, Clone)]
+enum Datum<'a> {
+	Bytes(Vec<u8>),
+	Word(Expr<'a>)
+}
+
+#[derive(Debug, Clone)]
+enum Item<'a> {
+	Instruction {opcode: u8, mode: AddrMode, operand: Option<Expr<'a>>},
+	Data(Vec<Datum<'a>>)
+}
+
+///A line that makes bytes, found in the first pass and encoded in the second once every label is known
+#[derive(Debug, Clone)]
+struct Statement<'a> {
+	///Index of the line in the listing
+	line: usize,
+	address: u16,
+	item: Item<'a>
+}
+
+/**Assembles source in the .asm6502 dialect for the given CPU model. Bytes are placed from the origin until a .ORG moves them.
+
+Each line can have a label, then a directive, an instruction, or DAT, then a ; comment.
+* `name:` labels the address of the next byte. `name: +$01` labels the next instruction's address plus the offset, so it can point at an operand.
+* `.ORG $0200` moves where the next bytes go, and `.END` stops the assembler.
+* `DAT $496E6974` puts bytes in the order they're written. Decimal values are one byte, and labels are two.
+* Numbers are hex with $, binary with %, or decimal. `#` makes an immediate operand.
+* Labels are always assembled as absolute addresses, except by branches and instructions that only have zero page modes.
+* Hex operands with 2 digits or less use zero page modes when the instruction has them.
+* SYS takes no operand, an immediate operand, or an address.*/
+pub fn assemble(source: &str, origin: u16, model: CpuModel) -> Result<Assembly, AsmError> {
+	let mut listing: Vec<ListingLine> = Vec::new();
+	let mut statements: Vec<Statement> = Vec::new();
+	let mut labels: HashMap<&str, u16> = HashMap::new();
+	let mut address_labels: Vec<(String, u16)> = Vec::new();
+	//labels written as `name: +offset` wait for the next instruction's address
+	let mut pending: Vec<(&str, i32, usize, usize)> = Vec::new();
+	let mut address: u32 = origin as u32;
+	for (i, line) in source.lines().enumerate() {
+		let line_number: usize = i + 1;
+		let error = |text: &str, kind: AsmErrorKind| {AsmError {line: line_number, column: column(line, text), kind}};
+		let located = |(column, kind): ParseError| {AsmError {line: line_number, column, kind}};
+		listing.push(ListingLine {address: address as u16, bytes: Vec::new(), source: String::from(line), data: false});
+		let code: &str = line.split(';').next().unwrap_or("");
+		let mut rest: &str = code.trim();
+		if rest.is_empty() {continue;}
+		
+		let name_len: usize = rest.find(|c: char| {!(c.is_ascii_alphanumeric() || c == '_')}).unwrap_or(rest.len());
+		if name_len > 0 && rest[name_len..].starts_with(':') {
+			let name: &str = &rest[..name_len];
+			if name.starts_with(|c: char| {c.is_ascii_digit()}) {
+				return Err(error(name, AsmErrorKind::Syntax(String::from("a label that starts with a letter or _"))));
+			}
+			if labels.contains_key(name) || pending.iter().any(|(pending_name, ..)| {*pending_name == name}) {
+				return Err(error(name, AsmErrorKind::DuplicateLabel(String::from(name))));
+			}
+			rest = rest[name_len + 1..].trim_start();
+			if rest.starts_with(['+', '-']) {
+				let offset: i32 = match parse_number(&rest[1..]) {
+					Some((offset, _)) => {offset as i32}
+					None => {return Err(error(rest, AsmErrorKind::Syntax(String::from("an offset like +$01 after the label"))));}
+				};
+				pending.push((name, if rest.starts_with('-') {-offset} else {offset}, line_number, column(line, name)));
+				continue;
+			}
+			labels.insert(name, address as u16);
+			address_labels.push((String::from(name), address as u16));
+		}
+		if rest.is_empty() {continue;}
+		
+		let word_len: usize = rest.find(char::is_whitespace).unwrap_or(rest.len());
+		let (word, operand) = (&rest[..word_len], rest[word_len..].trim());
+		if word.starts_with('.') {
+			match word.to_uppercase().as_str() {
+				".ORG" => {
+					let expr: Expr = parse_expr(line, operand).map_err(located)?;
+					address = match expr.value {
+						Value::Number {value, ..} => {value as u32}
+						Value::Label(label) => {*labels.get(label).ok_or(error(operand, AsmErrorKind::UnknownLabel(String::from(label))))? as u32}
+					};
+					listing.last_mut().expect("Listing line was just pushed").address = address as u16;
+				}
+				".END" => {break;}
+				_ => {return Err(error(word, AsmErrorKind::UnknownDirective(String::from(word))));}
+			}
+			continue;
+		}
+		
+		let (item, len) = if word.eq_ignore_ascii_case("DAT") {
+			let data: Vec<Datum> = parse_data(line, operand).map_err(located)?;
+			let len: usize = data.iter().map(|datum| {
+				match datum {
+					Datum::Bytes(bytes) => {bytes.len()}
+					Datum::Word(_) => {2}
+				}
+			}).sum();
+			(Item::Data(data), len)
+		} else {
+			let Some(mnemonic) = Mnemonic::from_name(word, model) else {return Err(error(word, AsmErrorKind::UnknownInstruction(String::from(word))));};
+			let operand: Operand = parse_operand(line, operand).map_err(located)?;
+			let Some((opcode, mode)) = choose_mode(mnemonic, &operand, model) else {return Err(error(word, AsmErrorKind::AddressingMode(word.to_uppercase())));};
+			let expr: Option<Expr> = match operand {
+				Operand::None | Operand::Accumulator => {None}
+				Operand::Immediate(expr) | Operand::Direct(expr) | Operand::IndexedX(expr) | Operand::IndexedY(expr)
+					| Operand::Indirect(expr) | Operand::IndirectX(expr) | Operand::IndirectY(expr) => {Some(expr)}
+			};
+			(Item::Instruction {opcode, mode, operand: expr}, 1 + mode.operand_len() as usize)
+		};
+		if address + len as u32 > 0x10000 {
+			return Err(error(word, AsmErrorKind::AddressSpace));
+		}
+		for (name, offset, line_number, column) in pending.drain(..) {
+			let Ok(value) = u16::try_from(address as i32 + offset) else {return Err(AsmError {line: line_number, column, kind: AsmErrorKind::AddressSpace});};
+			labels.insert(name, value);
+		}
+		statements.push(Statement {line: i, address: address as u16, item});
+		address += len as u32;
+	}
+	for (name, offset, line_number, column) in pending {
+		let Ok(value) = u16::try_from(address as i32 + offset) else {return Err(AsmError {line: line_number, column, kind: AsmErrorKind::AddressSpace});};
+		labels.insert(name, value);
+	}
+	
+	//second pass: encode the operands now that every label has an address
+	let mut memory: Vec<Option<u8>> = vec![None; 0x10000];
+	for statement in statements {
+		let line_number: usize = statement.line + 1;
+		let resolve = |expr: &Expr| -> Result<u16, AsmError> {
+			match expr.value {
+				Value::Number {value, ..} => {Ok(value)}
+				Value::Label(label) => {labels.get(label).copied().ok_or(AsmError {line: line_number, column: expr.column, kind: AsmErrorKind::UnknownLabel(String::from(label))})}
+			}
+		};
+		let mut bytes: Vec<u8> = Vec::new();
+		match &statement.item {
+			Item::Instruction {opcode, mode, operand} => {
+				bytes.push(*opcode);
+				if let Some(expr) = operand {
+					let value: u16 = resolve(expr)?;
+					if *mode == AddrMode::Relative {
+						let offset: i32 = value as i32 - (statement.address as i32 + 2);
+						let Ok(offset) = i8::try_from(offset) else {return Err(AsmError {line: line_number, column: expr.column, kind: AsmErrorKind::BranchRange {offset}});};
+						bytes.push(offset as u8);
+					} else if mode.operand_len() == 1 {
+						let Ok(value) = u8::try_from(value) else {return Err(AsmError {line: line_number, column: expr.column, kind: AsmErrorKind::OutOfRange {value}});};
+						bytes.push(value);
+					} else {
+						bytes.extend_from_slice(&value.to_le_bytes());
+					}
+				}
+			}
+			Item::Data(data) => {
+				for datum in data {
+					match datum {
+						Datum::Bytes(data) => {bytes.extend_from_slice(data);}
+						Datum::Word(expr) => {bytes.extend_from_slice(&resolve(expr)?.to_le_bytes());}
+					}
+				}
+			}
+		}
+		for (i, byte) in bytes.iter().enumerate() {
+			let address: usize = statement.address as usize + i;
+			if memory[address].is_some() {
+				let source: &str = listing[statement.line].source.as_str();
+				let start: usize = source.len() - source.trim_start().len();
+				return Err(AsmError {line: line_number, column: column(source, &source[start..]), kind: AsmErrorKind::Overlap {address: address as u16}});
+			}
+			memory[address] = Some(*byte);
+		}
+		listing[statement.line].bytes = bytes;
+		listing[statement.line].data = matches!(statement.item, Item::Data(_));
+	}
+	
+	let first: Option<usize> = memory.iter().position(Option::is_some);
+	let last: Option<usize> = memory.iter().rposition(Option::is_some);
+	let (origin, bytes) = match (first, last) {
+		(Some(first), Some(last)) => {(first as u16, memory[first..=last].iter().map(|byte| {byte.unwrap_or(0x00)}).collect())}
+		_ => {(origin, Vec::new())}
+	};
+	address_labels.sort_by_key(|(_, address)| {*address});
+	return Ok(Assembly {origin, bytes, listing, labels: address_labels});
+}
+
+///Column of a piece of the line, counted in characters from 1
+fn column(line: &str, piece: &str) -> usize {
+	let offset: usize = (piece.as_ptr() as usize).saturating_sub(line.as_ptr() as usize).min(line.len());
+	line[..offset].chars().count() + 1
+}
+
+///Picks the opcode and addressing mode for how the operand is written. Zero page modes are used for zero page numbers if the instruction has them.
+fn choose_mode(mnemonic: Mnemonic, operand: &Operand, model: CpuModel) -> Option<(u8, AddrMode)> {
+	use AddrMode::*;
+	let zero_page = |expr: &Expr| {matches!(expr.value, Value::Number {zero_page: true, ..})};
+	if mnemonic == Mnemonic::SYS {
+		//the operands of a system call are read by its handler, so they're written as they are
+		let opcode: u8 = Opcode::encode(Mnemonic::SYS, Implied, model)?;
+		return match operand {
+			Operand::None => {Some((opcode, Implied))}
+			Operand::Immediate(_) => {Some((opcode, Immediate))}
+			Operand::Direct(_) => {Some((opcode, Absolute))}
+			_ => {None}
+		};
+	}
+	let modes: Vec<AddrMode> = match operand {
+		Operand::None => {vec![Implied, Accumulator]}
+		Operand::Accumulator => {vec![Accumulator]}
+		Operand::Immediate(_) => {vec![Immediate]}
+		Operand::Direct(expr) if zero_page(expr) => {vec![Relative, ZeroPage, Absolute]}
+		Operand::Direct(_) => {vec![Relative, Absolute, ZeroPage]}
+		Operand::IndexedX(expr) if zero_page(expr) => {vec![ZeroPageX, AbsoluteX]}
+		Operand::IndexedX(_) => {vec![AbsoluteX, ZeroPageX]}
+		Operand::IndexedY(expr) if zero_page(expr) => {vec![ZeroPageY, AbsoluteY]}
+		Operand::IndexedY(_) => {vec![AbsoluteY, ZeroPageY]}
+		Operand::Indirect(_) => {vec![Indirect, ZeroPageIndirect]}
+		Operand::IndirectX(_) => {vec![IndexedIndirect, AbsoluteIndexedIndirect]}
+		Operand::IndirectY(_) => {vec![IndirectIndexed]}
+	};
+	modes.into_iter().find_map(|mode| {Opcode::encode(mnemonic, mode, model).map(|opcode| {(opcode, mode)})})
+}
+
+///Works out the addressing mode an operand is written in
+fn parse_operand<'a>(line: &str, text: &'a str) -> Result<Operand<'a>, ParseError> {
+	let upper: String = text.to_uppercase();
+	if text.is_empty() {
+		return Ok(Operand::None);
+	}
+	if upper == "A" {
+		return Ok(Operand::Accumulator);
+	}
+	if let Some(value) = text.strip_prefix('#') {
+		return Ok(Operand::Immediate(parse_expr(line, value.trim())?));
+	}
+	if text.starts_with('(') {
+		let without_space: String = upper.split_whitespace().collect();
+		let inner: &str = &text[1..text.rfind(')').unwrap_or(text.len())];
+		return if without_space.ends_with(",X)") {
+			//the comma is found in the text as written, since spaces and uppercasing move the offsets
+			let Some(comma) = inner.rfind(',').filter(|comma| {inner[comma + 1..].trim().eq_ignore_ascii_case("X")}) else {
+				return Err((column(line, text), AsmErrorKind::Syntax(String::from("(value,X)"))));
+			};
+			Ok(Operand::IndirectX(parse_expr(line, inner[..comma].trim())?))
+		} else if without_space.ends_with("),Y") {
+			Ok(Operand::IndirectY(parse_expr(line, inner.trim())?))
+		} else if without_space.ends_with(')') {
+			Ok(Operand::Indirect(parse_expr(line, inner.trim())?))
+		} else {
+			Err((column(line, text), AsmErrorKind::Syntax(String::from("a ) to close the indirect operand"))))
+		};
+	}
+	if let Some((value, index)) = text.rsplit_once(',') {
+		return match index.trim().to_uppercase().as_str() {
+			"X" => {Ok(Operand::IndexedX(parse_expr(line, value.trim())?))}
+			"Y" => {Ok(Operand::IndexedY(parse_expr(line, value.trim())?))}
+			_ => {Err((column(line, index), AsmErrorKind::Syntax(String::from("X or Y after the comma"))))}
+		};
+	}
+	return Ok(Operand::Direct(parse_expr(line, text)?));
+}
+
+///Reads the comma-separated values after DAT
+fn parse_data<'a>(line: &str, text: &'a str) -> Result<Vec<Datum<'a>>, ParseError> {
+	let mut data: Vec<Datum> = Vec::new();
+	for value in text.split(',').map(str::trim) {
+		if let Some(hex) = value.strip_prefix('$') {
+			//an odd number of digits is padded at the front
+			let padded: String = if hex.len() % 2 == 1 {format!("0{}", hex)} else {String::from(hex)};
+			let bytes: Option<Vec<u8>> = if hex.is_empty() || !hex.is_ascii() {
+				None
+			} else {
+				(0..padded.len()).step_by(2).map(|i| {u8::from_str_radix(&padded[i..i + 2], 16).ok()}).collect()
+			};
+			match bytes {
+				Some(bytes) => {data.push(Datum::Bytes(bytes));}
+				None => {return Err((column(line, value), AsmErrorKind::BadValue(String::from(value))));}
+			}
+			continue;
+		}
+		let expr: Expr = parse_expr(line, value)?;
+		match expr.value {
+			Value::Number {value: number, ..} => {
+				let Ok(byte) = u8::try_from(number) else {return Err((expr.column, AsmErrorKind::OutOfRange {value: number}));};
+				data.push(Datum::Bytes(vec![byte]));
+			}
+			Value::Label(_) => {data.push(Datum::Word(expr));}
+		}
+	}
+	return Ok(data);
+}
+
+///Reads a number or a label
+fn parse_expr<'a>(line: &str, text: &'a str) -> Result<Expr<'a>, ParseError> {
+	let column: usize = column(line, text);
+	if let Some((value, zero_page)) = parse_number(text) {
+		return Ok(Expr {value: Value::Number {value, zero_page}, column});
+	}
+	let is_label: bool = text.starts_with(|c: char| {c.is_ascii_alphabetic() || c == '_'}) && text.chars().all(|c| {c.is_ascii_alphanumeric() || c == '_'});
+	if is_label {
+		return Ok(Expr {value: Value::Label(text), column});
+	}
+	if text.is_empty() {
+		return Err((column, AsmErrorKind::Syntax(String::from("a value"))));
+	}
+	return Err((column, AsmErrorKind::BadValue(String::from(text))));
+}
+
+///Reads $hex, %binary, or decimal. Returns the value and whether it was written as a zero page address.
+fn parse_number(text: &str) -> Option<(u16, bool)> {
+	let (digits, radix, zero_page_digits) = if let Some(hex) = text.strip_prefix('$') {
+		(hex, 16, 2)
+	} else if let Some(binary) = text.strip_prefix('%') {
+		(binary, 2, 8)
+	} else if text.starts_with(|c: char| {c.is_ascii_digit()}) {
+		(text, 10, 3)
+	} else {
+		return None;
+	};
+	let value: u16 = u16::from_str_radix(digits, radix).ok()?;
+	let zero_page: bool = if radix == 10 {value <= 0xFF} else {digits.len() <= zero_page_digits};
+	return Some((value, zero_page));
+}
+
+///Bytes in hex separated by spaces
+fn hex_string(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| {format!("{:02X}", byte)}).collect::<Vec<String>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	const NMOS: CpuModel = CpuModel::Nmos6502 {undocumented: false};
+	
+	fn bytes(source: &str, model: CpuModel) -> Vec<u8> {
+		assemble(source, 0x0000, model).unwrap().bytes
+	}
+	fn error(source: &str) -> (usize, usize, AsmErrorKind) {
+		let error: AsmError = assemble(source, 0x0000, NMOS).unwrap_err();
+		(error.line, error.column, error.kind)
+	}
+	
+	#[test]
+	fn bubble_sort_matches_the_demo() {
+		let source: String = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/bubbleSort.asm6502")).unwrap();
+		let assembly: Assembly = assemble(&source, 0x0000, NMOS).unwrap();
+		assert_eq!(assembly.origin, 0x0000);
+		assert_eq!(assembly.bytes, crate::SORT_PROGRAM);
+	}
+	#[test]
+	fn addressing_modes() {
+		let cases: [(&str, &[u8]); 20] = [
+			("CLC", &[0x18]),
+			("ASL", &[0x0A]),
+			("ASL A", &[0x0A]),
+			("LDA #$10", &[0xA9, 0x10]),
+			("LDA #%00010000", &[0xA9, 0x10]),
+			("LDA #16", &[0xA9, 0x10]),
+			("LDA $10", &[0xA5, 0x10]),
+			("LDA $0010", &[0xAD, 0x10, 0x00]),
+			("LDA $10,X", &[0xB5, 0x10]),
+			("LDA $1234, x", &[0xBD, 0x34, 0x12]),
+			("LDX $10,Y", &[0xB6, 0x10]),
+			("LDA $1234,Y", &[0xB9, 0x34, 0x12]),
+			("JMP ($1234)", &[0x6C, 0x34, 0x12]),
+			("LDA ($10,X)", &[0xA1, 0x10]),
+			("LDA ($10, X)", &[0xA1, 0x10]),
+			("LDA ( $10 , x )", &[0xA1, 0x10]),
+			("LDA ($10),Y", &[0xB1, 0x10]),
+			("LDA ($10), y", &[0xB1, 0x10]),
+			("SYS", &[0xFF]),
+			("SYS #$03", &[0xFF, 0x03])
+		];
+		for (source, expected) in cases {
+			assert_eq!(bytes(&format!("\t{}", source), NMOS), expected, "{}", source);
+		}
+		assert_eq!(bytes("\tLDA ($10)\n\tJMP ($1234,X)", CpuModel::Wdc65C02), [0xB2, 0x10, 0x7C, 0x34, 0x12]);
+		assert_eq!(bytes("back:\tNOP\n\tBNE back\n\tBEQ ahead\nahead:\tRTS", NMOS), [0xEA, 0xD0, 0xFD, 0xF0, 0x00, 0x60]);
+	}
+	#[test]
+	fn bad_operands_are_errors() {
+		assert_eq!(error("\tLDA ($10,Y)"), (1, 7, AsmErrorKind::BadValue(String::from("$10,Y"))));
+		assert_eq!(error("\tLDA ($10"), (1, 6, AsmErrorKind::Syntax(String::from("a ) to close the indirect operand"))));
+		assert_eq!(error("\tLDA $10,Z"), (1, 10, AsmErrorKind::Syntax(String::from("X or Y after the comma"))));
+	}
+	#[test]
+	fn offset_labels() {
+		let assembly: Assembly = assemble("\tLDX #$01\nptr: +$01\n\tLDA $0300\nback: -2\n\tINC ptr\n\tINC back", 0x0000, NMOS).unwrap();
+		assert_eq!(assembly.bytes, [0xA2, 0x01, 0xAD, 0x00, 0x03, 0xEE, 0x03, 0x00, 0xEE, 0x03, 0x00]);
+		assert!(assembly.labels.is_empty(), "labels with an offset point into operands");
+	}
+	#[test]
+	fn data() {
+		let assembly: Assembly = assemble("\tDAT $496E6974, 10, $ABC, here\nhere:\tDAT %101", 0x0200, NMOS).unwrap();
+		assert_eq!(assembly.bytes, [0x49, 0x6E, 0x69, 0x74, 0x0A, 0x0A, 0xBC, 0x09, 0x02, 0x05]);
+		assert_eq!(assembly.labels, [(String::from("here"), 0x0209)]);
+		assert!(assembly.listing[0].data);
+		assert_eq!(error("\tDAT 300"), (1, 6, AsmErrorKind::OutOfRange {value: 300}));
+		assert_eq!(error("\tDAT $4G"), (1, 6, AsmErrorKind::BadValue(String::from("$4G"))));
+	}
+	#[test]
+	fn errors_have_the_line_and_column() {
+		assert_eq!(error("\tNOP\n  FOO $10"), (2, 3, AsmErrorKind::UnknownInstruction(String::from("FOO"))));
+		assert_eq!(error("\tNOP\n\tJMP nowhere"), (2, 6, AsmErrorKind::UnknownLabel(String::from("nowhere"))));
+		assert_eq!(error("\t.ORG $FFFE\n\tLDA $1234"), (2, 2, AsmErrorKind::AddressSpace));
+		assert_eq!(error("\t.ORG $FFFE\n\tDAT $010203"), (2, 2, AsmErrorKind::AddressSpace));
+		assert_eq!(error("a:\nb: NOP\na: NOP"), (3, 1, AsmErrorKind::DuplicateLabel(String::from("a"))));
+		assert_eq!(error("\tBNE far\n\t.ORG $0100\nfar: NOP"), (1, 6, AsmErrorKind::BranchRange {offset: 254}));
+		assert_eq!(error("\tNOP\n\t.ORG $0000\n\tNOP"), (3, 2, AsmErrorKind::Overlap {address: 0x0000}));
+		assert_eq!(error("\tSTX $1234,X"), (1, 2, AsmErrorKind::AddressingMode(String::from("STX"))));
+	}
+	#[test]
+	fn listing_columns() {
+		let assembly: Assembly = assemble("\t.ORG $0200\nstart:\tLDA $1234 ;load\n\tDAT $01020304\n\t.END\n\tNOP", 0x0000, NMOS).unwrap();
+		assert_eq!(assembly.listing_text(), "\
+0200            \t.ORG $0200
+0200  AD 34 12  start:\tLDA $1234 ;load
+0203  01 02 03  \tDAT $01020304
+0206  04
+0207            \t.END
+");
+	}
+}
\ No newline at end of file
@@ -0,0 +1,421 @@
+use {
+	crate::{
+		assembler::{self, Assembly},
+		hardware::{
+			cpu::{CpuModel, Opcode},
+			watchpoint::Watchpoint,
+			syscall::SyscallTable,
+			disassembler::{self, Instruction},
+			cache::{CacheConfig, Mapping},
+			replacement::Policy
+		},
+		image::Image,
+		lib::parse_number,
+		log::{Filter, Level}
+	},
+	std::fs
+};
+
+/**How the bytes of a program file are stored.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+	///Assembly if the file ends in .asm6502, .asm, .s, or .a65. Otherwise Intel HEX or S-records if the first line is a record,
+	///hex text if every word in the file is a byte written in hex, raw binary otherwise
+	Auto,
+	///The bytes of the file are the program
+	Raw,
+	///Bytes written in hex and separated by whitespace or commas, like the output of the assembler. 0x and $ prefixes are allowed.
+	HexText,
+	IntelHex,
+	SRecord,
+	///Source in the .asm6502 dialect, assembled for the CPU model being run
+	Assembly
+}
+
+impl Format {
+	///Guesses the format of an exported file from its extension. Intel HEX is used unless it's an S-record extension.
+	pub fn from_extension(path: &str) -> Self {
+		match Self::extension(path).as_str() {
+			"srec" | "s19" | "s28" | "s37" | "mot" => {Format::SRecord}
+			_ => {Format::IntelHex}
+		}
+	}
+	///Whether a file is read as assembly source with this format
+	pub fn is_assembly(self, path: &str) -> bool {
+		self == Format::Assembly || (self == Format::Auto && matches!(Format::extension(path).as_str(), "asm6502" | "asm" | "s" | "a65"))
+	}
+	fn extension(path: &str) -> String {
+		path.rsplit_once('.').map_or(String::new(), |(_, extension)| {extension.to_lowercase()})
+	}
+}
+
+/**Options given on the command line.*/
+pub struct Options {
+	///Print the usage and exit
+	pub help: bool,
+	///Program file to run. The bubble sort demo is run if there isn't one.
+	pub path: Option<String>,
+	pub format: Format,
+	///Where the program is loaded in memory. Intel HEX and S-records have their own addresses.
+	pub load_address: u16,
+	///Where the reset vector points. Defaults to the start address in the file, or the load address.
+	pub start_address: Option<u16>,
+	pub model: CpuModel,
+	///Delay between clock pulses (in microseconds or 0.001 milliseconds)
+	pub clock_interval_micro: Option<u64>,
+	///How many clock pulses run between each sleep
+	pub pulses_per_sleep: Option<u128>,
+	///Lines, line size, and mapping of the cache
+	pub cache: CacheConfig,
+	///Level each component logs at
+	pub log_filter: Filter,
+	///File the log is written to as text instead of the console
+	pub log_file: Option<String>,
+	///File the log is written to as JSON lines, or - for stdout
+	pub log_json: Option<String>,
+	///BRK halts the system when true, or goes through the IRQ/BRK vector when false
+	pub brk_halts: bool,
+	///File the memory is written to once the program halts
+	pub export_path: Option<String>,
+	///First and last address written to the export file
+	pub export_range: (u16, u16),
+	///File the listing of an assembled program is written to, or - for stdout
+	pub listing_path: Option<String>,
+	///Run the program from the debugger prompt instead of all at once
+	pub debugger: bool,
+	///Serve the GDB remote serial protocol on this port instead of running the program all at once
+	pub gdb_port: Option<u16>,
+	///Serve the Debug Adapter Protocol on this port instead of running the program all at once
+	pub dap_port: Option<u16>,
+	///Run the program in a full-screen dashboard instead of all at once
+	pub tui: bool,
+	///Watchpoints set before the program starts
+	pub watchpoints: Vec<Watchpoint>,
+	///File a line is written to for every instruction that retires
+	pub trace_path: Option<String>,
+	///Only instructions in this range are traced, including the end
+	pub trace_range: Option<(u16, u16)>,
+	///KiB the debugger can use to keep the cycles it can go back through
+	pub history_kib: Option<usize>,
+	///Print the hot spots when the program ends
+	pub profile: bool,
+	///File the profile is written to in the collapsed-stack format
+	pub profile_stacks: Option<String>,
+	///Bytes in each group of the profile, instead of grouping by label
+	pub profile_group: Option<u16>,
+	///File the coverage report is written to, or - for stdout
+	pub coverage_path: Option<String>,
+	///File the coverage is written to as JSON
+	pub coverage_json: Option<String>
+}
+
+impl Options {
+	pub const USAGE: &'static str = "\
+Usage: Rust-422-tsiraM [OPTIONS] [FILE]
+
+Runs FILE, or the bubble sort demo if no FILE is given.
+
+Options:
+  -f, --format <FORMAT>    auto, raw, hex, ihex, srec, or asm (default: auto)
+                           raw is a binary file, hex is whitespace-separated bytes in hex,
+                           ihex is Intel HEX, srec is Motorola S-records, and asm is assembly
+                           source, which auto picks for .asm6502, .asm, .s, and .a65 files
+  -l, --load <ADDRESS>     Address a raw or hex program is loaded at, or where assembly
+                           starts before any .ORG (default: 0x0000)
+  -s, --start <ADDRESS>    Address the reset vector points to
+                           (default: the start address in the file, or the load address)
+  -m, --model <MODEL>      nmos, nmos-undocumented, 65c02, 2a03, or 2a03-undocumented (default: nmos)
+  -i, --interval <MICROS>  Microseconds to sleep between clock pulses (default: 0)
+  -p, --pulses <PULSES>    Clock pulses between each sleep (default: 30)
+      --cache-lines <LINES>
+                           Lines in the cache (default: 16)
+      --cache-line-size <BYTES>
+                           Bytes in each cache line, a power of two (default: 8)
+      --cache-mapping <MAPPING>
+                           direct, N-way for sets of N lines, or full (default: full)
+      --cache-policy <POLICY>
+                           Line evicted from a full set: lru, plru (tree pseudo-LRU), fifo, random,
+                           random:SEED, or lfu (default: lru). A run uses one policy, and its evictions
+                           are printed with the totals, so run again with another to compare them
+  -d, --debug <PARTS>      Comma-separated parts to log every cycle of: clock, cpu, memory, or all.
+                           The same as --log PART=trace
+      --log <FILTER>       Levels to log: off, error, warn, info, debug, or trace, for every component
+                           or for one with COMPONENT=LEVEL, like info,cpu=debug (default: info)
+                           Components: system, clock, cpu, mmu, cache, memory, interrupt-controller, keyboard
+      --log-file <FILE>    Write the log to FILE instead of the console. Warnings are still printed
+      --log-json <FILE>    Write the log to FILE as JSON lines, or to stdout if FILE is -
+      --brk-vector         BRK goes through the IRQ/BRK vector instead of halting
+  -o, --export <FILE>      Write memory to FILE once the program halts, as S-records if FILE ends
+                           in .srec, .s19, .s28, .s37, or .mot, or as Intel HEX otherwise
+  -r, --range <START:END>  Addresses written by --export, including END (default: 0x0000:0xFFFF)
+  -t, --trace <FILE>       Write a line to FILE for every instruction that retires, in program order,
+                           with the registers before it ran and the cycle it was issued in
+      --trace-range <START:END>
+                           Only trace instructions at addresses from START to END, including END
+      --profile            Print the hot spots when the program ends: cycles, executions, stalls,
+                           and cache misses for each group and the hottest instructions
+      --profile-stacks <FILE>
+                           Write the cycles of each instruction to FILE in the collapsed-stack
+                           format read by flame graph tools
+      --profile-group <BYTES>
+                           Group the profile in ranges of BYTES instead of by label
+                           (default: the labels of assembly source, or ranges of 256)
+      --coverage <FILE>    Write the program's disassembly to FILE with the times each instruction ran
+                           and the ways each branch went, or to stdout if FILE is -
+      --coverage-json <FILE>
+                           Write the coverage to FILE as JSON, with the totals and every instruction,
+                           or to stdout if FILE is -
+  -L, --listing <FILE>     Write the listing of an assembled program to FILE, or to stdout if FILE is -
+  -g, --debugger           Run the program from a prompt with breakpoints and stepping
+      --history <KIB>      KiB the debugger keeps to step back through, or 0 for none (default: 65536)
+      --gdb <PORT>         Wait for a GDB client on 127.0.0.1:PORT and let it control the program
+      --dap <PORT>         Wait for an editor on 127.0.0.1:PORT and debug the program over the Debug Adapter Protocol
+      --tui                Run the program in a full-screen dashboard of the registers, the pipeline,
+                           the cache, memory, and output, with pause, step, and run keys
+  -w, --watch <WATCH>      Log or stop on accesses to memory, like \"change $7E log\". Can be repeated.
+                           <read|write|change> <ADDRESS>[-<END>] [if value <OP> <VALUE>] [stop|log]
+      --demo               Run the bubble sort demo
+  -h, --help               Print this message
+
+Addresses and numbers are decimal, or hex with a 0x or $ prefix.";
+
+	/**Reads the options from the arguments, not including the name of the executable.*/
+	pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+		let mut options: Self = Self {
+			help: false,
+			path: None,
+			format: Format::Auto,
+			load_address: 0x0000,
+			start_address: None,
+			model: CpuModel::Nmos6502 {undocumented: false},
+			clock_interval_micro: None,
+			pulses_per_sleep: None,
+			cache: CacheConfig::default(),
+			log_filter: Filter::default(),
+			log_file: None,
+			log_json: None,
+			brk_halts: true,
+			export_path: None,
+			export_range: (0x0000, 0xFFFF),
+			listing_path: None,
+			debugger: false,
+			gdb_port: None,
+			dap_port: None,
+			tui: false,
+			watchpoints: Vec::new(),
+			trace_path: None,
+			trace_range: None,
+			history_kib: None,
+			profile: false,
+			profile_stacks: None,
+			profile_group: None,
+			coverage_path: None,
+			coverage_json: None
+		};
+		let mut demo: bool = false;
+		let mut args = args.into_iter();
+		while let Some(arg) = args.next() {
+			match arg.as_str() {
+				"-h" | "--help" => {options.help = true;}
+				"--demo" => {demo = true;}
+				"--brk-vector" => {options.brk_halts = false;}
+				"-g" | "--debugger" => {options.debugger = true;}
+				"--history" => {options.history_kib = Some(parse_number(&Self::value(&arg, args.next())?)?);}
+				"--gdb" => {options.gdb_port = Some(parse_number(&Self::value(&arg, args.next())?)?);}
+				"--dap" => {options.dap_port = Some(parse_number(&Self::value(&arg, args.next())?)?);}
+				"--tui" => {options.tui = true;}
+				"-f" | "--format" => {
+					options.format = match Self::value(&arg, args.next())?.as_str() {
+						"auto" => {Format::Auto}
+						"raw" | "bin" => {Format::Raw}
+						"hex" => {Format::HexText}
+						"ihex" | "intel" => {Format::IntelHex}
+						"srec" | "s-record" => {Format::SRecord}
+						"asm" | "asm6502" => {Format::Assembly}
+						format => {return Err(format!("Unknown format: {}", format));}
+					};
+				}
+				"-l" | "--load" => {options.load_address = parse_number(&Self::value(&arg, args.next())?)?;}
+				"-s" | "--start" => {options.start_address = Some(parse_number(&Self::value(&arg, args.next())?)?);}
+				"-m" | "--model" => {
+					options.model = match Self::value(&arg, args.next())?.to_lowercase().as_str() {
+						"nmos" | "6502" => {CpuModel::Nmos6502 {undocumented: false}}
+						"nmos-undocumented" => {CpuModel::Nmos6502 {undocumented: true}}
+						"65c02" | "wdc65c02" => {CpuModel::Wdc65C02}
+						"2a03" | "ricoh2a03" => {CpuModel::Ricoh2A03 {undocumented: false}}
+						"2a03-undocumented" => {CpuModel::Ricoh2A03 {undocumented: true}}
+						model => {return Err(format!("Unknown CPU model: {}", model));}
+					};
+				}
+				"-o" | "--export" => {options.export_path = Some(Self::value(&arg, args.next())?);}
+				"-w" | "--watch" => {options.watchpoints.push(Watchpoint::parse(&Self::value(&arg, args.next())?)?);}
+				"-L" | "--listing" => {options.listing_path = Some(Self::value(&arg, args.next())?);}
+				"-r" | "--range" => {options.export_range = Self::range(&Self::value(&arg, args.next())?)?;}
+				"--profile" => {options.profile = true;}
+				"--profile-stacks" => {options.profile_stacks = Some(Self::value(&arg, args.next())?);}
+				"--profile-group" => {
+					let size: u16 = parse_number(&Self::value(&arg, args.next())?)?;
+					if size == 0 {
+						return Err(String::from("--profile-group needs at least 1 byte"));
+					}
+					options.profile_group = Some(size);
+				}
+				"--coverage" => {options.coverage_path = Some(Self::value(&arg, args.next())?);}
+				"--coverage-json" => {options.coverage_json = Some(Self::value(&arg, args.next())?);}
+				"-t" | "--trace" => {options.trace_path = Some(Self::value(&arg, args.next())?);}
+				"--trace-range" => {options.trace_range = Some(Self::range(&Self::value(&arg, args.next())?)?);}
+				"-i" | "--interval" => {options.clock_interval_micro = Some(parse_number(&Self::value(&arg, args.next())?)?);}
+				"-p" | "--pulses" => {options.pulses_per_sleep = Some(parse_number(&Self::value(&arg, args.next())?)?);}
+				"--log" => {options.log_filter.apply(&Self::value(&arg, args.next())?)?;}
+				"--log-file" => {options.log_file = Some(Self::value(&arg, args.next())?);}
+				"--log-json" => {options.log_json = Some(Self::value(&arg, args.next())?);}
+				"--cache-lines" => {options.cache.lines = parse_number(&Self::value(&arg, args.next())?)?;}
+				"--cache-line-size" => {options.cache.line_size = parse_number(&Self::value(&arg, args.next())?)?;}
+				"--cache-mapping" => {options.cache.mapping = Mapping::parse(&Self::value(&arg, args.next())?)?;}
+				"--cache-policy" => {options.cache.policy = Policy::parse(&Self::value(&arg, args.next())?)?;}
+				"-d" | "--debug" => {
+					for part in Self::value(&arg, args.next())?.split(',') {
+						match part.trim() {
+							part @ ("clock" | "cpu" | "memory") => {options.log_filter.set(part, Some(Level::Trace))?;}
+							"all" => {
+								for part in ["clock", "cpu", "memory"] {
+									options.log_filter.set(part, Some(Level::Trace))?;
+								}
+							}
+							part => {return Err(format!("Unknown part to debug: {}", part));}
+						}
+					}
+				}
+				flag if flag.starts_with('-') && flag.len() > 1 => {return Err(format!("Unknown option: {}", flag));}
+				_ => {
+					if options.path.is_some() {
+						return Err(format!("Only one program can be run, but {} was also given", arg));
+					}
+					options.path = Some(arg);
+				}
+			}
+		}
+		if demo && options.path.is_some() {
+			return Err(String::from("--demo can't be used with a program file"));
+		}
+		if [options.debugger, options.gdb_port.is_some(), options.dap_port.is_some(), options.tui].into_iter().filter(|mode| {*mode}).count() > 1 {
+			return Err(String::from("Only one of --debugger, --gdb, --dap, and --tui can be used"));
+		}
+		if options.listing_path.is_some() && options.path.is_none() {
+			return Err(String::from("--listing needs an assembly file"));
+		}
+		if options.history_kib.is_some() && !options.debugger {
+			return Err(String::from("--history needs --debugger"));
+		}
+		if options.profile_group.is_some() && !options.profile && options.profile_stacks.is_none() {
+			return Err(String::from("--profile-group needs --profile or --profile-stacks"));
+		}
+		if options.trace_range.is_some() && options.trace_path.is_none() {
+			return Err(String::from("--trace-range needs --trace"));
+		}
+		options.cache.validate()?;
+		return Ok(options);
+	}
+	
+	///The value after an option, or an error if the arguments ran out
+	fn value(option: &str, value: Option<String>) -> Result<String, String> {
+		value.ok_or(format!("{} needs a value", option))
+	}
+	///Parses START:END, where END is included
+	fn range(range: &str) -> Result<(u16, u16), String> {
+		let Some((start, end)) = range.split_once(':') else {return Err(format!("The range should look like START:END, not {}", range));};
+		let (start, end) = (parse_number(start)?, parse_number(end)?);
+		if start > end {
+			return Err(format!("The range {} ends before it starts", range));
+		}
+		return Ok((start, end));
+	}
+}
+
+/**The labels of a program, and the addresses they point at. Assembly source is assembled again to find them,
+and other formats don't have any.*/
+pub fn read_labels(path: &str, options: &Options) -> Vec<(String, u16)> {
+	if !options.format.is_assembly(path) {return Vec::new();}
+	let Ok(source) = fs::read_to_string(path) else {return Vec::new();};
+	assembler::assemble(&source, options.load_address, options.model).map_or(Vec::new(), |assembly| {assembly.labels})
+}
+
+/**The instructions of the program that was loaded, for the coverage report. Assembly source is assembled again
+so its DAT lines are left out, and the segments of other formats are disassembled with their data.*/
+pub fn read_code(path: Option<&str>, options: &Options, image: &Image, syscalls: &SyscallTable) -> Vec<Instruction> {
+	let source: Option<String> = path.filter(|path| {options.format.is_assembly(path)}).and_then(|path| {fs::read_to_string(path).ok()});
+	if let Some(Ok(assembly)) = source.map(|source| {assembler::assemble(&source, options.load_address, options.model)}) {
+		return assembly.listing.into_iter().filter(|line| {!line.data && !line.bytes.is_empty()}).map(|line| {
+			let opcode: Option<Opcode> = Opcode::from(line.bytes[0], options.model);
+			let text: String = opcode.map_or(String::new(), |opcode| {disassembler::instruction_text(opcode, &line.bytes[1..], line.address)});
+			Instruction {address: line.address, bytes: line.bytes, opcode, text}
+		}).collect();
+	}
+	image.segments.iter().flat_map(|segment| {disassembler::disassemble(&segment.data, segment.address, options.model, Some(syscalls))}).collect()
+}
+
+/**Reads the program in the file at the path with the format in the options. Raw and hex text programs are put at the load address,
+and assembly is assembled for the CPU model in the options and its listing is written if the options ask for it.*/
+pub fn read_program(path: &str, options: &Options) -> Result<Image, String> {
+	let load_address: u16 = options.load_address;
+	let bytes: Vec<u8> = fs::read(path).map_err(|error| {format!("Could not read {}: {}", path, error)})?;
+	let text: Option<&str> = std::str::from_utf8(&bytes).ok();
+	let format: Format = match (options.format, text) {
+		(Format::Auto, _) if Format::Auto.is_assembly(path) => {Format::Assembly}
+		(Format::Auto, Some(text)) => {
+			//records start every line with : or S and a digit, which can't be mistaken for hex text
+			let first_line: &str = text.lines().map(|line| {line.trim()}).find(|line| {!line.is_empty()}).unwrap_or("");
+			if first_line.starts_with(':') {
+				Format::IntelHex
+			} else if first_line.starts_with('S') && first_line[1..].starts_with(|c: char| {c.is_ascii_digit()}) {
+				Format::SRecord
+			} else if parse_hex_text(text).is_ok() {
+				Format::HexText
+			} else {
+				Format::Raw
+			}
+		}
+		(Format::Auto, None) => {Format::Raw}
+		(format, _) => {format}
+	};
+	let not_text = || {format!("{} is not a text file", path)};
+	let image = match format {
+		Format::Auto | Format::Raw => {Image::from_bytes(load_address, &bytes)}
+		Format::HexText => {
+			let program: Vec<u8> = parse_hex_text(text.ok_or_else(not_text)?).map_err(|word| {format!("{} is not a byte in hex in {}", word, path)})?;
+			Image::from_bytes(load_address, &program)
+		}
+		Format::IntelHex => {Image::from_intel_hex(text.ok_or_else(not_text)?)}
+		Format::SRecord => {Image::from_srecord(text.ok_or_else(not_text)?)}
+		Format::Assembly => {
+			let assembly: Assembly = assembler::assemble(text.ok_or_else(not_text)?, load_address, options.model).map_err(|error| {format!("Could not assemble {}: {}", path, error)})?;
+			match options.listing_path.as_deref() {
+				Some("-") => {print!("{}", assembly.listing_text());}
+				Some(listing_path) => {fs::write(listing_path, assembly.listing_text()).map_err(|error| {format!("Could not write {}: {}", listing_path, error)})?;}
+				None => {}
+			}
+			Image::from_bytes(assembly.origin, &assembly.bytes)
+		}
+	};
+	image.map_err(|error| {format!("Could not load {}: {}", path, error)})
+}
+
+/**Writes the image to the file at the path in the format its extension calls for.*/
+pub fn write_image(path: &str, image: &Image) -> Result<(), String> {
+	let text: String = match Format::from_extension(path) {
+		Format::SRecord => {image.to_srecord()}
+		_ => {image.to_intel_hex()}
+	};
+	fs::write(path, text).map_err(|error| {format!("Could not write {}: {}", path, error)})
+}
+
+/**Parses bytes written in hex and separated by whitespace or commas. Returns the first word that isn't a byte if it fails.*/
+fn parse_hex_text(text: &str) -> Result<Vec<u8>, String> {
+	text.split(|c: char| {c.is_whitespace() || c == ','}).filter(|word| {!word.is_empty()}).map(|word| {
+		let hex: &str = word.strip_prefix("0x").or(word.strip_prefix("0X")).or(word.strip_prefix('$')).unwrap_or(word);
+		if hex.len() > 2 {
+			return Err(word.to_string());
+		}
+		u8::from_str_radix(hex, 16).map_err(|_| {word.to_string()})
+	}).collect()
+}
\ No newline at end of file
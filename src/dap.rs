@@ -0,0 +1,793 @@
+use {
+	crate::{
+		assembler::{self, ListingLine},
+		cli::{self, Options},
+		hardware::{
+			cpu::{Cpu, Mnemonic},
+			disassembler::{self, Instruction},
+			fault::EmulatorError,
+			hardware::Hardware,
+			watchpoint::{WatchAction, WatchHit}
+		},
+		image::Image,
+		lib::parse_number,
+		system::System
+	},
+	serde_json::{json, Value},
+	std::{
+		fs,
+		io::{self, ErrorKind, Read, Write},
+		net::{TcpListener, TcpStream},
+		path::Path,
+		sync::mpsc::{self, Receiver}
+	}
+};
+
+/**Line numbers of an assembled source file, from the assembler's listing.*/
+struct SourceMap {
+	path: String,
+	///One line for each line of source, in order
+	listing: Vec<ListingLine>
+}
+
+impl SourceMap {
+	///Assembles the file again to get its listing. Returns None if the file isn't assembly source.
+	fn read(path: &str, options: &Options) -> Option<Self> {
+		if !options.format.is_assembly(path) {return None;}
+		let source: String = fs::read_to_string(path).ok()?;
+		let assembly = assembler::assemble(&source, options.load_address, options.model).ok()?;
+		return Some(Self {path: canonical(path), listing: assembly.listing});
+	}
+	///Address of the first instruction on or after the line, and the line it's on
+	fn address_of(&self, line: usize) -> Option<(u16, usize)> {
+		self.listing.iter().enumerate().skip(line.max(1) - 1).find(|(_, listing)| {!listing.bytes.is_empty()}).map(|(i, listing)| {(listing.address, i + 1)})
+	}
+	///Line that made the byte at the address
+	fn line_of(&self, address: u16) -> Option<usize> {
+		self.listing.iter().position(|listing| {
+			address >= listing.address && (address as usize) < listing.address as usize + listing.bytes.len()
+		}).map(|i| {i + 1})
+	}
+}
+
+/**A breakpoint set on a source line or on an instruction address. Both stop when the instruction is sent to an execution unit.*/
+struct Breakpoint {
+	id: u64,
+	address: u16,
+	///None for instruction breakpoints
+	line: Option<usize>
+}
+
+/**What the program is doing between requests.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Run {
+	Continue,
+	///Runs until the instruction count reaches the target and no deeper in the call stack than the depth
+	Step {target: u128, depth: usize}
+}
+
+/**Why the program stopped running.*/
+enum Stop {
+	Step,
+	Breakpoint(u64),
+	Watch(WatchHit),
+	Halted
+}
+
+/**Lets editors debug programs over the Debug Adapter Protocol. It listens on a local TCP port, so the editor's debug
+configuration connects with a debug server port. The server runs the clock itself while the program runs, and the program's
+output is sent to the editor as output events instead of stdout.
+The call stack is made of the JSRs, BRKs, and interrupted instructions that haven't returned yet, the variables are
+the registers and flags, and source lines are known for assembly files through the assembler's listing.*/
+pub struct DapServer {
+	listener: TcpListener,
+	///Sequence number of the next message sent to the client
+	seq: u64,
+	source: Option<SourceMap>,
+	breakpoints: Vec<Breakpoint>,
+	next_breakpoint_id: u64,
+	///Addresses of the JSRs that haven't returned, and of the BRKs and interrupted instructions whose handlers haven't, oldest first
+	call_stack: Vec<u16>,
+	///Set once an RTS or RTI is issued. Its frame is popped when the next instruction is issued or a handler is entered,
+	///so a step stops at the instruction it returned to instead of at the RTS or RTI.
+	returning: bool,
+	running: Option<Run>,
+	stop_on_entry: bool,
+	///Set once the client knows the CPU faulted, so running again ends the session
+	fault_reported: bool,
+	output: Receiver<String>
+}
+
+impl DapServer {
+	///Clock cycles run between checks for requests while the program runs
+	const BATCH_CYCLES: u32 = 1000;
+	const THREAD_ID: u64 = 1;
+	const REGISTERS_REFERENCE: u64 = 1;
+	const FLAGS_REFERENCE: u64 = 2;
+	///Most instructions a disassemble request gets, and the furthest its offset reaches: one for every address
+	const MAX_INSTRUCTIONS: i64 = 0x10000;
+	
+	/**Listens on 127.0.0.1 at the port. Use port 0 to let the OS pick one.*/
+	pub fn bind(port: u16) -> io::Result<Self> {
+		return Ok(Self {
+			listener: TcpListener::bind(("127.0.0.1", port))?,
+			seq: 1,
+			source: None,
+			breakpoints: Vec::new(),
+			next_breakpoint_id: 1,
+			call_stack: Vec::new(),
+			returning: false,
+			running: None,
+			stop_on_entry: false,
+			fault_reported: false,
+			output: mpsc::channel().1
+		});
+	}
+	
+	pub fn port(&self) -> io::Result<u16> {
+		return Ok(self.listener.local_addr()?.port());
+	}
+	
+	/**Waits for a client and serves it until it disconnects. The program loaded from the command line is used for attach,
+	and launch loads the program it's given. Returns an error if the memory bus fails.*/
+	pub fn run(&mut self, system: &mut System, options: &Options) -> Result<(), EmulatorError> {
+		system.clock.cpu.set_keyboard_listening(false);
+		system.log(format!("Waiting for a debug adapter client on 127.0.0.1:{}", self.port().unwrap_or(0)).as_str());
+		let mut connection: Connection = match self.listener.accept() {
+			Ok((stream, address)) => {
+				system.log(format!("Debug adapter client connected from {}", address).as_str());
+				Connection::new(stream)
+			}
+			Err(error) => {
+				system.warn(format!("Could not accept a debug adapter client: {}", error).as_str());
+				return Ok(());
+			}
+		};
+		let (sender, receiver) = mpsc::channel();
+		system.clock.cpu.syscalls.output.redirect(sender);
+		self.output = receiver;
+		match self.serve(system, options, &mut connection) {
+			Ok(()) => {system.log("Debug adapter client disconnected");}
+			Err(Error::Emulator(error)) => {return Err(error);}
+			Err(Error::Io(error)) if error.kind() == ErrorKind::UnexpectedEof => {system.log("Debug adapter client disconnected");}
+			Err(Error::Io(error)) => {system.warn(format!("Lost the debug adapter client: {}", error).as_str());}
+		}
+		return Ok(());
+	}
+	
+	///Handles requests, and runs the program between them while it's running
+	fn serve(&mut self, system: &mut System, options: &Options, connection: &mut Connection) -> Result<(), Error> {
+		loop {
+			//every waiting request is handled before more cycles run
+			if let Some(message) = connection.receive(self.running.is_none())? {
+				if !self.request(system, options, connection, &message)? {return Ok(());}
+				continue;
+			}
+			if let Some(run) = self.running {
+				let stop: Option<Stop> = self.run_cycles(system, connection, run)?;
+				self.forward_output(connection)?;
+				if let Some(stop) = stop {
+					self.running = None;
+					self.stopped(system, connection, stop)?;
+				}
+			}
+		}
+	}
+	
+	///Answers a request. Returns false once the client disconnects.
+	fn request(&mut self, system: &mut System, options: &Options, connection: &mut Connection, message: &Value) -> Result<bool, Error> {
+		let command: &str = message["command"].as_str().unwrap_or("");
+		let args: &Value = &message["arguments"];
+		let body: Result<Value, String> = match command {
+			"initialize" => {
+				self.respond(connection, message, Ok(json!({
+					"supportsConfigurationDoneRequest": true,
+					"supportsReadMemoryRequest": true,
+					"supportsWriteMemoryRequest": true,
+					"supportsDisassembleRequest": true,
+					"supportsInstructionBreakpoints": true,
+					"supportsTerminateRequest": true
+				})))?;
+				self.event(connection, "initialized", json!({}))?;
+				return Ok(true);
+			}
+			"launch" | "attach" => {self.start(system, options, command == "launch", args)}
+			"configurationDone" => {
+				self.respond(connection, message, Ok(json!({})))?;
+				if self.stop_on_entry {
+					self.event(connection, "stopped", json!({"reason": "entry", "threadId": Self::THREAD_ID, "allThreadsStopped": true}))?;
+				} else {
+					self.running = Some(Run::Continue);
+				}
+				return Ok(true);
+			}
+			"setBreakpoints" => {Ok(self.set_breakpoints(args))}
+			"setInstructionBreakpoints" => {Ok(self.set_instruction_breakpoints(args))}
+			"setExceptionBreakpoints" => {Ok(json!({"breakpoints": []}))}
+			"threads" => {Ok(json!({"threads": [{"id": Self::THREAD_ID, "name": "6502"}]}))}
+			"stackTrace" => {self.stack_trace(system).map_err(|error| {error.to_string()})}
+			"scopes" => {
+				Ok(json!({"scopes": [
+					{"name": "Registers", "variablesReference": Self::REGISTERS_REFERENCE, "expensive": false},
+					{"name": "Flags", "variablesReference": Self::FLAGS_REFERENCE, "expensive": false}
+				]}))
+			}
+			"variables" => {Ok(Self::variables(&system.clock.cpu, args["variablesReference"].as_u64().unwrap_or(0)))}
+			"continue" => {
+				self.running = Some(Run::Continue);
+				Ok(json!({"allThreadsContinued": true}))
+			}
+			"next" | "stepIn" | "stepOut" => {
+				let depth: usize = self.callers(&system.clock.cpu);
+				self.running = Some(Run::Step {
+					target: system.clock.cpu.instruction_counter + 1,
+					depth: match command {
+						"next" => {depth}
+						"stepOut" => {depth.saturating_sub(1)}
+						_ => {usize::MAX}
+					}
+				});
+				Ok(json!({}))
+			}
+			"pause" => {
+				self.respond(connection, message, Ok(json!({})))?;
+				if self.running.take().is_some() {
+					self.event(connection, "stopped", json!({"reason": "pause", "threadId": Self::THREAD_ID, "allThreadsStopped": true}))?;
+				}
+				return Ok(true);
+			}
+			"readMemory" => {Self::read_memory(system, args)}
+			"writeMemory" => {Self::write_memory(system, args)}
+			"disassemble" => {self.disassemble(system, args)}
+			"disconnect" | "terminate" => {
+				self.respond(connection, message, Ok(json!({})))?;
+				if command == "terminate" {
+					self.event(connection, "terminated", json!({}))?;
+				}
+				return Ok(false);
+			}
+			command => {Err(format!("{} is not supported", command))}
+		};
+		self.respond(connection, message, body)?;
+		return Ok(true);
+	}
+	
+	///Loads the program for launch, or uses the one already loaded for attach, and reads its source lines if it's assembly
+	fn start(&mut self, system: &mut System, options: &Options, launch: bool, args: &Value) -> Result<Value, String> {
+		self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+		self.call_stack.clear();
+		self.returning = false;
+		let path: Option<String> = args["program"].as_str().map(String::from).or(options.path.clone());
+		if launch {
+			if let Some(path) = &path {
+				let image: Image = cli::read_program(path, options)?;
+				system.restart().and_then(|_| {system.load_image(&image)}).map_err(|error| {error.to_string()})?;
+				if let Some(start) = options.start_address {
+					system.set_start_address(start).map_err(|error| {error.to_string()})?;
+				}
+			}
+		}
+		self.source = path.and_then(|path| {SourceMap::read(&path, options)});
+		return Ok(json!({}));
+	}
+	
+	///Replaces the breakpoints on source lines
+	fn set_breakpoints(&mut self, args: &Value) -> Value {
+		self.breakpoints.retain(|breakpoint| {breakpoint.line.is_none()});
+		let path: String = canonical(args["source"]["path"].as_str().unwrap_or(""));
+		let lines: Vec<usize> = args["breakpoints"].as_array().map_or(Vec::new(), |breakpoints| {
+			breakpoints.iter().filter_map(|breakpoint| {breakpoint["line"].as_u64()}).map(|line| {line as usize}).collect()
+		});
+		let mut replies: Vec<Value> = Vec::new();
+		for line in lines {
+			let resolved: Option<(u16, usize)> = self.source.as_ref().filter(|source| {source.path == path}).and_then(|source| {source.address_of(line)});
+			match resolved {
+				Some((address, line)) => {
+					let id: u64 = self.add_breakpoint(address, Some(line));
+					replies.push(json!({"id": id, "verified": true, "line": line, "instructionReference": reference(address)}));
+				}
+				None => {replies.push(json!({"verified": false, "line": line, "message": "No code on or after this line in the running program"}));}
+			}
+		}
+		return json!({"breakpoints": replies});
+	}
+	
+	///Replaces the breakpoints on instruction addresses
+	fn set_instruction_breakpoints(&mut self, args: &Value) -> Value {
+		self.breakpoints.retain(|breakpoint| {breakpoint.line.is_some()});
+		let mut replies: Vec<Value> = Vec::new();
+		for breakpoint in args["breakpoints"].as_array().map_or(&[][..], |breakpoints| {breakpoints.as_slice()}) {
+			let address: Option<u16> = breakpoint["instructionReference"].as_str().and_then(|text| {parse_number::<u16>(text).ok()})
+				.map(|address| {address.wrapping_add(breakpoint["offset"].as_i64().unwrap_or(0) as u16)});
+			match address {
+				Some(address) => {
+					let id: u64 = self.add_breakpoint(address, None);
+					replies.push(json!({"id": id, "verified": true, "instructionReference": reference(address)}));
+				}
+				None => {replies.push(json!({"verified": false, "message": "The instruction reference should be an address"}));}
+			}
+		}
+		return json!({"breakpoints": replies});
+	}
+	
+	fn add_breakpoint(&mut self, address: u16, line: Option<usize>) -> u64 {
+		let id: u64 = self.next_breakpoint_id;
+		self.next_breakpoint_id += 1;
+		self.breakpoints.push(Breakpoint {id, address, line});
+		return id;
+	}
+	
+	///Number of frames in the call stack below the instruction running now
+	fn callers(&self, cpu: &Cpu) -> usize {
+		//a JSR is added to the call stack when it's issued, so it's only a caller once it has run,
+		//and nothing is issued past an RTS or RTI until it's done, so it has returned once nothing is executing
+		return match cpu.issued() {
+			Some((_, opcode)) if opcode.mnemonic == Mnemonic::JSR => {self.call_stack.len().saturating_sub(1)}
+			_ if self.returning && !cpu.executing() => {self.call_stack.len().saturating_sub(1)}
+			_ => {self.call_stack.len()}
+		};
+	}
+	
+	///The instruction running now, then the callers that haven't returned, newest first
+	fn stack_trace(&self, system: &mut System) -> Result<Value, EmulatorError> {
+		let cpu: &Cpu = &system.clock.cpu;
+		//right after a breakpoint, the instruction that hit it is the one that was just issued
+		let current: u16 = cpu.issued().map_or(cpu.current_address(), |(address, _)| {address});
+		let callers: usize = self.callers(cpu);
+		let addresses: Vec<u16> = std::iter::once(current).chain(self.call_stack[..callers].iter().rev().copied()).collect();
+		let mut frames: Vec<Value> = Vec::new();
+		for (id, address) in addresses.into_iter().enumerate() {
+			let bytes: Vec<u8> = system.read_bytes(address, 3.min(0x10000 - address as usize))?;
+			let text: String = disassembler::disassemble(&bytes, address, system.clock.cpu.model, Some(&system.clock.cpu.syscalls))
+				.first().map_or(String::new(), |instruction| {instruction.text.clone()});
+			let mut frame: Value = json!({
+				"id": id,
+				"name": format!("{:04X}: {}", address, text),
+				"line": 0,
+				"column": 0,
+				"instructionPointerReference": reference(address)
+			});
+			if let Some((source, line)) = self.source.as_ref().and_then(|source| {source.line_of(address).map(|line| {(source, line)})}) {
+				frame["source"] = json!({"name": file_name(&source.path), "path": source.path});
+				frame["line"] = json!(line);
+				frame["column"] = json!(1);
+			}
+			frames.push(frame);
+		}
+		let total: usize = frames.len();
+		return Ok(json!({"stackFrames": frames, "totalFrames": total}));
+	}
+	
+	fn variables(cpu: &Cpu, reference_id: u64) -> Value {
+		let byte = |name: &str, value: u8| {json!({"name": name, "value": format!("0x{:02X} ({})", value, value), "variablesReference": 0})};
+		let variables: Vec<Value> = match reference_id {
+			Self::REGISTERS_REFERENCE => {
+				let pc: u16 = cpu.current_address();
+				let mut s: Value = byte("S", cpu.s);
+				s["memoryReference"] = json!(reference(Cpu::STACK_PAGE | cpu.s as u16));
+				vec![
+					byte("A", cpu.a),
+					byte("X", cpu.x),
+					byte("Y", cpu.y),
+					s,
+					json!({"name": "PC", "value": format!("0x{:04X}", pc), "variablesReference": 0, "memoryReference": reference(pc)}),
+					byte("P", cpu.nv_bdizc)
+				]
+			}
+			Self::FLAGS_REFERENCE => {
+				"NV-BDIZC".chars().enumerate().filter(|(_, flag)| {*flag != '-'}).map(|(i, flag)| {
+					let set: bool = cpu.nv_bdizc & (0x80 >> i) != 0;
+					json!({"name": flag.to_string(), "value": if set {"1"} else {"0"}, "variablesReference": 0})
+				}).collect()
+			}
+			_ => {Vec::new()}
+		};
+		return json!({"variables": variables});
+	}
+	
+	fn read_memory(system: &mut System, args: &Value) -> Result<Value, String> {
+		let address: i64 = memory_reference(args)?;
+		let count: i64 = args["count"].as_i64().unwrap_or(0).max(0);
+		//bytes outside the address space can't be read
+		let start: i64 = address.clamp(0, 0x10000);
+		let end: i64 = (address + count).clamp(0, 0x10000);
+		let bytes: Vec<u8> = system.read_bytes(start as u16, (end - start).max(0) as usize).map_err(|error| {error.to_string()})?;
+		return Ok(json!({"address": reference(start as u16), "data": base64(&bytes), "unreadableBytes": count - bytes.len() as i64}));
+	}
+	
+	fn write_memory(system: &mut System, args: &Value) -> Result<Value, String> {
+		let address: i64 = memory_reference(args)?;
+		let bytes: Vec<u8> = from_base64(args["data"].as_str().unwrap_or("")).ok_or("The data isn't base64")?;
+		if address < 0 || address as usize + bytes.len() > 0x10000 {
+			return Err(String::from("The bytes would go outside the address space"));
+		}
+		system.write_bytes(address as u16, &bytes).map_err(|error| {error.to_string()})?;
+		return Ok(json!({"bytesWritten": bytes.len()}));
+	}
+	
+	/**Disassembles instructionCount instructions, starting instructionOffset instructions from the address. Instructions can't
+	be read backwards, so decoding starts far enough back to cover the offset, and addresses outside memory are marked invalid.
+	The count and offset are capped at the size of the address space.*/
+	fn disassemble(&self, system: &mut System, args: &Value) -> Result<Value, String> {
+		let address: i64 = memory_reference(args)?.clamp(0, 0xFFFF);
+		let offset: i64 = args["instructionOffset"].as_i64().unwrap_or(0).clamp(-Self::MAX_INSTRUCTIONS, Self::MAX_INSTRUCTIONS);
+		let count: i64 = args["instructionCount"].as_i64().unwrap_or(0).clamp(0, Self::MAX_INSTRUCTIONS);
+		let start: i64 = (address + offset.min(0) * 3).max(0);
+		let len: i64 = ((address - start) + (count + offset.max(0)) * 3).min(0x10000 - start);
+		let bytes: Vec<u8> = system.read_bytes(start as u16, len as usize).map_err(|error| {error.to_string()})?;
+		let decoded: Vec<Instruction> = disassembler::disassemble(&bytes, start as u16, system.clock.cpu.model, Some(&system.clock.cpu.syscalls));
+		let position: i64 = decoded.iter().position(|instruction| {instruction.address as i64 >= address}).unwrap_or(decoded.len()) as i64;
+		let instructions: Vec<Value> = (position + offset..position + offset + count).map(|i| {
+			match usize::try_from(i).ok().and_then(|i| {decoded.get(i)}) {
+				Some(instruction) => {
+					let mut value: Value = json!({
+						"address": reference(instruction.address),
+						"instructionBytes": instruction.bytes.iter().map(|byte| {format!("{:02X}", byte)}).collect::<Vec<String>>().join(" "),
+						"instruction": instruction.text
+					});
+					if let Some((source, line)) = self.source.as_ref().and_then(|source| {source.line_of(instruction.address).map(|line| {(source, line)})}) {
+						value["location"] = json!({"name": file_name(&source.path), "path": source.path});
+						value["line"] = json!(line);
+					}
+					value
+				}
+				None => {json!({"address": reference(address as u16), "instruction": "??", "presentationHint": "invalid"})}
+			}
+		}).collect();
+		return Ok(json!({"instructions": instructions}));
+	}
+	
+	///Runs a batch of cycles and returns why the program stopped, or None if it's still running
+	fn run_cycles(&mut self, system: &mut System, connection: &mut Connection, run: Run) -> Result<Option<Stop>, Error> {
+		for _ in 0..Self::BATCH_CYCLES {
+			let cpu: &Cpu = &system.clock.cpu;
+			if cpu.halted() {return Ok(Some(Stop::Halted));}
+			if let Run::Step {target, depth} = run {
+				//a step doesn't stop halfway into an interrupt or BRK, so stepping over one runs its handler,
+				//or after an RTS or RTI finished but before the instruction it returned to is issued
+				let between: bool = cpu.in_interrupt_sequence() || (self.returning && !cpu.executing());
+				if cpu.instruction_counter >= target && self.callers(cpu) <= depth && !between {return Ok(Some(Stop::Step));}
+			}
+			let hits: Vec<WatchHit> = system.cycle()?;
+			for hit in &hits {
+				self.event(connection, "output", json!({"category": "console", "output": format!("{}\n", hit)}))?;
+			}
+			if let Some(hit) = hits.into_iter().find(|hit| {hit.watchpoint.action == WatchAction::Stop}) {
+				return Ok(Some(Stop::Watch(hit)));
+			}
+			if let Some(caller) = system.clock.cpu.entered_handler() {
+				self.returned();
+				self.call_stack.push(caller);
+			}
+			if let Some((address, opcode)) = system.clock.cpu.issued() {
+				self.returned();
+				match opcode.mnemonic {
+					Mnemonic::JSR => {self.call_stack.push(address);}
+					Mnemonic::RTS | Mnemonic::RTI => {self.returning = true;}
+					_ => {}
+				}
+				if let Some(breakpoint) = self.breakpoints.iter().find(|breakpoint| {breakpoint.address == address}) {
+					return Ok(Some(Stop::Breakpoint(breakpoint.id)));
+				}
+			}
+		}
+		return Ok(None);
+	}
+	
+	///Pops the frame of the RTS or RTI that was issued, now that it has returned
+	fn returned(&mut self) {
+		if std::mem::take(&mut self.returning) {
+			self.call_stack.pop();
+		}
+	}
+	
+	///Tells the client why the program stopped. The session ends once the CPU halts on BRK or STP, or runs again after a fault.
+	fn stopped(&mut self, system: &System, connection: &mut Connection, stop: Stop) -> Result<(), Error> {
+		let thread: u64 = Self::THREAD_ID;
+		match stop {
+			Stop::Step => {self.event(connection, "stopped", json!({"reason": "step", "threadId": thread, "allThreadsStopped": true}))?;}
+			Stop::Breakpoint(id) => {
+				self.event(connection, "stopped", json!({"reason": "breakpoint", "threadId": thread, "allThreadsStopped": true, "hitBreakpointIds": [id]}))?;
+			}
+			Stop::Watch(hit) => {
+				self.event(connection, "stopped", json!({"reason": "data breakpoint", "threadId": thread, "allThreadsStopped": true, "description": hit.to_string()}))?;
+			}
+			Stop::Halted => {
+				match system.clock.cpu.fault {
+					Some(fault) if !self.fault_reported => {
+						self.fault_reported = true;
+						self.event(connection, "stopped", json!({"reason": "exception", "threadId": thread, "allThreadsStopped": true, "description": format!("Halted on {}", fault), "text": fault.to_string()}))?;
+					}
+					fault => {
+						self.event(connection, "exited", json!({"exitCode": if fault.is_some() {1} else {0}}))?;
+						self.event(connection, "terminated", json!({}))?;
+					}
+				}
+			}
+		}
+		return Ok(());
+	}
+	
+	///Sends what the program printed since the last call as one output event
+	fn forward_output(&mut self, connection: &mut Connection) -> Result<(), Error> {
+		let text: String = self.output.try_iter().collect();
+		if !text.is_empty() {
+			self.event(connection, "output", json!({"category": "stdout", "output": text}))?;
+		}
+		return Ok(());
+	}
+	
+	fn respond(&mut self, connection: &mut Connection, request: &Value, body: Result<Value, String>) -> io::Result<()> {
+		let mut response: Value = json!({
+			"seq": self.seq,
+			"type": "response",
+			"request_seq": request["seq"],
+			"command": request["command"],
+			"success": body.is_ok()
+		});
+		match body {
+			Ok(body) => {response["body"] = body;}
+			Err(message) => {response["message"] = json!(message);}
+		}
+		self.seq += 1;
+		return connection.send(&response);
+	}
+	
+	fn event(&mut self, connection: &mut Connection, event: &str, body: Value) -> io::Result<()> {
+		let message: Value = json!({"seq": self.seq, "type": "event", "event": event, "body": body});
+		self.seq += 1;
+		return connection.send(&message);
+	}
+}
+
+/**A client connection that sends and receives messages with a Content-Length header.*/
+struct Connection {
+	stream: TcpStream,
+	///Bytes received that aren't part of a whole message yet
+	buffer: Vec<u8>
+}
+
+impl Connection {
+	fn new(stream: TcpStream) -> Self {
+		return Self {stream, buffer: Vec::new()};
+	}
+	
+	///Returns the next message. If blocking, waits for one, otherwise returns None if none is waiting. Returns an UnexpectedEof error once the client disconnects.
+	fn receive(&mut self, blocking: bool) -> io::Result<Option<Value>> {
+		loop {
+			if let Some(message) = self.take_message()? {return Ok(Some(message));}
+			let mut chunk: [u8; 4096] = [0; 4096];
+			self.stream.set_nonblocking(!blocking)?;
+			let received: io::Result<usize> = self.stream.read(&mut chunk);
+			self.stream.set_nonblocking(false)?;
+			match received {
+				Ok(0) => {return Err(io::Error::from(ErrorKind::UnexpectedEof));}
+				Ok(len) => {self.buffer.extend_from_slice(&chunk[..len]);}
+				Err(error) if error.kind() == ErrorKind::WouldBlock => {return Ok(None);}
+				Err(error) => {return Err(error);}
+			}
+		}
+	}
+	
+	///Takes a whole message out of the buffer if one has arrived
+	fn take_message(&mut self) -> io::Result<Option<Value>> {
+		let Some(header_end) = self.buffer.windows(4).position(|window| {window == b"\r\n\r\n"}) else {return Ok(None);};
+		let header: String = String::from_utf8_lossy(&self.buffer[..header_end]).into_owned();
+		let len: usize = header.lines().find_map(|line| {
+			let (name, value) = line.split_once(':')?;
+			if name.trim().eq_ignore_ascii_case("Content-Length") {value.trim().parse().ok()} else {None}
+		}).ok_or(io::Error::new(ErrorKind::InvalidData, "A message has no Content-Length"))?;
+		let start: usize = header_end + 4;
+		if self.buffer.len() < start + len {return Ok(None);}
+		let message: Vec<u8> = self.buffer.drain(..start + len).skip(start).collect();
+		return serde_json::from_slice(&message).map(Some).map_err(|error| {io::Error::new(ErrorKind::InvalidData, error)});
+	}
+	
+	fn send(&mut self, message: &Value) -> io::Result<()> {
+		let text: String = message.to_string();
+		return self.stream.write_all(format!("Content-Length: {}\r\n\r\n{}", text.len(), text).as_bytes());
+	}
+}
+
+///A broken connection ends the session, and a memory bus failure ends the program
+enum Error {
+	Io(io::Error),
+	Emulator(EmulatorError)
+}
+
+impl From<io::Error> for Error {
+	fn from(error: io::Error) -> Self {Error::Io(error)}
+}
+
+impl From<EmulatorError> for Error {
+	fn from(error: EmulatorError) -> Self {Error::Emulator(error)}
+}
+
+///Memory and instruction references are addresses written like 0x0013
+fn reference(address: u16) -> String {
+	format!("0x{:04X}", address)
+}
+
+///Reads memoryReference plus offset, which may be outside the address space
+fn memory_reference(args: &Value) -> Result<i64, String> {
+	let text: &str = args["memoryReference"].as_str().ok_or("The request has no memory reference")?;
+	let address: u16 = parse_number(text)?;
+	return Ok(address as i64 + args["offset"].as_i64().unwrap_or(0));
+}
+
+///Source paths are compared after resolving them, since the client and the command line may write them differently
+fn canonical(path: &str) -> String {
+	fs::canonicalize(path).map_or(String::from(path), |path| {path.to_string_lossy().into_owned()})
+}
+
+fn file_name(path: &str) -> String {
+	Path::new(path).file_name().map_or(String::from(path), |name| {name.to_string_lossy().into_owned()})
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+	let mut text: String = String::with_capacity(bytes.len().div_ceil(3) * 4);
+	for chunk in bytes.chunks(3) {
+		let group: u32 = chunk.iter().enumerate().fold(0, |group, (i, byte)| {group | (*byte as u32) << (16 - 8 * i)});
+		for i in 0..4 {
+			text.push(if i <= chunk.len() {BASE64[(group >> (18 - 6 * i) & 0x3F) as usize] as char} else {'='});
+		}
+	}
+	return text;
+}
+
+///Returns None if the text isn't base64
+fn from_base64(text: &str) -> Option<Vec<u8>> {
+	let mut bytes: Vec<u8> = Vec::with_capacity(text.len() / 4 * 3);
+	let mut group: u32 = 0;
+	let mut bits: u32 = 0;
+	for c in text.trim_end_matches('=').bytes() {
+		group = group << 6 | BASE64.iter().position(|&digit| {digit == c})? as u32;
+		bits += 6;
+		if bits >= 8 {
+			bits -= 8;
+			bytes.push((group >> bits) as u8);
+		}
+	}
+	return Some(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		crate::hardware::cpu::CpuModel,
+		std::{collections::VecDeque, thread, time::Duration}
+	};
+	
+	///The client's side of the connection, which keeps the events that arrive while it waits for a response
+	struct Client {
+		connection: Connection,
+		seq: u64,
+		events: VecDeque<Value>
+	}
+	
+	impl Client {
+		fn connect(port: u16) -> Self {
+			let stream: TcpStream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+			stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+			return Self {connection: Connection::new(stream), seq: 1, events: VecDeque::new()};
+		}
+		///Sends a request and returns the body of its response, which must have succeeded
+		fn request(&mut self, command: &str, arguments: Value) -> Value {
+			let seq: u64 = self.seq;
+			self.seq += 1;
+			self.connection.send(&json!({"seq": seq, "type": "request", "command": command, "arguments": arguments})).unwrap();
+			loop {
+				let message: Value = self.connection.receive(true).unwrap().unwrap();
+				if message["type"] == "event" {
+					self.events.push_back(message);
+					continue;
+				}
+				assert_eq!((message["request_seq"].as_u64(), message["command"].as_str()), (Some(seq), Some(command)));
+				assert_eq!(message["success"], true, "{}", message);
+				return message["body"].clone();
+			}
+		}
+		///Waits for the event, skipping output events
+		fn event(&mut self, event: &str) -> Value {
+			loop {
+				let message: Value = match self.events.pop_front() {
+					Some(message) => {message}
+					None => {self.connection.receive(true).unwrap().unwrap()}
+				};
+				if message["event"] == "output" {continue;}
+				assert_eq!(message["event"], event, "{}", message);
+				return message["body"].clone();
+			}
+		}
+		///Source lines of the frames in the call stack, the newest first
+		fn lines(&mut self) -> Vec<u64> {
+			let trace: Value = self.request("stackTrace", json!({"threadId": DapServer::THREAD_ID}));
+			return trace["stackFrames"].as_array().unwrap().iter().map(|frame| {frame["line"].as_u64().unwrap()}).collect();
+		}
+	}
+	
+	const SOURCE: &str = "\
+;the subroutine is called once, then the program halts
+start:  LDX #$03
+        JSR sub
+        NOP
+        BRK
+sub:    INX
+        RTS
+";
+
+	#[tokio::test]
+	async fn scripted_client() {
+		let path: String = std::env::temp_dir().join(format!("dap-test-{}.asm6502", std::process::id())).to_string_lossy().into_owned();
+		fs::write(&path, SOURCE).unwrap();
+		let options: Options = Options::parse([path.clone()]).unwrap();
+		let mut system: System = System::new(CpuModel::Nmos6502 {undocumented: false});
+		let mut server: DapServer = DapServer::bind(0).unwrap();
+		let port: u16 = server.port().unwrap();
+		let program: String = path.clone();
+		let client = thread::spawn(move || {
+			let mut client: Client = Client::connect(port);
+			assert_eq!(client.request("initialize", json!({"adapterID": "6502"}))["supportsInstructionBreakpoints"], true);
+			client.event("initialized");
+			client.request("launch", json!({"program": program}));
+			//a breakpoint past the last line can't be placed
+			let breakpoints: Value = client.request("setBreakpoints", json!({"source": {"path": program}, "breakpoints": [{"line": 6}, {"line": 20}]}));
+			assert_eq!(breakpoints["breakpoints"][0], json!({"id": 1, "verified": true, "line": 6, "instructionReference": "0x0007"}));
+			assert_eq!(breakpoints["breakpoints"][1]["verified"], false);
+			client.request("configurationDone", json!({}));
+			let stopped: Value = client.event("stopped");
+			assert_eq!((&stopped["reason"], &stopped["hitBreakpointIds"]), (&json!("breakpoint"), &json!([1])));
+			//INX is called from the JSR on line 3
+			assert_eq!(client.lines(), [6, 3]);
+			let registers: Value = client.request("variables", json!({"variablesReference": DapServer::REGISTERS_REFERENCE}));
+			assert_eq!(registers["variables"][1], json!({"name": "X", "value": "0x03 (3)", "variablesReference": 0}));
+			
+			client.request("next", json!({"threadId": DapServer::THREAD_ID}));
+			assert_eq!(client.event("stopped")["reason"], "step");
+			assert_eq!(client.lines(), [7, 3]);
+			//stepping over the RTS stops at the instruction after the JSR
+			client.request("next", json!({"threadId": DapServer::THREAD_ID}));
+			assert_eq!(client.event("stopped")["reason"], "step");
+			assert_eq!(client.lines(), [4]);
+			
+			let memory: Value = client.request("readMemory", json!({"memoryReference": "0x0000", "count": 4}));
+			assert_eq!((&memory["address"], &memory["data"], &memory["unreadableBytes"]), (&json!("0x0000"), &json!("ogMgBw=="), &json!(0)));
+			//the offset is added to the reference, so this is the high byte of the JSR and the NOP
+			let memory: Value = client.request("readMemory", json!({"memoryReference": "0x0005", "offset": -1, "count": 2}));
+			assert_eq!(from_base64(memory["data"].as_str().unwrap()), Some(vec![0x00, 0xEA]));
+			let memory: Value = client.request("readMemory", json!({"memoryReference": "0xFFFE", "count": 4}));
+			assert_eq!(memory["unreadableBytes"], 2);
+			
+			let instructions: Value = client.request("disassemble", json!({"memoryReference": "0x0002", "instructionCount": 2}));
+			assert_eq!(instructions["instructions"][0]["instruction"], "JSR $0007");
+			assert_eq!(instructions["instructions"][1]["line"], 4);
+			//a huge count is answered with one instruction for each address at most
+			let instructions: Value = client.request("disassemble", json!({"memoryReference": "0x0000", "instructionOffset": i64::MIN, "instructionCount": i64::MAX}));
+			assert_eq!(instructions["instructions"].as_array().unwrap().len(), DapServer::MAX_INSTRUCTIONS as usize);
+			
+			client.request("terminate", json!({}));
+			client.event("terminated");
+		});
+		assert_eq!(server.run(&mut system, &options), Ok(()));
+		client.join().unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(system.clock.cpu.x, 0x04);
+	}
+	
+	#[test]
+	fn base64_round_trip() {
+		for (bytes, text) in [(&b""[..], ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"), (b"foobar", "Zm9vYmFy"), (&[0xFB, 0xFF], "+/8=")] {
+			assert_eq!(base64(bytes), text);
+			assert_eq!(from_base64(text).as_deref(), Some(bytes));
+		}
+		let every_byte: Vec<u8> = (0..=u8::MAX).collect();
+		assert_eq!(from_base64(&base64(&every_byte)), Some(every_byte));
+		//padding can be left out, but other characters aren't base64
+		assert_eq!(from_base64("Zm8"), Some(b"fo".to_vec()));
+		assert_eq!(from_base64("Zm9v!"), None);
+		assert_eq!(from_base64("Zm 9v"), None);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,408 @@
+use {
+	crate::{
+		hardware::{
+			cpu::{Cpu, Mnemonic, Opcode},
+			disassembler::{self, Instruction},
+			fault::EmulatorError,
+			history::MemoryWrite,
+			watchpoint::{Watchpoint, WatchAction, WatchHit}
+		},
+		lib::parse_number,
+		system::System
+	},
+	std::{
+		fmt::{self, Display, Formatter},
+		io::{self, BufRead, Write}
+	}
+};
+
+/**Where the debugger stops a running program. Instructions are caught when they're sent to an execution unit,
+so the instruction hasn't run yet but older ones in the other execution unit may still be finishing.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Breakpoint {
+	///The instruction at the address is issued
+	Pc(u16),
+	///An instruction with the opcode is issued
+	Opcode(Opcode),
+	///An instruction with the mnemonic is issued, in any addressing mode
+	Mnemonic(Mnemonic),
+	///The CPU clock count reaches the value
+	Cycle(u128)
+}
+
+impl Breakpoint {
+	///Whether the breakpoint is hit by the cycle that just ran
+	fn hit(&self, cpu: &Cpu) -> bool {
+		match (self, cpu.issued()) {
+			(Breakpoint::Pc(pc), Some((address, _))) => {*pc == address}
+			(Breakpoint::Opcode(wanted), Some((_, opcode))) => {*wanted == opcode}
+			(Breakpoint::Mnemonic(mnemonic), Some((_, opcode))) => {*mnemonic == opcode.mnemonic}
+			(Breakpoint::Cycle(cycle), _) => {*cycle == cpu.cpu_clock_counter}
+			_ => {false}
+		}
+	}
+}
+
+impl Display for Breakpoint {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Breakpoint::Pc(pc) => {write!(f, "PC 0x{:04X}", pc)}
+			Breakpoint::Opcode(opcode) => {write!(f, "opcode {:?} {:?}", opcode.mnemonic, opcode.mode)}
+			Breakpoint::Mnemonic(mnemonic) => {write!(f, "opcode {:?}", mnemonic)}
+			Breakpoint::Cycle(cycle) => {write!(f, "cycle {}", cycle)}
+		}
+	}
+}
+
+/**An interactive prompt that runs the System a step at a time. Commands are read from stdin, and the keyboard stops
+listening to the console while the prompt is waiting so they don't become key presses.*/
+#[derive(Default)]
+pub struct Debugger {
+	breakpoints: Vec<Breakpoint>,
+	///Repeated when an empty line is entered
+	last_command: String
+}
+
+impl Debugger {
+	pub const HELP: &'static str = "\
+Commands:
+  step [N]             s     Run until N instructions finish (default: 1)
+  cycle [N]            cy    Run N clock cycles (default: 1)
+  continue             c     Run until a breakpoint or the CPU halts
+  rstep [N]            rs    Go back until N instructions are undone (default: 1)
+  rcycle [N]           rcy   Go back N clock cycles (default: 1)
+  rcontinue            rc    Go back to the last breakpoint hit, or as far as the history goes
+  rwrite <ADDRESS>           Go back to just before the last time the CPU wrote ADDRESS
+  history                    Show how far back the history goes
+  break pc <ADDRESS>   b     Stop when the instruction at ADDRESS is issued
+  break op <OPCODE>          Stop when an opcode byte, or any opcode of a mnemonic like LDA, is issued
+  break cycle <N>            Stop when the CPU clock count reaches N
+  break                      List the breakpoints
+  delete [N]                 Delete breakpoint N, or all of them
+  watch <WATCH>        wa    Stop or log when memory is read, written, or changed:
+                             <read|write|change> <ADDRESS>[-<END>] [if value <OP> <VALUE>] [stop|log]
+  watch                      List the watchpoints
+  unwatch [N]                Delete watchpoint N, or all of them
+  regs                 r     Show A, X, Y, S, PC, and the status flags
+  pipeline             p     Show the instructions in each pipeline stage and execution unit
+  mem <ADDRESS> [LEN]  m     Show LEN bytes of memory through the cache (default: 64)
+  write <ADDRESS> <BYTE>...  Write bytes to memory through the cache
+  dis [ADDRESS] [N]    u     Disassemble N instructions at ADDRESS (default: 16 around the pipeline)
+  help                 h     Show this message
+  quit                 q     Stop debugging
+An empty line repeats the last command. Numbers are decimal, or hex with a 0x or $ prefix.";
+	///Instructions shown before the address when disassembling around the pipeline
+	const CONTEXT: usize = 5;
+	///KiB of history kept by default
+	pub const HISTORY_KIB: usize = 65536;
+	
+	/**Reads commands until quit or the end of the input. Returns an error if the memory bus fails.*/
+	pub fn run(&mut self, system: &mut System) -> Result<(), EmulatorError> {
+		println!("{}", Self::HELP);
+		let stdin = io::stdin();
+		loop {
+			system.clock.cpu.set_keyboard_listening(false);
+			print!("(debug) ");
+			io::stdout().flush().expect("Could not flush output buffer");
+			let mut line: String = String::new();
+			match stdin.lock().read_line(&mut line) {
+				Ok(0) | Err(_) => {return Ok(());}
+				Ok(_) => {}
+			}
+			let line: String = match line.trim() {
+				"" => {self.last_command.clone()}
+				line => {String::from(line)}
+			};
+			self.last_command = line.clone();
+			let words: Vec<&str> = line.split_whitespace().collect();
+			if words.is_empty() {continue;}
+			match self.command(system, &words) {
+				Ok(true) => {}
+				Ok(false) => {return Ok(());}
+				Err(Error::Usage(message)) => {println!("{}", message);}
+				Err(Error::Emulator(error)) => {return Err(error);}
+			}
+		}
+	}
+	
+	///Runs a command. Returns Ok(false) to quit.
+	fn command(&mut self, system: &mut System, words: &[&str]) -> Result<bool, Error> {
+		let arg = |i: usize| {words.get(i).copied()};
+		match words[0] {
+			"s" | "step" => {
+				let count: u128 = arg(1).map_or(Ok(1), parse_number)?;
+				let target: u128 = system.clock.cpu.instruction_counter + count;
+				self.run_until(system, |cpu| {cpu.instruction_counter >= target}, false)?;
+			}
+			"cy" | "cycle" => {
+				let count: u128 = arg(1).map_or(Ok(1), parse_number)?;
+				let target: u128 = system.clock.cpu.cpu_clock_counter + count;
+				self.run_until(system, |cpu| {cpu.cpu_clock_counter >= target}, false)?;
+			}
+			"c" | "continue" => {self.run_until(system, |_| {false}, true)?;}
+			"rs" | "rstep" => {
+				let count: u128 = arg(1).map_or(Ok(1), parse_number)?;
+				let target: u128 = system.clock.cpu.instruction_counter.saturating_sub(count);
+				//stops at the first cycle after the target instruction count was reached, like step does going forward
+				self.run_back(system, |system, _| {
+					let instructions: u128 = system.clock.cpu.instruction_counter;
+					instructions <= target && system.clock.history.previous().is_none_or(|(_, previous)| {previous < instructions})
+				}, false);
+			}
+			"rcy" | "rcycle" => {
+				let count: u128 = arg(1).map_or(Ok(1), parse_number)?;
+				let target: u128 = system.clock.cpu.cpu_clock_counter.saturating_sub(count);
+				self.run_back(system, |system, _| {system.clock.cpu.cpu_clock_counter <= target}, false);
+			}
+			"rc" | "rcontinue" => {self.run_back(system, |_, _| {false}, true);}
+			"rwrite" => {
+				let address: u16 = parse_number(arg(1).ok_or(String::from("rwrite needs an address"))?)?;
+				let write: Option<MemoryWrite> = self.run_back(system, |_, writes| {writes.iter().any(|write| {write.address == address})}, false)
+					.and_then(|writes| {writes.into_iter().rev().find(|write| {write.address == address})});
+				if let Some(write) = write {
+					println!("0x{:04X} is written with 0x{:02X} in this cycle by the instruction at 0x{:04X}", write.address, write.value, write.pc);
+				}
+			}
+			"history" => {
+				let history = &system.clock.history;
+				match history.oldest() {
+					_ if history.budget() == 0 => {println!("The history is off. Start the debugger with --history <KIB> to keep one");}
+					Some(oldest) => {
+						println!("{} cycles back to cycle {}, using {} of {} KiB", history.len(), oldest, history.used().div_ceil(1024), history.budget() / 1024);
+					}
+					None => {println!("Nothing has run yet, using 0 of {} KiB", history.budget() / 1024);}
+				}
+			}
+			"b" | "break" => {
+				let Some(kind) = arg(1) else {
+					if self.breakpoints.is_empty() {
+						println!("No breakpoints");
+					}
+					self.breakpoints.iter().enumerate().for_each(|(i, breakpoint)| {println!("{}: {}", i, breakpoint);});
+					return Ok(true);
+				};
+				let value: &str = arg(2).ok_or(format!("break {} needs a value", kind))?;
+				let breakpoint: Breakpoint = match kind {
+					"pc" => {Breakpoint::Pc(parse_number(value)?)}
+					"cycle" => {Breakpoint::Cycle(parse_number(value)?)}
+					"op" | "opcode" => {
+						match parse_number::<u8>(value) {
+							Ok(byte) => {Breakpoint::Opcode(Opcode::from(byte, system.clock.cpu.model).ok_or(format!("0x{:02X} is not an opcode on this CPU model", byte))?)}
+							Err(_) => {Breakpoint::Mnemonic(Mnemonic::from_name(value, system.clock.cpu.model).ok_or(format!("{} is not an instruction on this CPU model", value))?)}
+						}
+					}
+					kind => {return Err(Error::Usage(format!("Unknown breakpoint: {}. Use pc, op, or cycle", kind)));}
+				};
+				println!("Breakpoint {}: {}", self.breakpoints.len(), breakpoint);
+				self.breakpoints.push(breakpoint);
+			}
+			"delete" => {
+				match arg(1) {
+					Some(index) => {
+						let index: usize = parse_number(index)?;
+						if index >= self.breakpoints.len() {
+							return Err(Error::Usage(format!("There is no breakpoint {}", index)));
+						}
+						println!("Deleted breakpoint {}: {}", index, self.breakpoints.remove(index));
+					}
+					None => {
+						self.breakpoints.clear();
+						println!("Deleted all breakpoints");
+					}
+				}
+			}
+			"wa" | "watch" => {
+				if words.len() == 1 {
+					let watchpoints: &[Watchpoint] = system.clock.cpu.mmu.cache.watchpoints.list();
+					if watchpoints.is_empty() {
+						println!("No watchpoints");
+					}
+					watchpoints.iter().enumerate().for_each(|(i, watchpoint)| {println!("{}: {}", i, watchpoint);});
+					return Ok(true);
+				}
+				let watchpoint: Watchpoint = Watchpoint::parse(&words[1..].join(" "))?;
+				println!("Watchpoint {}: {}", system.add_watchpoint(watchpoint)?, watchpoint);
+			}
+			"unwatch" => {
+				let watchpoints = &mut system.clock.cpu.mmu.cache.watchpoints;
+				match arg(1) {
+					Some(index) => {
+						let index: usize = parse_number(index)?;
+						let watchpoint: Watchpoint = watchpoints.remove(index).ok_or(format!("There is no watchpoint {}", index))?;
+						println!("Deleted watchpoint {}: {}", index, watchpoint);
+					}
+					None => {
+						watchpoints.clear();
+						println!("Deleted all watchpoints");
+					}
+				}
+			}
+			"r" | "regs" => {Self::print_registers(&system.clock.cpu);}
+			"p" | "pipeline" => {
+				for (stage, instruction) in system.clock.cpu.pipeline() {
+					match instruction {
+						Some((address, text)) => {println!("{:<17} 0x{:04X}: {}", stage, address, text);}
+						None => {println!("{:<17} empty", stage);}
+					}
+				}
+			}
+			"m" | "mem" => {
+				let start: u16 = parse_number(arg(1).ok_or(String::from("mem needs an address"))?)?;
+				let len: usize = arg(2).map_or(Ok(64), parse_number)?;
+				let bytes: Vec<u8> = system.read_bytes(start, len.min(0x10000 - start as usize))?;
+				for (i, row) in bytes.chunks(16).enumerate() {
+					let hex: Vec<String> = row.iter().map(|byte| {format!("{:02X}", byte)}).collect();
+					let text: String = row.iter().map(|&byte| {if byte.is_ascii_graphic() || byte == b' ' {byte as char} else {'.'}}).collect();
+					println!("{:04X}  {:<47}  {}", start as usize + i * 16, hex.join(" "), text);
+				}
+			}
+			"write" => {
+				let address: u16 = parse_number(arg(1).ok_or(String::from("write needs an address"))?)?;
+				let bytes: Vec<u8> = words[2..].iter().map(|word| {parse_number(word)}).collect::<Result<Vec<u8>, String>>()?;
+				if bytes.is_empty() {
+					return Err(Error::Usage(String::from("write needs bytes to write")));
+				}
+				if address as usize + bytes.len() > 0x10000 {
+					return Err(Error::Usage(String::from("The bytes would go past 0xFFFF")));
+				}
+				system.write_bytes(address, &bytes)?;
+				println!("Wrote {} bytes at 0x{:04X}", bytes.len(), address);
+			}
+			"u" | "dis" => {
+				let count: usize = arg(2).map_or(Ok(16), parse_number)?;
+				let instructions: Vec<Instruction> = match arg(1) {
+					Some(address) => {Self::disassemble(system, parse_number(address)?, count)?}
+					None => {
+						let current: u16 = system.clock.cpu.current_address();
+						Self::disassemble_around(system, current, count)?
+					}
+				};
+				let in_pipeline: Vec<u16> = system.clock.cpu.pipeline().into_iter().filter_map(|(_, instruction)| {instruction.map(|(address, _)| {address})}).collect();
+				for instruction in instructions {
+					let marker: char = if in_pipeline.contains(&instruction.address) {'>'} else {' '};
+					let breakpoint: char = if self.breakpoints.contains(&Breakpoint::Pc(instruction.address)) {'*'} else {' '};
+					println!("{}{} {}", marker, breakpoint, instruction);
+				}
+			}
+			"h" | "help" => {println!("{}", Self::HELP);}
+			"q" | "quit" => {return Ok(false);}
+			command => {return Err(Error::Usage(format!("Unknown command: {}. Type help to see the commands", command)));}
+		}
+		return Ok(true);
+	}
+	
+	/**Runs clock cycles until done returns true, the CPU halts, a watchpoint that stops is hit, or a breakpoint is hit
+	if breakpoints are checked. Watchpoint hits are printed as they happen. The keyboard listens to the console while the program runs.*/
+	fn run_until(&self, system: &mut System, done: impl Fn(&Cpu) -> bool, check_breakpoints: bool) -> Result<(), EmulatorError> {
+		if system.clock.cpu.halted() {
+			println!("The CPU has halted");
+			return Ok(());
+		}
+		system.clock.cpu.set_keyboard_listening(true);
+		let mut stopped_by: Option<Breakpoint> = None;
+		while !system.clock.cpu.halted() && !done(&system.clock.cpu) {
+			let hits: Vec<WatchHit> = system.cycle()?;
+			hits.iter().for_each(|hit| {println!("\n{}", hit);});
+			if hits.iter().any(|hit| {hit.watchpoint.action == WatchAction::Stop}) {break;}
+			if check_breakpoints {
+				stopped_by = self.breakpoints.iter().find(|breakpoint| {breakpoint.hit(&system.clock.cpu)}).copied();
+				if stopped_by.is_some() {break;}
+			}
+		}
+		system.clock.cpu.set_keyboard_listening(false);
+		println!();
+		if let Some(breakpoint) = stopped_by {
+			println!("Stopped at {}", breakpoint);
+			//the instruction that hit the breakpoint is the youngest one in the execution units
+			let issued = system.clock.cpu.issued().and_then(|(address, _)| {
+				system.clock.cpu.pipeline().into_iter().skip(2).find_map(|(_, instruction)| {instruction.filter(|(stage_address, _)| {*stage_address == address})})
+			});
+			if let Some((address, text)) = issued {
+				println!("Issued 0x{:04X}: {}", address, text);
+			}
+		}
+		match system.clock.cpu.fault {
+			Some(fault) => {println!("The CPU halted on {}", fault);}
+			None if system.clock.cpu.halted() => {println!("The CPU halted");}
+			None => {}
+		}
+		Self::print_registers(&system.clock.cpu);
+		return Ok(());
+	}
+	
+	/**Goes back a cycle at a time until done returns true, the history runs out, or a breakpoint is hit if breakpoints are checked.
+	done is given the writes the CPU made in the cycle that was just undone. Returns those writes if it stopped because of done.
+	A breakpoint is hit when the state is the one the forward run would have stopped in.*/
+	fn run_back(&self, system: &mut System, done: impl Fn(&System, &[MemoryWrite]) -> bool, check_breakpoints: bool) -> Option<Vec<MemoryWrite>> {
+		if system.clock.history.is_empty() {
+			println!("There's nothing to go back to");
+			return None;
+		}
+		let start: u128 = system.clock.cpu.cpu_clock_counter;
+		let mut stopped_by: Option<Breakpoint> = None;
+		let mut result: Option<Vec<MemoryWrite>> = None;
+		while let Some(writes) = system.clock.step_back() {
+			if done(system, &writes) {
+				result = Some(writes);
+				break;
+			}
+			if check_breakpoints {
+				stopped_by = self.breakpoints.iter().find(|breakpoint| {breakpoint.hit(&system.clock.cpu)}).copied();
+				if stopped_by.is_some() {break;}
+			}
+		}
+		println!("Went back {} cycles", start - system.clock.cpu.cpu_clock_counter);
+		match stopped_by {
+			Some(breakpoint) => {println!("Stopped at {}", breakpoint);}
+			None if result.is_none() && system.clock.history.is_empty() => {println!("Reached the start of the history");}
+			None => {}
+		}
+		Self::print_registers(&system.clock.cpu);
+		return result;
+	}
+	
+	fn print_registers(cpu: &Cpu) {
+		let flags: String = "NV-BDIZC".chars().enumerate().map(|(i, flag)| {
+			if cpu.nv_bdizc & (0x80 >> i) != 0 {flag} else {flag.to_ascii_lowercase()}
+		}).collect();
+		println!("PC: 0x{:04X}  A: 0x{:02X}  X: 0x{:02X}  Y: 0x{:02X}  S: 0x{:02X}  Status: {} (0b{:08b})  Cycle: {}  Instructions: {}",
+			cpu.pc, cpu.a, cpu.x, cpu.y, cpu.s, flags, cpu.nv_bdizc, cpu.cpu_clock_counter, cpu.instruction_counter);
+	}
+	
+	///Disassembles count instructions starting at the address
+	fn disassemble(system: &mut System, address: u16, count: usize) -> Result<Vec<Instruction>, EmulatorError> {
+		let len: usize = (count * 3).min(0x10000 - address as usize);
+		let bytes: Vec<u8> = system.read_bytes(address, len)?;
+		let mut instructions: Vec<Instruction> = disassembler::disassemble(&bytes, address, system.clock.cpu.model, Some(&system.clock.cpu.syscalls));
+		instructions.truncate(count);
+		return Ok(instructions);
+	}
+	
+	/**Disassembles count instructions with a few before the address. Instructions can't be read backwards,
+	so decoding starts from the furthest byte back that still lines up with the address.*/
+	fn disassemble_around(system: &mut System, address: u16, count: usize) -> Result<Vec<Instruction>, EmulatorError> {
+		for back in (0..=Self::CONTEXT * 3).rev() {
+			let Some(start) = address.checked_sub(back as u16) else {continue;};
+			let instructions: Vec<Instruction> = Self::disassemble(system, start, count + back)?;
+			if let Some(position) = instructions.iter().position(|instruction| {instruction.address == address}) {
+				let skip: usize = position.saturating_sub(Self::CONTEXT);
+				return Ok(instructions.into_iter().skip(skip).take(count).collect());
+			}
+		}
+		return Self::disassemble(system, address, count);
+	}
+}
+
+///Bad input is printed and the prompt goes on, but a memory bus failure ends the debugger
+enum Error {
+	Usage(String),
+	Emulator(EmulatorError)
+}
+
+impl From<String> for Error {
+	fn from(message: String) -> Self {Error::Usage(message)}
+}
+
+impl From<EmulatorError> for Error {
+	fn from(error: EmulatorError) -> Self {Error::Emulator(error)}
+}
\ No newline at end of file
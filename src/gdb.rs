@@ -0,0 +1,577 @@
+use {
+	crate::{
+		hardware::{
+			cpu::Cpu,
+			fault::{EmulatorError, FaultKind},
+			hardware::Hardware,
+			watchpoint::{Access, Watchpoint, WatchAction, WatchHit}
+		},
+		system::System
+	},
+	std::{
+		io::{self, ErrorKind, Read, Write},
+		net::{TcpListener, TcpStream}
+	}
+};
+
+/**Why the program stopped, sent to the client as a stop reply.*/
+enum Stop {
+	///A single step finished
+	Step,
+	///A breakpoint set with Z0 or Z1 was issued
+	Breakpoint {hardware: bool},
+	///A watchpoint that stops was hit
+	Watch(WatchHit),
+	///The client sent Ctrl-C
+	Interrupted,
+	///The CPU halted on BRK, STP, or a fault
+	Halted
+}
+
+/**Lets GDB, or anything else that speaks the GDB remote serial protocol, debug the program over a local TCP port.
+The server runs the clock itself while the program runs, so no console prompt or keyboard input is needed.
+Registers are sent in the order A, X, Y, S, PC, P, with PC as two bytes, and are described to the client in target.xml.*/
+pub struct GdbServer {
+	listener: TcpListener,
+	///Addresses of the breakpoints, and whether they were set as hardware breakpoints.
+	///Both kinds stop when the instruction is sent to an execution unit, like the debugger's breakpoints.
+	breakpoints: Vec<(u16, bool)>,
+	///Watchpoints set with Z2, Z3, or Z4, and the packet type that set them. Z4 sets a read and a write watchpoint.
+	watchpoints: Vec<(u8, Watchpoint)>,
+	///The last stop reply, sent again for ?
+	last_stop: String
+}
+
+impl GdbServer {
+	///Register description sent for qXfer:features:read
+	const TARGET_XML: &'static str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+	<feature name="org.gnu.gdb.m6502.core">
+		<reg name="a" bitsize="8" type="uint8" regnum="0"/>
+		<reg name="x" bitsize="8" type="uint8"/>
+		<reg name="y" bitsize="8" type="uint8"/>
+		<reg name="s" bitsize="8" type="uint8"/>
+		<reg name="pc" bitsize="16" type="code_ptr"/>
+		<reg name="p" bitsize="8" type="uint8"/>
+	</feature>
+</target>"#;
+	///Clock cycles run between checks for Ctrl-C from the client
+	const INTERRUPT_CHECK_CYCLES: u128 = 1000;
+	
+	/**Listens on 127.0.0.1 at the port. Use port 0 to let the OS pick one.*/
+	pub fn bind(port: u16) -> io::Result<Self> {
+		return Ok(Self {
+			listener: TcpListener::bind(("127.0.0.1", port))?,
+			breakpoints: Vec::new(),
+			watchpoints: Vec::new(),
+			last_stop: String::from("S05")
+		});
+	}
+	
+	pub fn port(&self) -> io::Result<u16> {
+		return Ok(self.listener.local_addr()?.port());
+	}
+	
+	/**Waits for a client and serves it until it detaches, kills the program, or disconnects. The program is stopped
+	before the first instruction when the client connects. Returns an error if the memory bus fails. A broken connection
+	is only warned about, since the program itself didn't fail.*/
+	pub fn run(&mut self, system: &mut System) -> Result<(), EmulatorError> {
+		system.clock.cpu.set_keyboard_listening(false);
+		system.log(format!("Waiting for a GDB client on 127.0.0.1:{}", self.port().unwrap_or(0)).as_str());
+		let mut connection: Connection = match self.listener.accept() {
+			Ok((stream, address)) => {
+				system.log(format!("GDB client connected from {}", address).as_str());
+				Connection::new(stream)
+			}
+			Err(error) => {
+				system.warn(format!("Could not accept a GDB client: {}", error).as_str());
+				return Ok(());
+			}
+		};
+		loop {
+			let packet: String = match connection.receive() {
+				Ok(Some(packet)) => {packet}
+				Ok(None) => {
+					system.log("GDB client disconnected");
+					return Ok(());
+				}
+				Err(error) => {
+					system.warn(format!("Lost the GDB client: {}", error).as_str());
+					return Ok(());
+				}
+			};
+			let reply: Option<String> = match self.command(system, &mut connection, &packet) {
+				Ok(reply) => {reply}
+				Err(Error::Emulator(error)) => {return Err(error);}
+				Err(Error::Io(error)) => {
+					system.warn(format!("Lost the GDB client: {}", error).as_str());
+					return Ok(());
+				}
+			};
+			//None ends the session
+			let Some(reply) = reply else {return Ok(());};
+			let sent = connection.send(&reply);
+			if packet == "QStartNoAckMode" {
+				connection.no_ack = true;
+			}
+			if let Err(error) = sent {
+				system.warn(format!("Lost the GDB client: {}", error).as_str());
+				return Ok(());
+			}
+			if packet.starts_with('D') {
+				system.log("GDB client detached");
+				return Ok(());
+			}
+		}
+	}
+	
+	///Answers a packet. Returns Ok(None) to end the session, and an empty reply for packets that aren't supported.
+	fn command(&mut self, system: &mut System, connection: &mut Connection, packet: &str) -> Result<Option<String>, Error> {
+		let cpu: &mut Cpu = &mut system.clock.cpu;
+		let reply: String = match packet.as_bytes().first().copied().unwrap_or(b' ') {
+			b'?' => {self.last_stop.clone()}
+			b'g' => {
+				let pc: [u8; 2] = cpu.current_address().to_le_bytes();
+				hex(&[cpu.a, cpu.x, cpu.y, cpu.s, pc[0], pc[1], cpu.nv_bdizc])
+			}
+			b'G' => {
+				match from_hex(&packet[1..]).as_deref() {
+					Some(&[a, x, y, s, pc_low, pc_high, p]) => {
+						Self::write_registers(cpu, [a, x, y, s, p], u16::from_le_bytes([pc_low, pc_high]));
+						String::from("OK")
+					}
+					_ => {String::from("E01")}
+				}
+			}
+			b'p' => {
+				let pc: [u8; 2] = cpu.current_address().to_le_bytes();
+				match u8::from_str_radix(&packet[1..], 16) {
+					Ok(0) => {hex(&[cpu.a])}
+					Ok(1) => {hex(&[cpu.x])}
+					Ok(2) => {hex(&[cpu.y])}
+					Ok(3) => {hex(&[cpu.s])}
+					Ok(4) => {hex(&pc)}
+					Ok(5) => {hex(&[cpu.nv_bdizc])}
+					_ => {String::from("E01")}
+				}
+			}
+			b'P' => {
+				let Some((register, value)) = packet[1..].split_once('=') else {return Ok(Some(String::from("E01")));};
+				let (Ok(register), Some(value)) = (u8::from_str_radix(register, 16), from_hex(value)) else {return Ok(Some(String::from("E01")));};
+				let mut registers: [u8; 5] = [cpu.a, cpu.x, cpu.y, cpu.s, cpu.nv_bdizc];
+				let mut pc: u16 = cpu.current_address();
+				match (register, value.as_slice()) {
+					(0..=3, &[value]) => {registers[register as usize] = value;}
+					(4, &[low, high]) => {pc = u16::from_le_bytes([low, high]);}
+					(5, &[value]) => {registers[4] = value;}
+					_ => {return Ok(Some(String::from("E01")));}
+				}
+				Self::write_registers(cpu, registers, pc);
+				String::from("OK")
+			}
+			b'm' => {
+				match parse_range(&packet[1..]) {
+					Some((address, len)) => {hex(&system.read_bytes(address, len.min(0x10000 - address as usize))?)}
+					None => {String::from("E01")}
+				}
+			}
+			b'M' => {
+				let Some((range, data)) = packet[1..].split_once(':') else {return Ok(Some(String::from("E01")));};
+				match (parse_range(range), from_hex(data)) {
+					(Some((address, len)), Some(bytes)) if bytes.len() == len && address as usize + len <= 0x10000 => {
+						system.write_bytes(address, &bytes)?;
+						String::from("OK")
+					}
+					_ => {String::from("E01")}
+				}
+			}
+			b's' => {
+				let target: u128 = cpu.instruction_counter + 1;
+				self.resume(system, connection, |cpu| {cpu.instruction_counter >= target})?
+			}
+			b'c' => {self.resume(system, connection, |_| {false})?}
+			b'Z' | b'z' => {self.set_point(system, packet)}
+			b'D' => {String::from("OK")}
+			b'k' => {return Ok(None);}
+			b'H' => {String::from("OK")}
+			_ => {
+				let (name, args) = packet.split_once(':').unwrap_or((packet, ""));
+				match name {
+					"qSupported" => {String::from("PacketSize=4000;QStartNoAckMode+;qXfer:features:read+;swbreak+;hwbreak+")}
+					"QStartNoAckMode" => {String::from("OK")}
+					"qAttached" => {String::from("1")}
+					"qXfer" => {
+						match args.strip_prefix("features:read:target.xml:").and_then(parse_range) {
+							Some((offset, len)) => {
+								let xml: &[u8] = Self::TARGET_XML.as_bytes();
+								let start: usize = (offset as usize).min(xml.len());
+								let end: usize = (start + len).min(xml.len());
+								format!("{}{}", if end == xml.len() {'l'} else {'m'}, String::from_utf8_lossy(&xml[start..end]))
+							}
+							None => {String::from("E00")}
+						}
+					}
+					_ => {String::new()}
+				}
+			}
+		};
+		return Ok(Some(reply));
+	}
+	
+	/**Writes A, X, Y, S, and P. Setting the PC somewhere other than the current instruction clears the pipeline,
+	so instructions that haven't finished are dropped and the CPU starts fetching at the new PC.*/
+	fn write_registers(cpu: &mut Cpu, [a, x, y, s, p]: [u8; 5], pc: u16) {
+		cpu.a = a;
+		cpu.x = x;
+		cpu.y = y;
+		cpu.s = s;
+		cpu.nv_bdizc = p;
+		if pc != cpu.current_address() {
+			cpu.clear_pipeline();
+			cpu.pc = pc;
+		}
+	}
+	
+	///Adds or removes a breakpoint or watchpoint for a Z or z packet
+	fn set_point(&mut self, system: &mut System, packet: &str) -> String {
+		let insert: bool = packet.starts_with('Z');
+		let mut fields = packet[1..].split([',', ';']);
+		let (Some(kind), Some(address), Some(len)) = (fields.next(), fields.next(), fields.next()) else {return String::from("E01");};
+		let (Ok(kind), Ok(address), Ok(len)) = (kind.parse::<u8>(), u16::from_str_radix(address, 16), usize::from_str_radix(len, 16)) else {
+			return String::from("E01");
+		};
+		match kind {
+			0 | 1 => {
+				let breakpoint: (u16, bool) = (address, kind == 1);
+				if insert {
+					self.breakpoints.push(breakpoint);
+				} else if let Some(i) = self.breakpoints.iter().position(|b| {*b == breakpoint}) {
+					self.breakpoints.remove(i);
+				}
+			}
+			2..=4 => {
+				let end: u16 = (address as usize + len.max(1) - 1).min(0xFFFF) as u16;
+				let accesses: &[Access] = match kind {
+					2 => {&[Access::Write]}
+					3 => {&[Access::Read]}
+					_ => {&[Access::Read, Access::Write]}
+				};
+				for (added, &access) in accesses.iter().enumerate() {
+					let watchpoint: Watchpoint = Watchpoint {start: address, end, access, condition: None, action: WatchAction::Stop};
+					if insert {
+						if system.add_watchpoint(watchpoint).is_err() {
+							//the packet failed, so the watchpoints it already added for Z4 are taken back out
+							Self::remove_watchpoint(system, watchpoint);
+							for (_, watchpoint) in self.watchpoints.split_off(self.watchpoints.len() - added) {
+								Self::remove_watchpoint(system, watchpoint);
+							}
+							return String::from("E02");
+						}
+						self.watchpoints.push((kind, watchpoint));
+					} else if let Some(i) = self.watchpoints.iter().position(|w| {*w == (kind, watchpoint)}) {
+						self.watchpoints.remove(i);
+						Self::remove_watchpoint(system, watchpoint);
+					}
+				}
+			}
+			_ => {return String::new();}
+		}
+		return String::from("OK");
+	}
+	///Removes a watchpoint from the cache
+	fn remove_watchpoint(system: &mut System, watchpoint: Watchpoint) {
+		let watchpoints = &mut system.clock.cpu.mmu.cache.watchpoints;
+		if let Some(index) = watchpoints.list().iter().position(|w| {*w == watchpoint}) {
+			watchpoints.remove(index);
+		}
+	}
+	
+	/**Runs the program until done returns true, a breakpoint or watchpoint is hit, the CPU halts, or the client sends Ctrl-C.
+	Returns the stop reply. Watchpoints that only log are printed as they're hit.*/
+	fn resume(&mut self, system: &mut System, connection: &mut Connection, done: impl Fn(&Cpu) -> bool) -> Result<String, Error> {
+		let mut stop: Stop = Stop::Halted;
+		let mut cycles: u128 = 0;
+		while !system.clock.cpu.halted() {
+			if done(&system.clock.cpu) {
+				stop = Stop::Step;
+				break;
+			}
+			let hits: Vec<WatchHit> = system.cycle()?;
+			hits.iter().filter(|hit| {hit.watchpoint.action == WatchAction::Log}).for_each(|hit| {system.log(hit.to_string().as_str());});
+			if let Some(hit) = hits.into_iter().find(|hit| {hit.watchpoint.action == WatchAction::Stop}) {
+				stop = Stop::Watch(hit);
+				break;
+			}
+			if let Some((address, _)) = system.clock.cpu.issued() {
+				if let Some(&(_, hardware)) = self.breakpoints.iter().find(|(breakpoint, _)| {*breakpoint == address}) {
+					stop = Stop::Breakpoint {hardware};
+					break;
+				}
+			}
+			cycles += 1;
+			if cycles.is_multiple_of(Self::INTERRUPT_CHECK_CYCLES) && connection.interrupted()? {
+				stop = Stop::Interrupted;
+				break;
+			}
+		}
+		self.last_stop = match stop {
+			Stop::Step => {String::from("S05")}
+			Stop::Breakpoint {hardware: false} => {String::from("T05swbreak:;")}
+			Stop::Breakpoint {hardware: true} => {String::from("T05hwbreak:;")}
+			Stop::Watch(hit) => {
+				let kind: u8 = self.watchpoints.iter().find(|(_, watchpoint)| {*watchpoint == hit.watchpoint}).map_or(4, |(kind, _)| {*kind});
+				let name: &str = match kind {
+					2 => {"watch"}
+					3 => {"rwatch"}
+					_ => {"awatch"}
+				};
+				format!("T05{}:{:04x};", name, hit.address)
+			}
+			Stop::Interrupted => {String::from("S02")}
+			Stop::Halted => {
+				//GDB signal numbers: SIGILL for code that can't run, SIGBUS for the memory bus and I/O devices, and an exit for BRK and STP
+				match system.clock.cpu.fault.map(|fault| {fault.kind}) {
+					Some(FaultKind::InvalidOpcode | FaultKind::InvalidSyscall {..}) => {String::from("S04")}
+					Some(FaultKind::Bus(_) | FaultKind::UnknownDevice {..}) => {String::from("S0a")}
+					None => {String::from("W00")}
+				}
+			}
+		};
+		return Ok(self.last_stop.clone());
+	}
+}
+
+/**A client connection that reads and writes packets like `$m0,4#fd`.*/
+struct Connection {
+	stream: TcpStream,
+	///Bytes received that aren't part of a whole packet yet
+	buffer: Vec<u8>,
+	///Set after QStartNoAckMode, when packets are no longer acknowledged with + or -
+	no_ack: bool
+}
+
+impl Connection {
+	const INTERRUPT: u8 = 0x03;
+	
+	fn new(stream: TcpStream) -> Self {
+		return Self {stream, buffer: Vec::new(), no_ack: false};
+	}
+	
+	///Waits for the next packet and acknowledges it. Returns None once the client disconnects.
+	fn receive(&mut self) -> io::Result<Option<String>> {
+		loop {
+			//acknowledgements and Ctrl-C while the program is stopped are dropped
+			let start: Option<usize> = self.buffer.iter().position(|&byte| {byte == b'$'});
+			if let Some(start) = start {
+				self.buffer.drain(..start);
+				if let Some(end) = self.buffer.iter().position(|&byte| {byte == b'#'}).filter(|end| {end + 2 < self.buffer.len()}) {
+					let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+					let data: &[u8] = &packet[1..end];
+					let checksum: Option<u8> = std::str::from_utf8(&packet[end + 1..]).ok().and_then(|text| {u8::from_str_radix(text, 16).ok()});
+					if !self.no_ack {
+						if checksum != Some(data.iter().fold(0u8, |sum, byte| {sum.wrapping_add(*byte)})) {
+							self.stream.write_all(b"-")?;
+							continue;
+						}
+						self.stream.write_all(b"+")?;
+					}
+					//bytes after } are escaped by XORing them with 0x20
+					let mut unescaped: Vec<u8> = Vec::with_capacity(data.len());
+					let mut bytes = data.iter();
+					while let Some(&byte) = bytes.next() {
+						unescaped.push(if byte == b'}' {bytes.next().map_or(byte, |next| {next ^ 0x20})} else {byte});
+					}
+					return Ok(Some(String::from_utf8_lossy(&unescaped).into_owned()));
+				}
+			} else {
+				self.buffer.clear();
+			}
+			let mut chunk: [u8; 4096] = [0; 4096];
+			let len: usize = self.stream.read(&mut chunk)?;
+			if len == 0 {return Ok(None);}
+			self.buffer.extend_from_slice(&chunk[..len]);
+		}
+	}
+	
+	///Sends a packet and, until QStartNoAckMode, waits for it to be acknowledged. It's sent again if the client asks.
+	fn send(&mut self, data: &str) -> io::Result<()> {
+		let checksum: u8 = data.bytes().fold(0u8, |sum, byte| {sum.wrapping_add(byte)});
+		let packet: String = format!("${}#{:02x}", data, checksum);
+		loop {
+			self.stream.write_all(packet.as_bytes())?;
+			if self.no_ack {return Ok(());}
+			let mut ack: [u8; 1] = [0];
+			loop {
+				if self.stream.read(&mut ack)? == 0 {
+					return Err(io::Error::from(ErrorKind::UnexpectedEof));
+				}
+				if ack[0] == b'+' || ack[0] == b'-' {break;}
+			}
+			if ack[0] == b'+' {return Ok(());}
+		}
+	}
+	
+	///Checks without waiting whether the client sent Ctrl-C. A disconnected client counts as one, so the program stops.
+	fn interrupted(&mut self) -> io::Result<bool> {
+		self.stream.set_nonblocking(true)?;
+		let mut chunk: [u8; 4096] = [0; 4096];
+		let received: io::Result<usize> = self.stream.read(&mut chunk);
+		self.stream.set_nonblocking(false)?;
+		match received {
+			Ok(0) => {return Ok(true);}
+			Ok(len) => {self.buffer.extend_from_slice(&chunk[..len]);}
+			Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+			Err(error) => {return Err(error);}
+		}
+		match self.buffer.iter().position(|&byte| {byte == Self::INTERRUPT}) {
+			Some(i) => {
+				self.buffer.remove(i);
+				return Ok(true);
+			}
+			None => {return Ok(false);}
+		}
+	}
+}
+
+///A broken connection ends the session, and a memory bus failure ends the program
+enum Error {
+	Io(io::Error),
+	Emulator(EmulatorError)
+}
+
+impl From<io::Error> for Error {
+	fn from(error: io::Error) -> Self {Error::Io(error)}
+}
+
+impl From<EmulatorError> for Error {
+	fn from(error: EmulatorError) -> Self {Error::Emulator(error)}
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| {format!("{:02x}", byte)}).collect()
+}
+
+///Returns None if the text isn't pairs of hex digits
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+	if !text.len().is_multiple_of(2) {return None;}
+	(0..text.len()).step_by(2).map(|i| {u8::from_str_radix(text.get(i..i + 2)?, 16).ok()}).collect()
+}
+
+///Parses ADDRESS,LENGTH in hex
+fn parse_range(text: &str) -> Option<(u16, usize)> {
+	let (address, len) = text.split_once(',')?;
+	Some((u16::from_str_radix(address, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		crate::hardware::cpu::CpuModel,
+		std::{thread, time::Duration}
+	};
+	
+	///The client's side of the connection, which checks every packet's checksum
+	struct Client {
+		stream: TcpStream,
+		ack: bool
+	}
+	
+	impl Client {
+		fn connect(port: u16) -> Self {
+			let stream: TcpStream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+			stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+			return Self {stream, ack: true};
+		}
+		fn byte(&mut self) -> u8 {
+			let mut byte: [u8; 1] = [0];
+			self.stream.read_exact(&mut byte).unwrap();
+			return byte[0];
+		}
+		fn send(&mut self, data: &str) {
+			let checksum: u8 = data.bytes().fold(0u8, |sum, byte| {sum.wrapping_add(byte)});
+			self.stream.write_all(format!("${}#{:02x}", data, checksum).as_bytes()).unwrap();
+			if self.ack {
+				assert_eq!(self.byte(), b'+', "{} wasn't acknowledged", data);
+			}
+		}
+		///Reads a reply and checks its checksum, without acknowledging it
+		fn receive(&mut self) -> String {
+			assert_eq!(self.byte(), b'$');
+			let mut data: Vec<u8> = Vec::new();
+			loop {
+				match self.byte() {
+					b'#' => {break;}
+					byte => {data.push(byte);}
+				}
+			}
+			let checksum: String = String::from_utf8(vec![self.byte(), self.byte()]).unwrap();
+			assert_eq!(u8::from_str_radix(&checksum, 16).unwrap(), data.iter().fold(0u8, |sum, byte| {sum.wrapping_add(*byte)}));
+			return String::from_utf8(data).unwrap();
+		}
+		fn ask(&mut self, data: &str) -> String {
+			self.send(data);
+			let reply: String = self.receive();
+			if self.ack {
+				self.stream.write_all(b"+").unwrap();
+			}
+			return reply;
+		}
+	}
+	
+	///LDA #$42, STA $0300, NOP, BRK
+	const PROGRAM: [u8; 7] = [0xA9, 0x42, 0x8D, 0x00, 0x03, 0xEA, 0x00];
+	
+	#[tokio::test]
+	async fn scripted_client() {
+		let mut system: System = System::new(CpuModel::Nmos6502 {undocumented: false});
+		system.load_main_program(0x0200, &PROGRAM).unwrap();
+		let mut server: GdbServer = GdbServer::bind(0).unwrap();
+		let port: u16 = server.port().unwrap();
+		let client = thread::spawn(move || {
+			let mut client: Client = Client::connect(port);
+			//a bad checksum is refused, and the packet is answered once it's sent again
+			client.stream.write_all(b"$?#00").unwrap();
+			assert_eq!(client.byte(), b'-');
+			assert_eq!(client.ask("?"), "S05");
+			//a reply that isn't acknowledged is sent again
+			client.send("m0200,7");
+			assert_eq!(client.receive(), "a9428d0003ea00");
+			client.stream.write_all(b"-").unwrap();
+			assert_eq!(client.receive(), "a9428d0003ea00");
+			client.stream.write_all(b"+").unwrap();
+			
+			assert_eq!(client.ask("M0300,2:abcd"), "OK");
+			assert_eq!(client.ask("m0300,2"), "abcd");
+			assert_eq!(client.ask("M0300,2:ab"), "E01");
+			
+			assert_eq!(client.ask("Z0,202,1"), "OK");
+			assert_eq!(client.ask("c"), "T05swbreak:;");
+			let registers: String = client.ask("g");
+			assert_eq!(&registers[..2], "42");
+			assert_eq!(&registers[8..12], "0202");
+			let written: String = format!("{}11{}", &registers[..2], &registers[4..]);
+			assert_eq!(client.ask(&format!("G{}", written)), "OK");
+			assert_eq!(client.ask("g"), written);
+			assert_eq!(client.ask("G00"), "E01");
+			assert_eq!(client.ask("z0,202,1"), "OK");
+			
+			assert_eq!(client.ask("Z2,300,1"), "OK");
+			assert_eq!(client.ask("c"), "T05watch:0300;");
+			assert_eq!(client.ask("m0300,1"), "42");
+			assert_eq!(client.ask("?"), "T05watch:0300;");
+			assert_eq!(client.ask("z2,300,1"), "OK");
+			assert_eq!(client.ask("s"), "S05");
+			
+			assert_eq!(client.ask("QStartNoAckMode"), "OK");
+			client.ack = false;
+			assert_eq!(client.ask("c"), "W00");
+			assert_eq!(client.ask("vUnknown"), "");
+			client.send("k");
+		});
+		assert_eq!(server.run(&mut system), Ok(()));
+		client.join().unwrap();
+		assert!(system.clock.cpu.mmu.cache.watchpoints.list().is_empty());
+	}
+}
\ No newline at end of file
@@ -1,22 +1,217 @@
 use {
 	crate::hardware::{
 		hardware::{Hardware, HardwareSpecs},
-		memory::{MemEvent, N_WAYS}
+		memory::{MemEvent, N_WAYS},
+		fault::BusError,
+		watchpoint::Watchpoints,
+		replacement::{Policy, ReplacementPolicy}
 	},
-	std::collections::HashMap,
-	tokio::sync::mpsc::{Receiver, Sender, error::TryRecvError}
+	std::{
+		collections::VecDeque,
+		fmt::{self, Display, Formatter}
+	},
+	tokio::sync::mpsc::{Receiver, Sender, error::{TryRecvError, TrySendError}}
 };
 
-const NUM_LINES: u8 = 16;
-///There should be no zeros in this number
-pub const INDEX_MASK: u8 = 0b111;
+/**How many lines an address can be cached in.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mapping {
+	///Each address has one line it can go in
+	Direct,
+	///Each address can go in any line of its set, which has this many lines
+	SetAssociative(usize),
+	///Any address can go in any line
+	Full
+}
+
+impl Mapping {
+	///Reads direct, full, or N-way, where N is the lines in each set
+	pub fn parse(text: &str) -> Result<Self, String> {
+		let text: String = text.trim().to_ascii_lowercase();
+		return match text.as_str() {
+			"direct" => {Ok(Self::Direct)}
+			"full" => {Ok(Self::Full)}
+			_ => {
+				match text.strip_suffix("-way").and_then(|ways| {ways.parse::<usize>().ok()}) {
+					Some(ways) => {Ok(Self::SetAssociative(ways))}
+					None => {Err(format!("Unknown cache mapping: {} (expected direct, full, or N-way)", text))}
+				}
+			}
+		};
+	}
+}
+
+impl Display for Mapping {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Mapping::Direct => {write!(f, "direct-mapped")}
+			Mapping::SetAssociative(ways) => {write!(f, "{}-way set-associative", ways)}
+			Mapping::Full => {write!(f, "fully associative")}
+		}
+	}
+}
+
+/**The shape of the cache, which can be picked for each run.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CacheConfig {
+	pub lines: usize,
+	///Bytes in each line, which is a power of two. Lines longer than the memory ways are read and written back over several cycles.
+	pub line_size: usize,
+	pub mapping: Mapping,
+	///Picks the line of a full set that's evicted
+	pub policy: Policy
+}
+
+impl Default for CacheConfig {
+	fn default() -> Self {
+		return Self {
+			lines: 16,
+			line_size: N_WAYS as usize,
+			mapping: Mapping::Full,
+			policy: Policy::Lru
+		};
+	}
+}
+
+impl Display for CacheConfig {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(f, "{} lines of {} bytes, {}, {}", self.lines, self.line_size, self.mapping, self.policy)
+	}
+}
+
+impl CacheConfig {
+	///Lines in each set
+	pub fn ways(&self) -> usize {
+		match self.mapping {
+			Mapping::Direct => {1}
+			Mapping::SetAssociative(ways) => {ways}
+			Mapping::Full => {self.lines}
+		}
+	}
+	pub fn sets(&self) -> usize {
+		self.lines / self.ways()
+	}
+	///Returns why the cache can't be built this way, if it can't
+	pub fn validate(&self) -> Result<(), String> {
+		if self.lines == 0 {
+			return Err(String::from("The cache needs at least one line"));
+		}
+		if !self.line_size.is_power_of_two() {
+			return Err(format!("The cache line size must be a power of two, not {}", self.line_size));
+		}
+		if self.lines.saturating_mul(self.line_size) > 0x10000 {
+			return Err(format!("{} lines of {} bytes is bigger than memory", self.lines, self.line_size));
+		}
+		if self.ways() == 0 || !self.lines.is_multiple_of(self.ways()) {
+			return Err(format!("{} lines can't be split into sets of {}", self.lines, self.ways()));
+		}
+		if self.policy == Policy::TreePlru && !self.ways().is_power_of_two() {
+			return Err(format!("Tree pseudo-LRU needs a power of two lines in each set, not {}", self.ways()));
+		}
+		Ok(())
+	}
+}
 
 pub struct Cache {
 	specs: HardwareSpecs,
-	lines: HashMap<u16, CacheLine>,
+	config: CacheConfig,
+	///Every line, with the lines of each set next to each other
+	lines: Vec<CacheLine>,
+	///The bytes of every line, line_size for each, in the same order as the lines
+	data: Vec<u8>,
 	pub memory: [(Sender<MemEvent>, Receiver<MemEvent>); N_WAYS as usize],
+	///The line that memory is currently reading for the cache
+	fill: Option<Fill>,
+	///Writes waiting for their memory way to be free, oldest first: written back lines and writes that missed
+	write_queue: VecDeque<(u16, u8)>,
+	///Goes up on every access, and the line that was accessed keeps it so the lines can be shown from the one used last
+	uses: u64,
+	policy: Box<dyn ReplacementPolicy>,
 	pub cache_hits: u128,
-	pub cache_accesses: u128
+	pub cache_accesses: u128,
+	///Reads and writes that went to memory. A read that waits for memory over several cycles is one miss.
+	pub cache_misses: u128,
+	///Lines evicted by the current policy to make room for another one, not counting flushes
+	pub cache_evictions: u128,
+	///Evictions made by the policies the cache used before the current one, each listed once if it made any
+	earlier_evictions: Vec<(Policy, u128)>,
+	///Told about every value read or written
+	pub watchpoints: Watchpoints,
+	///What changed since the journal was last taken, while the history is recording
+	journal: Option<CacheChanges>
+}
+
+/**The counters of the cache, which the history keeps for every cycle.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CacheCounters {
+	uses: u64,
+	cache_hits: u128,
+	cache_accesses: u128,
+	cache_misses: u128,
+	cache_evictions: u128
+}
+
+/**What the cache changed, with the values from before, oldest first. From Cache::take_journal().*/
+#[derive(Default)]
+pub struct CacheChanges(Vec<CacheChange>);
+
+impl CacheChanges {
+	///Adds newer changes after these ones
+	pub fn extend(&mut self, newer: CacheChanges) {
+		self.0.extend(newer.0);
+	}
+	///Bytes used on the heap
+	pub fn heap_size(&self) -> usize {
+		self.0.len() * size_of::<CacheChange>() + self.0.iter().map(|change| {
+			match change {
+				CacheChange::Data(_, data) => {data.len()}
+				CacheChange::Fill(Some(fill)) => {size_of::<Fill>() + fill.data.len() + fill.written.len() * size_of::<(usize, u8)>()}
+				_ => {0}
+			}
+		}).sum::<usize>()
+	}
+}
+
+///One thing the cache changed, with what it was before
+enum CacheChange {
+	///Index and value of a line
+	Line(usize, CacheLine),
+	///Index in the data and value of a byte
+	Byte(usize, u8),
+	///Index and bytes of a whole line
+	Data(usize, Vec<u8>),
+	///The line being read from memory, before it was started or finished
+	Fill(Option<Box<Fill>>),
+	///Row and ways awaited of the line being read
+	Row(usize, Option<u8>),
+	///Offset and value of a byte of the line being read
+	FillByte(usize, u8),
+	///A write was added to the line being read
+	FillWritten,
+	///Writes added to the back of the queue
+	Queued(usize),
+	///A write sent from the front of the queue
+	Sent((u16, u8)),
+	///Index of a line and what the replacement policy kept for it
+	Policy(usize, [u64; 3]),
+	///Address and last value seen by the watchpoints
+	Watched(u16, Option<u8>)
+}
+
+/**A line of the cache as it is between cycles, for displays.*/
+#[derive(Debug, PartialEq, Clone)]
+pub struct LineInfo {
+	///Set the line is in, which is always 0 when the cache is fully associative
+	pub set: usize,
+	pub tag: u16,
+	///First address the line holds
+	pub address: u16,
+	///How close the line is to being evicted by the replacement policy: the higher, the sooner.
+	///For LRU, it's the lines in the same set used since this one.
+	pub age: usize,
+	///Set when the line was written and memory still has the old values
+	pub dirty: bool,
+	pub data: Vec<u8>
 }
 
 impl Hardware for Cache {
@@ -25,148 +220,596 @@ impl Hardware for Cache {
 
 impl Cache {
 	pub fn new(channels: [(Sender<MemEvent>, Receiver<MemEvent>); N_WAYS as usize]) -> Self {
+		let config: CacheConfig = CacheConfig::default();
 		let cache: Self = Self {
 			specs: HardwareSpecs::new("Cache"),
-			lines: HashMap::with_capacity(NUM_LINES as usize),
+			config,
+			lines: vec![CacheLine::default(); config.lines],
+			data: vec![0x00; config.lines * config.line_size],
 			memory: channels,
+			fill: None,
+			write_queue: VecDeque::new(),
+			uses: 0,
+			policy: config.policy.build(config.sets(), config.ways()),
 			cache_hits: 0,
-			cache_accesses: 0
+			cache_accesses: 0,
+			cache_misses: 0,
+			cache_evictions: 0,
+			earlier_evictions: Vec::new(),
+			watchpoints: Watchpoints::default(),
+			journal: None
 		};
-		cache.log("Created");
+		cache.log(format!("Created - {}", config).as_str());
 		cache
 	}
 	
-	/**Returns Ok(Some(u8)) if cache hit, Ok(None) if cache miss, or Err(()) if it can't perform a read on this cycle.
+	pub fn config(&self) -> CacheConfig {
+		self.config
+	}
+	/**Rebuilds the cache with a new shape, which must have passed CacheConfig::validate(). Every line is dropped without
+	being written back, so the cache must be flushed first, like Mmu::configure_cache() does.*/
+	pub fn set_config(&mut self, config: CacheConfig) {
+		if config.policy != self.config.policy {
+			//the evictions of each policy are kept apart, so they can be compared on the same program
+			let earlier: u128 = self.earlier_evictions.iter().position(|(policy, _)| {*policy == config.policy})
+				.map_or(0, |index| {self.earlier_evictions.remove(index).1});
+			let evictions: u128 = std::mem::replace(&mut self.cache_evictions, earlier);
+			if evictions > 0 {
+				self.earlier_evictions.push((self.config.policy, evictions));
+			}
+		}
+		self.config = config;
+		self.lines = vec![CacheLine::default(); config.lines];
+		self.data = vec![0x00; config.lines * config.line_size];
+		self.fill = None;
+		self.policy = config.policy.build(config.sets(), config.ways());
+		if self.journal.is_some() {
+			self.journal = Some(CacheChanges::default());
+		}
+		self.log(format!("Configured - {}", config).as_str());
+	}
+	
+	/**Returns Ok(Some(u8)) if cache hit, or Ok(None) if cache miss or it can't perform a read on this cycle.
 	If the value is not returned, this function must be called again in a future cycle until it is. The value can either be
-	returned in a cache hit, or when the value is returned from memory.*/
-	pub fn read(&mut self, address: u16) -> Result<Option<u8>,()> {
+	returned in a cache hit, or when the value is returned from memory. Returns Err if the memory bus failed.*/
+	pub fn read(&mut self, address: u16) -> Result<Option<u8>, BusError> {
 		self.cache_accesses += 1;
-		let index: usize = (address & INDEX_MASK as u16) as usize;
-		let tag: u16 = (address & !INDEX_MASK as u16) / N_WAYS as u16;
-		let mut val: Option<u8> = None;
-		if let Some(line) = self.lines.get_mut(&tag) {
-			//cache hit - immediately return value
-			val = Some(line.data[index]);
-			let prev_age: u8 = line.status & INDEX_MASK;
-			line.status &= !INDEX_MASK;//set age to 0
-			for (t, l) in self.lines.iter_mut() {
-				if *t != tag && (l.status & INDEX_MASK) < prev_age {
-					l.status += 1;
-				}
+		let hit: bool = self.find(address).is_some();
+		self.receive()?;
+		if let Some(slot) = self.find(address) {
+			//cache hit, or the line just came back from memory - immediately return value
+			let val: u8 = self.data[slot * self.config.line_size + self.offset(address)];
+			self.touch(slot);
+			if hit {
+				self.cache_hits += 1;
 			}
-			//clear cache buffer and discard the value
-			self.memory.iter_mut().for_each(|(_, rx)| {rx.try_recv().ok();});
+			self.note_watched(address);
+			self.watchpoints.read(address, val);
+			return Ok(Some(val));
+		}
+		//cache miss
+		if self.fill.is_some() {
+			return Ok(None);//still waiting on memory
+		}
+		if !self.write_queue.is_empty() {
+			return Ok(None);//memory could still have an old value until the writes are done
+		}
+		//request to read from memory
+		let block: usize = address as usize / self.config.line_size;
+		self.note(|cache| {CacheChange::Fill(cache.fill.clone().map(Box::new))});
+		self.fill = Some(Fill {
+			block,
+			row: block * self.config.line_size / N_WAYS as usize,
+			awaiting: None,
+			data: vec![0x00; self.config.line_size],
+			written: Vec::new()
+		});
+		self.request_row()?;
+		self.cache_misses += 1;
+		Ok(None)
+	}
+	
+	///Returns Ok(true) if writes to cache, Ok(false) if writes to memory, or Err if the memory bus failed.
+	pub fn write(&mut self, address: u16, value: u8) -> Result<bool, BusError> {
+		self.cache_accesses += 1;
+		self.receive()?;
+		let offset: usize = self.offset(address);
+		if let Some(slot) = self.find(address) {
+			//cache hit
+			let index: usize = slot * self.config.line_size + offset;
+			self.note_watched(address);
+			self.note(|cache| {CacheChange::Byte(index, cache.data[index])});
+			self.note(|cache| {CacheChange::Line(slot, cache.lines[slot])});
+			let byte: &mut u8 = &mut self.data[index];
+			self.watchpoints.write(address, Some(*byte), value);
+			*byte = value;
+			self.lines[slot].dirty = true;
+			self.touch(slot);
 			self.cache_hits += 1;
+			Ok(true)
 		} else {
 			//cache miss
-			let mut data: [u8; N_WAYS as usize] = [0x00; N_WAYS as usize];
-			//read cache buffer
-			let mut responses: u8 = 0b00;
-			for (i, (_, rx)) in self.memory.iter_mut().enumerate() {
-				match rx.try_recv() {
-					Ok(MemEvent::MemReadResponse{mdr}) => {
-						data[i] = mdr;
-						responses |= 0b01;
-					}
-					Ok(MemEvent::MemWriteResponse) => {
-						responses |= 0b10;
-					}
-					Ok(_) => {
-						panic!("Received invalid response from memory.");
-					}
-					Err(TryRecvError::Empty) => {}
-					Err(TryRecvError::Disconnected) => {
-						panic!("Memory tx disconnected.");
-					}
-				}
+			let block: usize = address as usize / self.config.line_size;
+			if let Some(fill) = self.fill.as_mut().filter(|fill| {fill.block == block}) {
+				fill.written.push((offset, value));//memory may have already sent the old value for the line
+				self.note(|_| {CacheChange::FillWritten});
 			}
-			match responses {
-				0b00 | 0b10 => {
-					//request to read from memory
-					self.memory.iter_mut().for_each(|(tx, _)| {tx.try_send(MemEvent::MemReadRequest{mar: tag}).expect("Memory receiver buffer full.");});
-					return Ok(None);
-				}
-				0b11 => {
-					return Err(())//cannot read and write to memory simultaneously
+			self.write_queue.push_back((address, value));
+			self.note(|_| {CacheChange::Queued(1)});
+			self.send_writes()?;
+			self.cache_misses += 1;
+			self.note_watched(address);
+			self.watchpoints.write(address, None, value);
+			Ok(false)
+		}
+	}
+	
+	///Evictions made by the policies the cache has used that made any, and the current one last
+	pub fn evictions(&self) -> Vec<(Policy, u128)> {
+		self.earlier_evictions.iter().copied().chain([(self.config.policy, self.cache_evictions)]).collect()
+	}
+	///Sets the hits, accesses, misses, and evictions back to 0, like before a program starts. The counts of the other policies are kept.
+	pub fn reset_counters(&mut self) {
+		self.cache_hits = 0;
+		self.cache_accesses = 0;
+		self.cache_misses = 0;
+		self.cache_evictions = 0;
+	}
+	///Lines the cache can hold
+	pub fn capacity(&self) -> usize {
+		self.config.lines
+	}
+	///The lines in the cache, from the one used last to the one used least recently
+	pub fn lines(&self) -> Vec<LineInfo> {
+		let ways: usize = self.config.ways();
+		let mut slots: Vec<usize> = (0..self.lines.len()).filter(|slot| {self.lines[*slot].valid}).collect();
+		slots.sort_by_key(|slot| {std::cmp::Reverse(self.lines[*slot].used)});
+		return slots.into_iter().map(|slot| {
+			let line: &CacheLine = &self.lines[slot];
+			let set: usize = slot / ways;
+			LineInfo {
+				set,
+				tag: (line.block / self.config.sets()) as u16,
+				address: (line.block * self.config.line_size) as u16,
+				age: self.policy.age(set, slot % ways),
+				dirty: line.dirty,
+				data: self.data[slot * self.config.line_size..(slot + 1) * self.config.line_size].to_vec()
+			}
+		}).collect();
+	}
+	///The value at the address if its line is in the cache. Unlike read, nothing is counted and the ages don't change.
+	pub fn peek(&self, address: u16) -> Option<u8> {
+		self.find(address).map(|slot| {self.data[slot * self.config.line_size + self.offset(address)]})
+	}
+	
+	///Copies the counters so the history can go back to them
+	pub(super) fn counters(&self) -> CacheCounters {
+		CacheCounters {
+			uses: self.uses,
+			cache_hits: self.cache_hits,
+			cache_accesses: self.cache_accesses,
+			cache_misses: self.cache_misses,
+			cache_evictions: self.cache_evictions
+		}
+	}
+	pub(super) fn set_counters(&mut self, counters: CacheCounters) {
+		self.uses = counters.uses;
+		self.cache_hits = counters.cache_hits;
+		self.cache_accesses = counters.cache_accesses;
+		self.cache_misses = counters.cache_misses;
+		self.cache_evictions = counters.cache_evictions;
+	}
+	///Starts or stops keeping what changes for the history
+	pub(super) fn record_changes(&mut self, recording: bool) {
+		self.journal = if recording {Some(CacheChanges::default())} else {None};
+	}
+	///Takes what changed since the last call, oldest first
+	pub(super) fn take_journal(&mut self) -> CacheChanges {
+		self.journal.as_mut().map(std::mem::take).unwrap_or_default()
+	}
+	///Takes back the changes, newest first, without writing them in the journal
+	pub(super) fn undo(&mut self, changes: CacheChanges) {
+		let ways: usize = self.config.ways();
+		let line_size: usize = self.config.line_size;
+		for change in changes.0.into_iter().rev() {
+			match change {
+				CacheChange::Line(slot, line) => {self.lines[slot] = line;}
+				CacheChange::Byte(index, value) => {self.data[index] = value;}
+				CacheChange::Data(slot, data) => {self.data[slot * line_size..(slot + 1) * line_size].copy_from_slice(&data);}
+				CacheChange::Fill(fill) => {self.fill = fill.map(|fill| {*fill});}
+				CacheChange::Row(row, awaiting) => {
+					if let Some(fill) = &mut self.fill {
+						fill.row = row;
+						fill.awaiting = awaiting;
+					}
 				}
-				_ => {}
-			}
-			//the memory has responded with the data -
-			let new_line: CacheLine = CacheLine::new(data);
-			let mut prev_age: u8 = N_WAYS;
-			if self.lines.len() == NUM_LINES as usize {//if cache has filled all lines
-				//remove the oldest cache line
-				let Some((oldest_tag, _)) = self.lines.iter().max_by(|(_, line1), (_, line2)| {(line1.status & INDEX_MASK).cmp(&(line2.status & INDEX_MASK))}) else {panic!("Cache lines HashMap was empty and not empty at the same time.");};
-				let oldest_tag: u16 = oldest_tag.to_owned();
-				let removed_line: CacheLine = self.lines.remove(&oldest_tag).expect("Cache Line exists and doesn't exist at the same time.");
-				prev_age = removed_line.status & INDEX_MASK;
-				//write it back if dirty
-				if removed_line.status & !INDEX_MASK > 0 {
-					self.memory.iter_mut().enumerate().for_each(|(i, (tx, _))| {
-						tx.try_send(MemEvent::MemWriteRequest { mar: oldest_tag, mdr: removed_line.data[i] }).expect("Memory receiver buffer full.");
-					});
+				CacheChange::FillByte(offset, value) => {
+					if let Some(fill) = &mut self.fill {
+						fill.data[offset] = value;
+					}
 				}
-			}
-			for (_, l) in self.lines.iter_mut() {
-				if (l.status & INDEX_MASK) < prev_age {
-					l.status += 1;
+				CacheChange::FillWritten => {
+					if let Some(fill) = &mut self.fill {
+						fill.written.pop();
+					}
 				}
+				CacheChange::Queued(writes) => {self.write_queue.truncate(self.write_queue.len() - writes);}
+				CacheChange::Sent(write) => {self.write_queue.push_front(write);}
+				CacheChange::Policy(slot, saved) => {self.policy.load(slot / ways, slot % ways, saved);}
+				CacheChange::Watched(address, value) => {self.watchpoints.set_value(address, value);}
 			}
-			self.lines.insert(tag, new_line);
-			if let Some(line) = self.lines.get(&tag) {
-				val = Some(line.data[index]);
+		}
+	}
+	
+	/**Removes one line from the cache, writing it back to memory if it's dirty. Returns true once the cache is empty
+	and memory has finished every request, or false if this function must be called again in a future cycle.*/
+	pub fn evict_one(&mut self) -> Result<bool, BusError> {
+		self.receive()?;
+		if self.fill.is_some() || !self.write_queue.is_empty() || self.memory.iter().any(|(tx, _)| {tx.capacity() == 0}) {
+			return Ok(false);//memory is still busy
+		}
+		let Some(slot) = self.lines.iter().position(|line| {line.valid}) else {return Ok(true);};
+		self.remove(slot);
+		self.send_writes()?;
+		Ok(false)
+	}
+	
+	///Position of the address in its line
+	fn offset(&self, address: u16) -> usize {
+		address as usize & (self.config.line_size - 1)
+	}
+	///Index of the line that holds the address, if it's cached
+	fn find(&self, address: u16) -> Option<usize> {
+		let block: usize = address as usize / self.config.line_size;
+		let ways: usize = self.config.ways();
+		let first: usize = block % self.config.sets() * ways;
+		(first..first + ways).find(|slot| {self.lines[*slot].valid && self.lines[*slot].block == block})
+	}
+	///Writes what's about to change in the journal, if the history is recording
+	fn note(&mut self, change: impl FnOnce(&Self) -> CacheChange) {
+		if self.journal.is_none() {return;}
+		let change: CacheChange = change(self);
+		if let Some(journal) = &mut self.journal {
+			journal.0.push(change);
+		}
+	}
+	///Writes what the replacement policy keeps for the line in the journal before it's told about it
+	fn note_policy(&mut self, slot: usize) {
+		let ways: usize = self.config.ways();
+		self.note(|cache| {CacheChange::Policy(slot, cache.policy.save(slot / ways, slot % ways))});
+	}
+	///Writes the last value the watchpoints saw at the address in the journal before they're told about an access
+	fn note_watched(&mut self, address: u16) {
+		if !self.watchpoints.watches(address) {return;}
+		self.note(|cache| {CacheChange::Watched(address, cache.watchpoints.value(address))});
+	}
+	///Marks the line as the one used last
+	fn touch(&mut self, slot: usize) {
+		self.note(|cache| {CacheChange::Line(slot, cache.lines[slot])});
+		self.note_policy(slot);
+		self.uses += 1;
+		self.lines[slot].used = self.uses;
+		let ways: usize = self.config.ways();
+		self.policy.touch(slot / ways, slot % ways);
+	}
+	///Empties the line, queueing its bytes to be written back if it's dirty
+	fn remove(&mut self, slot: usize) {
+		self.note(|cache| {CacheChange::Line(slot, cache.lines[slot])});
+		let line: CacheLine = std::mem::take(&mut self.lines[slot]);
+		if !line.valid {return;}
+		let ways: usize = self.config.ways();
+		self.note_policy(slot);
+		self.policy.remove(slot / ways, slot % ways);
+		if line.dirty {
+			let start: usize = line.block * self.config.line_size;
+			for i in 0..self.config.line_size {
+				self.write_queue.push_back(((start + i) as u16, self.data[slot * self.config.line_size + i]));
 			}
+			self.note(|cache| {CacheChange::Queued(cache.config.line_size)});
 		}
-		Ok(val)
 	}
 	
-	///Whether a write operation is successful must be handled by the caller. Returns true if writes to cache, false if writes to memory.
-	pub fn write(&mut self, address: u16, value: u8) -> bool {
-		self.cache_accesses += 1;
-		//clear cache buffer
-		for (_, rx) in self.memory.iter_mut() {
+	/**Clears the cache buffer and sends the writes and the row of the line being read that memory has room for.
+	Once memory has sent every row of the line it's put in the cache, which is not necessarily the line being accessed now
+	if the pipeline was flushed while waiting on memory.*/
+	fn receive(&mut self) -> Result<(), BusError> {
+		let mut responses: [Option<u8>; N_WAYS as usize] = [None; N_WAYS as usize];
+		for (i, (_, rx)) in self.memory.iter_mut().enumerate() {
 			match rx.try_recv() {
-				Ok(MemEvent::MemReadResponse{..}) => {
-					panic!("MemReadResponse was not handled");
-				}
-				Err(TryRecvError::Disconnected) => {
-					panic!("Memory tx disconnected.");
-				}
+				Ok(MemEvent::MemReadResponse{mdr}) => {responses[i] = Some(mdr);}
 				Ok(MemEvent::MemWriteResponse) | Err(TryRecvError::Empty) => {}
-				Ok(_) => {
-					panic!("Cache received invalid value.");
-				}
+				Ok(_) => {return Err(BusError::UnexpectedEvent);}
+				Err(TryRecvError::Disconnected) => {return Err(BusError::Disconnected);}
 			}
 		}
-		let index: usize = (address & INDEX_MASK as u16) as usize;
-		let tag: u16 = (address & !INDEX_MASK as u16) / N_WAYS as u16;
-		if let Some(line) = self.lines.get_mut(&tag) {
-			//cache hit
-			line.data[index] = value;
-			let prev_age: u8 = line.status & INDEX_MASK;
-			line.status &= !INDEX_MASK;//set age to 0
-			line.status |= 0b1000_0000;//set dirty flag
-			for (t, l) in self.lines.iter_mut() {
-				if *t != tag && (l.status & INDEX_MASK) < prev_age {
-					l.status += 1;
+		if responses.iter().any(Option::is_some) {
+			let line_size: usize = self.config.line_size;
+			let Some(fill) = &mut self.fill else {return Err(BusError::UnexpectedEvent);};//a read response that was never requested
+			if let Some(journal) = &mut self.journal {
+				journal.0.push(CacheChange::Row(fill.row, fill.awaiting));
+			}
+			for (way, value) in responses.iter().enumerate().filter_map(|(way, value)| {value.map(|value| {(way, value)})}) {
+				let Some(awaiting) = fill.awaiting.as_mut().filter(|awaiting| {**awaiting & 1 << way != 0}) else {return Err(BusError::UnexpectedEvent);};
+				*awaiting &= !(1 << way);
+				let offset: usize = fill.row * N_WAYS as usize + way - fill.block * line_size;
+				if let Some(journal) = &mut self.journal {
+					journal.0.push(CacheChange::FillByte(offset, fill.data[offset]));
 				}
+				fill.data[offset] = value;
 			}
-			self.cache_hits += 1;
-			true
-		} else {
-			//cache miss
-			self.memory[index].0.try_send(MemEvent::MemWriteRequest {mar: tag, mdr: value}).expect("Memory receiver buffer full.");
-			false
+			if fill.awaiting == Some(0) {
+				fill.row += 1;
+				fill.awaiting = None;
+				if fill.row * N_WAYS as usize >= (fill.block + 1) * line_size {
+					let fill: Fill = self.fill.take().expect("The line being read is gone");
+					self.note(|_| {CacheChange::Fill(Some(Box::new(fill.clone())))});
+					self.insert(fill);
+				}
+			}
+		}
+		self.send_writes()?;
+		self.request_row()
+	}
+	///Puts a line that came back from memory in its set, evicting the line the policy picks if the set is full
+	fn insert(&mut self, mut fill: Fill) {
+		let ways: usize = self.config.ways();
+		let set: usize = fill.block % self.config.sets();
+		let first: usize = set * ways;
+		if self.lines[first..first + ways].iter().any(|line| {line.valid && line.block == fill.block}) {return;}
+		let slot: usize = match (first..first + ways).find(|slot| {!self.lines[*slot].valid}) {
+			Some(slot) => {slot}
+			None => {
+				self.cache_evictions += 1;
+				self.note_policy(first);
+				first + self.policy.victim(set)
+			}
+		};
+		self.remove(slot);
+		for (offset, value) in fill.written.drain(..) {
+			fill.data[offset] = value;
+		}
+		let line_size: usize = self.config.line_size;
+		self.note(|cache| {CacheChange::Data(slot, cache.data[slot * line_size..(slot + 1) * line_size].to_vec())});
+		self.data[slot * line_size..(slot + 1) * line_size].copy_from_slice(&fill.data);
+		self.note(|cache| {CacheChange::Line(slot, cache.lines[slot])});
+		self.lines[slot] = CacheLine {valid: true, block: fill.block, dirty: false, used: 0};
+		self.note_policy(slot);
+		self.policy.insert(set, slot - first);
+		self.uses += 1;
+		self.lines[slot].used = self.uses;
+	}
+	///Sends the oldest writes to memory while their ways are free. Writes to the same way stay in order.
+	fn send_writes(&mut self) -> Result<(), BusError> {
+		while let Some(&(address, value)) = self.write_queue.front() {
+			let (tx, _) = &mut self.memory[(address % N_WAYS as u16) as usize];
+			if tx.capacity() == 0 {break;}
+			tx.try_send(MemEvent::MemWriteRequest {mar: address / N_WAYS as u16, mdr: value}).map_err(bus_error)?;
+			self.write_queue.pop_front();
+			self.note(|_| {CacheChange::Sent((address, value))});
+		}
+		Ok(())
+	}
+	///Asks memory for the next row of the line being read, if it hasn't been asked yet and the ways it's in are free
+	fn request_row(&mut self) -> Result<(), BusError> {
+		let line_size: usize = self.config.line_size;
+		let Some(fill) = self.fill.as_mut().filter(|fill| {fill.awaiting.is_none()}) else {return Ok(());};
+		//a line shorter than a row only uses some of the ways
+		let first_way: usize = fill.block * line_size % N_WAYS as usize;
+		let ways: std::ops::Range<usize> = first_way..first_way + line_size.min(N_WAYS as usize);
+		if self.memory[ways.clone()].iter().any(|(tx, _)| {tx.capacity() == 0}) {
+			return Ok(());//cannot read and write to memory simultaneously
+		}
+		let mut awaiting: u8 = 0;
+		for way in ways {
+			self.memory[way].0.try_send(MemEvent::MemReadRequest{mar: fill.row as u16}).map_err(bus_error)?;
+			awaiting |= 1 << way;
+		}
+		if let Some(journal) = &mut self.journal {
+			journal.0.push(CacheChange::Row(fill.row, fill.awaiting));
 		}
+		fill.awaiting = Some(awaiting);
+		Ok(())
 	}
 }
 
+fn bus_error<T>(error: TrySendError<T>) -> BusError {
+	match error {
+		TrySendError::Full(_) => {BusError::Full}
+		TrySendError::Closed(_) => {BusError::Disconnected}
+	}
+}
+
+#[derive(Clone, Copy, Default)]
 struct CacheLine {
-	///0bD000_0AAA - D = dirty, A = age
-	status: u8,
-	data: [u8; N_WAYS as usize]
+	valid: bool,
+	///Address of the line divided by the line size
+	block: usize,
+	dirty: bool,
+	///Cache::uses when the line was last accessed
+	used: u64
+}
+
+/**A line memory is sending to the cache a row at a time.*/
+#[derive(Clone)]
+struct Fill {
+	block: usize,
+	///Row of every memory way that's being read, or is read next
+	row: usize,
+	///Ways that haven't answered the row that was asked for, or None if it hasn't been asked for yet
+	awaiting: Option<u8>,
+	data: Vec<u8>,
+	///Offsets and values written while the line was on its way, which replace what memory sent
+	written: Vec<(usize, u8)>
 }
 
-impl CacheLine {
-	fn new(data: [u8; N_WAYS as usize]) -> Self {Self {status: 0x00, data}}
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		crate::hardware::{memory::Memory, imp::clock_listener::ClockListener},
+		tokio::sync::mpsc::channel
+	};
+	
+	///A cache and the memory ways it reads from, which start out holding pattern() of every address
+	struct Bus {
+		cache: Cache,
+		memory: [Memory; N_WAYS as usize]
+	}
+	
+	impl Bus {
+		fn new(config: CacheConfig) -> Self {
+			let mut cache_side: Vec<(Sender<MemEvent>, Receiver<MemEvent>)> = Vec::new();
+			let memory: [Memory; N_WAYS as usize] = std::array::from_fn(|_| {
+				let (cache_tx, memory_rx) = channel::<MemEvent>(1);
+				let (memory_tx, cache_rx) = channel::<MemEvent>(1);
+				cache_side.push((cache_tx, cache_rx));
+				Memory::new(memory_tx, memory_rx)
+			});
+			let mut cache: Cache = Cache::new(cache_side.try_into().unwrap());
+			cache.set_config(config);
+			let mut bus: Self = Self {cache, memory};
+			for address in 0..=u16::MAX {
+				bus.memory[(address % N_WAYS as u16) as usize].undo_write(address / N_WAYS as u16, pattern(address));
+			}
+			return bus;
+		}
+		///Reads like the CPU does, trying again every cycle, and returns the value and the cycles it took
+		fn read(&mut self, address: u16) -> (u8, usize) {
+			for cycles in 1..=100 {
+				if let Some(value) = self.cache.read(address).unwrap() {
+					return (value, cycles);
+				}
+				self.memory.iter_mut().for_each(|mem| {mem.pulse();});
+			}
+			panic!("Reading 0x{:04X} never finished", address);
+		}
+		fn ram(&self, address: u16) -> u8 {
+			self.memory[(address % N_WAYS as u16) as usize].peek(address / N_WAYS as u16)
+		}
+	}
+	
+	fn pattern(address: u16) -> u8 {
+		(address as u8).wrapping_mul(3) ^ (address >> 8) as u8
+	}
+	fn config(lines: usize, line_size: usize, mapping: Mapping) -> CacheConfig {
+		CacheConfig {lines, line_size, mapping, policy: Policy::Lru}
+	}
+	
+	#[test]
+	fn addresses_are_split_into_tag_set_and_offset() {
+		//0x1234 is block 0x246 of 8 byte lines, at offset 4
+		for (mapping, set, tag) in [(Mapping::Direct, 0x6, 0x24), (Mapping::SetAssociative(4), 0x2, 0x91), (Mapping::Full, 0x0, 0x246)] {
+			let mut bus: Bus = Bus::new(config(16, 8, mapping));
+			let (value, cycles) = bus.read(0x1234);
+			assert_eq!(value, pattern(0x1234));
+			let line: &LineInfo = &bus.cache.lines()[0];
+			assert_eq!((line.set, line.tag, line.address, line.dirty), (set, tag, 0x1230, false), "{}", mapping);
+			assert_eq!(line.data, (0x1230..0x1238).map(pattern).collect::<Vec<u8>>());
+			//every other byte of the line is a hit
+			assert_eq!(bus.read(0x1237), (pattern(0x1237), 1));
+			assert_eq!(bus.read(0x1230), (pattern(0x1230), 1));
+			//the miss is counted as an access every cycle it waited
+			assert_eq!((bus.cache.cache_hits, bus.cache.cache_misses, bus.cache.cache_accesses), (2, 1, cycles as u128 + 2));
+		}
+	}
+	
+	#[test]
+	fn only_the_lines_of_a_set_are_evicted() {
+		//0x0000, 0x0080, and 0x0100 are all in set 0 when there are 16 sets of 8 bytes
+		let mut bus: Bus = Bus::new(config(16, 8, Mapping::Direct));
+		bus.read(0x0000);
+		bus.read(0x0008);
+		bus.read(0x0080);
+		assert_eq!(bus.cache.cache_evictions, 1);
+		assert_eq!(bus.cache.peek(0x0000), None);
+		assert_eq!(bus.cache.peek(0x0008), Some(pattern(0x0008)));
+		//two lines fit in each set of a 2-way cache, with 8 sets
+		let mut bus: Bus = Bus::new(config(16, 8, Mapping::SetAssociative(2)));
+		for address in [0x0000, 0x0040, 0x0000, 0x0080] {
+			bus.read(address);
+		}
+		assert_eq!(bus.cache.cache_evictions, 1);
+		assert_eq!(bus.cache.peek(0x0040), None, "The line used least recently is evicted");
+		assert_eq!(bus.cache.peek(0x0000), Some(pattern(0x0000)));
+		//any line can hold any address when the cache is fully associative
+		let mut bus: Bus = Bus::new(config(16, 8, Mapping::Full));
+		for line in 0..16 {
+			bus.read(line * 0x0080);
+		}
+		assert_eq!(bus.cache.cache_evictions, 0);
+		bus.read(0x0800);
+		assert_eq!(bus.cache.cache_evictions, 1);
+		assert_eq!(bus.cache.peek(0x0000), None);
+	}
+	
+	#[test]
+	fn lines_longer_than_a_row_take_more_cycles() {
+		let mut cycles: Vec<usize> = Vec::new();
+		for line_size in [4, N_WAYS as usize, 32] {
+			let mut bus: Bus = Bus::new(config(16, line_size, Mapping::Full));
+			let (value, taken) = bus.read(0x1234);
+			assert_eq!(value, pattern(0x1234));
+			let start: u16 = 0x1234 & !(line_size as u16 - 1);
+			assert_eq!(bus.cache.lines()[0].data, (start..start + line_size as u16).map(pattern).collect::<Vec<u8>>());
+			assert_eq!(bus.cache.cache_misses, 1, "A line read over several cycles is one miss");
+			cycles.push(taken);
+		}
+		//a line shorter than a row is read in one go like a whole row, and a longer one takes a cycle for each row after the first
+		assert_eq!(cycles, [2, 2, 5]);
+	}
+	
+	#[test]
+	fn dirty_lines_are_written_back_when_evicted() {
+		for line_size in [4, N_WAYS as usize, 32] {
+			let mut bus: Bus = Bus::new(config(4, line_size, Mapping::Direct));
+			let conflict: u16 = 0x1000 + 0x0200;//the same set, since the cache covers at most 128 bytes
+			bus.read(0x1001);
+			assert!(bus.cache.write(0x1001, 0xAA).unwrap(), "The line is cached, so the write is a hit");
+			assert!(bus.cache.lines()[0].dirty);
+			assert_eq!(bus.ram(0x1001), pattern(0x1001), "Memory isn't written until the line is evicted");
+			bus.read(conflict);
+			assert_eq!(bus.cache.cache_evictions, 1);
+			assert_eq!(bus.cache.peek(0x1001), None);
+			//the line is read again once it's been written back
+			assert_eq!(bus.read(0x1001).0, 0xAA);
+			assert_eq!(bus.ram(0x1001), 0xAA);
+			for address in 0x1000..0x1000 + line_size as u16 {
+				if address != 0x1001 {
+					assert_eq!(bus.ram(address), pattern(address), "Line size {}", line_size);
+				}
+			}
+			assert!(!bus.cache.lines().iter().any(|line| {line.dirty}));
+		}
+	}
+	
+	#[test]
+	fn clean_lines_and_missed_writes_go_straight_to_memory() {
+		let mut bus: Bus = Bus::new(config(4, 8, Mapping::Direct));
+		assert!(!bus.cache.write(0x2000, 0x55).unwrap(), "A write that misses isn't cached");
+		assert_eq!(bus.cache.peek(0x2000), None);
+		assert_eq!(bus.read(0x2000).0, 0x55);
+		//a clean line is dropped without being written
+		bus.read(0x2000 + 0x0020);
+		assert_eq!(bus.cache.cache_evictions, 1);
+		assert_eq!((bus.ram(0x2000), bus.ram(0x2001)), (0x55, pattern(0x2001)));
+	}
+	
+	#[test]
+	fn validate() {
+		let errors = [
+			(config(0, 8, Mapping::Full), "The cache needs at least one line"),
+			(config(16, 6, Mapping::Full), "The cache line size must be a power of two, not 6"),
+			(config(16, 0, Mapping::Full), "The cache line size must be a power of two, not 0"),
+			(config(4096, 32, Mapping::Direct), "4096 lines of 32 bytes is bigger than memory"),
+			(config(10, 8, Mapping::SetAssociative(4)), "10 lines can't be split into sets of 4"),
+			(config(16, 8, Mapping::SetAssociative(0)), "16 lines can't be split into sets of 0"),
+			(CacheConfig {policy: Policy::TreePlru, ..config(12, 8, Mapping::SetAssociative(3))}, "Tree pseudo-LRU needs a power of two lines in each set, not 3")
+		];
+		for (config, error) in errors {
+			assert_eq!(config.validate(), Err(String::from(error)));
+		}
+		for config in [CacheConfig::default(), config(1, 0x10000, Mapping::Direct), config(2048, 32, Mapping::SetAssociative(8)), config(12, 8, Mapping::SetAssociative(3))] {
+			assert_eq!(config.validate(), Ok(()), "{}", config);
+		}
+	}
 }
\ No newline at end of file
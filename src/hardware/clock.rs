@@ -1,9 +1,13 @@
 use {
-	crate::hardware::{
-		hardware::{Hardware, HardwareSpecs},
-		imp::clock_listener::ClockListener,
-		cpu::Cpu,
-		memory::{Memory, MemEvent, N_WAYS}
+	crate::{
+		hardware::{
+			hardware::{Hardware, HardwareSpecs},
+			imp::clock_listener::ClockListener,
+			cpu::{Cpu, CpuModel},
+			memory::{Memory, MemEvent, N_WAYS},
+			history::{History, MemoryWrite}
+		},
+		log
 	},
 	tokio::sync::mpsc::{Sender, Receiver, channel}
 };
@@ -11,11 +15,13 @@ use {
 pub struct Clock {
 	pub specs: HardwareSpecs,
 	pub cpu: Cpu,
-	pub memory: [Memory; N_WAYS as usize]
+	pub memory: [Memory; N_WAYS as usize],
+	///Records every cycle once it's given a budget, so the clock can go back
+	pub history: History
 }
 
 impl Clock {
-	pub fn new() -> Self {
+	pub fn new(model: CpuModel) -> Self {
 		//I know it's MULTIPLE producer single consumer, but it's too late, and I'm lazy
 		const ARRAY_REPEAT_VALUE: Option<(Sender<MemEvent>, Receiver<MemEvent>)> = None;
 		let mut cpu_to_mem_channels: [Option<(Sender<MemEvent>, Receiver<MemEvent>)>; N_WAYS as usize] = [ARRAY_REPEAT_VALUE; N_WAYS as usize];
@@ -33,17 +39,27 @@ impl Clock {
 					Some(pair) => {pair}
 					None => {panic!("Compile time logical error");}
 				}
-			})),
+			}), model),
 			memory: mem_to_cpu_channels.map(|element| {
 				match element {
 					Some(pair) => {Memory::new(pair.0, pair.1)}
 					None => {panic!("Compile time logical error");}
 				}
-			})
+			}),
+			history: History::default()
 		};
 		clock.log("Created");
 		clock
 	}
+	
+	///Sets the bytes the history can use to go back. 0 turns it off.
+	pub fn set_history_budget(&mut self, budget: usize) {
+		self.history.set_budget(budget, &mut self.cpu, &mut self.memory);
+	}
+	///Goes back to the state before the last cycle, and returns the writes the CPU made in it. Returns None if the history is empty.
+	pub fn step_back(&mut self) -> Option<Vec<MemoryWrite>> {
+		self.history.step_back(&mut self.cpu, &mut self.memory)
+	}
 }
 
 impl Hardware for Clock {
@@ -53,6 +69,9 @@ impl Hardware for Clock {
 impl ClockListener for Clock {
 	///Whether a device is registered is determined by if its pulse function is called here.
 	fn pulse(&mut self) {
+		//stamps the records logged in this cycle. It's only a store, so it costs nothing while logging is off
+		log::set_cycle(self.cpu.cpu_clock_counter);
+		self.history.checkpoint(&mut self.cpu, &mut self.memory);
 		self.cpu.pulse();
 		self.memory.iter_mut().for_each(|mem| {mem.pulse();});
 	}
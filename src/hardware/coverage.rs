@@ -0,0 +1,136 @@
+use {
+	crate::hardware::{
+		cpu::{AddrMode, Mnemonic},
+		disassembler::Instruction
+	},
+	serde_json::{json, Value},
+	std::{
+		collections::HashMap,
+		fmt::Write
+	}
+};
+
+/**How many times the instruction at one address retired, and which way it went if it's a branch.*/
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Hits {
+	pub executions: u128,
+	///Times the branch went to its target
+	pub taken: u128,
+	///Times the branch fell through to the next instruction
+	pub not_taken: u128
+}
+
+/**What fraction of a program ran.*/
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Summary {
+	///Instructions in the program
+	pub instructions: usize,
+	///Instructions that retired at least once
+	pub executed: usize,
+	///Ways the branches could go: two for every conditional branch
+	pub branch_directions: usize,
+	///Ways the branches went at least once
+	pub covered_directions: usize
+}
+
+impl Summary {
+	pub fn instruction_percent(&self) -> f64 {
+		Self::percent(self.executed, self.instructions)
+	}
+	pub fn branch_percent(&self) -> f64 {
+		Self::percent(self.covered_directions, self.branch_directions)
+	}
+	fn percent(part: usize, whole: usize) -> f64 {
+		if whole == 0 {100.0} else {part as f64 * 100.0 / whole as f64}
+	}
+}
+
+/**Counts the instructions that retire from the execution units by the address of their opcode, and the ways each branch went.*/
+#[derive(Default)]
+pub struct Coverage {
+	hits: HashMap<u16, Hits>
+}
+
+impl Coverage {
+	///Mark for lines that never ran, like gcov's
+	const NEVER: &'static str = "#####";
+	
+	pub(super) fn retire(&mut self, address: u16) {
+		self.hits.entry(address).or_default().executions += 1;
+	}
+	pub(super) fn branch(&mut self, address: u16, taken: bool) {
+		let hits: &mut Hits = self.hits.entry(address).or_default();
+		if taken {
+			hits.taken += 1;
+		} else {
+			hits.not_taken += 1;
+		}
+	}
+	///What was counted for the instruction at the address
+	pub fn hits(&self, address: u16) -> Hits {
+		return self.hits.get(&address).copied().unwrap_or_default();
+	}
+	
+	///Whether the instruction is a branch that can go two ways. BRA always goes to its target.
+	fn is_branch(instruction: &Instruction) -> bool {
+		instruction.opcode.is_some_and(|opcode| {opcode.mode == AddrMode::Relative && opcode.mnemonic != Mnemonic::BRA})
+	}
+	/**Coverage of the code, which is the program's instructions in address order. Bytes that aren't valid code are skipped.*/
+	pub fn summary(&self, code: &[Instruction]) -> Summary {
+		let mut summary: Summary = Summary::default();
+		for instruction in code.iter().filter(|instruction| {instruction.is_valid()}) {
+			let hits: Hits = self.hits(instruction.address);
+			summary.instructions += 1;
+			if hits.executions > 0 {
+				summary.executed += 1;
+			}
+			if Self::is_branch(instruction) {
+				summary.branch_directions += 2;
+				summary.covered_directions += (hits.taken > 0) as usize + (hits.not_taken > 0) as usize;
+			}
+		}
+		return summary;
+	}
+	/**The code as a disassembly with the times each instruction retired in front of it, or ##### if it never did.
+	Branches are followed by the times they were taken and not taken. The totals are at the end.*/
+	pub fn report(&self, code: &[Instruction]) -> String {
+		let mut text: String = String::new();
+		for instruction in code {
+			if !instruction.is_valid() {
+				let _ = writeln!(text, "{:>10}  {}", "", instruction);
+				continue;
+			}
+			let hits: Hits = self.hits(instruction.address);
+			let count: String = if hits.executions == 0 {String::from(Self::NEVER)} else {hits.executions.to_string()};
+			let line: String = format!("{:>10}  {}", count, instruction);
+			if Self::is_branch(instruction) {
+				let mark = |times: u128| {if times == 0 {String::from(Self::NEVER)} else {times.to_string()}};
+				let _ = writeln!(text, "{:<42}taken: {}, not taken: {}", line, mark(hits.taken), mark(hits.not_taken));
+			} else {
+				let _ = writeln!(text, "{}", line);
+			}
+		}
+		let summary: Summary = self.summary(code);
+		let _ = writeln!(text, "\nInstructions executed: {} of {} ({:.1}%)", summary.executed, summary.instructions, summary.instruction_percent());
+		let _ = writeln!(text, "Branch directions taken: {} of {} ({:.1}%)", summary.covered_directions, summary.branch_directions, summary.branch_percent());
+		return text;
+	}
+	/**The report as JSON, with the totals and every instruction, so scripts can compare runs.*/
+	pub fn to_json(&self, code: &[Instruction]) -> Value {
+		let summary: Summary = self.summary(code);
+		let instructions: Vec<Value> = code.iter().filter(|instruction| {instruction.is_valid()}).map(|instruction| {
+			let hits: Hits = self.hits(instruction.address);
+			let mut entry: Value = json!({"address": instruction.address, "text": instruction.text, "executions": hits.executions as u64});
+			if Self::is_branch(instruction) {
+				entry["taken"] = json!(hits.taken as u64);
+				entry["notTaken"] = json!(hits.not_taken as u64);
+			}
+			entry
+		}).collect();
+		return json!({
+			"instructions": {"total": summary.instructions, "executed": summary.executed, "percent": summary.instruction_percent()},
+			"branches": {"directions": summary.branch_directions, "covered": summary.covered_directions, "percent": summary.branch_percent()},
+			"code": instructions
+		});
+	}
+}
\ No newline at end of file
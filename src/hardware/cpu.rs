@@ -1,24 +1,35 @@
 use {
 	crate::{
-		ascii::ascii,
 		hardware::{
 			hardware::{Hardware, HardwareSpecs},
 			interrupt_controller::InterruptController,
 			imp::clock_listener::ClockListener,
 			mmu::Mmu,
-			memory::{MemEvent, N_WAYS}
-		}
+			memory::{MemEvent, N_WAYS},
+			fault::{CpuFault, FaultKind},
+			syscall::{SyscallTable, SyscallContext, SyscallRequest},
+			watchpoint::WatchHit,
+			trace::{Trace, Registers},
+			profiler::Profiler,
+			coverage::Coverage,
+			disassembler,
+			imp::interrupt::InterruptSpecs
+		},
+		log::Level
 	},
 	tokio::sync::mpsc::{Sender, Receiver},
 	std::{
-		io::{self, Write},
-		cmp::PartialEq
+		cmp::PartialEq,
+		collections::BinaryHeap,
+		sync::atomic::Ordering
 	}
 };
 
 /**Just a bunch of match expressions.*/
 pub struct Cpu {
 	pub specs: HardwareSpecs,
+	///Decides which opcodes are decoded and how some instructions behave
+	pub model: CpuModel,
 	interrupt_controller: InterruptController,
 	pub mmu: Mmu,
 	pub cpu_clock_counter: u128,
@@ -27,12 +38,50 @@ pub struct Cpu {
 	pub pc: u16,
 	///Set to Some to let fetch and decode know if they need to run. Operands are set to Some to tell decode if the instruction is ready to be executed
 	ir: Option<(Opcode, Option<u8>, Option<u8>)>,
-	a: u8,
-	x: u8,
-	y: u8,
+	///Address of the opcode in the IR
+	ir_address: u16,
+	///The byte the opcode in the IR was decoded from, since undocumented opcodes can share an Opcode
+	ir_byte: u8,
+	///Holds a decoded instruction while its effective address is computed, between decode and the execution units
+	ar: Option<AddressRegister>,
+	///Address and opcode of the instruction sent to an execution unit in the last cycle
+	issued: Option<(u16, Opcode)>,
+	///Caller of the interrupt or BRK handler the CPU jumped to in the last cycle
+	entered: Option<u16>,
+	pub a: u8,
+	pub x: u8,
+	pub y: u8,
+	///Stack pointer. The stack lives in page $01 and grows downward, so the next push goes to $0100 + s.
+	pub s: u8,
 	pub nv_bdizc: u8,
+	///Reports when a push wraps the stack pointer from $00 to $FF or a pull wraps it from $FF to $00
+	pub stack_diagnostics: bool,
+	///BRK stops the system when true, like the programs written for this emulator expect. When false, BRK goes through the IRQ/BRK vector.
+	pub brk_halts: bool,
+	///Set to Some while the CPU is taking an interrupt, a BRK, or a reset
+	interrupt: Option<InterruptSequence>,
+	///Set by WAI. Nothing new goes down the pipeline until an interrupt is requested.
+	waiting: bool,
+	///Set when the CPU halts on something it can't run
+	pub fault: Option<CpuFault>,
+	///Host services called by SYS, selected by X
+	pub syscalls: SyscallTable,
 	execution_units: [ExecutionUnit; 2],
-	pipe_mem_user: PipeMemUser
+	pipe_mem_user: PipeMemUser,
+	///Watchpoints hit by the accesses of the CPU since they were last taken
+	watch_hits: Vec<WatchHit>,
+	///Writes a line for every instruction that retires when Some
+	pub trace: Option<Trace>,
+	///Counts what each instruction does when Some
+	pub profiler: Option<Profiler>,
+	///Counts the instructions that retire and the ways the branches go when Some
+	pub coverage: Option<Coverage>,
+	///Set when the instruction in the AR was held back this cycle by a hazard or busy execution units
+	ar_stalled: bool,
+	///Part of the CPU that used memory this cycle, for displays
+	bus_user: Option<PipeMemUser>,
+	///Address, value, and instruction address of every write since they were last taken, while the history is recording
+	writes: Option<Vec<(u16, u8, u16)>>
 }
 
 impl Hardware for Cpu {
@@ -42,15 +91,22 @@ impl Hardware for Cpu {
 impl ClockListener for Cpu {
 	/**Fetch, decode, execute, write back, and interrupt check all handled in this massive match expression.*/
 	fn pulse(&mut self) {
-		self.log(format!("CPU clock count: {}, PC: 0x{:04X}, IR: {}, Acc: 0x{:02X}, X: 0x{:02X}, Y: 0x{:02X}, Status: 0b{:08b}",
-		    self.cpu_clock_counter,
-			self.pc,
-			if let Some((opcode, _, _)) = self.ir.to_owned() {format!("{:?}", opcode)} else {String::from("Empty")},
-			self.a,
-			self.x,
-			self.y,
-			self.nv_bdizc
-		).as_str());
+		if self.fault.is_some() {return;}
+		self.issued = None;
+		self.entered = None;
+		self.ar_stalled = false;
+		self.bus_user = None;
+		self.log_at(Level::Trace, || {
+			format!("CPU clock count: {}, PC: 0x{:04X}, IR: {}, Acc: 0x{:02X}, X: 0x{:02X}, Y: 0x{:02X}, Status: 0b{:08b}",
+				self.cpu_clock_counter,
+				self.pc,
+				if let Some((opcode, _, _)) = self.ir.to_owned() {format!("{:?}", opcode.mnemonic)} else {String::from("Empty")},
+				self.a,
+				self.x,
+				self.y,
+				self.nv_bdizc
+			)
+		});
 		self.cpu_clock_counter += 1;
 		/*Fetch, Decode, Execute are called in reverse order to prioritize memory access to the first function to be called.
 		The pipeline is running like an assembly line. If the pipeline doesn't stall too much, it should be able to execute
@@ -58,103 +114,255 @@ impl ClockListener for Cpu {
 		for i in 0..self.execution_units.len() {
 			self.execute(i);
 		}
-		self.decode();
-		self.fetch_opcode();
+		//A fault leaves everything as it was when the faulting instruction ran
+		if self.fault.is_some() {return;}
+		//While an interrupt is being taken, nothing new goes down the pipeline
+		if self.interrupt.is_some() {
+			self.interrupt_sequence();
+		} else if !self.waiting {
+			self.address();
+			self.decode();
+			self.fetch_opcode();
+		}
 		//Allow memory access if nobody needs it in the next cycle
 		if self.pipe_mem_user == PipeMemUser::Complete {
 			self.pipe_mem_user = PipeMemUser::Free;
-			self.interrupt_check();//I put this here just so it does an interrupt check a couple of times per instruction cycle rather than every clock cycle
 		}
+		if self.interrupt.is_none() && self.fault.is_none() {
+			self.interrupt_check();
+		}
+		self.profile_cycle();
 	}
 }
 
 impl Cpu {
-	const NEGATIVE_FLAG: u8 = 0b1000_0000;
+	pub const NEGATIVE_FLAG: u8 = 0b1000_0000;
 	const OVERFLOW_FLAG: u8 = 0b0100_0000;
+	const UNUSED_FLAG: u8 = 0b0010_0000;
 	pub const BREAK_FLAG: u8 = 0b0001_0000;
+	const DECIMAL_FLAG: u8 = 0b0000_1000;
 	const INTERRUPT_FLAG: u8 = 0b0000_0100;
-	const ZERO_FLAG: u8 = 0b0000_0010;
-	const CARRY_FLAG: u8 = 0b0000_0001;
+	pub const ZERO_FLAG: u8 = 0b0000_0010;
+	pub const CARRY_FLAG: u8 = 0b0000_0001;
+	pub const STACK_PAGE: u16 = 0x0100;
+	pub const NMI_VECTOR: u16 = 0xFFFA;
+	pub const RESET_VECTOR: u16 = 0xFFFC;
+	pub const IRQ_VECTOR: u16 = 0xFFFE;
 	
-	pub fn new(channels: [(Sender<MemEvent>, Receiver<MemEvent>); N_WAYS as usize]) -> Self {
+	pub fn new(channels: [(Sender<MemEvent>, Receiver<MemEvent>); N_WAYS as usize], model: CpuModel) -> Self {
+		let interrupt_controller: InterruptController = InterruptController::new();
+		let syscalls: SyscallTable = SyscallTable::with_keyboard(interrupt_controller.key_buf.clone());
 		let cpu: Self = Self {
 			specs: HardwareSpecs::new("Cpu"),
-			interrupt_controller: InterruptController::new(),
+			model,
+			interrupt_controller,
 			mmu: Mmu::new(channels),
 			cpu_clock_counter: 0,
 			instruction_counter: 0,
 			pc: 0x0000,
 			ir: None,
+			ir_address: 0x0000,
+			ir_byte: 0x00,
+			ar: None,
+			issued: None,
+			entered: None,
 			a: 0x00,
 			x: 0x00,
 			y: 0x00,
+			s: 0x00,
 			nv_bdizc: 0b00100000,
+			stack_diagnostics: false,
+			brk_halts: true,
+			//the PC is loaded from the reset vector in the first cycles after power-on
+			interrupt: Some(InterruptSequence::new(InterruptKind::Reset, None)),
+			waiting: false,
+			fault: None,
+			syscalls,
 			execution_units: [ExecutionUnit::new(0), ExecutionUnit::new(1)],
-			pipe_mem_user: PipeMemUser::Free
+			pipe_mem_user: PipeMemUser::Free,
+			watch_hits: Vec::new(),
+			trace: None,
+			profiler: None,
+			coverage: None,
+			ar_stalled: false,
+			bus_user: None,
+			writes: None
 		};
-		cpu.log("Created");
+		cpu.log(format!("Created - Model: {:?}", model).as_str());
 		cpu
 	}
 	
-	fn sys_out_char(c: char) {
-		print!("{}", c);
-		io::stdout().flush().expect("Could not flush output buffer");//This prints each character one at a time immediately, rather than printing a buffer of many characters
+	///Sets the pipe_mem_user and returns the value from Cache::read(addr) once it's ready. Halts the CPU if the memory bus fails.
+	fn read(&mut self, addr: u16, user: PipeMemUser) -> Option<u8> {
+		self.pipe_mem_user = user.clone();
+		self.bus_user = Some(user.clone());
+		let misses: u128 = self.mmu.cache.cache_misses;
+		match self.mmu.cache.read(addr) {
+			Ok(value) => {
+				self.profile_miss(misses, &user);
+				self.take_watch_hits_of(user);
+				value
+			}
+			Err(error) => {
+				self.fault(FaultKind::Bus(error), self.ir_address, None);
+				None
+			}
+		}
 	}
-	
-	fn sys_out_u8(n: u8) {
-		print!("{:X}", n);
-		io::stdout().flush().expect("Could not flush output buffer");
+	///Sets the pipe_mem_user and calls Cache::write(addr, value). Halts the CPU if the memory bus fails.
+	fn write(&mut self, addr: u16, value: u8, user: PipeMemUser) {
+		self.pipe_mem_user = user.clone();
+		self.bus_user = Some(user.clone());
+		let misses: u128 = self.mmu.cache.cache_misses;
+		if let Err(error) = self.mmu.cache.write(addr, value) {
+			self.fault(FaultKind::Bus(error), self.ir_address, None);
+		} else if self.writes.is_some() {
+			let pc: u16 = self.address_of(&user);
+			self.writes.iter_mut().for_each(|writes| {writes.push((addr, value, pc));});
+		}
+		self.profile_miss(misses, &user);
+		self.take_watch_hits_of(user);
+	}
+	///Tells the profiler about a miss if the cache counted one since it had the given count
+	fn profile_miss(&mut self, misses: u128, user: &PipeMemUser) {
+		if self.profiler.is_none() || self.mmu.cache.cache_misses == misses {return;}
+		let address: u16 = self.address_of(user);
+		self.profiler.iter_mut().for_each(|profiler| {profiler.miss(address);});
+	}
+	///Tells the profiler what's in each stage at the end of the cycle, and whether the instruction in the AR was held back by a hazard
+	fn profile_cycle(&mut self) {
+		let oldest: u16 = self.current_address();
+		let Some(profiler) = &mut self.profiler else {return;};
+		profiler.cycle(
+			oldest,
+			self.ir.map(|_| {self.ir_address}),
+			self.ar.as_ref().map(|ar| {(ar.address, self.ar_stalled)}),
+			self.execution_units.iter().filter(|exe| {exe.busy}).map(|exe| {exe.address})
+		);
+	}
+	///Address of the instruction that's using memory, or the PC for fetches and interrupts
+	fn address_of(&self, user: &PipeMemUser) -> u16 {
+		match user {
+			PipeMemUser::Execute(i) => {self.execution_units[*i as usize].address}
+			PipeMemUser::Decode => {self.ir_address}
+			PipeMemUser::Address => {self.ar.as_ref().map_or(self.ir_address, |ar| {ar.address})}
+			_ => {self.pc}
+		}
+	}
+	///Adds the address of the instruction that made the last access and the cycle to the watchpoints it hit
+	fn take_watch_hits_of(&mut self, user: PipeMemUser) {
+		let pc: u16 = self.address_of(&user);
+		let hits: Vec<WatchHit> = self.mmu.cache.watchpoints.take_hits(pc, self.cpu_clock_counter);
+		self.watch_hits.extend(hits);
+	}
+	///Records the fault and halts the CPU. Registers and memory are left alone so they can be inspected.
+	fn fault(&mut self, kind: FaultKind, pc: u16, opcode: Option<u8>) {
+		let fault: CpuFault = CpuFault {kind, pc, opcode, cycle: self.cpu_clock_counter};
+		self.warn(format!("Halted on {}", fault).as_str());
+		self.fault = Some(fault);
+		self.clear_pipeline();
+	}
+	///Returns true once the CPU has stopped, because of BRK, STP, or a fault
+	pub fn halted(&self) -> bool {
+		self.fault.is_some() || self.nv_bdizc & Self::BREAK_FLAG == Self::BREAK_FLAG
 	}
 	
-	///Sets the pipe_mem_user and returns Cache::read(addr)
-	fn read(&mut self, addr: u16, user: PipeMemUser) -> Result<Option<u8>,()> {
-		self.pipe_mem_user = user;
-		self.mmu.cache.read(addr)
+	///Writes a value to the top of the stack through the cache and decrements the stack pointer. The address is the instruction doing it, for diagnostics.
+	fn push(&mut self, value: u8, user: PipeMemUser, address: u16) {
+		self.write(Self::STACK_PAGE | self.s as u16, value, user);
+		if self.s == 0x00 && self.stack_diagnostics {
+			self.warn(format!("Stack overflow: push at PC 0x{:04X} wrapped the stack pointer to 0xFF", address).as_str());
+		}
+		self.s = self.s.wrapping_sub(1);
 	}
-	///Sets the pipe_mem_user and returns Cache::write(addr, value)
-	fn write(&mut self, addr: u16, value: u8, user: PipeMemUser) -> bool {
-		self.pipe_mem_user = user;
-		self.mmu.cache.write(addr, value)
+	///Reads the value on top of the stack through the cache. The stack pointer is only incremented once the value is returned.
+	fn pull(&mut self, user: PipeMemUser, address: u16) -> Option<u8> {
+		let num = self.read(Self::STACK_PAGE | self.s.wrapping_add(1) as u16, user)?;
+		if self.s == 0xFF && self.stack_diagnostics {
+			self.warn(format!("Stack underflow: pull at PC 0x{:04X} wrapped the stack pointer to 0x00", address).as_str());
+		}
+		self.s = self.s.wrapping_add(1);
+		Some(num)
 	}
 	
 	//functions to set the status register bit flags
-	fn set_negative(&mut self, n: u8) {
-		if n & Self::NEGATIVE_FLAG == Self::NEGATIVE_FLAG {
-			self.nv_bdizc |= Self::NEGATIVE_FLAG;
+	fn set_flag(&mut self, flag: u8, value: bool) {
+		if value {
+			self.nv_bdizc |= flag;
 		} else {
-			self.nv_bdizc &= !Self::NEGATIVE_FLAG;
+			self.nv_bdizc &= !flag;
 		}
 	}
+	fn set_negative(&mut self, n: u8) {
+		self.set_flag(Self::NEGATIVE_FLAG, n & Self::NEGATIVE_FLAG == Self::NEGATIVE_FLAG);
+	}
+	fn set_overflow(&mut self, v: bool) {
+		self.set_flag(Self::OVERFLOW_FLAG, v);
+	}
 	fn set_zero(&mut self, n: u8) {
-		if n == 0 {
-			self.nv_bdizc |= Self::ZERO_FLAG;
-		} else {
-			self.nv_bdizc &= !Self::ZERO_FLAG;
-		}
+		self.set_flag(Self::ZERO_FLAG, n == 0);
 	}
 	fn set_carry(&mut self, c: bool) {
-		if c {
-			self.nv_bdizc |= Self::CARRY_FLAG;
-		} else {
-			self.nv_bdizc &= !Self::CARRY_FLAG;
-		}
+		self.set_flag(Self::CARRY_FLAG, c);
+	}
+	fn carry(&self) -> u8 {
+		self.nv_bdizc & Self::CARRY_FLAG
+	}
+	///Whether ADC and SBC work on decimal digits. The 2A03 has the D flag, but it doesn't do anything.
+	fn decimal(&self) -> bool {
+		self.nv_bdizc & Self::DECIMAL_FLAG != 0 && self.model.has_decimal_mode()
+	}
+	
+	/**Starts the reset sequence, which loads the PC from the reset vector.
+	The pipeline is cleared, the stack pointer is decremented by 3 like the real reset sequence, and interrupts are disabled.*/
+	pub fn reset(&mut self) {
+		self.clear_pipeline();
+		self.nv_bdizc &= !Self::BREAK_FLAG;
+		self.waiting = false;
+		self.fault = None;
+		self.interrupt = Some(InterruptSequence::new(InterruptKind::Reset, None));
 	}
 	
-	/**Clears all buffers if there's a BRK or branch to prevent executing more instructions.
-	There's no branch speculation. So the pipeline is cleared on a branch, and it continues as normal otherwise.*/
+	/**Clears all buffers if there's a BRK to prevent executing more instructions.*/
 	pub fn clear_pipeline(&mut self) {
-		self.ir = None;
+		self.flush();
 		self.execution_units.iter_mut().for_each(|exe| {exe.busy = false;});
+		if let Some(Err(error)) = self.trace.as_mut().map(|trace| {trace.discard()}) {
+			self.trace = None;
+			self.warn(format!("Could not write the trace, so it was stopped: {}", error).as_str());
+		}
+		self.pipe_mem_user = PipeMemUser::Free;
+	}
+	/**Discards the partially decoded instructions when a branch or jump changes the PC.
+	There's no branch speculation. So the pipeline is flushed on a taken branch, and it continues as normal otherwise.
+	Nothing younger than a control flow instruction is dispatched, so the other execution unit can only hold an older instruction, which must finish.*/
+	fn flush(&mut self) {
+		self.ir = None;
+		self.ar = None;
+		//the cache keeps track of the line that was requested, so fetch, decode, or address can give up the memory without waiting for the response
+		if matches!(self.pipe_mem_user, PipeMemUser::Fetch | PipeMemUser::Decode | PipeMemUser::Address | PipeMemUser::Interrupt) {
+			self.pipe_mem_user = PipeMemUser::Complete;
+		}
 	}
 	
 	///Loads the PC into the MAR, increments the pipeline_step, tells the MMU to request a read operation in memory, and increments the PC.
 	fn fetch_opcode(&mut self) {
-		if self.ir.is_some() {return;}
+		if self.ir.is_some() || self.store_pending() || self.fault.is_some() {return;}
 		match self.pipe_mem_user {
 			PipeMemUser::Fetch | PipeMemUser::Free => {
-				if let Ok(Some(num)) = self.read(self.pc, PipeMemUser::Fetch) {
-					let opcode: Opcode = Opcode::from(num).expect("Received invalid opcode.");
+				if let Some(num) = self.read(self.pc, PipeMemUser::Fetch) {
+					let Some(opcode) = Opcode::from(num, self.model) else {
+						//the byte might be data after a branch that hasn't been resolved yet, so try again next cycle
+						if self.control_pending() || self.in_flight() {
+							self.pipe_mem_user = PipeMemUser::Complete;
+							return;
+						}
+						self.fault(FaultKind::InvalidOpcode, self.pc, Some(num));
+						return;
+					};
 					self.ir = Some((opcode, None, None));
+					self.ir_address = self.pc;
+					self.ir_byte = num;
 					self.pc = self.pc.wrapping_add(1);
 					self.pipe_mem_user = PipeMemUser::Complete;
 				}
@@ -162,12 +370,145 @@ impl Cpu {
 			_ => {}
 		}
 	}
+	///Takes the watchpoints hit since the last call, in the order of the accesses
+	pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+		std::mem::take(&mut self.watch_hits)
+	}
+	///Address and opcode of the instruction sent to an execution unit in the last cycle. It's going to run, since nothing is issued past a branch that hasn't been resolved.
+	pub fn issued(&self) -> Option<(u16, Opcode)> {
+		self.issued
+	}
+	/**Returns the caller if an IRQ, NMI, or BRK handler was jumped to in the last cycle, so debuggers can add it to the call stack.
+	The caller is the BRK, or the instruction that was interrupted, which runs once the handler returns with RTI. Resets aren't included.*/
+	pub fn entered_handler(&self) -> Option<u16> {
+		self.entered
+	}
+	///Returns true while the CPU is between instructions, pushing its state or loading the PC for an interrupt, BRK, or reset
+	pub fn in_interrupt_sequence(&self) -> bool {
+		self.interrupt.is_some()
+	}
+	///Returns true if an instruction is in one of the execution units
+	pub fn executing(&self) -> bool {
+		self.execution_units.iter().any(|exe| {exe.busy})
+	}
+	/**Describes each stage of the pipeline for debuggers: the IR, the AR, then the execution units.
+	Each stage has the address and text of its instruction, or None if it's empty.*/
+	pub fn pipeline(&self) -> Vec<(String, Option<(u16, String)>)> {
+		let mut stages: Vec<(String, Option<(u16, String)>)> = Vec::new();
+		stages.push((String::from("IR"), self.ir.map(|(opcode, operand1, operand2)| {
+			let operands: Vec<u8> = [operand1, operand2].into_iter().flatten().collect();
+			let text: String = if opcode.mnemonic != Mnemonic::SYS && operands.len() >= opcode.mode.operand_len() as usize {
+				disassembler::instruction_text(opcode, &operands[..opcode.mode.operand_len() as usize], self.ir_address)
+			} else {
+				format!("{:?} (decoding operands)", opcode.mnemonic)
+			};
+			(self.ir_address, text)
+		})));
+		stages.push((String::from("AR"), self.ar.as_ref().map(|ar| {
+			let operand_len: usize = ar.ip.wrapping_sub(ar.address).wrapping_sub(1) as usize;
+			let mut text: String = disassembler::instruction_text(ar.opcode, &[ar.operand1, ar.operand2][..operand_len.min(2)], ar.address);
+			if let Some(ea) = ar.ea {
+				text.push_str(format!(" (EA: 0x{:04X})", ea).as_str());
+			}
+			(ar.address, text)
+		})));
+		for exe in &self.execution_units {
+			let operand_len: usize = exe.ip.wrapping_sub(exe.address).wrapping_sub(1) as usize;
+			let text: String = format!("{} (EA: 0x{:04X}, step {})", disassembler::instruction_text(exe.ir.0, &[exe.ir.1, exe.ir.2][..operand_len.min(2)], exe.address), exe.ea, exe.step);
+			stages.push((format!("Execution unit {}", exe.id), if exe.busy {Some((exe.address, text))} else {None}));
+		}
+		return stages;
+	}
+	///Part of the CPU that read or wrote memory in the last cycle, or Free if none did
+	pub fn memory_user(&self) -> String {
+		match &self.bus_user {
+			Some(PipeMemUser::Fetch) => {String::from("Fetch")}
+			Some(PipeMemUser::Decode) => {String::from("Decode")}
+			Some(PipeMemUser::Address) => {String::from("Address")}
+			Some(PipeMemUser::Execute(id)) => {format!("Execution unit {}", id)}
+			Some(PipeMemUser::Interrupt) => {String::from("Interrupt")}
+			Some(PipeMemUser::Complete | PipeMemUser::Free) | None => {String::from("Free")}
+		}
+	}
+	///Address of the instruction furthest down the pipeline, which is the one running now, or the PC if the pipeline is empty
+	pub fn current_address(&self) -> u16 {
+		if let Some(exe) = self.execution_units.iter().rev().find(|exe| {exe.busy}) {
+			return exe.address;
+		}
+		return self.ar.as_ref().map(|ar| {ar.address}).or(self.ir.map(|_| {self.ir_address})).unwrap_or(self.pc);
+	}
+	/**Copies everything the CPU keeps between cycles, except the cache, so the history can go back to it.
+	The syscall table, the keyboard, and the trace are left out, since they talk to the host.*/
+	pub fn state(&self) -> CpuState {
+		CpuState {
+			cpu_clock_counter: self.cpu_clock_counter,
+			instruction_counter: self.instruction_counter,
+			pc: self.pc,
+			ir: self.ir,
+			ir_address: self.ir_address,
+			ir_byte: self.ir_byte,
+			ar: self.ar.clone(),
+			issued: self.issued,
+			entered: self.entered,
+			registers: [self.a, self.x, self.y, self.s, self.nv_bdizc],
+			interrupt: self.interrupt.clone(),
+			waiting: self.waiting,
+			fault: self.fault,
+			execution_units: self.execution_units.clone(),
+			pipe_mem_user: self.pipe_mem_user.clone(),
+			priority_queue: self.interrupt_controller.priority_queue.clone(),
+			data_latch: self.interrupt_controller.data_latch
+		}
+	}
+	/**Puts the CPU back the way it was when the state was copied. The trace forgets the instructions in flight,
+	since the lines it already wrote can't be taken back.*/
+	pub fn restore(&mut self, state: CpuState) {
+		self.cpu_clock_counter = state.cpu_clock_counter;
+		self.instruction_counter = state.instruction_counter;
+		self.pc = state.pc;
+		self.ir = state.ir;
+		self.ir_address = state.ir_address;
+		self.ir_byte = state.ir_byte;
+		self.ar = state.ar;
+		self.issued = state.issued;
+		self.entered = state.entered;
+		[self.a, self.x, self.y, self.s, self.nv_bdizc] = state.registers;
+		self.interrupt = state.interrupt;
+		self.waiting = state.waiting;
+		self.fault = state.fault;
+		self.execution_units = state.execution_units;
+		self.pipe_mem_user = state.pipe_mem_user;
+		self.interrupt_controller.priority_queue = state.priority_queue;
+		self.interrupt_controller.data_latch = state.data_latch;
+		self.watch_hits.clear();
+		if let Some(Err(error)) = self.trace.as_mut().map(|trace| {trace.discard()}) {
+			self.trace = None;
+			self.warn(format!("Could not write the trace, so it was stopped: {}", error).as_str());
+		}
+	}
+	///Starts or stops keeping the writes for the history
+	pub fn record_writes(&mut self, recording: bool) {
+		self.writes = if recording {Some(Vec::new())} else {None};
+	}
+	///Takes the address, value, and instruction address of the writes since the last call
+	pub fn take_writes(&mut self) -> Vec<(u16, u8, u16)> {
+		self.writes.as_mut().map(std::mem::take).unwrap_or_default()
+	}
+	///Stops the keyboard from reading the console while something else, like a debugger prompt, is reading it
+	pub fn set_keyboard_listening(&self, listening: bool) {
+		self.interrupt_controller.keyboard_listening.store(listening, Ordering::Relaxed);
+	}
+	///Whether older instructions are still going through the pipeline. They're allowed to finish before a fault so the registers show everything up to the faulting instruction.
+	fn in_flight(&self) -> bool {
+		self.ar.is_some() || self.execution_units.iter().any(|exe| {exe.busy})
+	}
 	///Like fetch_opcode() but the value is returned instead of loaded into the IR
 	fn fetch_operand(&mut self) -> Option<u8> {
-		if self.ir.is_none() {return None;}
+		self.ir.as_ref()?;
+		if self.store_pending() {return None;}
 		match self.pipe_mem_user {
 			PipeMemUser::Decode | PipeMemUser::Free => {
-				if let Ok(Some(num)) = self.read(self.pc, PipeMemUser::Decode) {
+				if let Some(num) = self.read(self.pc, PipeMemUser::Decode) {
 					self.pc = self.pc.wrapping_add(1);
 					self.pipe_mem_user = PipeMemUser::Complete;
 					return Some(num);
@@ -177,286 +518,873 @@ impl Cpu {
 		}
 		None
 	}
-	///Decodes the value in the IR and loads it into an available execution unit if finished decoding
+	///Decodes the value in the IR and loads it into the AR if finished decoding
 	fn decode(&mut self) {
-		if self.ir.is_none() {return;}
+		if self.ir.is_none() || self.fault.is_some() {return;}
 		let Some((opcode, mut operand1, mut operand2)) = self.ir.to_owned() else {return;};
-		//see which storage areas will be affected in the next cycle
-		let mut affected_storages: Vec<Storage> = Vec::new();
-		self.execution_units.iter_mut().filter(|exe| {exe.busy}).for_each(|exe| {
-			affected_storages.append(&mut exe.ir.0.affected_storage());
-		});
+		//see which storage areas will be affected by the instructions that are ahead in the pipeline
+		let (mut affected_storages, _) = self.storages_in_use();
+		if let Some(ar) = &self.ar {
+			affected_storages.append(&mut ar.affected.clone());
+		}
 		//get operands
-		match opcode {
-			Opcode::TXA | Opcode::TYA | Opcode::TAX | Opcode::TAY | Opcode::NOP | Opcode::BRK => {
+		let operand_len: u8 = if opcode.mnemonic == Mnemonic::SYS {
+			//We can't decode a SYS if the execution units will affect the X register, since X picks the system call
+			if affected_storages.contains(&Storage::X) {return;}
+			match self.syscalls.get(self.x) {
+				Some(call) => {call.operands}
+				None => {
+					if !self.in_flight() {
+						self.fault(FaultKind::InvalidSyscall {x: self.x}, self.ir_address, Some(0xFF));
+					}
+					return;
+				}
+			}
+		} else {
+			opcode.mode.operand_len()
+		};
+		match operand_len {
+			0 => {
 				operand1 = Some(0x00);
 				operand2 = Some(0x00);
 			}
-			Opcode::LDAi | Opcode::LDXi | Opcode::LDYi | Opcode::BNEr => {
+			1 => {
 				if operand1.is_none() {
 					operand1 = self.fetch_operand();
 				}
 				operand2 = Some(0x00);
 			}
-			Opcode::LDAa | Opcode::STAa | Opcode::ADCa | Opcode::LDXa | Opcode::LDYa | Opcode::CPXa | Opcode::INCa => {
+			_ => {
 				if operand1.is_none() {
 					operand1 = self.fetch_operand();
 				} else if operand2.is_none() {
 					operand2 = self.fetch_operand();
 				}
 			}
-			Opcode::SYS => {
-				//We can't decode a SYS if the execution units will affect the X register
-				if !affected_storages.contains(&Storage::X) {
-					match self.x {
-						0x01 | 0x02 => {
-							operand1 = Some(0x00);
-							operand2 = Some(0x00);
-						}
-						0x03 => {
-							if operand1.is_none() {
-								operand1 = self.fetch_operand();
-							} else if operand2.is_none() {
-								operand2 = self.fetch_operand();
-							}
-						}
-						_ => {panic!("Invalid arguments for system call.");}
-					}
+		}
+		self.ir = Some((opcode, operand1, operand2));
+		if self.ar.is_some() {return;}
+		if let (Some(operand1), Some(operand2)) = (operand1, operand2) {
+			let mut ar: AddressRegister = AddressRegister::new(opcode, self.ir_byte, self.ir_address, self.pc, operand1, operand2);
+			if let (Mnemonic::SYS, Some(call)) = (opcode.mnemonic, self.syscalls.get(self.x)) {
+				//the hazards of a system call are whatever its handler declared
+				ar.syscall = Some(self.x);
+				ar.affected = call.affected.clone();
+				ar.dependent = call.dependent.clone();
+				if !ar.dependent.contains(&Storage::X) {
+					ar.dependent.push(Storage::X);
 				}
 			}
+			self.ar = Some(ar);
+			self.ir = None;
 		}
-		self.ir = Some((opcode.clone(), operand1, operand2));
-		//if the instruction is ready to be sent to an execution unit, and if the execution units are ready to take the instruction
-		if !opcode.dependent_storage().iter().any(|storage| {affected_storages.contains(storage)}) {
-			let Some(exe) = self.execution_units.iter_mut().find(|exe| {!exe.busy}) else {return;};
-			if let Some((opcode, Some(operand1), Some(operand2))) = self.ir.to_owned() {
-				exe.set_instruction(self.pc, (opcode, operand1, operand2));
-				self.ir = None;
+	}
+	/**Returns true if an instruction further down the pipeline may write to memory. Programs patch their own operands,
+	so the instruction stream can't be read until the write is done.*/
+	fn store_pending(&self) -> bool {
+		self.ar.as_ref().is_some_and(|ar| {ar.affected.contains(&Storage::Memory)})
+			|| self.execution_units.iter().any(|exe| {exe.busy && exe.affected.contains(&Storage::Memory)})
+	}
+	///Returns true if an instruction further down the pipeline may change the PC
+	fn control_pending(&self) -> bool {
+		self.ar.as_ref().is_some_and(|ar| {ar.affected.contains(&Storage::PC)})
+			|| self.execution_units.iter().any(|exe| {exe.busy && exe.affected.contains(&Storage::PC)})
+	}
+	///Returns all the storages that the busy execution units may affect, and all the storages they may depend on
+	fn storages_in_use(&self) -> (Vec<Storage>, Vec<Storage>) {
+		let mut affected_storages: Vec<Storage> = Vec::new();
+		let mut dependent_storages: Vec<Storage> = Vec::new();
+		self.execution_units.iter().filter(|exe| {exe.busy}).for_each(|exe| {
+			affected_storages.append(&mut exe.affected.clone());
+			dependent_storages.append(&mut exe.dependent.clone());
+		});
+		(affected_storages, dependent_storages)
+	}
+	///Like fetch_operand() but for the address stage. Reads a byte of a pointer.
+	fn fetch_pointer(&mut self, addr: u16) -> Option<u8> {
+		match self.pipe_mem_user {
+			PipeMemUser::Address | PipeMemUser::Free => {
+				if let Some(num) = self.read(addr, PipeMemUser::Address) {
+					self.pipe_mem_user = PipeMemUser::Complete;
+					return Some(num);
+				}
 			}
+			_ => {}
+		}
+		None
+	}
+	/**Computes the effective address of the instruction in the AR, then loads it into an available execution unit.
+	Indexed modes need the index register to be settled, and indirect modes read the pointer one byte per cycle.
+	Zero page addressing wraps around inside the zero page, and so does the pointer of (zp,X) and (zp),Y.*/
+	fn address(&mut self) {
+		if self.fault.is_some() {return;}
+		let Some(mut ar) = self.ar.to_owned() else {return;};
+		let (affected_storages, dependent_storages) = self.storages_in_use();
+		//Nothing is dispatched while the PC might still change, which means the execution units never hold an instruction that should not run.
+		if affected_storages.contains(&Storage::PC) {
+			self.ar_stalled = true;
+			return;
+		}
+		//the index registers and pointers can't be used until the instructions ahead are done writing them (RAW)
+		if ar.dependent.iter().any(|storage| {affected_storages.contains(storage)}) {
+			self.ar_stalled = true;
+			return;
+		}
+		if ar.ea.is_none() {
+			let operand: u16 = u16::from_le_bytes([ar.operand1, ar.operand2]);
+			match ar.opcode.mode {
+				AddrMode::Implied | AddrMode::Accumulator | AddrMode::Immediate | AddrMode::Relative | AddrMode::Absolute => {ar.ea = Some(operand);}
+				AddrMode::ZeroPage => {ar.ea = Some(ar.operand1 as u16);}
+				AddrMode::ZeroPageX => {ar.ea = Some(ar.operand1.wrapping_add(self.x) as u16);}
+				AddrMode::ZeroPageY => {ar.ea = Some(ar.operand1.wrapping_add(self.y) as u16);}
+				AddrMode::AbsoluteX => {ar.ea = Some(operand.wrapping_add(self.x as u16));}
+				AddrMode::AbsoluteY => {ar.ea = Some(operand.wrapping_add(self.y as u16));}
+				AddrMode::Indirect => {
+					//NMOS bug: the high byte of JMP ($xxFF) comes from $xx00 instead of crossing the page. The 65C02 fixed it.
+					let pointer_high: u16 = if self.model == CpuModel::Wdc65C02 {operand.wrapping_add(1)} else {(operand & 0xFF00) | (operand.wrapping_add(1) & 0x00FF)};
+					if ar.pointer_low.is_none() {
+						ar.pointer_low = self.fetch_pointer(operand);
+					} else if let Some(high) = self.fetch_pointer(pointer_high) {
+						ar.ea = Some(u16::from_le_bytes([ar.pointer_low.unwrap_or(0x00), high]));
+					}
+				}
+				AddrMode::IndexedIndirect => {
+					let pointer: u8 = ar.operand1.wrapping_add(self.x);
+					if ar.pointer_low.is_none() {
+						ar.pointer_low = self.fetch_pointer(pointer as u16);
+					} else if let Some(high) = self.fetch_pointer(pointer.wrapping_add(1) as u16) {
+						ar.ea = Some(u16::from_le_bytes([ar.pointer_low.unwrap_or(0x00), high]));
+					}
+				}
+				AddrMode::IndirectIndexed => {
+					if ar.pointer_low.is_none() {
+						ar.pointer_low = self.fetch_pointer(ar.operand1 as u16);
+					} else if let Some(high) = self.fetch_pointer(ar.operand1.wrapping_add(1) as u16) {
+						ar.ea = Some(u16::from_le_bytes([ar.pointer_low.unwrap_or(0x00), high]).wrapping_add(self.y as u16));
+					}
+				}
+				AddrMode::ZeroPageIndirect => {
+					if ar.pointer_low.is_none() {
+						ar.pointer_low = self.fetch_pointer(ar.operand1 as u16);
+					} else if let Some(high) = self.fetch_pointer(ar.operand1.wrapping_add(1) as u16) {
+						ar.ea = Some(u16::from_le_bytes([ar.pointer_low.unwrap_or(0x00), high]));
+					}
+				}
+				AddrMode::AbsoluteIndexedIndirect => {
+					let pointer: u16 = operand.wrapping_add(self.x as u16);
+					if ar.pointer_low.is_none() {
+						ar.pointer_low = self.fetch_pointer(pointer);
+					} else if let Some(high) = self.fetch_pointer(pointer.wrapping_add(1)) {
+						ar.ea = Some(u16::from_le_bytes([ar.pointer_low.unwrap_or(0x00), high]));
+					}
+				}
+			}
+		}
+		self.ar = Some(ar.clone());
+		let Some(ea) = ar.ea else {return;};
+		//if the instruction doesn't read anything that is still being written (RAW), and doesn't write anything that is still being read or written (WAR, WAW)
+		if ar.affected.iter().any(|storage| {affected_storages.contains(storage) || dependent_storages.contains(storage)}) {
+			self.ar_stalled = true;
+			return;
+		}
+		let Some(exe_index) = self.execution_units.iter().position(|exe| {!exe.busy}) else {
+			self.ar_stalled = true;
+			return;
+		};
+		let operand_len: usize = ar.ip.wrapping_sub(ar.address).wrapping_sub(1) as usize;
+		let operands: &[u8] = &[ar.operand1, ar.operand2][..operand_len.min(2)];
+		//the text is only built for what uses it, since this runs for every instruction
+		let describe = || {disassembler::instruction_text(ar.opcode, operands, ar.address)};
+		let text: Option<String> = (self.profiler.is_some() || self.trace.is_some()).then(describe);
+		self.log_at(Level::Debug, || {format!("Issued 0x{:04X}: {}", ar.address, text.clone().unwrap_or_else(describe))});
+		let registers: Registers = self.registers();
+		if let (Some(profiler), Some(text)) = (&mut self.profiler, &text) {
+			profiler.issue(ar.address, text);
+		}
+		if let (Some(trace), Some(text)) = (&mut self.trace, text) {
+			trace.issue(exe_index as u8, ar.address, [&[ar.byte][..], operands].concat(), text, self.cpu_clock_counter, registers);
+		}
+		self.issued = Some((ar.address, ar.opcode));
+		self.execution_units[exe_index].set_instruction(ar, ea);
+		self.ar = None;
+	}
+	///Marks the instruction in the execution unit as finished
+	fn retire(&mut self, exe_index: usize) {
+		self.trace_retire(exe_index);
+		if let Some(profiler) = &mut self.profiler {
+			profiler.retire(self.execution_units[exe_index].address);
+		}
+		if let Some(coverage) = &mut self.coverage {
+			coverage.retire(self.execution_units[exe_index].address);
+		}
+		self.execution_units[exe_index].busy = false;
+		self.instruction_counter += 1;
+	}
+	///Tells the trace the instruction in the execution unit finished. The trace is closed if the file can't be written.
+	fn trace_retire(&mut self, exe_index: usize) {
+		let registers: Registers = self.registers();
+		let Some(trace) = &mut self.trace else {return;};
+		if let Err(error) = trace.retire(exe_index as u8, registers, &self.execution_units[exe_index].affected) {
+			self.trace = None;
+			self.warn(format!("Could not write the trace, so it was stopped: {}", error).as_str());
+		}
+	}
+	///The registers as they are right now, for the trace
+	fn registers(&self) -> Registers {
+		Registers {a: self.a, x: self.x, y: self.y, p: self.nv_bdizc, s: self.s}
+	}
+	/**Writes the rest of the trace and closes the file.*/
+	pub fn finish_trace(&mut self) {
+		let Some(trace) = self.trace.take() else {return;};
+		if let Err(error) = trace.finish() {
+			self.warn(format!("Could not write the trace: {}", error).as_str());
 		}
 	}
 	///Executes the instruction in the execution unit at the given index of the exe_units array
 	fn execute(&mut self, exe_index: usize) {
 		if !self.execution_units[exe_index].busy {return;}
-		match self.execution_units[exe_index].ir.0 {
-			Opcode::LDAi => {
-				self.a = self.execution_units[exe_index].ir.1;
-				self.set_zero(self.a);
-				self.set_negative(self.a);
-				self.execution_units[exe_index].busy = false;
-				self.instruction_counter += 1;
+		let (opcode, operand1, _) = self.execution_units[exe_index].ir;
+		match opcode.mode {
+			_ if matches!(opcode.mnemonic, Mnemonic::PHA | Mnemonic::PHP | Mnemonic::PLA | Mnemonic::PLP | Mnemonic::JSR | Mnemonic::RTS | Mnemonic::RTI
+				| Mnemonic::PHX | Mnemonic::PHY | Mnemonic::PLX | Mnemonic::PLY) => {
+				self.execute_stack(exe_index);
+			}
+			AddrMode::Immediate if opcode.mnemonic == Mnemonic::BIT => {
+				//65C02 BIT #imm only sets Z, since N and V of an immediate value are already known
+				self.set_zero(self.a & operand1);
+				self.retire(exe_index);
+			}
+			AddrMode::Immediate => {
+				self.alu(opcode.mnemonic, operand1);
+				self.retire(exe_index);
+			}
+			AddrMode::Accumulator => {
+				self.a = self.shift(opcode.mnemonic, self.a);
+				self.retire(exe_index);
+			}
+			AddrMode::Relative => {
+				let taken: bool = match opcode.mnemonic {
+					Mnemonic::BPL => {self.nv_bdizc & Self::NEGATIVE_FLAG == 0}
+					Mnemonic::BMI => {self.nv_bdizc & Self::NEGATIVE_FLAG != 0}
+					Mnemonic::BVC => {self.nv_bdizc & Self::OVERFLOW_FLAG == 0}
+					Mnemonic::BVS => {self.nv_bdizc & Self::OVERFLOW_FLAG != 0}
+					Mnemonic::BCC => {self.nv_bdizc & Self::CARRY_FLAG == 0}
+					Mnemonic::BCS => {self.nv_bdizc & Self::CARRY_FLAG != 0}
+					Mnemonic::BNE => {self.nv_bdizc & Self::ZERO_FLAG == 0}
+					Mnemonic::BEQ => {self.nv_bdizc & Self::ZERO_FLAG != 0}
+					Mnemonic::BRA => {true}
+					_ => {unreachable!("Only branches use relative addressing.")}
+				};
+				if let Some(coverage) = &mut self.coverage {
+					coverage.branch(self.execution_units[exe_index].address, taken);
+				}
+				if taken {
+					self.pc = (self.execution_units[exe_index].ip as i16).wrapping_add(operand1 as i8 as i16) as u16;
+					self.flush();
+				}
+				self.retire(exe_index);
+			}
+			AddrMode::Implied => {
+				self.execute_implied(exe_index);
 			}
-			Opcode::TXA => {
+			_ => {
+				self.execute_memory(exe_index);
+			}
+		}
+	}
+	///Executes instructions that only use registers, except for SYS which might read memory
+	fn execute_implied(&mut self, exe_index: usize) {
+		match self.execution_units[exe_index].ir.0.mnemonic {
+			Mnemonic::TXA => {
 				self.a = self.x;
 				self.set_zero(self.a);
 				self.set_negative(self.a);
-				self.execution_units[exe_index].busy = false;
-				self.instruction_counter += 1;
 			}
-			Opcode::TYA => {
+			Mnemonic::TYA => {
 				self.a = self.y;
 				self.set_zero(self.a);
 				self.set_negative(self.a);
-				self.execution_units[exe_index].busy = false;
-				self.instruction_counter += 1;
 			}
-			Opcode::LDXi => {
-				self.x = self.execution_units[exe_index].ir.1;
+			Mnemonic::TAX => {
+				self.x = self.a;
 				self.set_zero(self.x);
 				self.set_negative(self.x);
-				self.execution_units[exe_index].busy = false;
-				self.instruction_counter += 1;
 			}
-			Opcode::TAX => {
-				self.x = self.a;
+			Mnemonic::TAY => {
+				self.y = self.a;
+				self.set_zero(self.y);
+				self.set_negative(self.y);
+			}
+			Mnemonic::TSX => {
+				self.x = self.s;
+				self.set_zero(self.x);
+				self.set_negative(self.x);
+			}
+			Mnemonic::TXS => {self.s = self.x;}
+			Mnemonic::INX => {
+				self.x = self.x.wrapping_add(1);
+				self.set_zero(self.x);
+				self.set_negative(self.x);
+			}
+			Mnemonic::DEX => {
+				self.x = self.x.wrapping_sub(1);
 				self.set_zero(self.x);
 				self.set_negative(self.x);
-				self.execution_units[exe_index].busy = false;
-				self.instruction_counter += 1;
 			}
-			Opcode::LDYi => {
-				self.y = self.execution_units[exe_index].ir.1;
+			Mnemonic::INY => {
+				self.y = self.y.wrapping_add(1);
 				self.set_zero(self.y);
 				self.set_negative(self.y);
-				self.execution_units[exe_index].busy = false;
-				self.instruction_counter += 1;
 			}
-			Opcode::TAY => {
-				self.y = self.a;
+			Mnemonic::DEY => {
+				self.y = self.y.wrapping_sub(1);
 				self.set_zero(self.y);
 				self.set_negative(self.y);
-				self.execution_units[exe_index].busy = false;
-				self.instruction_counter += 1;
-			}
-			Opcode::NOP => {
-				self.execution_units[exe_index].busy = false;
-				self.instruction_counter += 1;
 			}
-			Opcode::BRK => {
-				//I'm pretty sure the BRK actually takes 7 cycles because it messes with the stack
+			Mnemonic::CLC => {self.set_flag(Self::CARRY_FLAG, false);}
+			Mnemonic::SEC => {self.set_flag(Self::CARRY_FLAG, true);}
+			Mnemonic::CLI => {self.set_flag(Self::INTERRUPT_FLAG, false);}
+			Mnemonic::SEI => {self.set_flag(Self::INTERRUPT_FLAG, true);}
+			Mnemonic::CLV => {self.set_flag(Self::OVERFLOW_FLAG, false);}
+			Mnemonic::CLD => {self.set_flag(Self::DECIMAL_FLAG, false);}
+			Mnemonic::SED => {self.set_flag(Self::DECIMAL_FLAG, true);}
+			Mnemonic::NOP => {}
+			Mnemonic::BRK if self.brk_halts => {
 				self.nv_bdizc |= Self::BREAK_FLAG;
 				self.nv_bdizc |= Self::INTERRUPT_FLAG;//doesn't check for an interrupt at the end of this instruction cycle
+				//the trace forgets what's in the pipeline when it's cleared, so it's told about the BRK first
+				self.trace_retire(exe_index);
+				self.clear_pipeline();
+			}
+			Mnemonic::BRK => {
+				//the byte after BRK is padding, so RTI returns to the byte after that
+				let return_address: u16 = self.execution_units[exe_index].ip.wrapping_add(1);
+				self.interrupt = Some(InterruptSequence::new(InterruptKind::Brk, Some(return_address)));
+			}
+			Mnemonic::WAI => {
+				//resume at the next instruction once an interrupt is requested
+				self.pc = self.execution_units[exe_index].ip;
+				self.flush();
+				self.waiting = true;
+			}
+			Mnemonic::STP => {
+				//the clock stops until a reset, so the system halts like it does for BRK
+				self.nv_bdizc |= Self::BREAK_FLAG;
+				self.trace_retire(exe_index);
 				self.clear_pipeline();
-				self.instruction_counter += 1;
 			}
-			Opcode::BNEr => {
-				if self.nv_bdizc & Self::ZERO_FLAG == 0 {
-					self.pc = (self.execution_units[exe_index].ip as i16).wrapping_add(self.execution_units[exe_index].ir.1 as i8 as i16) as u16;
-					self.clear_pipeline();
+			Mnemonic::SYS => {
+				self.execute_sys(exe_index);
+				return;
+			}
+			mnemonic => {unreachable!("{:?} does not use implied addressing.", mnemonic)}
+		}
+		self.retire(exe_index);
+	}
+	/**Runs the handler of a system call. The handler is called again in the same cycle when the memory access it asked for is done,
+	so it can move one byte per cycle, like the other instructions.*/
+	fn execute_sys(&mut self, exe_index: usize) {
+		let Some((x, mut context)) = self.execution_units[exe_index].syscall.take() else {
+			unreachable!("SYS was dispatched without a system call.");
+		};
+		let Some((name, cycles)) = self.syscalls.get(x).map(|call| {(call.name.clone(), call.cycles)}) else {
+			//the handler was unregistered after the SYS was decoded
+			self.retire(exe_index);
+			return;
+		};
+		if self.execution_units[exe_index].step == 0 {
+			self.log_at(Level::Debug, || {format!("SYS {}: {}", x, name)});
+		}
+		//the handler isn't called until the cycles it takes have passed
+		if self.execution_units[exe_index].step < cycles - 1 {
+			self.execution_units[exe_index].step += 1;
+			self.execution_units[exe_index].syscall = Some((x, context));
+			return;
+		}
+		let user: PipeMemUser = PipeMemUser::Execute(self.execution_units[exe_index].id);
+		let mut accessed: bool = false;
+		loop {
+			if let Some(request) = context.request {
+				//only one memory access per cycle
+				if accessed || !(self.pipe_mem_user == PipeMemUser::Free || self.pipe_mem_user == user) {break;}
+				match request {
+					SyscallRequest::Read(addr) => {
+						let Some(num) = self.read(addr, user.clone()) else {break;};
+						context.set_data(num);
+					}
+					SyscallRequest::Write(addr, value) => {self.write(addr, value, user.clone());}
 				}
-				self.execution_units[exe_index].busy = false;
-				self.instruction_counter += 1;
-			}
-			Opcode::SYS if self.x == 1 => {
-				Self::sys_out_u8(self.y);
-				self.execution_units[exe_index].busy = false;
-				self.instruction_counter += 1;
-			}
-			_ => {//instructions that use memory
-				if self.pipe_mem_user == PipeMemUser::Free || matches!(self.pipe_mem_user, PipeMemUser::Execute(id) if id == self.execution_units[exe_index].id) {
-					match self.execution_units[exe_index].ir.0 {
-						Opcode::LDAa => {
-							if let Ok(Some(num)) = self.read(u16::from_le_bytes([self.execution_units[exe_index].ir.1, self.execution_units[exe_index].ir.2]), PipeMemUser::Execute(self.execution_units[exe_index].id)) {
-								self.a = num;
-								self.set_zero(self.a);
-								self.set_negative(self.a);
-								self.execution_units[exe_index].busy = false;
-								self.pipe_mem_user = PipeMemUser::Complete;
-								self.instruction_counter += 1;
-							}
-						}
-						Opcode::STAa => {
-							self.write(u16::from_le_bytes([self.execution_units[exe_index].ir.1, self.execution_units[exe_index].ir.2]), self.a, PipeMemUser::Execute(self.execution_units[exe_index].id));
-							self.execution_units[exe_index].busy = false;
-							self.pipe_mem_user = PipeMemUser::Complete;
-							self.instruction_counter += 1;
-						}
-						Opcode::ADCa => {
-							if let Ok(Some(num)) = self.read(u16::from_le_bytes([self.execution_units[exe_index].ir.1, self.execution_units[exe_index].ir.2]), PipeMemUser::Execute(self.execution_units[exe_index].id)) {
-								let (result, overflow) = self.a.overflowing_add(num);
-								self.set_zero(result);
-								self.set_negative(result);
-								self.set_carry(result <= self.a && num != 0);
-								if overflow {
-									self.nv_bdizc |= Self::OVERFLOW_FLAG;
-								} else {
-									self.nv_bdizc &= !Self::OVERFLOW_FLAG;
-								}
-								self.a = result;
-								self.execution_units[exe_index].busy = false;
-								self.pipe_mem_user = PipeMemUser::Complete;
-								self.instruction_counter += 1;
-							}
-						}
-						Opcode::LDXa => {
-							if let Ok(Some(num)) = self.read(u16::from_le_bytes([self.execution_units[exe_index].ir.1, self.execution_units[exe_index].ir.2]), PipeMemUser::Execute(self.execution_units[exe_index].id)) {
-								self.x = num;
-								self.set_zero(self.x);
-								self.set_negative(self.x);
-								self.execution_units[exe_index].busy = false;
-								self.pipe_mem_user = PipeMemUser::Complete;
-								self.instruction_counter += 1;
-							}
-						}
-						Opcode::LDYa => {
-							if let Ok(Some(num)) = self.read(u16::from_le_bytes([self.execution_units[exe_index].ir.1, self.execution_units[exe_index].ir.2]), PipeMemUser::Execute(self.execution_units[exe_index].id)) {
-								self.y = num;
-								self.set_zero(self.y);
-								self.set_negative(self.y);
-								self.execution_units[exe_index].busy = false;
-								self.pipe_mem_user = PipeMemUser::Complete;
-								self.instruction_counter += 1;
-							}
-						}
-						Opcode::CPXa => {
-							if let Ok(Some(num)) = self.read(u16::from_le_bytes([self.execution_units[exe_index].ir.1, self.execution_units[exe_index].ir.2]), PipeMemUser::Execute(self.execution_units[exe_index].id)) {
-								let difference: u8 = self.x.wrapping_sub(num);
-								self.set_zero(difference);
-								self.set_negative(difference);
-								self.set_carry(self.x >= num);
-								self.execution_units[exe_index].busy = false;
-								self.pipe_mem_user = PipeMemUser::Complete;
-								self.instruction_counter += 1;
-							}
-						}
-						Opcode::INCa => {
-							let addr: u16 = u16::from_le_bytes([self.execution_units[exe_index].ir.1, self.execution_units[exe_index].ir.2]);
-							if let Ok(Some(mut num)) = self.read(addr, PipeMemUser::Execute(self.execution_units[exe_index].id)) {
-								num = num.wrapping_add(1);
-								self.set_zero(num);
-								self.set_negative(num);
-								self.write(addr, num, PipeMemUser::Execute(self.execution_units[exe_index].id));
-								self.execution_units[exe_index].busy = false;
-								self.pipe_mem_user = PipeMemUser::Complete;
-								self.instruction_counter += 1;
-							}
-						}
-						Opcode::SYS if self.x != 1 => {
-							match self.x {
-								0x02 => {
-									let addr: u16 = u16::from_le_bytes([self.execution_units[exe_index].ir.1, self.execution_units[exe_index].ir.2]).wrapping_add(self.y as u16);
-									if let Ok(Some(num)) = self.read(addr, PipeMemUser::Execute(self.execution_units[exe_index].id)) {
-										Self::sys_out_char(ascii::ENCODER.get(&num).unwrap_or(&'\0').clone());
-										self.execution_units[exe_index].busy = false;
-										self.pipe_mem_user = PipeMemUser::Complete;
-										self.instruction_counter += 1;
-									}
-								}
-								0x03 => {
-									if let Ok(Some(num)) = self.read(u16::from_le_bytes([self.execution_units[exe_index].ir.1, self.execution_units[exe_index].ir.2]), PipeMemUser::Execute(self.execution_units[exe_index].id)) {
-										let c: char = ascii::ENCODER.get(&num).unwrap_or(&'\0').clone();
-										if c != '\0' {
-											Self::sys_out_char(c);
-											let (result, overflow) = self.execution_units[exe_index].ir.1.overflowing_add(1);
-											self.execution_units[exe_index].ir.1 = result;
-											if overflow {
-												self.execution_units[exe_index].ir.2 = self.execution_units[exe_index].ir.2.wrapping_add(1);
-											}
-										} else {
-											self.execution_units[exe_index].busy = false;
-											self.pipe_mem_user = PipeMemUser::Complete;
-											self.instruction_counter += 1;
-										}
-									}
-								}
-								_ => {panic!("Invalid arguments for system call")}
-							}
-						}
-						_ => {}
+				self.pipe_mem_user = PipeMemUser::Complete;
+				context.request = None;
+				accessed = true;
+			}
+			context.a = self.a;
+			context.x = self.x;
+			context.y = self.y;
+			context.nv_bdizc = self.nv_bdizc;
+			context.data_latch = self.interrupt_controller.data_latch;
+			let finished: bool = self.syscalls.call(x, &mut context);
+			context.calls = context.calls.wrapping_add(1);
+			self.a = context.a;
+			self.x = context.x;
+			self.y = context.y;
+			self.nv_bdizc = context.nv_bdizc;
+			if finished {
+				self.retire(exe_index);
+				return;
+			}
+			//the handler is waiting on something other than memory, so try again next cycle
+			if context.request.is_none() {break;}
+		}
+		self.execution_units[exe_index].syscall = Some((x, context));
+	}
+	///Executes instructions that push to or pull from the stack. Only one byte is moved per cycle.
+	fn execute_stack(&mut self, exe_index: usize) {
+		if !(self.pipe_mem_user == PipeMemUser::Free || matches!(self.pipe_mem_user, PipeMemUser::Execute(id) if id == self.execution_units[exe_index].id)) {return;}
+		let user: PipeMemUser = PipeMemUser::Execute(self.execution_units[exe_index].id);
+		let step: u8 = self.execution_units[exe_index].step;
+		let address: u16 = self.execution_units[exe_index].address;
+		match self.execution_units[exe_index].ir.0.mnemonic {
+			Mnemonic::PHA => {self.push(self.a, user, address);}
+			Mnemonic::PHX => {self.push(self.x, user, address);}
+			Mnemonic::PHY => {self.push(self.y, user, address);}
+			Mnemonic::PHP => {self.push(self.nv_bdizc | Self::BREAK_FLAG | Self::UNUSED_FLAG, user, address);}
+			Mnemonic::PLA => {
+				let Some(num) = self.pull(user, address) else {return;};
+				self.a = num;
+				self.set_zero(self.a);
+				self.set_negative(self.a);
+			}
+			Mnemonic::PLP => {
+				let Some(num) = self.pull(user, address) else {return;};
+				self.set_status(num);
+			}
+			Mnemonic::PLX => {
+				let Some(num) = self.pull(user, address) else {return;};
+				self.x = num;
+				self.set_zero(self.x);
+				self.set_negative(self.x);
+			}
+			Mnemonic::PLY => {
+				let Some(num) = self.pull(user, address) else {return;};
+				self.y = num;
+				self.set_zero(self.y);
+				self.set_negative(self.y);
+			}
+			Mnemonic::JSR => {
+				//pushes the address of the last byte of the JSR, high byte first
+				let [low, high] = self.execution_units[exe_index].ip.wrapping_sub(1).to_le_bytes();
+				if step == 0 {
+					self.push(high, user, address);
+					self.next_step(exe_index);
+					return;
+				}
+				self.push(low, user, address);
+				self.pc = self.execution_units[exe_index].ea;
+				self.flush();
+			}
+			Mnemonic::RTS => {
+				let Some(num) = self.pull(user, address) else {return;};
+				if step == 0 {
+					self.execution_units[exe_index].buffer = num;
+					self.next_step(exe_index);
+					return;
+				}
+				self.pc = u16::from_le_bytes([self.execution_units[exe_index].buffer, num]).wrapping_add(1);
+				self.flush();
+			}
+			Mnemonic::RTI => {
+				let Some(num) = self.pull(user, address) else {return;};
+				match step {
+					0 => {
+						self.set_status(num);
+						self.next_step(exe_index);
+						return;
+					}
+					1 => {
+						self.execution_units[exe_index].buffer = num;
+						self.next_step(exe_index);
+						return;
+					}
+					_ => {
+						self.pc = u16::from_le_bytes([self.execution_units[exe_index].buffer, num]);
+						self.flush();
 					}
 				}
 			}
+			mnemonic => {unreachable!("{:?} does not use the stack.", mnemonic)}
+		}
+		self.pipe_mem_user = PipeMemUser::Complete;
+		self.retire(exe_index);
+	}
+	///Lets a multi-cycle instruction give up the memory until the next cycle
+	fn next_step(&mut self, exe_index: usize) {
+		self.execution_units[exe_index].step += 1;
+		self.pipe_mem_user = PipeMemUser::Complete;
+	}
+	///Loads a status byte pulled from the stack. The break flag is only meaningful in the pushed copy, and bit 5 is always set.
+	fn set_status(&mut self, num: u8) {
+		self.nv_bdizc = (num & !Self::BREAK_FLAG) | Self::UNUSED_FLAG;
+	}
+	///Executes instructions that use an operand in memory
+	fn execute_memory(&mut self, exe_index: usize) {
+		let opcode: Opcode = self.execution_units[exe_index].ir.0;
+		let addr: u16 = self.execution_units[exe_index].ea;
+		if opcode.mnemonic == Mnemonic::JMP {
+			//the effective address is the destination, so there's nothing to read
+			self.pc = addr;
+			self.flush();
+			self.retire(exe_index);
+			return;
+		}
+		if !(self.pipe_mem_user == PipeMemUser::Free || matches!(self.pipe_mem_user, PipeMemUser::Execute(id) if id == self.execution_units[exe_index].id)) {return;}
+		let user: PipeMemUser = PipeMemUser::Execute(self.execution_units[exe_index].id);
+		match opcode.mnemonic {
+			Mnemonic::STA => {self.write(addr, self.a, user);}
+			Mnemonic::STX => {self.write(addr, self.x, user);}
+			Mnemonic::STY => {self.write(addr, self.y, user);}
+			Mnemonic::STZ => {self.write(addr, 0x00, user);}
+			Mnemonic::SAX => {self.write(addr, self.a & self.x, user);}
+			Mnemonic::ASL | Mnemonic::LSR | Mnemonic::ROL | Mnemonic::ROR | Mnemonic::INC | Mnemonic::DEC => {
+				let Some(num) = self.read(addr, user.clone()) else {return;};
+				let result: u8 = self.shift(opcode.mnemonic, num);
+				self.write(addr, result, user);
+			}
+			Mnemonic::TSB | Mnemonic::TRB => {
+				let Some(num) = self.read(addr, user.clone()) else {return;};
+				self.set_zero(self.a & num);
+				let result: u8 = if opcode.mnemonic == Mnemonic::TSB {num | self.a} else {num & !self.a};
+				self.write(addr, result, user);
+			}
+			mnemonic if mnemonic.combined().is_some() => {
+				//undocumented read-modify-write instructions that feed the result to an ALU instruction
+				let Some(num) = self.read(addr, user.clone()) else {return;};
+				let Some((modify, combine)) = mnemonic.combined() else {return;};
+				let result: u8 = self.shift(modify, num);
+				self.write(addr, result, user);
+				self.alu(combine, result);
+			}
+			mnemonic => {
+				let Some(num) = self.read(addr, user) else {return;};
+				self.alu(mnemonic, num);
+			}
+		}
+		self.pipe_mem_user = PipeMemUser::Complete;
+		self.retire(exe_index);
+	}
+	///Instructions that combine a value with a register
+	fn alu(&mut self, mnemonic: Mnemonic, num: u8) {
+		match mnemonic {
+			Mnemonic::LDA => {
+				self.a = num;
+				self.set_zero(self.a);
+				self.set_negative(self.a);
+			}
+			Mnemonic::LDX => {
+				self.x = num;
+				self.set_zero(self.x);
+				self.set_negative(self.x);
+			}
+			Mnemonic::LDY => {
+				self.y = num;
+				self.set_zero(self.y);
+				self.set_negative(self.y);
+			}
+			Mnemonic::LAX => {
+				self.a = num;
+				self.x = num;
+				self.set_zero(self.a);
+				self.set_negative(self.a);
+			}
+			Mnemonic::ADC if self.decimal() => {self.add_decimal(num);}
+			Mnemonic::ADC => {self.add_binary(num);}
+			Mnemonic::SBC if self.decimal() => {self.subtract_decimal(num);}
+			Mnemonic::SBC => {
+				//A - M - (1 - C) is the same as A + !M + C
+				self.add_binary(!num);
+			}
+			Mnemonic::AND => {
+				self.a &= num;
+				self.set_zero(self.a);
+				self.set_negative(self.a);
+			}
+			Mnemonic::ORA => {
+				self.a |= num;
+				self.set_zero(self.a);
+				self.set_negative(self.a);
+			}
+			Mnemonic::EOR => {
+				self.a ^= num;
+				self.set_zero(self.a);
+				self.set_negative(self.a);
+			}
+			Mnemonic::CMP => {self.compare(self.a, num);}
+			Mnemonic::CPX => {self.compare(self.x, num);}
+			Mnemonic::CPY => {self.compare(self.y, num);}
+			Mnemonic::BIT => {
+				self.set_zero(self.a & num);
+				self.set_negative(num);
+				self.set_overflow(num & Self::OVERFLOW_FLAG != 0);
+			}
+			Mnemonic::ANC => {
+				self.a &= num;
+				self.set_zero(self.a);
+				self.set_negative(self.a);
+				self.set_carry(self.a & 0x80 != 0);
+			}
+			Mnemonic::ALR => {
+				self.a = self.shift(Mnemonic::LSR, self.a & num);
+			}
+			Mnemonic::ARR => {self.and_rotate(num);}
+			Mnemonic::SBX => {
+				//like CMP, the borrow isn't used
+				let value: u8 = self.a & self.x;
+				self.compare(value, num);
+				self.x = value.wrapping_sub(num);
+			}
+			Mnemonic::NOP => {}//undocumented NOPs still read their operand
+			mnemonic => {unreachable!("{:?} does not read an operand.", mnemonic)}
 		}
 	}
-	///Immediately prints the ASCII representation of the byte in the device's output buffer
+	///A + M + C
+	fn add_binary(&mut self, num: u8) {
+		let sum: u16 = self.a as u16 + num as u16 + self.carry() as u16;
+		let result: u8 = sum as u8;
+		//signed overflow happens when both inputs have the same sign and the result has the other sign
+		self.set_overflow((self.a ^ result) & (num ^ result) & 0x80 != 0);
+		self.set_carry(sum > 0xFF);
+		self.set_zero(result);
+		self.set_negative(result);
+		self.a = result;
+	}
+	/**A + M + C where each nibble is a decimal digit. Like the NMOS 6502, Z comes from the binary sum,
+	and N and V come from the intermediate result before the high digit is adjusted.
+	Invalid BCD inputs give the same results as the NMOS 6502.*/
+	fn add_decimal(&mut self, num: u8) {
+		let binary: u8 = self.a.wrapping_add(num).wrapping_add(self.carry());
+		let mut low: u16 = (self.a & 0x0F) as u16 + (num & 0x0F) as u16 + self.carry() as u16;
+		if low >= 0x0A {
+			low = ((low + 0x06) & 0x0F) + 0x10;
+		}
+		let mut sum: u16 = (self.a & 0xF0) as u16 + (num & 0xF0) as u16 + low;
+		let signed: i16 = (self.a & 0xF0) as i8 as i16 + (num & 0xF0) as i8 as i16 + low as i16;
+		self.set_negative(sum as u8);
+		self.set_overflow(!(-128..=127).contains(&signed));
+		if sum >= 0xA0 {
+			sum += 0x60;
+		}
+		self.set_carry(sum > 0xFF);
+		self.set_zero(binary);
+		self.a = sum as u8;
+		if self.model == CpuModel::Wdc65C02 {
+			//the 65C02 takes an extra cycle to get N and Z from the decimal result
+			self.set_zero(self.a);
+			self.set_negative(self.a);
+		}
+	}
+	/**A - M - (1 - C) where each nibble is a decimal digit. Like the NMOS 6502, N, V, Z, and C are the same as a binary subtraction.
+	Invalid BCD inputs give the same results as the NMOS 6502. The 65C02 adjusts the digits in a different order, and gets N and Z from the decimal result.*/
+	fn subtract_decimal(&mut self, num: u8) {
+		if self.model == CpuModel::Wdc65C02 {
+			let low: i16 = (self.a & 0x0F) as i16 - (num & 0x0F) as i16 + self.carry() as i16 - 1;
+			let mut difference: i16 = self.a as i16 - num as i16 + self.carry() as i16 - 1;
+			if difference < 0 {
+				difference -= 0x60;
+			}
+			if low < 0 {
+				difference -= 0x06;
+			}
+			self.add_binary(!num);
+			self.a = difference as u8;
+			self.set_zero(self.a);
+			self.set_negative(self.a);
+			return;
+		}
+		let mut low: i16 = (self.a & 0x0F) as i16 - (num & 0x0F) as i16 + self.carry() as i16 - 1;
+		if low < 0 {
+			low = ((low - 0x06) & 0x0F) - 0x10;
+		}
+		let mut difference: i16 = (self.a & 0xF0) as i16 - (num & 0xF0) as i16 + low;
+		if difference < 0 {
+			difference -= 0x60;
+		}
+		self.add_binary(!num);
+		self.a = difference as u8;
+	}
+	/**Undocumented ARR: AND, then ROR the accumulator. C comes from bit 6 of the result, and V from bit 6 XOR bit 5.
+	In decimal mode, the NMOS 6502 gets V from the bit that changed while shifting, and fixes up each digit of the result.*/
+	fn and_rotate(&mut self, num: u8) {
+		let value: u8 = self.a & num;
+		let mut result: u8 = (value >> 1) | (self.carry() << 7);
+		self.set_zero(result);
+		self.set_negative(result);
+		if self.decimal() {
+			self.set_overflow((result ^ value) & 0x40 != 0);
+			if (value & 0x0F) + (value & 0x01) > 0x05 {
+				result = (result & 0xF0) | (result.wrapping_add(0x06) & 0x0F);
+			}
+			let carry: bool = (value & 0xF0) as u16 + (value & 0x10) as u16 > 0x50;
+			if carry {
+				result = (result & 0x0F) | (result.wrapping_add(0x60) & 0xF0);
+			}
+			self.set_carry(carry);
+		} else {
+			self.set_carry(result & 0x40 != 0);
+			self.set_overflow(((result >> 6) ^ (result >> 5)) & 0x01 != 0);
+		}
+		self.a = result;
+	}
+	fn compare(&mut self, register: u8, num: u8) {
+		let difference: u8 = register.wrapping_sub(num);
+		self.set_zero(difference);
+		self.set_negative(difference);
+		self.set_carry(register >= num);
+	}
+	///Read-modify-write instructions, which work on either the accumulator or memory. Returns the modified value.
+	fn shift(&mut self, mnemonic: Mnemonic, num: u8) -> u8 {
+		let result: u8 = match mnemonic {
+			Mnemonic::ASL => {
+				self.set_carry(num & 0x80 != 0);
+				num << 1
+			}
+			Mnemonic::LSR => {
+				self.set_carry(num & 0x01 != 0);
+				num >> 1
+			}
+			Mnemonic::ROL => {
+				let result: u8 = (num << 1) | self.carry();
+				self.set_carry(num & 0x80 != 0);
+				result
+			}
+			Mnemonic::ROR => {
+				let result: u8 = (num >> 1) | (self.carry() << 7);
+				self.set_carry(num & 0x01 != 0);
+				result
+			}
+			Mnemonic::INC => {num.wrapping_add(1)}
+			Mnemonic::DEC => {num.wrapping_sub(1)}
+			mnemonic => {unreachable!("{:?} is not a read-modify-write instruction.", mnemonic)}
+		};
+		self.set_zero(result);
+		self.set_negative(result);
+		result
+	}
+	/**Gathers the interrupts sent by I/O devices. If the interrupt flag is clear, or if the device raises an NMI,
+	the interrupt with the highest priority starts the interrupt sequence.*/
 	fn interrupt_check(&mut self) {
-		if self.nv_bdizc & Self::INTERRUPT_FLAG == 0 {
-			let mut recv: bool = true;
-			while recv {
-				match self.interrupt_controller.io_rx.try_recv() {
-					Ok(specs) => {self.interrupt_controller.priority_queue.push(specs);}
-					Err(_) => {recv = false;}
+		while let Ok(specs) = self.interrupt_controller.io_rx.try_recv() {
+			self.interrupt_controller.priority_queue.push(specs);
+		}
+		let Some(event) = self.interrupt_controller.priority_queue.peek() else {return;};
+		//WAI resumes on any interrupt request, even if the interrupt flag keeps the IRQ from being taken
+		self.waiting = false;
+		if !event.nmi && self.nv_bdizc & Self::INTERRUPT_FLAG != 0 {return;}//IRQs wait until the program clears the interrupt flag
+		let Some(event) = self.interrupt_controller.priority_queue.pop() else {return;};
+		if !self.interrupt_controller.io_devices.contains_key(&event.iqr) {
+			self.warn(format!("Could not find I/O device Name: {}, IQR: {}", event.name, event.iqr).as_str());
+			self.fault(FaultKind::UnknownDevice {iqr: event.iqr}, self.current_address(), None);
+			return;
+		}
+		//the handler gets the device's byte with SYS 4
+		self.interrupt_controller.data_latch = self.interrupt_controller.io_devices[&event.iqr].get_out_buf();
+		let kind: InterruptKind = if event.nmi {InterruptKind::Nmi} else {InterruptKind::Irq};
+		self.interrupt = Some(InterruptSequence::new(kind, None));
+	}
+	/**Takes the interrupt in self.interrupt at an instruction boundary. The instructions in the execution units are allowed to finish,
+	then the rest of the pipeline is flushed, and the interrupted instruction becomes the return address.
+	PC and status are pushed, interrupts are disabled, and the PC is loaded from the vector, one memory access per cycle.
+	A reset doesn't write to the stack, it only moves the stack pointer down 3 bytes.*/
+	fn interrupt_sequence(&mut self) {
+		if self.execution_units.iter().any(|exe| {exe.busy}) {return;}
+		let Some(mut sequence) = self.interrupt.to_owned() else {return;};
+		if sequence.return_address.is_none() {
+			//the oldest instruction that hasn't been dispatched yet is the next one to run after the interrupt
+			sequence.return_address = Some(if let Some(ar) = &self.ar {ar.address} else if self.ir.is_some() {self.ir_address} else {self.pc});
+		}
+		if self.pipe_mem_user != PipeMemUser::Interrupt {
+			self.flush();
+		}
+		if !(self.pipe_mem_user == PipeMemUser::Free || self.pipe_mem_user == PipeMemUser::Interrupt) {
+			self.interrupt = Some(sequence);
+			return;
+		}
+		let address: u16 = sequence.return_address.unwrap_or(self.pc);
+		let [low, high] = address.to_le_bytes();
+		match sequence.step {
+			0 if sequence.kind == InterruptKind::Reset => {
+				self.s = self.s.wrapping_sub(3);
+				self.nv_bdizc |= Self::INTERRUPT_FLAG;
+				if self.model == CpuModel::Wdc65C02 {
+					self.nv_bdizc &= !Self::DECIMAL_FLAG;
 				}
+				sequence.step = 3;
+				self.interrupt = Some(sequence);
+				return;
 			}
-			if let Some(event) = self.interrupt_controller.priority_queue.pop() {
-				if let Some(interrupt) = self.interrupt_controller.io_devices.get(&event.iqr) {
-					if let Some(c) = ascii::ENCODER.get(&interrupt.get_out_buf()) {
-						Self::sys_out_char(c.to_owned());
-					}
-				} else {
-					panic!("Could not find I/O device Name: {}, IQR: {}", event.name, event.iqr);
+			0 => {self.push(high, PipeMemUser::Interrupt, address);}
+			1 => {self.push(low, PipeMemUser::Interrupt, address);}
+			2 => {
+				//the pushed copy of the status is the only place the break flag exists
+				let brk: u8 = if sequence.kind == InterruptKind::Brk {Self::BREAK_FLAG} else {0x00};
+				self.push((self.nv_bdizc & !Self::BREAK_FLAG) | brk | Self::UNUSED_FLAG, PipeMemUser::Interrupt, address);
+				self.nv_bdizc |= Self::INTERRUPT_FLAG;
+				//the 65C02 makes sure handlers don't start in decimal mode
+				if self.model == CpuModel::Wdc65C02 {
+					self.nv_bdizc &= !Self::DECIMAL_FLAG;
 				}
 			}
+			3 => {
+				let Some(num) = self.read(sequence.kind.vector(), PipeMemUser::Interrupt) else {
+					self.interrupt = Some(sequence);
+					return;
+				};
+				sequence.vector_low = num;
+			}
+			_ => {
+				let Some(num) = self.read(sequence.kind.vector().wrapping_add(1), PipeMemUser::Interrupt) else {
+					self.interrupt = Some(sequence);
+					return;
+				};
+				self.pc = u16::from_le_bytes([sequence.vector_low, num]);
+				self.pipe_mem_user = PipeMemUser::Complete;
+				self.interrupt = None;
+				self.entered = match sequence.kind {
+					InterruptKind::Reset => {None}
+					//BRK's return address skips the padding byte after it
+					InterruptKind::Brk => {sequence.return_address.map(|address| {address.wrapping_sub(2)})}
+					InterruptKind::Irq | InterruptKind::Nmi => {sequence.return_address}
+				};
+				self.log_at(Level::Debug, || {format!("Took {:?}, PC: 0x{:04X}", sequence.kind, self.pc)});
+				return;
+			}
 		}
+		sequence.step += 1;
+		self.pipe_mem_user = PipeMemUser::Complete;
+		self.interrupt = Some(sequence);
 	}
 }
 
+#[derive(Clone)]
 struct ExecutionUnit {
 	///Index in the cpu's array
 	id: u8,
+	///Address of the opcode
+	address: u16,
 	///Points to the byte after the last byte of the instruction
 	ip: u16,
 	ir: (Opcode, u8, u8),
+	///Effective address computed by the address stage
+	ea: u16,
+	///Counts the cycles of instructions that access memory more than once, like JSR and RTS
+	step: u8,
+	///Holds the first byte pulled from the stack by RTS and RTI until the second one arrives
+	buffer: u8,
+	///All the storages the instruction MAY affect
+	affected: Vec<Storage>,
+	///All the storages the instruction MAY depend on
+	dependent: Vec<Storage>,
+	///The X that selected the system call, and what its handler keeps between cycles
+	syscall: Option<(u8, SyscallContext)>,
 	busy: bool
 }
 
@@ -464,108 +1392,755 @@ impl ExecutionUnit {
 	fn new(id: u8) -> Self {
 		Self {
 			id,
+			address: 0x0000,
 			ip: 0x00,
-			ir: (Opcode::BRK, 0x00, 0x00),
+			ir: (Opcode {mnemonic: Mnemonic::BRK, mode: AddrMode::Implied}, 0x00, 0x00),
+			ea: 0x0000,
+			step: 0,
+			buffer: 0x00,
+			affected: Vec::new(),
+			dependent: Vec::new(),
+			syscall: None,
 			busy: false
 		}
 	}
-	fn set_instruction(&mut self, ip: u16, ir: (Opcode, u8, u8)) {
-		self.ip = ip;
-		self.ir = ir;
+	///Whether the unit holds the same instruction as the other one, however far along it is or if it's done
+	fn same_instruction(&self, other: &Self) -> bool {
+		(self.id, self.address, self.ip, self.ir, self.ea) == (other.id, other.address, other.ip, other.ir, other.ea)
+			&& self.affected == other.affected && self.dependent == other.dependent
+	}
+	fn set_instruction(&mut self, ar: AddressRegister, ea: u16) {
+		self.address = ar.address;
+		self.ip = ar.ip;
+		self.ir = (ar.opcode, ar.operand1, ar.operand2);
+		self.ea = ea;
+		self.step = 0;
+		self.affected = ar.affected;
+		self.dependent = ar.dependent;
+		self.syscall = ar.syscall.map(|x| {(x, SyscallContext::new(ea))});
 		self.busy = true;
 	}
 }
 
-/**6502 ASM opcodes.
-Lowercase 4th letter indicates addressing mode.
-i = immediate, a = absolute, r = relative, none = accumulator.
-Parameters indicate operands.*/
-#[repr(u8)]
-#[derive(Debug, PartialEq, Clone)]
-enum Opcode {
-	LDAi = 0xA9,     //load immediate u8 into a
-	LDAa = 0xAD,     //load value from memory into a
-	STAa = 0x8D,     //store a into memory
-	TXA  = 0x8A,     //transfer x to a
-	TYA  = 0x98,     //transfer y to a
-	ADCa = 0x6D,     //add value from memory to a
-	LDXi = 0xA2,     //load immediate u8 into x
-	LDXa = 0xAE,     //load value from memory into x
-	TAX  = 0xAA,     //transfer a to x
-	LDYi = 0xA0,     //load immediate u8 into y
-	LDYa = 0xAC,     //load value from memory into y
-	TAY  = 0xA8,     //transfer a to y
-	NOP  = 0xEA,     //no operation
-	BRK  = 0x00,     //break
-	CPXa = 0xEC,     //compare x with value from memory
-	BNEr = 0xD0,     //branch if zero-flag != 0
-	INCa = 0xEE,     //increment a
-	SYS  = 0xFF,     //syscall may have operands
+///Things that make the CPU push its state and jump through a vector
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum InterruptKind {
+	Irq,
+	Nmi,
+	Brk,
+	Reset
+}
+
+impl InterruptKind {
+	///Address of the little-endian pointer to the handler
+	fn vector(&self) -> u16 {
+		match self {
+			InterruptKind::Nmi => {Cpu::NMI_VECTOR}
+			InterruptKind::Reset => {Cpu::RESET_VECTOR}
+			InterruptKind::Irq | InterruptKind::Brk => {Cpu::IRQ_VECTOR}
+		}
+	}
+}
+
+///Progress of an interrupt, BRK, or reset
+#[derive(PartialEq, Clone)]
+struct InterruptSequence {
+	kind: InterruptKind,
+	step: u8,
+	///Address pushed to the stack. BRK knows it up front, the others use the oldest instruction that was flushed.
+	return_address: Option<u16>,
+	vector_low: u8
+}
+
+impl InterruptSequence {
+	fn new(kind: InterruptKind, return_address: Option<u16>) -> Self {
+		Self {
+			kind,
+			step: 0,
+			return_address,
+			vector_low: 0x00
+		}
+	}
+}
+
+/**Everything the CPU keeps between cycles, from Cpu::state().*/
+#[derive(Clone)]
+pub struct CpuState {
+	pub cpu_clock_counter: u128,
+	pub instruction_counter: u128,
+	pc: u16,
+	ir: Option<(Opcode, Option<u8>, Option<u8>)>,
+	ir_address: u16,
+	ir_byte: u8,
+	ar: Option<AddressRegister>,
+	issued: Option<(u16, Opcode)>,
+	entered: Option<u16>,
+	///A, X, Y, S, and the status register
+	registers: [u8; 5],
+	interrupt: Option<InterruptSequence>,
+	waiting: bool,
+	fault: Option<CpuFault>,
+	execution_units: [ExecutionUnit; 2],
+	pipe_mem_user: PipeMemUser,
+	priority_queue: BinaryHeap<InterruptSpecs>,
+	data_latch: u8
+}
+
+impl CpuState {
+	///What is different in a newer state, with the values from this one
+	pub fn changes(&self, newer: &CpuState) -> CpuDelta {
+		let mut changes: Vec<CpuChange> = Vec::new();
+		if (self.cpu_clock_counter, self.instruction_counter) != (newer.cpu_clock_counter, newer.instruction_counter) {
+			changes.push(CpuChange::Counters(self.cpu_clock_counter, self.instruction_counter));
+		}
+		if (self.pc, self.ir, self.ir_address, self.ir_byte) != (newer.pc, newer.ir, newer.ir_address, newer.ir_byte) {
+			changes.push(CpuChange::Fetch(self.pc, self.ir, self.ir_address, self.ir_byte));
+		}
+		if self.ar != newer.ar {
+			changes.push(CpuChange::Ar(self.ar.clone().map(Box::new)));
+		}
+		if (self.issued, self.entered) != (newer.issued, newer.entered) {
+			changes.push(CpuChange::Issued(self.issued, self.entered));
+		}
+		if self.registers != newer.registers {
+			changes.push(CpuChange::Registers(self.registers));
+		}
+		if (&self.pipe_mem_user, self.waiting, self.fault) != (&newer.pipe_mem_user, newer.waiting, newer.fault) {
+			changes.push(CpuChange::Control(self.pipe_mem_user.clone(), self.waiting, self.fault));
+		}
+		if self.interrupt != newer.interrupt {
+			changes.push(CpuChange::Interrupt(self.interrupt.clone()));
+		}
+		for (i, (exe, newer_exe)) in self.execution_units.iter().zip(&newer.execution_units).enumerate() {
+			if !exe.same_instruction(newer_exe) {
+				changes.push(CpuChange::ExecutionUnit(i, Box::new(exe.clone())));
+				continue;
+			}
+			if (exe.step, exe.buffer, exe.busy) != (newer_exe.step, newer_exe.buffer, newer_exe.busy) {
+				changes.push(CpuChange::Progress(i, exe.step, exe.buffer, exe.busy));
+			}
+			if exe.syscall != newer_exe.syscall {
+				changes.push(CpuChange::Syscall(i, exe.syscall.clone().map(Box::new)));
+			}
+		}
+		//the same interrupts can be kept in a different order, which only costs a copy
+		if self.data_latch != newer.data_latch || !self.priority_queue.iter().map(|specs| {specs.iqr}).eq(newer.priority_queue.iter().map(|specs| {specs.iqr})) {
+			changes.push(CpuChange::Interrupts(self.priority_queue.clone(), self.data_latch));
+		}
+		CpuDelta(changes)
+	}
+	///Goes back to the older state the changes were taken from
+	pub fn undo(&mut self, delta: CpuDelta) {
+		for change in delta.0 {
+			match change {
+				CpuChange::Counters(cycles, instructions) => {
+					self.cpu_clock_counter = cycles;
+					self.instruction_counter = instructions;
+				}
+				CpuChange::Fetch(pc, ir, ir_address, ir_byte) => {
+					self.pc = pc;
+					self.ir = ir;
+					self.ir_address = ir_address;
+					self.ir_byte = ir_byte;
+				}
+				CpuChange::Ar(ar) => {self.ar = ar.map(|ar| {*ar});}
+				CpuChange::Issued(issued, entered) => {
+					self.issued = issued;
+					self.entered = entered;
+				}
+				CpuChange::Registers(registers) => {self.registers = registers;}
+				CpuChange::Control(pipe_mem_user, waiting, fault) => {
+					self.pipe_mem_user = pipe_mem_user;
+					self.waiting = waiting;
+					self.fault = fault;
+				}
+				CpuChange::Interrupt(interrupt) => {self.interrupt = interrupt;}
+				CpuChange::ExecutionUnit(i, exe) => {self.execution_units[i] = *exe;}
+				CpuChange::Progress(i, step, buffer, busy) => {
+					self.execution_units[i].step = step;
+					self.execution_units[i].buffer = buffer;
+					self.execution_units[i].busy = busy;
+				}
+				CpuChange::Syscall(i, syscall) => {self.execution_units[i].syscall = syscall.map(|syscall| {*syscall});}
+				CpuChange::Interrupts(priority_queue, data_latch) => {
+					self.priority_queue = priority_queue;
+					self.data_latch = data_latch;
+				}
+			}
+		}
+	}
+}
+
+/**The parts of the CPU that changed between two states, with the values from the older one. From CpuState::changes().*/
+#[derive(Default)]
+pub struct CpuDelta(Vec<CpuChange>);
+
+impl CpuDelta {
+	///Bytes used on the heap
+	pub fn heap_size(&self) -> usize {
+		self.0.len() * size_of::<CpuChange>() + self.0.iter().map(|change| {
+			match change {
+				CpuChange::Ar(Some(ar)) => {size_of::<AddressRegister>() + (ar.affected.len() + ar.dependent.len()) * size_of::<Storage>()}
+				CpuChange::ExecutionUnit(_, exe) => {size_of::<ExecutionUnit>() + (exe.affected.len() + exe.dependent.len()) * size_of::<Storage>()}
+				CpuChange::Syscall(_, Some(_)) => {size_of::<(u8, SyscallContext)>()}
+				CpuChange::Interrupts(priority_queue, _) => {priority_queue.len() * size_of::<InterruptSpecs>()}
+				_ => {0}
+			}
+		}).sum::<usize>()
+	}
+}
+
+///One part of the CPU that changed, with what it was before
+enum CpuChange {
+	///Clock cycles and instructions
+	Counters(u128, u128),
+	///PC, IR, and the address and byte of the opcode in the IR
+	Fetch(u16, Option<(Opcode, Option<u8>, Option<u8>)>, u16, u8),
+	Ar(Option<Box<AddressRegister>>),
+	///The instruction issued last and the handler entered last
+	Issued(Option<(u16, Opcode)>, Option<u16>),
+	Registers([u8; 5]),
+	///Who uses memory, whether the CPU waits for an interrupt, and the fault
+	Control(PipeMemUser, bool, Option<CpuFault>),
+	Interrupt(Option<InterruptSequence>),
+	///A unit that started or finished an instruction
+	ExecutionUnit(usize, Box<ExecutionUnit>),
+	///Step, buffer, and busy flag of a unit still on the same instruction, or done with it
+	Progress(usize, u8, u8, bool),
+	Syscall(usize, Option<Box<(u8, SyscallContext)>>),
+	///Interrupts waiting and the data latch
+	Interrupts(BinaryHeap<InterruptSpecs>, u8)
+}
+
+///The address stage's latch
+#[derive(PartialEq, Clone)]
+struct AddressRegister {
+	opcode: Opcode,
+	///The byte the opcode was decoded from
+	byte: u8,
+	///Address of the opcode
+	address: u16,
+	///Points to the byte after the last byte of the instruction
+	ip: u16,
+	operand1: u8,
+	operand2: u8,
+	///Low byte of the pointer used by indirect addressing modes, read in the cycle before the high byte
+	pointer_low: Option<u8>,
+	///Set to Some once the effective address is known
+	ea: Option<u16>,
+	///All the storages the instruction MAY affect. Comes from the handler for a system call.
+	affected: Vec<Storage>,
+	///All the storages the instruction MAY depend on. Comes from the handler for a system call.
+	dependent: Vec<Storage>,
+	///The X that selected the system call, if the instruction is a SYS
+	syscall: Option<u8>
+}
+
+impl AddressRegister {
+	fn new(opcode: Opcode, byte: u8, address: u16, ip: u16, operand1: u8, operand2: u8) -> Self {
+		Self {
+			opcode,
+			byte,
+			address,
+			ip,
+			operand1,
+			operand2,
+			pointer_low: None,
+			ea: None,
+			affected: opcode.affected_storage(),
+			dependent: opcode.dependent_storage(),
+			syscall: None
+		}
+	}
+}
+
+/**6502 ASM mnemonics. SYS is not a real 6502 instruction, it's a syscall to the emulator.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mnemonic {
+	ADC, AND, ASL, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRK, BVC, BVS, CLC,
+	CLD, CLI, CLV, CMP, CPX, CPY, DEC, DEX, DEY, EOR, INC, INX, INY, JMP,
+	JSR, LDA, LDX, LDY, LSR, NOP, ORA, PHA, PHP, PLA, PLP, ROL, ROR, RTI,
+	RTS, SBC, SEC, SED, SEI, STA, STX, STY, TAX, TAY, TSX, TXA, TXS, TYA,
+	//stable undocumented NMOS instructions
+	ALR, ANC, ARR, DCP, ISC, LAX, RLA, RRA, SAX, SBX, SLO, SRE,
+	//65C02 instructions
+	BRA, PHX, PHY, PLX, PLY, STP, STZ, TRB, TSB, WAI,
+	SYS
+}
+
+impl Mnemonic {
+	///Finds the mnemonic with the given name, in any case, among the instructions the model decodes
+	pub fn from_name(name: &str, model: CpuModel) -> Option<Self> {
+		(0x00..=0xFF).filter_map(|opcode| {Opcode::from(opcode, model)}).map(|opcode| {opcode.mnemonic}).find(|mnemonic| {format!("{:?}", mnemonic).eq_ignore_ascii_case(name)})
+	}
+	///The read-modify-write and ALU instructions that an undocumented combined instruction is made of
+	fn combined(&self) -> Option<(Mnemonic, Mnemonic)> {
+		match self {
+			Mnemonic::SLO => {Some((Mnemonic::ASL, Mnemonic::ORA))}
+			Mnemonic::RLA => {Some((Mnemonic::ROL, Mnemonic::AND))}
+			Mnemonic::SRE => {Some((Mnemonic::LSR, Mnemonic::EOR))}
+			Mnemonic::RRA => {Some((Mnemonic::ROR, Mnemonic::ADC))}
+			Mnemonic::DCP => {Some((Mnemonic::DEC, Mnemonic::CMP))}
+			Mnemonic::ISC => {Some((Mnemonic::INC, Mnemonic::SBC))}
+			_ => {None}
+		}
+	}
+}
+
+/**The 6502 derivative being emulated. The model decides which opcodes are decoded and how a few instructions behave.
+SYS is $FF on every model, which takes the place of ISC abs,X on the NMOS 6502 and BBS7 on the 65C02.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CpuModel {
+	///The original MOS 6502. The stable undocumented opcodes are decoded when undocumented is true.
+	Nmos6502 {undocumented: bool},
+	///The WDC 65C02. Adds new instructions and (zp) addressing, fixes JMP indirect, and has valid N and Z flags in decimal mode.
+	///The Rockwell bit instructions (RMB, SMB, BBR, BBS) are not supported.
+	Wdc65C02,
+	///The Ricoh 2A03 from the NES. It's an NMOS 6502 without decimal mode.
+	Ricoh2A03 {undocumented: bool}
+}
+
+impl CpuModel {
+	fn has_decimal_mode(&self) -> bool {
+		!matches!(self, CpuModel::Ricoh2A03 {..})
+	}
+}
+
+/**How the operands of an instruction are turned into the value it works on.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AddrMode {
+	///No operands, or the operands depend on the instruction like SYS
+	Implied,
+	///Works on the A register instead of memory
+	Accumulator,
+	///The operand is the value
+	Immediate,
+	///The operand is an address in the zero page
+	ZeroPage,
+	///The operand plus X, wrapping around inside the zero page
+	ZeroPageX,
+	///The operand plus Y, wrapping around inside the zero page
+	ZeroPageY,
+	///The operands are a little-endian address
+	Absolute,
+	///The operands plus X
+	AbsoluteX,
+	///The operands plus Y
+	AbsoluteY,
+	///The operands are the address of a pointer. Only used by JMP
+	Indirect,
+	///(zp,X) - the operand plus X is the address of a pointer in the zero page
+	IndexedIndirect,
+	///(zp),Y - the operand is the address of a pointer in the zero page, and Y is added to the pointer
+	IndirectIndexed,
+	///The operand is a signed offset from the address of the next instruction
+	Relative,
+	///(zp) - the operand is the address of a pointer in the zero page. 65C02 only
+	ZeroPageIndirect,
+	///(abs,X) - the operands plus X are the address of a pointer. Only used by the 65C02 JMP
+	AbsoluteIndexedIndirect
+}
+
+impl AddrMode {
+	///Number of bytes that follow the opcode
+	pub fn operand_len(&self) -> u8 {
+		match self {
+			AddrMode::Implied | AddrMode::Accumulator => {0}
+			AddrMode::Immediate | AddrMode::Relative | AddrMode::ZeroPage | AddrMode::ZeroPageX | AddrMode::ZeroPageY
+				| AddrMode::IndexedIndirect | AddrMode::IndirectIndexed | AddrMode::ZeroPageIndirect => {1}
+			AddrMode::Absolute | AddrMode::AbsoluteX | AddrMode::AbsoluteY | AddrMode::Indirect | AddrMode::AbsoluteIndexedIndirect => {2}
+		}
+	}
+	///Whether the instruction works on a value in memory
+	fn uses_memory(&self) -> bool {
+		!matches!(self, AddrMode::Implied | AddrMode::Accumulator | AddrMode::Immediate | AddrMode::Relative)
+	}
+}
+
+/**A decoded 6502 opcode. The mnemonic says what the instruction does, and the addressing mode says where it gets its value.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Opcode {
+	pub mnemonic: Mnemonic,
+	pub mode: AddrMode
 }
 
 impl Opcode {
-	fn from(opcode: u8) -> Option<Self> {
-		match opcode {
-			0xA9 => {Some(Opcode::LDAi)}
-			0xAD => {Some(Opcode::LDAa)}
-			0x8D => {Some(Opcode::STAa)}
-			0x8A => {Some(Opcode::TXA)}
-			0x98 => {Some(Opcode::TYA)}
-			0x6D => {Some(Opcode::ADCa)}
-			0xA2 => {Some(Opcode::LDXi)}
-			0xAE => {Some(Opcode::LDXa)}
-			0xAA => {Some(Opcode::TAX)}
-			0xA0 => {Some(Opcode::LDYi)}
-			0xAC => {Some(Opcode::LDYa)}
-			0xA8 => {Some(Opcode::TAY)}
-			0xEA => {Some(Opcode::NOP)}
-			0x00 => {Some(Opcode::BRK)}
-			0xEC => {Some(Opcode::CPXa)}
-			0xD0 => {Some(Opcode::BNEr)}
-			0xEE => {Some(Opcode::INCa)}
-			0xFF => {Some(Opcode::SYS)}
+	///Decodes an opcode byte for the given CPU model. Returns None if the byte isn't an instruction on that model.
+	pub fn from(opcode: u8, model: CpuModel) -> Option<Self> {
+		if opcode == 0xFF {
+			return Some(Self {mnemonic: Mnemonic::SYS, mode: AddrMode::Implied});//syscall may have operands
+		}
+		if let Some(documented) = Self::documented(opcode) {
+			return Some(documented);
+		}
+		match model {
+			CpuModel::Nmos6502 {undocumented: true} | CpuModel::Ricoh2A03 {undocumented: true} => {Self::undocumented(opcode)}
+			CpuModel::Wdc65C02 => {Self::wdc65c02(opcode)}
 			_ => {None}
 		}
 	}
+	///Finds the opcode byte for an instruction on the given CPU model. Documented opcodes are picked over undocumented ones that do the same thing.
+	pub fn encode(mnemonic: Mnemonic, mode: AddrMode, model: CpuModel) -> Option<u8> {
+		let wanted: Option<Self> = Some(Self {mnemonic, mode});
+		(0x00..=0xFF).find(|&opcode| {Self::documented(opcode) == wanted}).or_else(|| {(0x00..=0xFF).find(|&opcode| {Self::from(opcode, model) == wanted})})
+	}
+	///The 151 opcodes that every model has
+	fn documented(opcode: u8) -> Option<Self> {
+		use {Mnemonic::*, AddrMode::*};
+		let (mnemonic, mode) = match opcode {
+			0x69 => {(ADC, Immediate)}
+			0x65 => {(ADC, ZeroPage)}
+			0x75 => {(ADC, ZeroPageX)}
+			0x6D => {(ADC, Absolute)}
+			0x7D => {(ADC, AbsoluteX)}
+			0x79 => {(ADC, AbsoluteY)}
+			0x61 => {(ADC, IndexedIndirect)}
+			0x71 => {(ADC, IndirectIndexed)}
+			0x29 => {(AND, Immediate)}
+			0x25 => {(AND, ZeroPage)}
+			0x35 => {(AND, ZeroPageX)}
+			0x2D => {(AND, Absolute)}
+			0x3D => {(AND, AbsoluteX)}
+			0x39 => {(AND, AbsoluteY)}
+			0x21 => {(AND, IndexedIndirect)}
+			0x31 => {(AND, IndirectIndexed)}
+			0x0A => {(ASL, Accumulator)}
+			0x06 => {(ASL, ZeroPage)}
+			0x16 => {(ASL, ZeroPageX)}
+			0x0E => {(ASL, Absolute)}
+			0x1E => {(ASL, AbsoluteX)}
+			0x90 => {(BCC, Relative)}
+			0xB0 => {(BCS, Relative)}
+			0xF0 => {(BEQ, Relative)}
+			0x24 => {(BIT, ZeroPage)}
+			0x2C => {(BIT, Absolute)}
+			0x30 => {(BMI, Relative)}
+			0xD0 => {(BNE, Relative)}
+			0x10 => {(BPL, Relative)}
+			0x00 => {(BRK, Implied)}
+			0x50 => {(BVC, Relative)}
+			0x70 => {(BVS, Relative)}
+			0x18 => {(CLC, Implied)}
+			0xD8 => {(CLD, Implied)}
+			0x58 => {(CLI, Implied)}
+			0xB8 => {(CLV, Implied)}
+			0xC9 => {(CMP, Immediate)}
+			0xC5 => {(CMP, ZeroPage)}
+			0xD5 => {(CMP, ZeroPageX)}
+			0xCD => {(CMP, Absolute)}
+			0xDD => {(CMP, AbsoluteX)}
+			0xD9 => {(CMP, AbsoluteY)}
+			0xC1 => {(CMP, IndexedIndirect)}
+			0xD1 => {(CMP, IndirectIndexed)}
+			0xE0 => {(CPX, Immediate)}
+			0xE4 => {(CPX, ZeroPage)}
+			0xEC => {(CPX, Absolute)}
+			0xC0 => {(CPY, Immediate)}
+			0xC4 => {(CPY, ZeroPage)}
+			0xCC => {(CPY, Absolute)}
+			0xC6 => {(DEC, ZeroPage)}
+			0xD6 => {(DEC, ZeroPageX)}
+			0xCE => {(DEC, Absolute)}
+			0xDE => {(DEC, AbsoluteX)}
+			0xCA => {(DEX, Implied)}
+			0x88 => {(DEY, Implied)}
+			0x49 => {(EOR, Immediate)}
+			0x45 => {(EOR, ZeroPage)}
+			0x55 => {(EOR, ZeroPageX)}
+			0x4D => {(EOR, Absolute)}
+			0x5D => {(EOR, AbsoluteX)}
+			0x59 => {(EOR, AbsoluteY)}
+			0x41 => {(EOR, IndexedIndirect)}
+			0x51 => {(EOR, IndirectIndexed)}
+			0xE6 => {(INC, ZeroPage)}
+			0xF6 => {(INC, ZeroPageX)}
+			0xEE => {(INC, Absolute)}
+			0xFE => {(INC, AbsoluteX)}
+			0xE8 => {(INX, Implied)}
+			0xC8 => {(INY, Implied)}
+			0x4C => {(JMP, Absolute)}
+			0x6C => {(JMP, Indirect)}
+			0x20 => {(JSR, Absolute)}
+			0xA9 => {(LDA, Immediate)}
+			0xA5 => {(LDA, ZeroPage)}
+			0xB5 => {(LDA, ZeroPageX)}
+			0xAD => {(LDA, Absolute)}
+			0xBD => {(LDA, AbsoluteX)}
+			0xB9 => {(LDA, AbsoluteY)}
+			0xA1 => {(LDA, IndexedIndirect)}
+			0xB1 => {(LDA, IndirectIndexed)}
+			0xA2 => {(LDX, Immediate)}
+			0xA6 => {(LDX, ZeroPage)}
+			0xB6 => {(LDX, ZeroPageY)}
+			0xAE => {(LDX, Absolute)}
+			0xBE => {(LDX, AbsoluteY)}
+			0xA0 => {(LDY, Immediate)}
+			0xA4 => {(LDY, ZeroPage)}
+			0xB4 => {(LDY, ZeroPageX)}
+			0xAC => {(LDY, Absolute)}
+			0xBC => {(LDY, AbsoluteX)}
+			0x4A => {(LSR, Accumulator)}
+			0x46 => {(LSR, ZeroPage)}
+			0x56 => {(LSR, ZeroPageX)}
+			0x4E => {(LSR, Absolute)}
+			0x5E => {(LSR, AbsoluteX)}
+			0xEA => {(NOP, Implied)}
+			0x09 => {(ORA, Immediate)}
+			0x05 => {(ORA, ZeroPage)}
+			0x15 => {(ORA, ZeroPageX)}
+			0x0D => {(ORA, Absolute)}
+			0x1D => {(ORA, AbsoluteX)}
+			0x19 => {(ORA, AbsoluteY)}
+			0x01 => {(ORA, IndexedIndirect)}
+			0x11 => {(ORA, IndirectIndexed)}
+			0x48 => {(PHA, Implied)}
+			0x08 => {(PHP, Implied)}
+			0x68 => {(PLA, Implied)}
+			0x28 => {(PLP, Implied)}
+			0x2A => {(ROL, Accumulator)}
+			0x26 => {(ROL, ZeroPage)}
+			0x36 => {(ROL, ZeroPageX)}
+			0x2E => {(ROL, Absolute)}
+			0x3E => {(ROL, AbsoluteX)}
+			0x6A => {(ROR, Accumulator)}
+			0x66 => {(ROR, ZeroPage)}
+			0x76 => {(ROR, ZeroPageX)}
+			0x6E => {(ROR, Absolute)}
+			0x7E => {(ROR, AbsoluteX)}
+			0x40 => {(RTI, Implied)}
+			0x60 => {(RTS, Implied)}
+			0xE9 => {(SBC, Immediate)}
+			0xE5 => {(SBC, ZeroPage)}
+			0xF5 => {(SBC, ZeroPageX)}
+			0xED => {(SBC, Absolute)}
+			0xFD => {(SBC, AbsoluteX)}
+			0xF9 => {(SBC, AbsoluteY)}
+			0xE1 => {(SBC, IndexedIndirect)}
+			0xF1 => {(SBC, IndirectIndexed)}
+			0x38 => {(SEC, Implied)}
+			0xF8 => {(SED, Implied)}
+			0x78 => {(SEI, Implied)}
+			0x85 => {(STA, ZeroPage)}
+			0x95 => {(STA, ZeroPageX)}
+			0x8D => {(STA, Absolute)}
+			0x9D => {(STA, AbsoluteX)}
+			0x99 => {(STA, AbsoluteY)}
+			0x81 => {(STA, IndexedIndirect)}
+			0x91 => {(STA, IndirectIndexed)}
+			0x86 => {(STX, ZeroPage)}
+			0x96 => {(STX, ZeroPageY)}
+			0x8E => {(STX, Absolute)}
+			0x84 => {(STY, ZeroPage)}
+			0x94 => {(STY, ZeroPageX)}
+			0x8C => {(STY, Absolute)}
+			0xAA => {(TAX, Implied)}
+			0xA8 => {(TAY, Implied)}
+			0xBA => {(TSX, Implied)}
+			0x8A => {(TXA, Implied)}
+			0x9A => {(TXS, Implied)}
+			0x98 => {(TYA, Implied)}
+			_ => {return None;}
+		};
+		Some(Self {mnemonic, mode})
+	}
+	///The stable undocumented opcodes of the NMOS 6502. The unstable ones and the opcodes that jam the CPU are not decoded.
+	fn undocumented(opcode: u8) -> Option<Self> {
+		use {Mnemonic::*, AddrMode::*};
+		let (mnemonic, mode) = match opcode {
+			0x4B => {(ALR, Immediate)}
+			0x0B | 0x2B => {(ANC, Immediate)}
+			0x6B => {(ARR, Immediate)}
+			0xC7 => {(DCP, ZeroPage)}
+			0xD7 => {(DCP, ZeroPageX)}
+			0xCF => {(DCP, Absolute)}
+			0xDF => {(DCP, AbsoluteX)}
+			0xDB => {(DCP, AbsoluteY)}
+			0xC3 => {(DCP, IndexedIndirect)}
+			0xD3 => {(DCP, IndirectIndexed)}
+			0xE7 => {(ISC, ZeroPage)}
+			0xF7 => {(ISC, ZeroPageX)}
+			0xEF => {(ISC, Absolute)}
+			0xFB => {(ISC, AbsoluteY)}
+			0xE3 => {(ISC, IndexedIndirect)}
+			0xF3 => {(ISC, IndirectIndexed)}
+			0xA7 => {(LAX, ZeroPage)}
+			0xB7 => {(LAX, ZeroPageY)}
+			0xAF => {(LAX, Absolute)}
+			0xBF => {(LAX, AbsoluteY)}
+			0xA3 => {(LAX, IndexedIndirect)}
+			0xB3 => {(LAX, IndirectIndexed)}
+			0x27 => {(RLA, ZeroPage)}
+			0x37 => {(RLA, ZeroPageX)}
+			0x2F => {(RLA, Absolute)}
+			0x3F => {(RLA, AbsoluteX)}
+			0x3B => {(RLA, AbsoluteY)}
+			0x23 => {(RLA, IndexedIndirect)}
+			0x33 => {(RLA, IndirectIndexed)}
+			0x67 => {(RRA, ZeroPage)}
+			0x77 => {(RRA, ZeroPageX)}
+			0x6F => {(RRA, Absolute)}
+			0x7F => {(RRA, AbsoluteX)}
+			0x7B => {(RRA, AbsoluteY)}
+			0x63 => {(RRA, IndexedIndirect)}
+			0x73 => {(RRA, IndirectIndexed)}
+			0x87 => {(SAX, ZeroPage)}
+			0x97 => {(SAX, ZeroPageY)}
+			0x8F => {(SAX, Absolute)}
+			0x83 => {(SAX, IndexedIndirect)}
+			0xEB => {(SBC, Immediate)}
+			0xCB => {(SBX, Immediate)}
+			0x07 => {(SLO, ZeroPage)}
+			0x17 => {(SLO, ZeroPageX)}
+			0x0F => {(SLO, Absolute)}
+			0x1F => {(SLO, AbsoluteX)}
+			0x1B => {(SLO, AbsoluteY)}
+			0x03 => {(SLO, IndexedIndirect)}
+			0x13 => {(SLO, IndirectIndexed)}
+			0x47 => {(SRE, ZeroPage)}
+			0x57 => {(SRE, ZeroPageX)}
+			0x4F => {(SRE, Absolute)}
+			0x5F => {(SRE, AbsoluteX)}
+			0x5B => {(SRE, AbsoluteY)}
+			0x43 => {(SRE, IndexedIndirect)}
+			0x53 => {(SRE, IndirectIndexed)}
+			0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => {(NOP, Implied)}
+			0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => {(NOP, Immediate)}
+			0x04 | 0x44 | 0x64 => {(NOP, ZeroPage)}
+			0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => {(NOP, ZeroPageX)}
+			0x0C => {(NOP, Absolute)}
+			0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => {(NOP, AbsoluteX)}
+			_ => {return None;}
+		};
+		Some(Self {mnemonic, mode})
+	}
+	///The opcodes the 65C02 adds to the NMOS 6502. Every other opcode is a NOP, except for the Rockwell bit instructions.
+	fn wdc65c02(opcode: u8) -> Option<Self> {
+		use {Mnemonic::*, AddrMode::*};
+		let (mnemonic, mode) = match opcode {
+			0x72 => {(ADC, ZeroPageIndirect)}
+			0x32 => {(AND, ZeroPageIndirect)}
+			0x89 => {(BIT, Immediate)}
+			0x34 => {(BIT, ZeroPageX)}
+			0x3C => {(BIT, AbsoluteX)}
+			0x80 => {(BRA, Relative)}
+			0xD2 => {(CMP, ZeroPageIndirect)}
+			0x3A => {(DEC, Accumulator)}
+			0x52 => {(EOR, ZeroPageIndirect)}
+			0x1A => {(INC, Accumulator)}
+			0x7C => {(JMP, AbsoluteIndexedIndirect)}
+			0xB2 => {(LDA, ZeroPageIndirect)}
+			0x12 => {(ORA, ZeroPageIndirect)}
+			0xDA => {(PHX, Implied)}
+			0x5A => {(PHY, Implied)}
+			0xFA => {(PLX, Implied)}
+			0x7A => {(PLY, Implied)}
+			0xF2 => {(SBC, ZeroPageIndirect)}
+			0x92 => {(STA, ZeroPageIndirect)}
+			0xDB => {(STP, Implied)}
+			0x64 => {(STZ, ZeroPage)}
+			0x74 => {(STZ, ZeroPageX)}
+			0x9C => {(STZ, Absolute)}
+			0x9E => {(STZ, AbsoluteX)}
+			0x14 => {(TRB, ZeroPage)}
+			0x1C => {(TRB, Absolute)}
+			0x04 => {(TSB, ZeroPage)}
+			0x0C => {(TSB, Absolute)}
+			0xCB => {(WAI, Implied)}
+			0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xC2 | 0xE2 => {(NOP, Immediate)}
+			0x44 => {(NOP, ZeroPage)}
+			0x54 | 0xD4 | 0xF4 => {(NOP, ZeroPageX)}
+			0x5C | 0xDC | 0xFC => {(NOP, Absolute)}
+			//$x3 and $xB are single byte NOPs. $x7 and $xF are the Rockwell bit instructions.
+			opcode if opcode & 0x07 == 0x03 => {(NOP, Implied)}
+			_ => {return None;}
+		};
+		Some(Self {mnemonic, mode})
+	}
 	///All the storages that this instruction MAY affect
 	fn affected_storage(&self) -> Vec<Storage> {
-		match self {
-			Opcode::LDAi => {vec![Storage::A, Storage::ZeroFlag]}
-			Opcode::LDAa => {vec![Storage::A, Storage::ZeroFlag]}
-			Opcode::STAa => {vec![Storage::Memory]}
-			Opcode::TXA => {vec![Storage::A, Storage::ZeroFlag]}
-			Opcode::TYA => {vec![Storage::A, Storage::ZeroFlag]}
-			Opcode::ADCa => {vec![Storage::A, Storage::ZeroFlag]}
-			Opcode::LDXi => {vec![Storage::X, Storage::ZeroFlag]}
-			Opcode::LDXa => {vec![Storage::X, Storage::ZeroFlag]}
-			Opcode::TAX => {vec![Storage::X, Storage::ZeroFlag]}
-			Opcode::LDYi => {vec![Storage::Y, Storage::ZeroFlag]}
-			Opcode::LDYa => {vec![Storage::Y, Storage::ZeroFlag]}
-			Opcode::TAY => {vec![Storage::Y, Storage::ZeroFlag]}
-			Opcode::CPXa => {vec![Storage::ZeroFlag]}
-			Opcode::BNEr => {vec![Storage::PC]}
-			Opcode::INCa => {vec![Storage::Memory, Storage::ZeroFlag]}
-			_ => {vec![]}
+		use Storage::*;
+		match self.mnemonic {
+			Mnemonic::LDA | Mnemonic::TXA | Mnemonic::TYA | Mnemonic::AND | Mnemonic::ORA | Mnemonic::EOR => {vec![A, NegativeFlag, ZeroFlag]}
+			Mnemonic::LDX | Mnemonic::TAX | Mnemonic::TSX | Mnemonic::INX | Mnemonic::DEX => {vec![X, NegativeFlag, ZeroFlag]}
+			Mnemonic::LDY | Mnemonic::TAY | Mnemonic::INY | Mnemonic::DEY => {vec![Y, NegativeFlag, ZeroFlag]}
+			Mnemonic::ADC | Mnemonic::SBC | Mnemonic::ARR => {vec![A, NegativeFlag, OverflowFlag, ZeroFlag, CarryFlag]}
+			Mnemonic::LAX => {vec![A, X, NegativeFlag, ZeroFlag]}
+			Mnemonic::ANC | Mnemonic::ALR => {vec![A, NegativeFlag, ZeroFlag, CarryFlag]}
+			Mnemonic::SBX => {vec![X, NegativeFlag, ZeroFlag, CarryFlag]}
+			Mnemonic::DCP => {vec![Memory, NegativeFlag, ZeroFlag, CarryFlag]}
+			Mnemonic::SLO | Mnemonic::RLA | Mnemonic::SRE => {vec![Memory, A, NegativeFlag, ZeroFlag, CarryFlag]}
+			Mnemonic::RRA | Mnemonic::ISC => {vec![Memory, A, NegativeFlag, OverflowFlag, ZeroFlag, CarryFlag]}
+			Mnemonic::TSB | Mnemonic::TRB => {vec![Memory, ZeroFlag]}
+			Mnemonic::CMP | Mnemonic::CPX | Mnemonic::CPY => {vec![NegativeFlag, ZeroFlag, CarryFlag]}
+			Mnemonic::BIT => {vec![NegativeFlag, OverflowFlag, ZeroFlag]}
+			Mnemonic::ASL | Mnemonic::LSR | Mnemonic::ROL | Mnemonic::ROR => {
+				vec![if self.mode == AddrMode::Accumulator {A} else {Memory}, NegativeFlag, ZeroFlag, CarryFlag]
+			}
+			Mnemonic::INC | Mnemonic::DEC => {vec![if self.mode == AddrMode::Accumulator {A} else {Memory}, NegativeFlag, ZeroFlag]}
+			Mnemonic::STA | Mnemonic::STX | Mnemonic::STY | Mnemonic::STZ | Mnemonic::SAX => {vec![Memory]}
+			Mnemonic::BPL | Mnemonic::BMI | Mnemonic::BVC | Mnemonic::BVS | Mnemonic::BCC | Mnemonic::BCS | Mnemonic::BNE | Mnemonic::BEQ | Mnemonic::BRA | Mnemonic::JMP => {vec![PC]}
+			Mnemonic::WAI | Mnemonic::STP => {vec![PC]}
+			Mnemonic::BRK => {vec![PC, S, Memory, InterruptFlag, DecimalFlag]}
+			Mnemonic::PHA | Mnemonic::PHP | Mnemonic::PHX | Mnemonic::PHY => {vec![S, Memory]}
+			Mnemonic::PLA => {vec![S, A, NegativeFlag, ZeroFlag]}
+			Mnemonic::PLX => {vec![S, X, NegativeFlag, ZeroFlag]}
+			Mnemonic::PLY => {vec![S, Y, NegativeFlag, ZeroFlag]}
+			Mnemonic::PLP => {vec![S, NegativeFlag, OverflowFlag, DecimalFlag, InterruptFlag, ZeroFlag, CarryFlag]}
+			Mnemonic::JSR => {vec![PC, S, Memory]}
+			Mnemonic::RTS => {vec![PC, S]}
+			Mnemonic::RTI => {vec![PC, S, NegativeFlag, OverflowFlag, DecimalFlag, InterruptFlag, ZeroFlag, CarryFlag]}
+			Mnemonic::TXS => {vec![S]}
+			Mnemonic::CLC | Mnemonic::SEC => {vec![CarryFlag]}
+			Mnemonic::CLI | Mnemonic::SEI => {vec![InterruptFlag]}
+			Mnemonic::CLV => {vec![OverflowFlag]}
+			Mnemonic::CLD | Mnemonic::SED => {vec![DecimalFlag]}
+			//system calls use the storages declared by their handler instead
+			Mnemonic::SYS => {vec![A, NegativeFlag, ZeroFlag]}
+			Mnemonic::NOP => {vec![]}
 		}
 	}
 	///All the storages that this instruction MAY depend on
 	fn dependent_storage(&self) -> Vec<Storage> {
-		match self {
-			Opcode::LDAa => {vec![Storage::Memory]}
-			Opcode::STAa => {vec![Storage::A]}
-			Opcode::TXA => {vec![Storage::X]}
-			Opcode::TYA => {vec![Storage::Y]}
-			Opcode::ADCa => {vec![Storage::A, Storage::Memory]}
-			Opcode::LDXa => {vec![Storage::Memory]}
-			Opcode::TAX => {vec![Storage::A]}
-			Opcode::LDYa => {vec![Storage::Memory]}
-			Opcode::TAY => {vec![Storage::A]}
-			Opcode::CPXa => {vec![Storage::X, Storage::Memory]}
-			Opcode::BNEr => {vec![Storage::ZeroFlag]}
-			Opcode::INCa => {vec![Storage::Memory]}
-			Opcode::SYS => {vec![Storage::X, Storage::Y, Storage::Memory]}
+		use Storage::*;
+		let mut storages: Vec<Storage> = match self.mnemonic {
+			Mnemonic::STA | Mnemonic::TAX | Mnemonic::TAY | Mnemonic::AND | Mnemonic::ORA | Mnemonic::EOR | Mnemonic::CMP | Mnemonic::BIT => {vec![A]}
+			Mnemonic::TSB | Mnemonic::TRB | Mnemonic::ANC | Mnemonic::ALR | Mnemonic::SLO | Mnemonic::SRE | Mnemonic::DCP => {vec![A]}
+			Mnemonic::ADC | Mnemonic::SBC | Mnemonic::ARR | Mnemonic::RRA | Mnemonic::ISC => {vec![A, CarryFlag, DecimalFlag]}
+			Mnemonic::RLA => {vec![A, CarryFlag]}
+			Mnemonic::SAX | Mnemonic::SBX => {vec![A, X]}
+			Mnemonic::INC | Mnemonic::DEC if self.mode == AddrMode::Accumulator => {vec![A]}
+			Mnemonic::STX | Mnemonic::TXA | Mnemonic::TXS | Mnemonic::CPX | Mnemonic::INX | Mnemonic::DEX => {vec![X]}
+			Mnemonic::STY | Mnemonic::TYA | Mnemonic::CPY | Mnemonic::INY | Mnemonic::DEY => {vec![Y]}
+			Mnemonic::ASL | Mnemonic::LSR if self.mode == AddrMode::Accumulator => {vec![A]}
+			Mnemonic::ROL | Mnemonic::ROR if self.mode == AddrMode::Accumulator => {vec![A, CarryFlag]}
+			Mnemonic::ROL | Mnemonic::ROR => {vec![CarryFlag]}
+			Mnemonic::BPL | Mnemonic::BMI => {vec![NegativeFlag]}
+			Mnemonic::BVC | Mnemonic::BVS => {vec![OverflowFlag]}
+			Mnemonic::BCC | Mnemonic::BCS => {vec![CarryFlag]}
+			Mnemonic::BNE | Mnemonic::BEQ => {vec![ZeroFlag]}
+			Mnemonic::SYS => {vec![X, Y, Memory]}
+			Mnemonic::TSX | Mnemonic::JSR => {vec![S]}
+			Mnemonic::PHA => {vec![S, A]}
+			Mnemonic::PHX => {vec![S, X]}
+			Mnemonic::PHY => {vec![S, Y]}
+			Mnemonic::PHP | Mnemonic::BRK => {vec![S, NegativeFlag, OverflowFlag, DecimalFlag, InterruptFlag, ZeroFlag, CarryFlag]}
+			Mnemonic::PLA | Mnemonic::PLP | Mnemonic::PLX | Mnemonic::PLY | Mnemonic::RTS | Mnemonic::RTI => {vec![S, Memory]}
 			_ => {vec![]}
+		};
+		match self.mode {
+			AddrMode::ZeroPageX | AddrMode::AbsoluteX => {storages.push(X);}
+			AddrMode::ZeroPageY | AddrMode::AbsoluteY => {storages.push(Y);}
+			AddrMode::IndexedIndirect => {storages.append(&mut vec![X, Memory]);}
+			AddrMode::IndirectIndexed => {storages.append(&mut vec![Y, Memory]);}
+			AddrMode::Indirect | AddrMode::ZeroPageIndirect => {storages.push(Memory);}
+			AddrMode::AbsoluteIndexedIndirect => {storages.append(&mut vec![X, Memory]);}
+			_ => {}
 		}
+		//everything that reads its value from memory, and read-modify-write instructions
+		if self.mode.uses_memory() && !matches!(self.mnemonic, Mnemonic::STA | Mnemonic::STX | Mnemonic::STY | Mnemonic::STZ | Mnemonic::SAX | Mnemonic::JMP | Mnemonic::JSR) && !storages.contains(&Memory) {
+			storages.push(Memory);
+		}
+		storages
 	}
 }
 
@@ -574,19 +2149,172 @@ impl Opcode {
 enum PipeMemUser {
 	Fetch,
 	Decode,
+	Address,
 	Execute(u8),
+	Interrupt,
 	///Users set pipe_mem_user to Complete when they finish, as opposed to Free because only one user can access memory in a cycle
 	Complete,
 	///If pipe_mem_user is Complete at the end of a cpu cycle, it is set to free to allow users to access memory in the next cycle
 	Free
 }
 
-#[derive(PartialEq)]
-enum Storage {
+/**Parts of the CPU and memory that instructions depend on and affect, used to find hazards in the pipeline.
+System calls declare theirs when they're registered.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Storage {
 	A,
 	X,
 	Y,
+	NegativeFlag,
+	OverflowFlag,
+	DecimalFlag,
+	InterruptFlag,
 	ZeroFlag,
+	CarryFlag,
+	///Stack pointer
+	S,
 	Memory,
 	PC
+}
+
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		tokio::sync::mpsc::channel
+	};
+	
+	const FLAGS: u8 = Cpu::NEGATIVE_FLAG | Cpu::OVERFLOW_FLAG | Cpu::ZERO_FLAG | Cpu::CARRY_FLAG;
+	
+	///Needs a runtime for the keyboard, which never gets to run since the tests don't await
+	fn cpu(model: CpuModel) -> Cpu {
+		Cpu::new(std::array::from_fn(|_| {channel::<MemEvent>(1)}), model)
+	}
+	fn flags(n: bool, v: bool, z: bool, c: bool) -> u8 {
+		let mut flags: u8 = 0;
+		for (flag, set) in [(Cpu::NEGATIVE_FLAG, n), (Cpu::OVERFLOW_FLAG, v), (Cpu::ZERO_FLAG, z), (Cpu::CARRY_FLAG, c)] {
+			if set {flags |= flag;}
+		}
+		return flags;
+	}
+	
+	//reference implementations, which return the result and N, V, Z, and C
+	
+	fn adc_binary(a: u8, m: u8, c: bool) -> (u8, u8) {
+		let unsigned: u16 = a as u16 + m as u16 + c as u16;
+		let signed: i16 = a as i8 as i16 + m as i8 as i16 + c as i16;
+		let result: u8 = unsigned as u8;
+		(result, flags(result & 0x80 != 0, !(-128..=127).contains(&signed), result == 0, unsigned > 0xFF))
+	}
+	fn sbc_binary(a: u8, m: u8, c: bool) -> (u8, u8) {
+		let unsigned: i16 = a as i16 - m as i16 - !c as i16;
+		let signed: i16 = a as i8 as i16 - m as i8 as i16 - !c as i16;
+		let result: u8 = unsigned as u8;
+		(result, flags(result & 0x80 != 0, !(-128..=127).contains(&signed), result == 0, unsigned >= 0))
+	}
+	///NMOS decimal ADC, worked out digit by digit with the flags taken along the way like the real chip
+	fn adc_decimal_nmos(a: u8, m: u8, c: bool) -> (u8, u8) {
+		let mut sum: u16 = (a & 0x0F) as u16 + (m & 0x0F) as u16 + c as u16;
+		if sum > 0x09 {
+			sum += 0x06;
+		}
+		sum = (sum & 0x0F) + (a & 0xF0) as u16 + (m & 0xF0) as u16 + if sum > 0x0F {0x10} else {0x00};
+		let zero: bool = a.wrapping_add(m).wrapping_add(c as u8) == 0;
+		let negative: bool = sum & 0x80 != 0;
+		let overflow: bool = (a as u16 ^ sum) & 0x80 != 0 && (a ^ m) & 0x80 == 0;
+		if sum & 0x1F0 > 0x90 {
+			sum += 0x60;
+		}
+		(sum as u8, flags(negative, overflow, zero, sum & 0xFF0 > 0xF0))
+	}
+	///NMOS decimal SBC, where every flag comes from the binary subtraction
+	fn sbc_decimal_nmos(a: u8, m: u8, c: bool) -> (u8, u8) {
+		let borrow: i16 = !c as i16;
+		let mut low: i16 = (a & 0x0F) as i16 - (m & 0x0F) as i16 - borrow;
+		let high: i16 = (a & 0xF0) as i16 - (m & 0xF0) as i16;
+		let mut difference: i16 = if low & 0x10 != 0 {
+			low = (low - 0x06) & 0x0F;
+			low | (high - 0x10)
+		} else {
+			(low & 0x0F) | high
+		};
+		if difference & 0x100 != 0 {
+			difference -= 0x60;
+		}
+		(difference as u8, sbc_binary(a, m, c).1)
+	}
+	///65C02 decimal ADC: the same sum, carry, and overflow as the NMOS 6502, with N and Z from the sum
+	fn adc_decimal_cmos(a: u8, m: u8, c: bool) -> (u8, u8) {
+		let (result, nmos) = adc_decimal_nmos(a, m, c);
+		(result, flags(result & 0x80 != 0, nmos & Cpu::OVERFLOW_FLAG != 0, result == 0, nmos & Cpu::CARRY_FLAG != 0))
+	}
+	///65C02 decimal SBC: the binary difference is adjusted, with C and V from the binary subtraction and N and Z from the result
+	fn sbc_decimal_cmos(a: u8, m: u8, c: bool) -> (u8, u8) {
+		let borrow: i16 = !c as i16;
+		let low: i16 = (a & 0x0F) as i16 - (m & 0x0F) as i16 - borrow;
+		let mut difference: i16 = a as i16 - m as i16 - borrow;
+		if difference < 0 {
+			difference -= 0x60;
+		}
+		if low < 0 {
+			difference -= 0x06;
+		}
+		let result: u8 = difference as u8;
+		let binary: u8 = sbc_binary(a, m, c).1;
+		(result, flags(result & 0x80 != 0, binary & Cpu::OVERFLOW_FLAG != 0, result == 0, binary & Cpu::CARRY_FLAG != 0))
+	}
+	
+	///Runs the instruction on every A, operand, and carry, and compares the result and N, V, Z, and C with the reference
+	fn check(model: CpuModel, decimal: bool, mnemonic: Mnemonic, reference: fn(u8, u8, bool) -> (u8, u8)) {
+		let mut cpu: Cpu = cpu(model);
+		for a in 0..=0xFF_u8 {
+			for m in 0..=0xFF_u8 {
+				for c in [false, true] {
+					cpu.a = a;
+					cpu.nv_bdizc = Cpu::UNUSED_FLAG | if decimal {Cpu::DECIMAL_FLAG} else {0} | c as u8;
+					cpu.alu(mnemonic, m);
+					assert_eq!((cpu.a, cpu.nv_bdizc & FLAGS), reference(a, m, c), "{:?} {:?} A: 0x{:02X}, M: 0x{:02X}, C: {}, D: {}", model, mnemonic, a, m, c, decimal);
+				}
+			}
+		}
+	}
+	
+	#[tokio::test]
+	async fn nmos_binary() {
+		check(CpuModel::Nmos6502 {undocumented: false}, false, Mnemonic::ADC, adc_binary);
+		check(CpuModel::Nmos6502 {undocumented: false}, false, Mnemonic::SBC, sbc_binary);
+	}
+	#[tokio::test]
+	async fn nmos_decimal() {
+		check(CpuModel::Nmos6502 {undocumented: false}, true, Mnemonic::ADC, adc_decimal_nmos);
+		check(CpuModel::Nmos6502 {undocumented: false}, true, Mnemonic::SBC, sbc_decimal_nmos);
+	}
+	#[tokio::test]
+	async fn cmos_binary() {
+		check(CpuModel::Wdc65C02, false, Mnemonic::ADC, adc_binary);
+		check(CpuModel::Wdc65C02, false, Mnemonic::SBC, sbc_binary);
+	}
+	#[tokio::test]
+	async fn cmos_decimal() {
+		check(CpuModel::Wdc65C02, true, Mnemonic::ADC, adc_decimal_cmos);
+		check(CpuModel::Wdc65C02, true, Mnemonic::SBC, sbc_decimal_cmos);
+	}
+	#[tokio::test]
+	async fn ricoh_ignores_decimal() {
+		check(CpuModel::Ricoh2A03 {undocumented: false}, true, Mnemonic::ADC, adc_binary);
+		check(CpuModel::Ricoh2A03 {undocumented: false}, true, Mnemonic::SBC, sbc_binary);
+	}
+	///A few results from the real chips, so the references are checked too
+	#[test]
+	fn known_results() {
+		assert_eq!(adc_decimal_nmos(0x58, 0x46, true), (0x05, Cpu::NEGATIVE_FLAG | Cpu::OVERFLOW_FLAG | Cpu::CARRY_FLAG));
+		assert_eq!(adc_decimal_nmos(0x99, 0x01, false), (0x00, Cpu::NEGATIVE_FLAG | Cpu::CARRY_FLAG));
+		assert_eq!(adc_decimal_cmos(0x99, 0x01, false), (0x00, Cpu::ZERO_FLAG | Cpu::CARRY_FLAG));
+		assert_eq!(adc_decimal_nmos(0x79, 0x00, true), (0x80, Cpu::NEGATIVE_FLAG | Cpu::OVERFLOW_FLAG));
+		assert_eq!(sbc_decimal_nmos(0x46, 0x12, true), (0x34, Cpu::CARRY_FLAG));
+		assert_eq!(sbc_decimal_nmos(0x12, 0x21, true), (0x91, Cpu::NEGATIVE_FLAG));
+		assert_eq!(sbc_decimal_cmos(0x00, 0x01, true), (0x99, Cpu::NEGATIVE_FLAG));
+		assert_eq!(adc_binary(0x7F, 0x01, false), (0x80, Cpu::NEGATIVE_FLAG | Cpu::OVERFLOW_FLAG));
+		assert_eq!(sbc_binary(0x00, 0x01, true), (0xFF, Cpu::NEGATIVE_FLAG));
+	}
 }
\ No newline at end of file
@@ -0,0 +1,166 @@
+use {
+	crate::hardware::{
+		cpu::{AddrMode, CpuModel, Mnemonic, Opcode},
+		syscall::SyscallTable
+	},
+	std::fmt::{self, Display, Formatter}
+};
+
+/**An instruction decoded from memory, or a byte that isn't one.*/
+#[derive(Debug, PartialEq, Clone)]
+pub struct Instruction {
+	///Address of the first byte
+	pub address: u16,
+	///The opcode and its operands, or the byte that couldn't be decoded
+	pub bytes: Vec<u8>,
+	///None if the byte isn't an opcode on the model, or the operands run past the end of the bytes
+	pub opcode: Option<Opcode>,
+	///6502 syntax like `LDA $00EB`, or `DAT $02` for a byte that isn't code
+	pub text: String
+}
+
+impl Instruction {
+	///Whether the bytes decoded to an instruction
+	pub fn is_valid(&self) -> bool {
+		self.opcode.is_some()
+	}
+}
+
+impl Display for Instruction {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let bytes: String = self.bytes.iter().map(|byte| {format!("{:02X}", byte)}).collect::<Vec<String>>().join(" ");
+		write!(f, "{:04X}  {:<8}  {}", self.address, bytes, self.text)?;
+		if !self.is_valid() {
+			write!(f, "\t;not valid code")?;
+		}
+		Ok(())
+	}
+}
+
+/**Decodes bytes that start at an address into instructions, one after another. A byte that isn't an opcode on the model
+is marked as data and decoding goes on from the next byte.
+SYS operands depend on X when the program runs, so SYS is given the operands of the system call selected by the last
+LDX immediate if there's a table, and none otherwise.*/
+pub fn disassemble(bytes: &[u8], start: u16, model: CpuModel, syscalls: Option<&SyscallTable>) -> Vec<Instruction> {
+	let mut instructions: Vec<Instruction> = Vec::new();
+	let mut last_x: Option<u8> = None;
+	let mut i: usize = 0;
+	while i < bytes.len() {
+		let address: u16 = start.wrapping_add(i as u16);
+		let opcode: Option<Opcode> = Opcode::from(bytes[i], model);
+		let operand_len: usize = match opcode {
+			Some(Opcode {mnemonic: Mnemonic::SYS, ..}) => {
+				syscalls.zip(last_x).and_then(|(table, x)| {table.get(x)}).map_or(0, |call| {call.operands as usize})
+			}
+			Some(opcode) => {opcode.mode.operand_len() as usize}
+			None => {0}
+		};
+		let instruction: Instruction = match opcode {
+			Some(opcode) if i + operand_len < bytes.len() => {
+				let operands: &[u8] = &bytes[i + 1..i + 1 + operand_len];
+				last_x = match (opcode.mnemonic, opcode.mode) {
+					(Mnemonic::LDX, AddrMode::Immediate) => {Some(operands[0])}
+					(Mnemonic::LDX | Mnemonic::TAX | Mnemonic::TSX | Mnemonic::INX | Mnemonic::DEX | Mnemonic::PLX | Mnemonic::LAX | Mnemonic::SBX, _) => {None}
+					_ => {last_x}
+				};
+				Instruction {address, bytes: bytes[i..i + 1 + operand_len].to_vec(), opcode: Some(opcode), text: instruction_text(opcode, operands, address)}
+			}
+			_ => {Instruction {address, bytes: vec![bytes[i]], opcode: None, text: format!("DAT ${:02X}", bytes[i])}}
+		};
+		i += instruction.bytes.len();
+		instructions.push(instruction);
+	}
+	return instructions;
+}
+
+/**Writes an instruction at an address in 6502 syntax. Relative branches show the address they go to.
+A SYS is written with as many operands as it's given, as an immediate for one byte and an address for two.*/
+pub fn instruction_text(opcode: Opcode, operands: &[u8], address: u16) -> String {
+	let byte: u8 = operands.first().copied().unwrap_or(0x00);
+	let word: u16 = u16::from_le_bytes([byte, operands.get(1).copied().unwrap_or(0x00)]);
+	let operand: String = if opcode.mnemonic == Mnemonic::SYS {
+		match operands.len() {
+			0 => {String::new()}
+			1 => {format!("#${:02X}", byte)}
+			_ => {format!("${:04X}", word)}
+		}
+	} else {
+		match opcode.mode {
+			AddrMode::Implied => {String::new()}
+			AddrMode::Accumulator => {String::from("A")}
+			AddrMode::Immediate => {format!("#${:02X}", byte)}
+			AddrMode::ZeroPage => {format!("${:02X}", byte)}
+			AddrMode::ZeroPageX => {format!("${:02X},X", byte)}
+			AddrMode::ZeroPageY => {format!("${:02X},Y", byte)}
+			AddrMode::Absolute => {format!("${:04X}", word)}
+			AddrMode::AbsoluteX => {format!("${:04X},X", word)}
+			AddrMode::AbsoluteY => {format!("${:04X},Y", word)}
+			AddrMode::Indirect => {format!("(${:04X})", word)}
+			AddrMode::IndexedIndirect => {format!("(${:02X},X)", byte)}
+			AddrMode::IndirectIndexed => {format!("(${:02X}),Y", byte)}
+			AddrMode::Relative => {format!("${:04X}", address.wrapping_add(2).wrapping_add(byte as i8 as u16))}
+			AddrMode::ZeroPageIndirect => {format!("(${:02X})", byte)}
+			AddrMode::AbsoluteIndexedIndirect => {format!("(${:04X},X)", word)}
+		}
+	};
+	if operand.is_empty() {
+		return format!("{:?}", opcode.mnemonic);
+	}
+	return format!("{:?} {}", opcode.mnemonic, operand);
+}
+
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		crate::hardware::syscall::Syscall
+	};
+	
+	const NMOS: CpuModel = CpuModel::Nmos6502 {undocumented: false};
+	
+	fn texts(bytes: &[u8], start: u16, model: CpuModel, syscalls: Option<&SyscallTable>) -> Vec<String> {
+		disassemble(bytes, start, model, syscalls).into_iter().map(|instruction| {instruction.text}).collect()
+	}
+	
+	#[test]
+	fn branch_targets() {
+		//the offset is from the instruction after the branch
+		assert_eq!(texts(&[0xD0, 0xFE, 0xF0, 0x02, 0x10, 0x80], 0x0200, NMOS, None), ["BNE $0200", "BEQ $0206", "BPL $0186"]);
+		//backward from the bottom of memory, and forward from the top, wrap around
+		assert_eq!(texts(&[0xF0, 0x80, 0x30, 0xF0], 0x0000, NMOS, None), ["BEQ $FF82", "BMI $FFF4"]);
+		assert_eq!(texts(&[0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0x90, 0x7F], 0xFFF8, NMOS, None)[6], "BCC $007F");
+		assert_eq!(texts(&[0x80, 0xFC], 0x0001, CpuModel::Wdc65C02, None), ["BRA $FFFF"]);
+	}
+	
+	#[test]
+	fn invalid_bytes_depend_on_the_model() {
+		let bytes: [u8; 5] = [0x80, 0x02, 0xDA, 0xA7, 0x10];
+		//a byte that isn't an opcode is data, and so is one whose operands run past the end
+		let documented: Vec<&str> = vec!["DAT $80", "DAT $02", "DAT $DA", "DAT $A7", "DAT $10"];
+		assert_eq!(texts(&bytes, 0x0000, NMOS, None), documented);
+		assert_eq!(texts(&bytes, 0x0000, CpuModel::Ricoh2A03 {undocumented: false}, None), documented);
+		assert_eq!(texts(&bytes, 0x0000, CpuModel::Nmos6502 {undocumented: true}, None), ["NOP #$02", "NOP", "LAX $10"]);
+		assert_eq!(texts(&bytes, 0x0000, CpuModel::Ricoh2A03 {undocumented: true}, None), ["NOP #$02", "NOP", "LAX $10"]);
+		assert_eq!(texts(&bytes, 0x0000, CpuModel::Wdc65C02, None), ["BRA $0004", "PHX", "DAT $A7", "DAT $10"]);
+		
+		let decoded: Vec<Instruction> = disassemble(&bytes, 0x0000, NMOS, None);
+		assert!(decoded.iter().all(|instruction| {!instruction.is_valid() && instruction.bytes.len() == 1}));
+		assert_eq!(decoded[4].to_string(), "0004  10        DAT $10\t;not valid code");
+		assert_eq!(disassemble(&[0xAD, 0x34, 0x12], 0xC000, NMOS, None)[0].to_string(), "C000  AD 34 12  LDA $1234");
+	}
+	
+	#[test]
+	fn syscall_operands() {
+		let mut table: SyscallTable = SyscallTable::default();
+		table.register(0x20, Syscall::new("Test", 1, vec![], vec![], 1, |_| {true}));
+		//X is known from the LDX immediate before each SYS: print string takes an address, print hex nothing, and the test call a byte
+		let bytes: [u8; 15] = [0xA2, 0x03, 0xFF, 0x00, 0x03, 0xA2, 0x01, 0xFF, 0xA2, 0x20, 0xFF, 0x42, 0xE8, 0xFF, 0xEA];
+		assert_eq!(texts(&bytes, 0x0000, NMOS, Some(&table)), ["LDX #$03", "SYS $0300", "LDX #$01", "SYS", "LDX #$20", "SYS #$42", "INX", "SYS", "NOP"]);
+		//after INX, or without a table, X isn't known and the SYS takes no operands
+		assert_eq!(texts(&bytes[..5], 0x0000, NMOS, None), ["LDX #$03", "SYS", "BRK", "DAT $03"]);
+		//SYS is $FF on every model
+		for model in [CpuModel::Nmos6502 {undocumented: true}, CpuModel::Wdc65C02, CpuModel::Ricoh2A03 {undocumented: false}] {
+			assert_eq!(texts(&bytes[..5], 0x0000, model, Some(&table)), ["LDX #$03", "SYS $0300"]);
+		}
+	}
+}
\ No newline at end of file
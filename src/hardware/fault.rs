@@ -0,0 +1,94 @@
+use std::fmt::{self, Display, Formatter};
+
+/**Problems on the channels between the cache and the memory modules.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BusError {
+	///The receiver's buffer still holds a request or response that hasn't been handled
+	Full,
+	///The other end of the channel was dropped
+	Disconnected,
+	///A request or response showed up that the receiver wasn't waiting for
+	UnexpectedEvent
+}
+
+impl Display for BusError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			BusError::Full => {write!(f, "memory bus buffer full")}
+			BusError::Disconnected => {write!(f, "memory bus disconnected")}
+			BusError::UnexpectedEvent => {write!(f, "unexpected event on the memory bus")}
+		}
+	}
+}
+
+/**The reason the CPU stopped.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FaultKind {
+	///The opcode byte isn't an instruction on the CPU model being emulated
+	InvalidOpcode,
+	///SYS was executed with an X register that isn't a system call
+	InvalidSyscall {x: u8},
+	///The cache couldn't talk to memory
+	Bus(BusError),
+	///An interrupt was raised with an IRQ number that no I/O device has
+	UnknownDevice {iqr: u8}
+}
+
+/**Recorded by the CPU when it halts on something it can't run. The CPU keeps its registers, and memory is left as it was,
+so they can still be inspected after System::start returns.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CpuFault {
+	pub kind: FaultKind,
+	///Address of the instruction that faulted
+	pub pc: u16,
+	///None if the fault wasn't caused by an instruction
+	pub opcode: Option<u8>,
+	///CPU clock cycle the fault happened on
+	pub cycle: u128
+}
+
+impl Display for CpuFault {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self.kind {
+			FaultKind::InvalidOpcode => {write!(f, "invalid opcode")?;}
+			FaultKind::InvalidSyscall {x} => {write!(f, "invalid system call X: 0x{:02X}", x)?;}
+			FaultKind::Bus(error) => {write!(f, "{}", error)?;}
+			FaultKind::UnknownDevice {iqr} => {write!(f, "interrupt from unknown I/O device IQR: {}", iqr)?;}
+		}
+		write!(f, " at PC: 0x{:04X}", self.pc)?;
+		if let Some(opcode) = self.opcode {
+			write!(f, ", Opcode: 0x{:02X}", opcode)?;
+		}
+		write!(f, ", Cycle: {}", self.cycle)
+	}
+}
+
+impl std::error::Error for CpuFault {}
+
+/**Errors returned to whoever is running the System.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EmulatorError {
+	///The CPU halted on a fault
+	Cpu(CpuFault),
+	///A memory module, or the host loading and dumping memory, couldn't use the memory bus
+	Memory(BusError)
+}
+
+impl Display for EmulatorError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			EmulatorError::Cpu(fault) => {write!(f, "CPU fault: {}", fault)}
+			EmulatorError::Memory(error) => {write!(f, "Memory error: {}", error)}
+		}
+	}
+}
+
+impl std::error::Error for EmulatorError {}
+
+impl From<CpuFault> for EmulatorError {
+	fn from(fault: CpuFault) -> Self {EmulatorError::Cpu(fault)}
+}
+
+impl From<BusError> for EmulatorError {
+	fn from(error: BusError) -> Self {EmulatorError::Memory(error)}
+}
\ No newline at end of file
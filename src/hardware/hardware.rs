@@ -1,19 +1,17 @@
-use crate::lib;
+use crate::log::{self, Level};
 
 /**Metadata for Hardware objects.*/
 pub struct HardwareSpecs {
 	pub id: u8,
-	pub(crate) name: String,
-	pub debug: bool
+	pub(crate) name: String
 }
 
 impl HardwareSpecs {
-	/**Creates a new instance of HardwareSpecs. Defaults id to 0 and debug to true.*/
+	/**Creates a new instance of HardwareSpecs. Defaults id to 0.*/
 	pub fn new(name: &str) -> Self {
 		return Self {
 			id: 0,
-			name: String::from(name),
-			debug: true
+			name: String::from(name)
 		};
 	}
 }
@@ -21,11 +19,20 @@ impl HardwareSpecs {
 pub trait Hardware {
 	/**Returns the HardwareSpecs of this object. This function exists because there's no inheritance in Rust.*/
 	fn get_specs(&self) -> &HardwareSpecs;
-	/**Logs a message to the console with a specific format with hardware specs. Use this instead of println!() when printing.
+	/**Logs a message at the info level with the hardware specs. Use this instead of println!() when printing.
 	I tried to use impl Into<String> but that's not object safe for traits that impl Hardware*/
 	fn log(&self, message: &str) {
-		if self.get_specs().debug {
-			println!("ID: {} - Name: {} - Time: {:?} - Message: {}", self.get_specs().id, self.get_specs().name, lib::elapsed_ms(), message);
+		log::write(self.get_specs().id, &self.get_specs().name, Level::Info, format_args!("{}", message));
+	}
+	/**Logs the message the closure makes, which is only called if this part logs at the level.
+	Use this for anything logged every cycle or instruction, so nothing is formatted while logging is off.*/
+	fn log_at(&self, level: Level, message: impl FnOnce() -> String) where Self: Sized {
+		if log::enabled(&self.get_specs().name, level) {
+			log::write(self.get_specs().id, &self.get_specs().name, level, format_args!("{}", message()));
 		}
 	}
+	///Like log, but at the warn level, which goes to stderr, because it reports a problem in the emulated program
+	fn warn(&self, message: &str) {
+		log::write(self.get_specs().id, &self.get_specs().name, Level::Warn, format_args!("{}", message));
+	}
 }
\ No newline at end of file
@@ -0,0 +1,313 @@
+use {
+	crate::hardware::{
+		cache::{CacheChanges, CacheCounters},
+		cpu::{Cpu, CpuState, CpuDelta},
+		memory::{Memory, MemEvent, N_WAYS},
+		fault::BusError
+	},
+	std::collections::VecDeque
+};
+
+/**A write the CPU made, kept by the history.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MemoryWrite {
+	pub address: u16,
+	pub value: u8,
+	///Address of the instruction that made the write, or the PC for interrupts
+	pub pc: u16
+}
+
+///What a clock cycle changed, and the bus before it
+struct Record {
+	///CPU clock count before the cycle
+	cycle: u128,
+	///What the CPU had before the cycle that it didn't have after it. Empty for the last record until the next cycle starts.
+	cpu: CpuDelta,
+	///What the cache changed in the cycle, with the values from before
+	cache: CacheChanges,
+	cache_counters: CacheCounters,
+	///Way, request waiting for memory, and response waiting for the cache, on the ways of the bus that had one
+	bus: Vec<(usize, Option<MemEvent>, Option<MemEvent>)>,
+	faults: [Option<BusError>; N_WAYS as usize],
+	///Way, index, and old value of each RAM write, oldest first. Writes made by the host between cycles are included.
+	journal: Vec<(usize, u16, u8)>,
+	///Writes the CPU made in the cycle
+	writes: Vec<MemoryWrite>
+}
+
+impl Record {
+	///Bytes used by the record, including the heap
+	fn size(&self) -> usize {
+		size_of::<Self>() + self.cpu.heap_size() + self.cache.heap_size()
+			+ self.bus.len() * size_of::<(usize, Option<MemEvent>, Option<MemEvent>)>()
+			+ self.journal.len() * size_of::<(usize, u16, u8)>()
+			+ self.writes.len() * size_of::<MemoryWrite>()
+	}
+}
+
+/**Keeps a record of every clock cycle so the system can go back to the state before it. A record only holds what the cycle
+changed: the parts of the CPU's registers and pipeline, the cache lines and bytes, and the RAM bytes, each with the value
+it replaced, along with the bus. The CPU's changes are found by comparing it with a copy from the start of the cycle.
+The oldest records are dropped to stay within the budget.
+Going back doesn't take back what the host did: printed output stays printed, keys that were read stay read,
+and trace lines stay written. A system call that was partway done picks up from its SyscallContext, including the scratch
+bytes the built-in line reads keep their place in, but anything a handler keeps in its own closure isn't taken back.*/
+#[derive(Default)]
+pub struct History {
+	///Most bytes the records can use. Nothing is recorded when it's 0.
+	budget: usize,
+	///Bytes used by the records
+	used: usize,
+	///Oldest first
+	records: VecDeque<Record>,
+	///The CPU before the last cycle recorded, which the next cycle is compared with
+	last: Option<CpuState>
+}
+
+impl History {
+	///Sets the bytes the records can use, dropping the oldest ones if they don't fit. 0 stops recording and drops them all.
+	pub fn set_budget(&mut self, budget: usize, cpu: &mut Cpu, memory: &mut [Memory; N_WAYS as usize]) {
+		self.budget = budget;
+		cpu.record_writes(budget > 0);
+		cpu.mmu.cache.record_changes(budget > 0);
+		memory.iter_mut().for_each(|mem| {mem.record_writes(budget > 0);});
+		self.trim();
+	}
+	pub fn budget(&self) -> usize {
+		self.budget
+	}
+	///Bytes used by the records
+	pub fn used(&self) -> usize {
+		self.used
+	}
+	///Number of cycles that can be gone back through
+	pub fn len(&self) -> usize {
+		self.records.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.records.is_empty()
+	}
+	///The CPU clock count and instruction count before the last cycle recorded
+	pub fn previous(&self) -> Option<(u128, u128)> {
+		self.last.as_ref().map(|last| {(last.cpu_clock_counter, last.instruction_counter)})
+	}
+	///The CPU clock count of the oldest state that can be gone back to
+	pub fn oldest(&self) -> Option<u128> {
+		self.records.front().map(|record| {record.cycle})
+	}
+	///Drops every record, like after the memory was changed without the history knowing
+	pub fn clear(&mut self) {
+		self.records.clear();
+		self.last = None;
+		self.used = 0;
+	}
+	
+	///Called by the clock before every cycle
+	pub(super) fn checkpoint(&mut self, cpu: &mut Cpu, memory: &mut [Memory; N_WAYS as usize]) {
+		if self.budget == 0 {return;}
+		self.collect(cpu, memory);
+		//the channels can't be peeked at, so whatever is waiting is taken out and sent again
+		let bus: Vec<(usize, Option<MemEvent>, Option<MemEvent>)> = memory.iter_mut().zip(cpu.mmu.cache.memory.iter_mut()).enumerate().filter_map(|(way, (mem, (tx, rx)))| {
+			let request: Option<MemEvent> = mem.take_request();
+			if let Some(request) = &request {
+				let _ = tx.try_send(request.clone());
+			}
+			let response: Option<MemEvent> = rx.try_recv().ok();
+			if let Some(response) = &response {
+				mem.respond(response.clone());
+			}
+			(request.is_some() || response.is_some()).then_some((way, request, response))
+		}).collect();
+		let state: CpuState = cpu.state();
+		if let (Some(record), Some(last)) = (self.records.back_mut(), &self.last) {
+			record.cpu = last.changes(&state);
+			self.used += record.cpu.heap_size();
+		}
+		let record: Record = Record {
+			cycle: state.cpu_clock_counter,
+			cpu: CpuDelta::default(),
+			cache: CacheChanges::default(),
+			cache_counters: cpu.mmu.cache.counters(),
+			bus,
+			faults: memory.each_ref().map(|mem| {mem.fault}),
+			journal: Vec::new(),
+			writes: Vec::new()
+		};
+		self.last = Some(state);
+		self.used += record.size();
+		self.records.push_back(record);
+		self.trim();
+	}
+	/**Goes back to the state before the last cycle recorded, and returns the writes the CPU made in that cycle.
+	Returns None if there's nothing to go back to.*/
+	pub fn step_back(&mut self, cpu: &mut Cpu, memory: &mut [Memory; N_WAYS as usize]) -> Option<Vec<MemoryWrite>> {
+		self.collect(cpu, memory);
+		let record: Record = self.records.pop_back()?;
+		self.used -= record.size();
+		let state: CpuState = self.last.take().expect("The CPU before the last cycle recorded is gone");
+		if let Some(previous) = self.records.back_mut() {
+			let mut last: CpuState = state.clone();
+			self.used -= previous.cpu.heap_size();
+			last.undo(std::mem::take(&mut previous.cpu));
+			self.last = Some(last);
+		}
+		for (way, index, old) in record.journal.into_iter().rev() {
+			memory[way].undo_write(index, old);
+		}
+		for (i, (_, rx)) in cpu.mmu.cache.memory.iter_mut().enumerate() {
+			memory[i].take_request();
+			let _ = rx.try_recv();
+			memory[i].fault = record.faults[i];
+		}
+		for (way, request, response) in record.bus {
+			if let Some(request) = request {
+				let _ = cpu.mmu.cache.memory[way].0.try_send(request);
+			}
+			if let Some(response) = response {
+				memory[way].respond(response);
+			}
+		}
+		cpu.mmu.cache.undo(record.cache);
+		cpu.mmu.cache.set_counters(record.cache_counters);
+		cpu.restore(state);
+		return Some(record.writes);
+	}
+	
+	///Adds the writes and cache changes since the last checkpoint to the last record. They're dropped if there isn't one.
+	fn collect(&mut self, cpu: &mut Cpu, memory: &mut [Memory; N_WAYS as usize]) {
+		let writes: Vec<MemoryWrite> = cpu.take_writes().into_iter().map(|(address, value, pc)| {MemoryWrite {address, value, pc}}).collect();
+		let journal: Vec<(usize, u16, u8)> = memory.iter_mut().enumerate().flat_map(|(way, mem)| {
+			mem.take_journal().into_iter().map(move |(index, old)| {(way, index, old)})
+		}).collect();
+		let cache: CacheChanges = cpu.mmu.cache.take_journal();
+		let Some(record) = self.records.back_mut() else {return;};
+		let before: usize = record.size();
+		record.writes.extend(writes);
+		record.journal.extend(journal);
+		record.cache.extend(cache);
+		self.used = self.used + record.size() - before;
+	}
+	///Drops the oldest records until the rest fit in the budget
+	fn trim(&mut self) {
+		while self.used > self.budget {
+			let Some(record) = self.records.pop_front() else {break;};
+			self.used -= record.size();
+		}
+		if self.records.is_empty() {
+			self.last = None;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		crate::{
+			system::System,
+			hardware::{cache::LineInfo, cpu::CpuModel}
+		},
+		std::sync::mpsc::{Receiver, channel}
+	};
+	
+	///Everything going back should take back
+	struct Snapshot {
+		cpu: CpuState,
+		lines: Vec<LineInfo>,
+		counters: CacheCounters,
+		ram: Vec<u8>
+	}
+	
+	impl Snapshot {
+		fn new(system: &System) -> Self {
+			Self {
+				cpu: system.clock.cpu.state(),
+				lines: system.clock.cpu.mmu.cache.lines(),
+				counters: system.clock.cpu.mmu.cache.counters(),
+				ram: system.clock.memory.iter().flat_map(|mem| {(0..0x10000 / N_WAYS as usize).map(|i| {mem.peek(i as u16)})}).collect()
+			}
+		}
+		fn assert_eq(&self, other: &Self) {
+			//the CPU keeps a binary heap, so it's compared by the changes between the two
+			assert_eq!(self.cpu.changes(&other.cpu).heap_size(), 0, "The CPU is different at cycle {}", other.cpu.cpu_clock_counter);
+			assert_eq!(self.lines, other.lines);
+			assert_eq!(self.counters, other.counters);
+			assert!(self.ram == other.ram, "The RAM is different at cycle {}", other.cpu.cpu_clock_counter);
+		}
+	}
+	
+	///The demo with its output sent to a channel
+	fn demo(budget: usize) -> (System, Receiver<String>) {
+		let mut system: System = System::new(CpuModel::Nmos6502 {undocumented: false});
+		let (sender, output) = channel::<String>();
+		system.clock.cpu.syscalls.output.redirect(sender);
+		system.load_main_program(0x0000, crate::SORT_PROGRAM).unwrap();
+		system.clock.set_history_budget(budget);
+		(system, output)
+	}
+	fn run(system: &mut System, cycles: usize) {
+		for _ in 0..cycles {
+			system.cycle().unwrap();
+		}
+	}
+	
+	#[tokio::test]
+	async fn step_back_and_run_again() {
+		const BEFORE: usize = 1500;
+		const BACK: usize = 700;
+		let (mut system, output) = demo(usize::MAX);
+		run(&mut system, BEFORE);
+		let before: Snapshot = Snapshot::new(&system);
+		let printed: String = output.try_iter().collect();
+		run(&mut system, BACK);
+		let after: Snapshot = Snapshot::new(&system);
+		let printed_after: String = output.try_iter().collect();
+		assert!(before.ram != after.ram && before.lines != after.lines, "The demo should be sorting in the cycles that are gone back through");
+		assert_eq!(system.clock.history.len(), BEFORE + BACK);
+		for _ in 0..BACK {
+			assert!(system.clock.step_back().is_some());
+		}
+		before.assert_eq(&Snapshot::new(&system));
+		assert_eq!(system.clock.history.previous().map(|(cycle, _)| {cycle}), Some(BEFORE as u128 - 1));
+		run(&mut system, BACK);
+		after.assert_eq(&Snapshot::new(&system));
+		//going back doesn't take back the output, so it's printed again
+		assert_eq!(output.try_iter().collect::<String>(), printed_after);
+		
+		//all the way back to the start
+		while system.clock.step_back().is_some() {}
+		assert!(system.clock.history.is_empty());
+		assert_eq!(system.clock.cpu.cpu_clock_counter, 0);
+		run(&mut system, BEFORE);
+		before.assert_eq(&Snapshot::new(&system));
+		assert_eq!(output.try_iter().collect::<String>(), printed);
+	}
+	
+	#[tokio::test]
+	async fn trimmed_to_the_budget() {
+		const BUDGET: usize = 64 * 1024;
+		let (mut system, _output) = demo(BUDGET);
+		run(&mut system, 5000);
+		let history: &History = &system.clock.history;
+		assert!(history.used() <= BUDGET);
+		assert!(!history.is_empty() && history.len() < 5000);
+		let oldest: u128 = history.oldest().unwrap();
+		assert_eq!(oldest, 5000 - history.len() as u128);
+		let mut replay: System = demo(0).0;
+		run(&mut replay, oldest as usize);
+		while system.clock.step_back().is_some() {}
+		assert_eq!(system.clock.cpu.cpu_clock_counter, oldest);
+		Snapshot::new(&replay).assert_eq(&Snapshot::new(&system));
+		
+		//a smaller budget drops the oldest records, and 0 drops them all
+		run(&mut system, 1000);
+		system.clock.set_history_budget(BUDGET / 4);
+		assert!(system.clock.history.used() <= BUDGET / 4);
+		assert!(system.clock.history.oldest().unwrap() > oldest);
+		system.clock.set_history_budget(0);
+		assert!(system.clock.history.is_empty());
+		assert_eq!(system.clock.history.used(), 0);
+		run(&mut system, 10);
+		assert!(system.clock.step_back().is_none());
+	}
+}
\ No newline at end of file
@@ -16,26 +16,29 @@ pub trait Interrupt: Hardware {
 pub struct InterruptSpecs {
 	pub iqr: u8,
 	pub priority: u8,
-	pub name: String
+	pub name: String,
+	///Non-maskable interrupts go through the NMI vector and ignore the interrupt flag
+	pub nmi: bool
 }
 
 impl InterruptSpecs {
-	pub fn new(iqr: u8, priority: u8, name: &str) -> Self {
+	pub fn new(iqr: u8, priority: u8, name: &str, nmi: bool) -> Self {
 		Self {
 			iqr,
 			priority,
-			name: String::from(name)
+			name: String::from(name),
+			nmi
 		}
 	}
 }
-//Traits needed for BinaryHeap comparison. All comparisons check the value of the priority and nothing else.
+//Traits needed for BinaryHeap comparison. NMIs always come first, then the value of the priority is compared.
 impl Eq for InterruptSpecs {}
 impl PartialEq<Self> for InterruptSpecs {
-	fn eq(&self, other: &Self) -> bool {PartialEq::eq(&self.priority, &other.priority)}
+	fn eq(&self, other: &Self) -> bool {self.cmp(other) == Ordering::Equal}
 }
 impl PartialOrd<Self> for InterruptSpecs {
-	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {PartialOrd::partial_cmp(&self.priority, &other.priority)}
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {Some(self.cmp(other))}
 }
 impl Ord for InterruptSpecs {
-	fn cmp(&self, other: &Self) -> Ordering {Ord::cmp(&self.priority, &other.priority)}
+	fn cmp(&self, other: &Self) -> Ordering {Ord::cmp(&(self.nmi, self.priority), &(other.nmi, other.priority))}
 }
\ No newline at end of file
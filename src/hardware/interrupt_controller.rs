@@ -2,7 +2,7 @@ use {
 	crate::hardware::{
 		hardware::{Hardware, HardwareSpecs},
 		imp::interrupt::{Interrupt, InterruptSpecs},
-		keyboard::Keyboard
+		keyboard::{Keyboard, KeyBuffer}
 	},
 	std::{
 		collections::{BinaryHeap, HashMap},
@@ -16,13 +16,19 @@ use {
 
 /**The io_rx receives InterruptSpecs from any I/O device "connected". At the end of every instruction cycle,
 the CPU gathers all the received InterruptSpecs from the io_rx and pushes them into the priority queue if any exist.
-Immediately after, the CPU pops an InterruptSpecs from the priority queue, uses its IQR to lookup the Interrupt device in the io_devices HashMap,
-and reads the value in that devices output buffer.*/
+Immediately after, if the interrupt can be taken, the CPU pops an InterruptSpecs from the priority queue, uses its IQR to lookup the Interrupt device
+in the io_devices HashMap, copies the value in that devices output buffer into the data latch, and jumps through the IRQ or NMI vector.*/
 pub struct InterruptController {
 	specs: HardwareSpecs,
 	pub priority_queue: BinaryHeap<InterruptSpecs>,
 	pub io_devices: HashMap<u8, Box<dyn Interrupt>>,
 	pub io_rx: UnboundedReceiver<InterruptSpecs>,
+	///Output buffer of the last device that interrupted the CPU
+	pub data_latch: u8,
+	///Keys typed on the keyboard, for the input system calls
+	pub key_buf: KeyBuffer,
+	///The keyboard ignores the console while this is false
+	pub keyboard_listening: Arc<AtomicBool>,
 	running: Arc<AtomicBool>
 }
 
@@ -31,13 +37,18 @@ impl InterruptController {
 		let mut map: HashMap<u8, Box<dyn Interrupt>> = HashMap::new();
 		let (tx, rx) = unbounded_channel::<InterruptSpecs>();
 		let running: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
-		let keyboard: Box<Keyboard> = Box::new(Keyboard::new(tx.clone(), running.clone()));
+		let keyboard_listening: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
+		let keyboard: Box<Keyboard> = Box::new(Keyboard::new(tx.clone(), running.clone(), keyboard_listening.clone()));
+		let key_buf: KeyBuffer = keyboard.key_buf.clone();
 		map.insert(keyboard.get_interrupt_specs().iqr, keyboard);
 		let int_ctrl: Self = Self {
 			specs: HardwareSpecs::new("Interrupt Controller"),
 			priority_queue: BinaryHeap::new(),
 			io_devices: map,
 			io_rx: rx,
+			data_latch: 0x00,
+			key_buf,
+			keyboard_listening,
 			running
 		};
 		int_ctrl.log("Created");
@@ -8,8 +8,10 @@ use {
 	},
 	crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
 	std::{
+		collections::VecDeque,
 		sync::{
 			Arc,
+			Mutex,
 			atomic::{AtomicBool, AtomicU8, Ordering}
 		},
 		time::Duration
@@ -17,10 +19,14 @@ use {
 	tokio::sync::mpsc::UnboundedSender
 };
 
+///The keys typed, in order, until the input system calls take them. It holds Keyboard::BUFFER_LEN keys at most.
+pub type KeyBuffer = Arc<Mutex<VecDeque<u8>>>;
+
 pub struct Keyboard {
 	hard_specs: HardwareSpecs,
 	int_specs: InterruptSpecs,
-	pub out_buf: Arc<AtomicU8>
+	pub out_buf: Arc<AtomicU8>,
+	pub key_buf: KeyBuffer
 }
 
 impl Hardware for Keyboard {
@@ -33,24 +39,48 @@ impl Interrupt for Keyboard {
 }
 
 impl Keyboard {
-	pub fn new(tx: UnboundedSender<InterruptSpecs>, running: Arc<AtomicBool>) -> Self {
+	///How often the keyboard checks whether it can listen again while something else is reading the console
+	const IDLE_INTERVAL: Duration = Duration::from_millis(10);
+	///Most keys kept for the input system calls. Once it's full, the oldest key is dropped for each new one.
+	pub const BUFFER_LEN: usize = 256;
+	
+	pub fn new(tx: UnboundedSender<InterruptSpecs>, running: Arc<AtomicBool>, listening: Arc<AtomicBool>) -> Self {
 		let keyboard: Self = Self {
 			hard_specs: HardwareSpecs::new("Keyboard"),
-			int_specs: InterruptSpecs::new(0, 0, "Keyboard"),
-			out_buf: Arc::new(AtomicU8::new(0x00))
+			int_specs: InterruptSpecs::new(0, 0, "Keyboard", false),
+			out_buf: Arc::new(AtomicU8::new(0x00)),
+			key_buf: Arc::new(Mutex::new(VecDeque::with_capacity(Self::BUFFER_LEN)))
 		};
 		keyboard.log("Created");
 		//make an async task to listen for keyboard input
 		//this represents the connection between the physical keyboard and the virtual keyboard
 		let specs: InterruptSpecs = keyboard.int_specs.clone();
 		let out_buf: Arc<AtomicU8> = keyboard.out_buf.clone();
+		let key_buf: KeyBuffer = keyboard.key_buf.clone();
 		tokio::spawn(async move {
 			//keeps running until the owning InterruptController is dropped
 			while running.load(Ordering::Relaxed) {
+				//something else is reading the console, so check back later instead of keeping a core busy
+				if !listening.load(Ordering::Relaxed) {
+					tokio::time::sleep(Self::IDLE_INTERVAL).await;
+					continue;
+				}
 				if event::poll(Duration::from_secs(0)).unwrap() {
-					if let Ok(Event::Key(KeyEvent{code: KeyCode::Char(c), modifiers: _, kind: KeyEventKind::Press, state: _ })) = event::read() {
-						out_buf.store(ascii::DECODER.get(&c).unwrap_or(&0x00).clone(), Ordering::Relaxed);
-						if let Err(_) = tx.send(specs.clone()) {
+					if let Ok(Event::Key(KeyEvent{code, modifiers: _, kind: KeyEventKind::Press, state: _ })) = event::read() {
+						let c: char = match code {
+							KeyCode::Char(c) => {c}
+							KeyCode::Enter => {'\n'}
+							KeyCode::Tab => {'\t'}
+							KeyCode::Backspace => {'\x08'}
+							_ => {continue;}
+						};
+						let num: u8 = *ascii::DECODER.get(&c).unwrap_or(&0x00);
+						out_buf.store(num, Ordering::Relaxed);
+						//characters that can't be encoded still interrupt, but the input system calls don't see them
+						if num != 0x00 {
+							Self::buffer(&key_buf, num);
+						}
+						if tx.send(specs.clone()).is_err() {
 							break;
 						}
 					}
@@ -59,4 +89,29 @@ impl Keyboard {
 		});
 		keyboard
 	}
+	
+	///Adds a key for the input system calls, dropping the oldest one if the buffer is full
+	fn buffer(key_buf: &KeyBuffer, key: u8) {
+		let mut keys = key_buf.lock().expect("Keyboard buffer poisoned");
+		if keys.len() >= Self::BUFFER_LEN {
+			keys.pop_front();
+		}
+		keys.push_back(key);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn full_buffer_drops_the_oldest_keys() {
+		let key_buf: KeyBuffer = KeyBuffer::default();
+		for key in 0..Keyboard::BUFFER_LEN + 10 {
+			Keyboard::buffer(&key_buf, key as u8);
+		}
+		let keys = key_buf.lock().unwrap();
+		assert_eq!(keys.len(), Keyboard::BUFFER_LEN);
+		assert_eq!((keys.front(), keys.back()), (Some(&10), Some(&((Keyboard::BUFFER_LEN + 9) as u8))));
+	}
 }
\ No newline at end of file
@@ -1,13 +1,18 @@
 use {
-	crate::hardware::{
-		hardware::{Hardware, HardwareSpecs},
-		imp::clock_listener::ClockListener,
-		cache::INDEX_MASK
+	crate::{
+		hardware::{
+			hardware::{Hardware, HardwareSpecs},
+			imp::clock_listener::ClockListener,
+			cpu::Cpu,
+			fault::BusError
+		},
+		log::Level
 	},
-	tokio::sync::mpsc::{Sender, Receiver, error::TryRecvError}
+	tokio::sync::mpsc::{Sender, Receiver, error::{TryRecvError, TrySendError}}
 };
 
-pub const N_WAYS: u8 = INDEX_MASK + 1;
+///Memory modules that are read at once. The cache reads a line a row of the ways at a time.
+pub const N_WAYS: u8 = 8;
 
 /**Contains 0x10000 memory addresses in RAM. Interleaved n-ways.
 MAR and MDR are not variables, but are represented in the channel*/
@@ -17,7 +22,11 @@ pub struct Memory {
 	tx: Sender<MemEvent>,
 	/**Represents bus lines from Cache to Memory.*/
 	rx: Receiver<MemEvent>,
-	ram: Box<[u8; 0x10000 / N_WAYS as usize]>//unique_ptr because it's too big for the stack
+	ram: Box<[u8; 0x10000 / N_WAYS as usize]>,//unique_ptr because it's too big for the stack
+	///Set when the memory bus fails. Memory stops answering requests until it's reset.
+	pub fault: Option<BusError>,
+	///Index and old value of every write since the journal was last taken, while the history is recording
+	journal: Option<Vec<(u16, u8)>>
 }
 
 impl Hardware for Memory {
@@ -26,20 +35,34 @@ impl Hardware for Memory {
 
 impl ClockListener for Memory {
 	fn pulse(&mut self) {
-		self.log("Received clock pulse");
+		self.log_at(Level::Trace, || {String::from("Received clock pulse")});
+		if self.fault.is_some() {return;}
 		//see if the MMU requested a read or write
-		match self.rx.try_recv() {
+		let response: Result<(), TrySendError<MemEvent>> = match self.rx.try_recv() {
 			Ok(MemEvent::MemReadRequest{mar}) => {
-				self.tx.try_send(MemEvent::MemReadResponse{mdr: self.ram[mar as usize]}).expect("Cache receiver buffer full");
+				self.tx.try_send(MemEvent::MemReadResponse{mdr: self.ram[mar as usize]})
 			}
 			Ok(MemEvent::MemWriteRequest{mar, mdr}) => {
+				if let Some(journal) = &mut self.journal {
+					journal.push((mar, self.ram[mar as usize]));
+				}
 				self.ram[mar as usize] = mdr;
-				self.tx.try_send(MemEvent::MemWriteResponse).expect("Cache receiver buffer full");//Cache is supposed to clear the buffer before sending requests to memory
+				self.tx.try_send(MemEvent::MemWriteResponse)//Cache is supposed to clear the buffer before sending requests to memory
 			}
-			Err(TryRecvError::Empty) => {}//no memory action needed this cycle
-			_ => {
-				panic!("Received invalid value from memory receiver");
+			Err(TryRecvError::Empty) => {Ok(())}//no memory action needed this cycle
+			Err(TryRecvError::Disconnected) => {
+				self.fault = Some(BusError::Disconnected);
+				Ok(())
 			}
+			Ok(_) => {
+				self.fault = Some(BusError::UnexpectedEvent);
+				Ok(())
+			}
+		};
+		match response {
+			Ok(()) => {}
+			Err(TrySendError::Full(_)) => {self.fault = Some(BusError::Full);}
+			Err(TrySendError::Closed(_)) => {self.fault = Some(BusError::Disconnected);}
 		}
 	}
 }
@@ -51,6 +74,8 @@ impl Memory {
 			tx,
 			rx,
 			ram: Box::new([0x00; 0x10000 / N_WAYS as usize]),
+			fault: None,
+			journal: None
 		};
 		memory.log(format!("Created - Addressable Range: 0x{:04X}", 0x10000 / N_WAYS as usize).as_str());
 		memory
@@ -58,13 +83,41 @@ impl Memory {
 	
 	/**Fills RAM (save the reset vector) with 0x00.*/
 	pub fn reset(&mut self) {
-		for i in 0x0000..0xFFFC {
+		//the last line holds the NMI, reset, and IRQ vectors
+		let vector_line: usize = Cpu::NMI_VECTOR as usize / N_WAYS as usize;
+		for i in 0..vector_line {
 			self.ram[i] = 0x00;
 		}
+		self.fault = None;
+	}
+	
+	///Starts or stops keeping the old values of writes for the history
+	pub(super) fn record_writes(&mut self, recording: bool) {
+		self.journal = if recording {Some(Vec::new())} else {None};
+	}
+	///Takes the index and old value of the writes since the last call, oldest first
+	pub(super) fn take_journal(&mut self) -> Vec<(u16, u8)> {
+		self.journal.as_mut().map(std::mem::take).unwrap_or_default()
+	}
+	///Puts back a value without writing it in the journal
+	pub(super) fn undo_write(&mut self, index: u16, old: u8) {
+		self.ram[index as usize] = old;
+	}
+	///The value at an index of this way's RAM, without a request
+	pub(super) fn peek(&self, index: u16) -> u8 {
+		self.ram[index as usize]
+	}
+	///Takes the request the cache sent that memory hasn't read yet
+	pub(super) fn take_request(&mut self) -> Option<MemEvent> {
+		self.rx.try_recv().ok()
+	}
+	///Sends a response back to the cache, like one memory sent earlier. Returns false if there's already one waiting.
+	pub(super) fn respond(&mut self, event: MemEvent) -> bool {
+		self.tx.try_send(event).is_ok()
 	}
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MemEvent {
 	MemReadRequest{mar: u16},
 	MemWriteRequest{mar: u16, mdr: u8},
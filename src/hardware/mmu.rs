@@ -3,7 +3,11 @@ use {
 		hardware::{Hardware, HardwareSpecs},
 		imp::clock_listener::ClockListener,
 		memory::{Memory, MemEvent, N_WAYS},
-		cache::Cache
+		cache::{Cache, CacheConfig},
+		fault::BusError,
+		cpu::CpuModel,
+		syscall::SyscallTable,
+		disassembler
 	},
 	tokio::sync::mpsc::{Sender, Receiver}
 };
@@ -27,31 +31,73 @@ impl Mmu {
 		mmu
 	}
 	
-	//Startup functions that are called before the clock starts pulsing
+	//Startup functions that are called before the clock starts pulsing. Watchpoints don't fire on them, but they see the values.
 	///Takes a &\[u8] and flashes it into RAM. The first value in the slice is stored at start_addr.
-	pub fn static_load(&mut self, memory: &mut [Memory; N_WAYS as usize], values: &[u8], start_addr: u16) {
+	pub fn static_load(&mut self, memory: &mut [Memory; N_WAYS as usize], values: &[u8], start_addr: u16) -> Result<(), BusError> {
 		let mut iter = values.iter().enumerate();
-		let Some((mut i, mut val)) = iter.next() else {return;};
+		let Some((mut i, mut val)) = iter.next() else {return Ok(());};
 		loop {
-			self.cache.write(start_addr + i as u16, *val);
+			self.cache.write(start_addr + i as u16, *val)?;
+			self.cache.watchpoints.discard_hits();
 			memory.iter_mut().for_each(|mem| {mem.pulse();});
-			let Some((next_i, next_val)) = iter.next() else {return;};//advance the iterator if memory action was successful
+			Self::memory_fault(memory)?;
+			let Some((next_i, next_val)) = iter.next() else {return Ok(());};//advance the iterator if memory action was successful
 			i = next_i;
 			val = next_val;
 		}
 	}
+	///Writes back and removes every line in the cache so the next accesses go to memory
+	pub fn flush_cache(&mut self, memory: &mut [Memory; N_WAYS as usize]) -> Result<(), BusError> {
+		while !self.cache.evict_one()? {
+			memory.iter_mut().for_each(|mem| {mem.pulse();});//force memory to do its thing
+			Self::memory_fault(memory)?;
+		}
+		Ok(())
+	}
+	///Writes back every line in the cache and rebuilds it with a new shape, which must have passed CacheConfig::validate()
+	pub fn configure_cache(&mut self, memory: &mut [Memory; N_WAYS as usize], config: CacheConfig) -> Result<(), BusError> {
+		self.flush_cache(memory)?;
+		self.cache.set_config(config);
+		Ok(())
+	}
 	///Logs the values at each memory address in the range start_addr..end_addr
-	pub fn memory_dump(&mut self, memory: &mut [Memory; N_WAYS as usize], start_addr: u16, end_addr: u16) {
-		let mut iter = start_addr..end_addr;
-		let Some(mut i) = iter.next() else {return;};
-		loop {
-			if let Ok(Some(mdr)) = self.cache.read(i) {
-				self.log(format!("Address 0x{:04x}: | 0x{:02X}", i, mdr).as_str());
-				let Some(next) = iter.next() else {return;};//advance the iterator if memory action was successful
-				i = next;
+	///Logs the instructions that start from start_addr up to end_addr, not including end_addr. The last instruction may read past end_addr for its operands.
+	pub fn memory_dump(&mut self, memory: &mut [Memory; N_WAYS as usize], start_addr: u16, end_addr: u16, model: CpuModel, syscalls: &SyscallTable) -> Result<(), BusError> {
+		let len: usize = end_addr.saturating_sub(start_addr) as usize;
+		let values: Vec<u8> = self.static_read(memory, start_addr, (len + 2).min(0x10000 - start_addr as usize))?;
+		for instruction in disassembler::disassemble(&values, start_addr, model, Some(syscalls)) {
+			if instruction.address as usize >= start_addr as usize + len {break;}
+			self.log(instruction.to_string().as_str());
+		}
+		Ok(())
+	}
+	///Reads len values from RAM through the cache, starting at start_addr
+	pub fn static_read(&mut self, memory: &mut [Memory; N_WAYS as usize], start_addr: u16, len: usize) -> Result<Vec<u8>, BusError> {
+		let mut values: Vec<u8> = Vec::with_capacity(len);
+		while values.len() < len {
+			if let Some(mdr) = self.cache.read(start_addr.wrapping_add(values.len() as u16))? {
+				self.cache.watchpoints.discard_hits();
+				values.push(mdr);//advance to the next address if memory action was successful
 			} else {
 				memory.iter_mut().for_each(|mem| {mem.pulse();});//force memory to do its thing
+				Self::memory_fault(memory)?;
 			}
 		}
+		Ok(values)
+	}
+	/**Reads len values starting at start_addr from the cache, or from RAM where the line isn't cached. Nothing goes over
+	the memory bus and the cache doesn't count it, so it can be called while a program runs without changing what it does.*/
+	pub fn peek(&self, memory: &[Memory; N_WAYS as usize], start_addr: u16, len: usize) -> Vec<u8> {
+		(0..len).map(|i| {
+			let address: u16 = start_addr.wrapping_add(i as u16);
+			self.cache.peek(address).unwrap_or_else(|| {memory[(address % N_WAYS as u16) as usize].peek(address / N_WAYS as u16)})
+		}).collect()
+	}
+	///Returns the first error reported by a memory module
+	pub fn memory_fault(memory: &[Memory; N_WAYS as usize]) -> Result<(), BusError> {
+		match memory.iter().find_map(|mem| {mem.fault}) {
+			Some(error) => {Err(error)}
+			None => {Ok(())}
+		}
 	}
 }
\ No newline at end of file
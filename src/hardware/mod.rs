@@ -6,4 +6,13 @@ pub mod imp;
 mod mmu;
 pub mod keyboard;
 pub mod interrupt_controller;
-mod cache;
+pub mod fault;
+pub mod syscall;
+pub mod disassembler;
+pub mod watchpoint;
+pub mod trace;
+pub mod history;
+pub mod profiler;
+pub mod coverage;
+pub mod cache;
+pub mod replacement;
@@ -0,0 +1,157 @@
+use std::{
+	collections::HashMap,
+	fmt::Write
+};
+
+/**What the profiler counted for the instruction at one address.*/
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Counters {
+	///Times the instruction retired
+	pub executions: u128,
+	///Cycles it was the oldest instruction in the pipeline. Every cycle is given to one instruction, so these add up to the total.
+	pub cycles: u128,
+	///Cycles it spent in the IR, being fetched and decoded
+	pub ir_cycles: u128,
+	///Cycles it spent in the AR, having its effective address computed or waiting to be issued
+	pub ar_cycles: u128,
+	///Cycles it spent in an execution unit
+	pub execute_cycles: u128,
+	///Cycles it waited in the AR because of a hazard or busy execution units
+	pub stall_cycles: u128,
+	///Reads and writes that missed the cache and went to memory, including fetching the instruction
+	pub cache_misses: u128,
+	///Disassembly of the instruction the last time it was issued, since a program can change its own code
+	pub text: String
+}
+
+impl Counters {
+	fn add(&mut self, other: &Counters) {
+		self.executions += other.executions;
+		self.cycles += other.cycles;
+		self.ir_cycles += other.ir_cycles;
+		self.ar_cycles += other.ar_cycles;
+		self.execute_cycles += other.execute_cycles;
+		self.stall_cycles += other.stall_cycles;
+		self.cache_misses += other.cache_misses;
+	}
+}
+
+/**How instructions are grouped in the hot-spot report and the stacks.*/
+#[derive(Debug, PartialEq, Clone)]
+pub enum Grouping {
+	///Under the closest label at or before the address. Addresses before the first label are grouped by page.
+	Labels(Vec<(String, u16)>),
+	///In ranges of this many bytes, starting at 0x0000
+	Ranges(u16)
+}
+
+impl Grouping {
+	///Bytes in a range when there's nothing else to group by
+	pub const RANGE: u16 = 0x100;
+	
+	///Name of the group the address is in
+	fn group(&self, address: u16) -> String {
+		let size: u16 = match self {
+			Grouping::Labels(labels) => {
+				if let Some((label, _)) = labels.iter().rev().find(|(_, label_address)| {*label_address <= address}) {
+					return label.clone();
+				}
+				Self::RANGE
+			}
+			Grouping::Ranges(size) => {(*size).max(1)}
+		};
+		let start: u16 = address - address % size;
+		return format!("0x{:04X}-0x{:04X}", start, start.saturating_add(size - 1));
+	}
+}
+
+/**Counts what each instruction did, by the address of its opcode. The CPU tells it about every cycle, every instruction
+issued and retired, and every cache miss.*/
+#[derive(Default)]
+pub struct Profiler {
+	counters: HashMap<u16, Counters>,
+	///Cycles counted
+	cycles: u128
+}
+
+impl Profiler {
+	///Instructions listed under the groups in the report
+	const HOTTEST: usize = 20;
+	
+	///What was counted for each instruction, by the address of its opcode
+	pub fn counters(&self) -> &HashMap<u16, Counters> {
+		return &self.counters;
+	}
+	
+	/**Called at the end of every cycle with the instruction furthest down the pipeline (or the PC if it's empty),
+	the instructions in the IR, the AR, and the execution units, and whether the one in the AR is stalled.*/
+	pub(super) fn cycle(&mut self, oldest: u16, ir: Option<u16>, ar: Option<(u16, bool)>, executing: impl Iterator<Item = u16>) {
+		self.cycles += 1;
+		self.counters.entry(oldest).or_default().cycles += 1;
+		if let Some(address) = ir {
+			self.counters.entry(address).or_default().ir_cycles += 1;
+		}
+		if let Some((address, stalled)) = ar {
+			let counters: &mut Counters = self.counters.entry(address).or_default();
+			counters.ar_cycles += 1;
+			if stalled {
+				counters.stall_cycles += 1;
+			}
+		}
+		for address in executing {
+			self.counters.entry(address).or_default().execute_cycles += 1;
+		}
+	}
+	///Called when an instruction is sent to an execution unit
+	pub(super) fn issue(&mut self, address: u16, text: &str) {
+		let counters: &mut Counters = self.counters.entry(address).or_default();
+		if counters.text != text {
+			counters.text = String::from(text);
+		}
+	}
+	pub(super) fn retire(&mut self, address: u16) {
+		self.counters.entry(address).or_default().executions += 1;
+	}
+	///Called when a read or write by the instruction at the address misses the cache
+	pub(super) fn miss(&mut self, address: u16) {
+		self.counters.entry(address).or_default().cache_misses += 1;
+	}
+	
+	/**The hot spots: every group sorted by the cycles spent in it, then the hottest instructions.*/
+	pub fn report(&self, grouping: &Grouping) -> String {
+		let mut groups: HashMap<String, Counters> = HashMap::new();
+		for (address, counters) in self.counters() {
+			groups.entry(grouping.group(*address)).or_default().add(counters);
+		}
+		let mut groups: Vec<(String, Counters)> = groups.into_iter().collect();
+		groups.sort_by(|(name1, counters1), (name2, counters2)| {counters2.cycles.cmp(&counters1.cycles).then(name1.cmp(name2))});
+		let mut instructions: Vec<(&u16, &Counters)> = self.counters().iter().collect();
+		instructions.sort_by(|(address1, counters1), (address2, counters2)| {counters2.cycles.cmp(&counters1.cycles).then(address1.cmp(address2))});
+		
+		let percent = |cycles: u128| {if self.cycles == 0 {0.0} else {cycles as f64 * 100.0 / self.cycles as f64}};
+		let mut text: String = format!("Hot spots over {} cycles:\n", self.cycles);
+		let _ = writeln!(text, "{:>10} {:>6} {:>10} {:>8} {:>8}  Group", "Cycles", "%", "Executed", "Stalls", "Misses");
+		for (name, counters) in &groups {
+			let _ = writeln!(text, "{:>10} {:>5.1}% {:>10} {:>8} {:>8}  {}", counters.cycles, percent(counters.cycles), counters.executions, counters.stall_cycles, counters.cache_misses, name);
+		}
+		let _ = writeln!(text, "\nHottest instructions:");
+		let _ = writeln!(text, "{:>10} {:>6} {:>10} {:>8} {:>8} {:>8} {:>8} {:>8}  Instruction", "Cycles", "%", "Executed", "IR", "AR", "Execute", "Stalls", "Misses");
+		for (address, counters) in instructions.into_iter().take(Self::HOTTEST) {
+			let _ = writeln!(text, "{:>10} {:>5.1}% {:>10} {:>8} {:>8} {:>8} {:>8} {:>8}  0x{:04X}: {:<16} {}", counters.cycles, percent(counters.cycles), counters.executions,
+				counters.ir_cycles, counters.ar_cycles, counters.execute_cycles, counters.stall_cycles, counters.cache_misses, address, counters.text, grouping.group(*address));
+		}
+		return text;
+	}
+	/**The cycles in the collapsed-stack format read by flame graph tools: one line per instruction with its group and
+	itself as the frames, then its cycles, like `sort_loop;0x0074 LDA $00F5 812`.*/
+	pub fn collapsed_stacks(&self, grouping: &Grouping) -> String {
+		let mut instructions: Vec<(&u16, &Counters)> = self.counters().iter().filter(|(_, counters)| {counters.cycles > 0}).collect();
+		instructions.sort_by_key(|(address, _)| {**address});
+		let mut text: String = String::new();
+		for (address, counters) in instructions {
+			let frame: String = if counters.text.is_empty() {format!("0x{:04X}", address)} else {format!("0x{:04X} {}", address, counters.text)};
+			let _ = writeln!(text, "{};{} {}", grouping.group(*address), frame, counters.cycles);
+		}
+		return text;
+	}
+}
\ No newline at end of file
@@ -0,0 +1,402 @@
+use {
+	crate::lib::parse_number,
+	std::fmt::{self, Display, Formatter}
+};
+
+/**Picks which line of a full set the cache evicts. The cache tells it about every line that's used, comes in, or is removed,
+by its set and its way in the set. Lines that aren't in use are always filled before a victim is asked for.*/
+pub trait ReplacementPolicy: Send {
+	///Called when the line is read or written
+	fn touch(&mut self, set: usize, way: usize);
+	///Called when a line comes into the way
+	fn insert(&mut self, set: usize, way: usize);
+	///Called when the line in the way is evicted or flushed
+	fn remove(&mut self, set: usize, way: usize);
+	///Way of the line to evict from a full set
+	fn victim(&mut self, set: usize) -> usize;
+	///How close the line is to being evicted, for displays: the higher, the sooner. Policies that can't tell return 0.
+	fn age(&self, set: usize, way: usize) -> usize;
+	///What the policy keeps for the way and the set it's in, so the history can put it back after a cycle is taken back
+	fn save(&self, set: usize, way: usize) -> [u64; 3];
+	///Puts back what save() returned
+	fn load(&mut self, set: usize, way: usize, saved: [u64; 3]);
+}
+
+/**The replacement policies that can be picked for a run.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Policy {
+	///Least recently used
+	Lru,
+	///Tree pseudo-LRU, which keeps one bit per node of a binary tree over each set instead of an order
+	TreePlru,
+	///First in, first out: the line that came in first, however much it was used
+	Fifo,
+	///A random line, from a generator started with the seed so runs can be repeated
+	Random(u64),
+	///Least frequently used, with the least recently used of those evicted on a tie
+	Lfu
+}
+
+impl Display for Policy {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Policy::Lru => {write!(f, "LRU")}
+			Policy::TreePlru => {write!(f, "tree pseudo-LRU")}
+			Policy::Fifo => {write!(f, "FIFO")}
+			Policy::Random(seed) => {write!(f, "random, seed {}", seed)}
+			Policy::Lfu => {write!(f, "LFU")}
+		}
+	}
+}
+
+impl Policy {
+	///Seed of the random policy when none is given
+	pub const SEED: u64 = 0x6502;
+	
+	///Reads lru, plru, fifo, random, random:SEED, or lfu
+	pub fn parse(text: &str) -> Result<Self, String> {
+		let text: String = text.trim().to_ascii_lowercase();
+		return match text.as_str() {
+			"lru" => {Ok(Self::Lru)}
+			"plru" => {Ok(Self::TreePlru)}
+			"fifo" => {Ok(Self::Fifo)}
+			"random" => {Ok(Self::Random(Self::SEED))}
+			"lfu" => {Ok(Self::Lfu)}
+			_ => {
+				match text.strip_prefix("random:").map(parse_number::<u64>) {
+					Some(Ok(seed)) => {Ok(Self::Random(seed))}
+					Some(Err(error)) => {Err(error)}
+					None => {Err(format!("Unknown cache policy: {} (expected lru, plru, fifo, random, random:SEED, or lfu)", text))}
+				}
+			}
+		};
+	}
+	///Makes the policy for a cache with the given sets and lines in each set
+	pub fn build(self, sets: usize, ways: usize) -> Box<dyn ReplacementPolicy> {
+		match self {
+			Policy::Lru => {Box::new(Lru::new(sets, ways, true))}
+			Policy::TreePlru => {Box::new(TreePlru::new(sets, ways))}
+			Policy::Fifo => {Box::new(Lru::new(sets, ways, false))}
+			Policy::Random(seed) => {Box::new(Random::new(ways, seed))}
+			Policy::Lfu => {Box::new(Lfu::new(sets, ways))}
+		}
+	}
+}
+
+/**Evicts the line with the oldest stamp. The stamp is renewed on every use for LRU, or only when the line comes in for FIFO.*/
+#[derive(Clone)]
+struct Lru {
+	ways: usize,
+	///Stamp of each line, 0 if it's empty
+	stamps: Vec<u64>,
+	clock: u64,
+	///Whether using a line renews its stamp
+	on_use: bool
+}
+
+impl Lru {
+	fn new(sets: usize, ways: usize, on_use: bool) -> Self {
+		Self {ways, stamps: vec![0; sets * ways], clock: 0, on_use}
+	}
+	fn stamp(&mut self, set: usize, way: usize) {
+		self.clock += 1;
+		self.stamps[set * self.ways + way] = self.clock;
+	}
+}
+
+impl ReplacementPolicy for Lru {
+	fn touch(&mut self, set: usize, way: usize) {
+		if self.on_use {
+			self.stamp(set, way);
+		}
+	}
+	fn insert(&mut self, set: usize, way: usize) {
+		self.stamp(set, way);
+	}
+	fn remove(&mut self, set: usize, way: usize) {
+		self.stamps[set * self.ways + way] = 0;
+	}
+	fn victim(&mut self, set: usize) -> usize {
+		(0..self.ways).min_by_key(|way| {self.stamps[set * self.ways + way]}).unwrap_or(0)
+	}
+	fn age(&self, set: usize, way: usize) -> usize {
+		let stamps: &[u64] = &self.stamps[set * self.ways..(set + 1) * self.ways];
+		stamps.iter().filter(|stamp| {**stamp > stamps[way]}).count()
+	}
+	fn save(&self, set: usize, way: usize) -> [u64; 3] {
+		[self.clock, self.stamps[set * self.ways + way], 0]
+	}
+	fn load(&mut self, set: usize, way: usize, saved: [u64; 3]) {
+		[self.clock, self.stamps[set * self.ways + way], _] = saved;
+	}
+}
+
+/**Keeps a binary tree of bits over the ways of each set. Each bit points to the half that was used less recently,
+so following them from the root finds a line that hasn't been used in a while. The ways must be a power of two.*/
+#[derive(Clone)]
+struct TreePlru {
+	ways: usize,
+	///ways - 1 nodes for each set, each one's children at 2i + 1 and 2i + 2. A set bit points to the right half.
+	bits: Vec<bool>
+}
+
+impl TreePlru {
+	fn new(sets: usize, ways: usize) -> Self {
+		Self {ways, bits: vec![false; sets * ways.saturating_sub(1)]}
+	}
+	///Levels of the tree
+	fn depth(&self) -> u32 {
+		self.ways.trailing_zeros()
+	}
+	///The nodes from the root down to the way, with the half the way is in at each one
+	fn path(&self, set: usize, way: usize) -> impl Iterator<Item = (usize, bool)> + '_ {
+		let depth: u32 = self.depth();
+		let first: usize = set * (self.ways - 1);
+		(0..depth).scan(0, move |node: &mut usize, level: u32| {
+			let right: bool = way >> (depth - 1 - level) & 1 == 1;
+			let at: usize = *node;
+			*node = 2 * *node + 1 + right as usize;
+			Some((first + at, right))
+		})
+	}
+}
+
+impl ReplacementPolicy for TreePlru {
+	fn touch(&mut self, set: usize, way: usize) {
+		let path: Vec<(usize, bool)> = self.path(set, way).collect();
+		for (node, right) in path {
+			self.bits[node] = !right;//point away from the line that was used
+		}
+	}
+	fn insert(&mut self, set: usize, way: usize) {
+		self.touch(set, way);
+	}
+	fn remove(&mut self, set: usize, way: usize) {
+		let path: Vec<(usize, bool)> = self.path(set, way).collect();
+		for (node, right) in path {
+			self.bits[node] = right;//point at the empty line
+		}
+	}
+	fn victim(&mut self, set: usize) -> usize {
+		let first: usize = set * (self.ways - 1);
+		let mut node: usize = 0;
+		let mut way: usize = 0;
+		for _ in 0..self.depth() {
+			let right: bool = self.bits[first + node];
+			way = way << 1 | right as usize;
+			node = 2 * node + 1 + right as usize;
+		}
+		return way;
+	}
+	fn age(&self, set: usize, way: usize) -> usize {
+		self.path(set, way).filter(|(node, right)| {self.bits[*node] == *right}).count()
+	}
+	///The bits on the way's path, the root's first
+	fn save(&self, set: usize, way: usize) -> [u64; 3] {
+		[self.path(set, way).fold(0, |bits: u64, (node, _)| {bits << 1 | self.bits[node] as u64}), 0, 0]
+	}
+	fn load(&mut self, set: usize, way: usize, saved: [u64; 3]) {
+		let path: Vec<(usize, bool)> = self.path(set, way).collect();
+		let depth: usize = path.len();
+		for (level, (node, _)) in path.into_iter().enumerate() {
+			self.bits[node] = saved[0] >> (depth - 1 - level) & 1 == 1;
+		}
+	}
+}
+
+/**Evicts a random way, using splitmix64 so the same seed always picks the same ways.*/
+#[derive(Clone)]
+struct Random {
+	ways: usize,
+	state: u64
+}
+
+impl Random {
+	fn new(ways: usize, seed: u64) -> Self {
+		Self {ways, state: seed}
+	}
+	fn next(&mut self) -> u64 {
+		self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z: u64 = self.state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^ (z >> 31)
+	}
+}
+
+impl ReplacementPolicy for Random {
+	fn touch(&mut self, _set: usize, _way: usize) {}
+	fn insert(&mut self, _set: usize, _way: usize) {}
+	fn remove(&mut self, _set: usize, _way: usize) {}
+	fn victim(&mut self, _set: usize) -> usize {
+		(self.next() % self.ways as u64) as usize
+	}
+	fn age(&self, _set: usize, _way: usize) -> usize {
+		0
+	}
+	fn save(&self, _set: usize, _way: usize) -> [u64; 3] {
+		[self.state, 0, 0]
+	}
+	fn load(&mut self, _set: usize, _way: usize, saved: [u64; 3]) {
+		self.state = saved[0];
+	}
+}
+
+/**Evicts the line used the fewest times since it came in. Ties go to the one used least recently.*/
+#[derive(Clone)]
+struct Lfu {
+	ways: usize,
+	///Uses and last use of each line, (0, 0) if it's empty
+	counts: Vec<(u64, u64)>,
+	clock: u64
+}
+
+impl Lfu {
+	fn new(sets: usize, ways: usize) -> Self {
+		Self {ways, counts: vec![(0, 0); sets * ways], clock: 0}
+	}
+}
+
+impl ReplacementPolicy for Lfu {
+	fn touch(&mut self, set: usize, way: usize) {
+		self.clock += 1;
+		let (uses, last) = &mut self.counts[set * self.ways + way];
+		*uses += 1;
+		*last = self.clock;
+	}
+	fn insert(&mut self, set: usize, way: usize) {
+		self.clock += 1;
+		self.counts[set * self.ways + way] = (1, self.clock);
+	}
+	fn remove(&mut self, set: usize, way: usize) {
+		self.counts[set * self.ways + way] = (0, 0);
+	}
+	fn victim(&mut self, set: usize) -> usize {
+		(0..self.ways).min_by_key(|way| {self.counts[set * self.ways + way]}).unwrap_or(0)
+	}
+	fn age(&self, set: usize, way: usize) -> usize {
+		let counts: &[(u64, u64)] = &self.counts[set * self.ways..(set + 1) * self.ways];
+		counts.iter().filter(|count| {**count > counts[way]}).count()
+	}
+	fn save(&self, set: usize, way: usize) -> [u64; 3] {
+		let (uses, last) = self.counts[set * self.ways + way];
+		[self.clock, uses, last]
+	}
+	fn load(&mut self, set: usize, way: usize, saved: [u64; 3]) {
+		let [clock, uses, last] = saved;
+		self.clock = clock;
+		self.counts[set * self.ways + way] = (uses, last);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	const SETS: usize = 2;
+	const WAYS: usize = 4;
+	///The set the steps are taken in, so each policy has to find it among the others
+	const SET: usize = 1;
+	
+	#[derive(Clone, Copy)]
+	enum Step {
+		Insert(usize),
+		Touch(usize),
+		///Evicts the victim and puts a new line in its way, like the cache does when a set is full
+		Replace
+	}
+	
+	use Step::*;
+	
+	///Fills the set, then uses ways 0, 1, 1, and 2
+	const FILL: [Step; 8] = [Insert(0), Insert(1), Insert(2), Insert(3), Touch(0), Touch(1), Touch(1), Touch(2)];
+	///Replaces a line, uses way 3, then replaces three more lines
+	const REPLACE: [Step; 5] = [Replace, Touch(3), Replace, Replace, Replace];
+	
+	/**Takes the steps and returns the victims. What the policy kept for each way is pushed to the log before it changes,
+	the way the cache writes it in the history.*/
+	fn run(policy: &mut dyn ReplacementPolicy, steps: &[Step], log: &mut Vec<(usize, [u64; 3])>) -> Vec<usize> {
+		let mut victims: Vec<usize> = Vec::new();
+		for step in steps {
+			match *step {
+				Insert(way) => {
+					log.push((way, policy.save(SET, way)));
+					policy.insert(SET, way);
+				}
+				Touch(way) => {
+					log.push((way, policy.save(SET, way)));
+					policy.touch(SET, way);
+				}
+				Replace => {
+					log.push((0, policy.save(SET, 0)));
+					let way: usize = policy.victim(SET);
+					assert!(way < WAYS);
+					log.push((way, policy.save(SET, way)));
+					policy.remove(SET, way);
+					log.push((way, policy.save(SET, way)));
+					policy.insert(SET, way);
+					victims.push(way);
+				}
+			}
+		}
+		return victims;
+	}
+	fn filled(policy: Policy) -> Box<dyn ReplacementPolicy> {
+		let mut built: Box<dyn ReplacementPolicy> = policy.build(SETS, WAYS);
+		run(built.as_mut(), &FILL, &mut Vec::new());
+		return built;
+	}
+	fn ages(policy: &dyn ReplacementPolicy) -> Vec<usize> {
+		(0..WAYS).map(|way| {policy.age(SET, way)}).collect()
+	}
+	
+	#[test]
+	fn victims() {
+		let table = [
+			//ways 0 to 3 were last used in the order 3, 0, 1, 2
+			(Policy::Lru, [3, 0, 1, 2], [2, 1, 0, 3]),
+			//the lines came in in the order 0, 1, 2, 3, however much they were used
+			(Policy::Fifo, [0, 1, 2, 3], [3, 2, 1, 0]),
+			//the tree points away from the half used last at each level, which picks way 0 after way 2 was used
+			(Policy::TreePlru, [0, 1, 2, 0], [2, 1, 0, 1]),
+			//way 3 was used least, and then each new line is used least until it's used again
+			(Policy::Lfu, [3, 0, 0, 0], [2, 0, 1, 3])
+		];
+		for (policy, victims, ages_after_fill) in table {
+			let mut built: Box<dyn ReplacementPolicy> = filled(policy);
+			assert_eq!(ages(built.as_ref()), ages_after_fill, "{}", policy);
+			assert_eq!(run(built.as_mut(), &REPLACE, &mut Vec::new()), victims, "{}", policy);
+		}
+	}
+	
+	#[test]
+	fn random_victims_repeat_with_the_seed() {
+		let steps: Vec<Step> = vec![Replace; 32];
+		let victims: Vec<usize> = run(filled(Policy::Random(7)).as_mut(), &steps, &mut Vec::new());
+		assert_eq!(run(filled(Policy::Random(7)).as_mut(), &steps, &mut Vec::new()), victims);
+		assert_ne!(run(filled(Policy::Random(8)).as_mut(), &steps, &mut Vec::new()), victims);
+		assert!((0..WAYS).all(|way| {victims.contains(&way)}), "Every way should come up: {:?}", victims);
+		assert_eq!(ages(filled(Policy::Random(7)).as_ref()), [0; WAYS]);
+	}
+	
+	#[test]
+	fn save_and_load() {
+		for policy in [Policy::Lru, Policy::Fifo, Policy::TreePlru, Policy::Random(Policy::SEED), Policy::Lfu] {
+			let mut built: Box<dyn ReplacementPolicy> = filled(policy);
+			let saved: Vec<[u64; 3]> = (0..WAYS).map(|way| {built.save(SET, way)}).collect();
+			let before: Vec<usize> = ages(built.as_ref());
+			let mut log: Vec<(usize, [u64; 3])> = Vec::new();
+			run(built.as_mut(), &REPLACE, &mut log);
+			//loading what was saved before each change, newest first, goes back to before the steps
+			for (way, saved) in log.into_iter().rev() {
+				built.load(SET, way, saved);
+			}
+			assert_eq!((0..WAYS).map(|way| {built.save(SET, way)}).collect::<Vec<[u64; 3]>>(), saved, "{}", policy);
+			assert_eq!(ages(built.as_ref()), before, "{}", policy);
+			assert_eq!(run(built.as_mut(), &REPLACE, &mut Vec::new()), run(filled(policy).as_mut(), &REPLACE, &mut Vec::new()), "{}", policy);
+			//the other set wasn't touched
+			let empty: Box<dyn ReplacementPolicy> = policy.build(SETS, WAYS);
+			assert!((0..WAYS).all(|way| {built.age(0, way) == empty.age(0, way)}), "{}", policy);
+		}
+	}
+}
\ No newline at end of file
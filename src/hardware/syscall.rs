@@ -0,0 +1,326 @@
+use {
+	crate::{
+		ascii::ascii,
+		hardware::{
+			cpu::{Cpu, Storage},
+			keyboard::KeyBuffer
+		}
+	},
+	std::{
+		collections::HashMap,
+		io::{self, Write},
+		sync::{Arc, Mutex, mpsc::Sender}
+	}
+};
+
+/**Runs a system call. It's called once the call's cycles have passed, then again every cycle until it returns true.
+A handler that asks for a memory access with SyscallContext::read or SyscallContext::write is called again as soon as the access is done.
+State kept between calls belongs in SyscallContext::scratch rather than in the closure, so the history can take it back.*/
+pub type SyscallHandler = Box<dyn FnMut(&mut SyscallContext) -> bool + Send>;
+
+/**A host service the program can call with SYS. X selects the service when the SYS is decoded.*/
+pub struct Syscall {
+	pub name: String,
+	///Bytes after the SYS opcode that are read as the operand, from 0 to 2
+	pub operands: u8,
+	///Storages the handler reads, so it waits for older instructions that write them. X is always included.
+	pub dependent: Vec<Storage>,
+	///Storages the handler may change, so younger instructions wait for it. Include Memory if the handler writes memory.
+	pub affected: Vec<Storage>,
+	///Cycles spent in the execution unit before the handler is first called. Memory accesses made by the handler add to it.
+	pub cycles: u8,
+	handler: SyscallHandler
+}
+
+impl Syscall {
+	pub fn new(name: &str, operands: u8, dependent: Vec<Storage>, affected: Vec<Storage>, cycles: u8, handler: impl FnMut(&mut SyscallContext) -> bool + Send + 'static) -> Self {
+		Self {
+			name: String::from(name),
+			operands: operands.min(2),
+			dependent,
+			affected,
+			cycles: cycles.max(1),
+			handler: Box::new(handler)
+		}
+	}
+}
+
+/**The system calls the CPU knows about, by the value of X. SyscallTable::default() has the built-in calls:
+* 1 prints Y in hex
+* 2 prints the character at the address in Y
+* 3 prints the null-terminated string at the 2 byte operand
+* 4 loads the byte latched from the last interrupting device into A
+
+SyscallTable::with_keyboard() adds the input calls:
+* 5 waits for a key and loads it into A
+* 6 loads a key into A and sets carry, or loads 0 and clears carry if no key was typed
+* 7 waits for a line and stores it at the 2 byte operand
+* 8 stores a line at the 2 byte operand and sets carry if a whole line was typed, or clears carry and stores nothing
+
+Y is the number of keys still waiting after 5 and 6. For 7 and 8, Y is the most characters to store going in and the number stored coming out.
+The line ends at Enter, which isn't stored, and is null-terminated, so the buffer needs Y + 1 bytes.*/
+pub struct SyscallTable {
+	calls: HashMap<u8, Syscall>,
+	///Where the built-in calls print
+	pub output: ProgramOutput
+}
+
+impl SyscallTable {
+	///A table without any system calls
+	pub fn new() -> Self {
+		Self {calls: HashMap::new(), output: ProgramOutput::default()}
+	}
+	///Adds a system call for the given X, and returns the one it replaced
+	pub fn register(&mut self, x: u8, call: Syscall) -> Option<Syscall> {
+		self.calls.insert(x, call)
+	}
+	pub fn get(&self, x: u8) -> Option<&Syscall> {
+		self.calls.get(&x)
+	}
+	///The built-in calls, and the calls that read from the keyboard
+	pub fn with_keyboard(key_buf: KeyBuffer) -> Self {
+		use Storage::*;
+		let mut table: Self = Self::default();
+		table.register(0x05, Syscall::new("Read key", 0, vec![], vec![A, Y, NegativeFlag, ZeroFlag], 1, Self::read_key(key_buf.clone(), true)));
+		table.register(0x06, Syscall::new("Read key without waiting", 0, vec![], vec![A, Y, NegativeFlag, ZeroFlag, CarryFlag], 1, Self::read_key(key_buf.clone(), false)));
+		table.register(0x07, Syscall::new("Read line", 2, vec![Y], vec![Memory, Y, NegativeFlag, ZeroFlag], 1, Self::read_line(key_buf.clone(), true)));
+		table.register(0x08, Syscall::new("Read line without waiting", 2, vec![Y], vec![Memory, Y, NegativeFlag, ZeroFlag, CarryFlag], 1, Self::read_line(key_buf, false)));
+		table
+	}
+	///Runs the handler for the given X. Returns true once the call is finished, or if there's no handler anymore.
+	pub(super) fn call(&mut self, x: u8, context: &mut SyscallContext) -> bool {
+		match self.calls.get_mut(&x) {
+			Some(call) => {(call.handler)(context)}
+			None => {true}
+		}
+	}
+	
+	///Loads the next key into A. If blocking, the handler keeps the SYS in the execution unit until a key is typed.
+	fn read_key(key_buf: KeyBuffer, blocking: bool) -> impl FnMut(&mut SyscallContext) -> bool + Send {
+		move |context| {
+			let mut keys = key_buf.lock().expect("Keyboard buffer poisoned");
+			let key: Option<u8> = keys.pop_front();
+			if blocking && key.is_none() {return false;}
+			if !blocking {
+				context.set_carry(key.is_some());
+			}
+			context.y = keys.len().min(u8::MAX as usize) as u8;
+			context.set_a(key.unwrap_or(0x00));
+			true
+		}
+	}
+	///Stores keys at the operand, one per cycle, until Enter or until Y characters are stored, then writes the null terminator.
+	///Backspace removes the last character. Without blocking, nothing is stored unless a whole line is waiting.
+	fn read_line(key_buf: KeyBuffer, blocking: bool) -> impl FnMut(&mut SyscallContext) -> bool + Send {
+		//kept in the context so going back in the history takes back a line that was partway stored
+		const MAX_LEN: usize = 0;
+		const LEN: usize = 1;
+		const TERMINATED: usize = 2;
+		move |context| {
+			if context.calls == 0 {
+				context.scratch[MAX_LEN] = context.y;
+				context.scratch[LEN] = 0;
+				context.scratch[TERMINATED] = 0;
+				if !blocking {
+					let keys = key_buf.lock().expect("Keyboard buffer poisoned");
+					let max_len: u8 = context.scratch[MAX_LEN];
+					//the line is ready if Enter was typed, or if enough characters were typed after taking out the backspaces
+					let mut typed: u8 = 0;
+					let ready: bool = max_len == 0 || keys.iter().any(|&key| {
+						match key {
+							b'\n' | b'\r' => {true}
+							0x08 => {
+								typed = typed.saturating_sub(1);
+								false
+							}
+							_ => {
+								typed += 1;
+								typed == max_len
+							}
+						}
+					});
+					context.set_carry(ready);
+					if !ready {
+						context.set_y(0);
+						return true;
+					}
+				}
+			}
+			let len: u8 = context.scratch[LEN];
+			if context.scratch[TERMINATED] != 0 {
+				context.set_y(len);
+				return true;
+			}
+			let key: Option<u8> = if len == context.scratch[MAX_LEN] {
+				None
+			} else {
+				match key_buf.lock().expect("Keyboard buffer poisoned").pop_front() {
+					Some(key) => {Some(key)}
+					None => {return false;}//wait for the next key
+				}
+			};
+			match key {
+				None | Some(b'\n') | Some(b'\r') => {
+					context.write(context.operand.wrapping_add(len as u16), 0x00);
+					context.scratch[TERMINATED] = 1;
+				}
+				Some(0x08) => {context.scratch[LEN] = len.saturating_sub(1);}
+				Some(key) => {
+					context.write(context.operand.wrapping_add(len as u16), key);
+					context.scratch[LEN] = len + 1;
+				}
+			}
+			false
+		}
+	}
+}
+
+impl Default for SyscallTable {
+	fn default() -> Self {
+		use Storage::*;
+		let mut table: Self = Self::new();
+		let output: ProgramOutput = table.output.clone();
+		table.register(0x01, Syscall::new("Print hex", 0, vec![Y], vec![], 1, move |context| {
+			output.print(format!("{:X}", context.y).as_str());
+			true
+		}));
+		let output: ProgramOutput = table.output.clone();
+		table.register(0x02, Syscall::new("Print character", 0, vec![Y, Memory], vec![], 1, move |context| {
+			match context.data() {
+				Some(num) => {
+					output.print(ascii::ENCODER.get(&num).unwrap_or(&'\0').to_string().as_str());
+					true
+				}
+				None => {
+					context.read(context.operand.wrapping_add(context.y as u16));
+					false
+				}
+			}
+		}));
+		let output: ProgramOutput = table.output.clone();
+		table.register(0x03, Syscall::new("Print string", 2, vec![Memory], vec![], 1, move |context| {
+			//the operand is moved along the string one character at a time
+			if let Some(num) = context.data() {
+				let c: char = *ascii::ENCODER.get(&num).unwrap_or(&'\0');
+				if c == '\0' {return true;}
+				output.print(c.to_string().as_str());
+				context.operand = context.operand.wrapping_add(1);
+			}
+			context.read(context.operand);
+			false
+		}));
+		table.register(0x04, Syscall::new("Load data latch", 0, vec![], vec![A, NegativeFlag, ZeroFlag], 1, |context| {
+			context.set_a(context.data_latch);
+			true
+		}));
+		table
+	}
+}
+
+/**Where the program's output goes. It's printed to stdout unless it's sent somewhere else, like a debug adapter.
+Clones share the same destination.*/
+#[derive(Clone, Default)]
+pub struct ProgramOutput {
+	redirect: Arc<Mutex<Option<Sender<String>>>>
+}
+
+impl ProgramOutput {
+	///Sends the output to the channel instead of stdout
+	pub fn redirect(&self, sender: Sender<String>) {
+		*self.redirect.lock().expect("Program output poisoned") = Some(sender);
+	}
+	///Prints text right away, rather than waiting for a buffer of many characters. Goes back to stdout if the channel was dropped.
+	pub fn print(&self, text: &str) {
+		let mut redirect = self.redirect.lock().expect("Program output poisoned");
+		if let Some(sender) = redirect.as_ref() {
+			if sender.send(String::from(text)).is_ok() {return;}
+			*redirect = None;
+		}
+		print!("{}", text);
+		io::stdout().flush().expect("Could not flush output buffer");
+	}
+}
+
+///A memory access asked for by a handler
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(super) enum SyscallRequest {
+	Read(u16),
+	Write(u16, u8)
+}
+
+/**What a handler can see and change. The registers are copied in before every call and copied back after it,
+and the rest is kept in the execution unit until the system call is finished.*/
+#[derive(PartialEq, Clone)]
+pub struct SyscallContext {
+	pub a: u8,
+	pub x: u8,
+	pub y: u8,
+	pub nv_bdizc: u8,
+	///The operand after the SYS opcode as a little-endian address. Handlers can use it as a pointer and move it along.
+	pub operand: u16,
+	///Byte latched from the last I/O device that raised an interrupt
+	pub data_latch: u8,
+	///Number of times the handler has been called for this SYS
+	pub calls: u16,
+	///Zeroed when the SYS is issued and kept between calls for the handler's own use. It's saved with the CPU state.
+	pub scratch: [u8; 4],
+	///Result of the read requested in the last call
+	data: Option<u8>,
+	pub(super) request: Option<SyscallRequest>
+}
+
+impl SyscallContext {
+	pub(super) fn new(operand: u16) -> Self {
+		Self {
+			a: 0x00,
+			x: 0x00,
+			y: 0x00,
+			nv_bdizc: 0x00,
+			operand,
+			data_latch: 0x00,
+			calls: 0,
+			scratch: [0x00; 4],
+			data: None,
+			request: None
+		}
+	}
+	///Reads a byte through the cache. The value is returned by data() in the next call.
+	pub fn read(&mut self, addr: u16) {
+		self.request = Some(SyscallRequest::Read(addr));
+	}
+	///Writes a byte through the cache before the next call
+	pub fn write(&mut self, addr: u16, value: u8) {
+		self.request = Some(SyscallRequest::Write(addr, value));
+	}
+	///Takes the byte from the last read. Returns None if nothing was read since the last call.
+	pub fn data(&mut self) -> Option<u8> {
+		self.data.take()
+	}
+	pub(super) fn set_data(&mut self, num: u8) {
+		self.data = Some(num);
+	}
+	///Loads A and sets the negative and zero flags like LDA
+	pub fn set_a(&mut self, num: u8) {
+		self.a = num;
+		self.set_nz(num);
+	}
+	///Loads Y and sets the negative and zero flags like LDY
+	pub fn set_y(&mut self, num: u8) {
+		self.y = num;
+		self.set_nz(num);
+	}
+	pub fn set_carry(&mut self, c: bool) {
+		if c {
+			self.nv_bdizc |= Cpu::CARRY_FLAG;
+		} else {
+			self.nv_bdizc &= !Cpu::CARRY_FLAG;
+		}
+	}
+	fn set_nz(&mut self, num: u8) {
+		self.nv_bdizc &= !(Cpu::NEGATIVE_FLAG | Cpu::ZERO_FLAG);
+		self.nv_bdizc |= num & Cpu::NEGATIVE_FLAG;
+		if num == 0 {
+			self.nv_bdizc |= Cpu::ZERO_FLAG;
+		}
+	}
+}
\ No newline at end of file
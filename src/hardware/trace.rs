@@ -0,0 +1,133 @@
+use {
+	crate::hardware::cpu::Storage,
+	std::{
+		collections::VecDeque,
+		fs::File,
+		io::{self, BufWriter, Write}
+	}
+};
+
+/**The registers written on each line of the trace.*/
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Registers {
+	pub a: u8,
+	pub x: u8,
+	pub y: u8,
+	pub p: u8,
+	pub s: u8
+}
+
+impl Registers {
+	///Bit of a flag in P
+	fn flag_bit(storage: Storage) -> Option<u8> {
+		match storage {
+			Storage::NegativeFlag => {Some(0b10000000)}
+			Storage::OverflowFlag => {Some(0b01000000)}
+			Storage::DecimalFlag => {Some(0b00001000)}
+			Storage::InterruptFlag => {Some(0b00000100)}
+			Storage::ZeroFlag => {Some(0b00000010)}
+			Storage::CarryFlag => {Some(0b00000001)}
+			_ => {None}
+		}
+	}
+	///Copies the storages an instruction affected from the registers it left behind
+	fn commit(&mut self, from: &Registers, affected: &[Storage]) {
+		if affected.iter().filter(|storage| {Self::flag_bit(**storage).is_some()}).count() == 6 {
+			//PLP and RTI load the whole status register, including B
+			self.p = from.p;
+		}
+		for storage in affected {
+			match storage {
+				Storage::A => {self.a = from.a;}
+				Storage::X => {self.x = from.x;}
+				Storage::Y => {self.y = from.y;}
+				Storage::S => {self.s = from.s;}
+				flag => {
+					if let Some(bit) = Self::flag_bit(*flag) {
+						self.p = (self.p & !bit) | (from.p & bit);
+					}
+				}
+			}
+		}
+	}
+}
+
+///An instruction that was issued but not written yet
+struct Entry {
+	///Execution unit running the instruction
+	exe: u8,
+	address: u16,
+	bytes: Vec<u8>,
+	text: String,
+	///Cycle the instruction was issued in
+	cycle: u128,
+	///Registers after the instruction retired and the storages it affected, or None while it's running
+	retired: Option<(Registers, Vec<Storage>)>
+}
+
+/**Writes a line for every instruction that retires, in the style of nestest.log:
+
+	C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7
+
+The registers are the ones before the instruction ran, and the cycle is the one it was issued in.
+Instructions can retire out of order, so they're held until every older instruction has retired. The registers are kept
+in program order by copying only the storages each instruction affected when it retired, which no other instruction
+in flight can touch. They're read from the CPU again whenever nothing is in flight, which catches interrupts.*/
+pub struct Trace {
+	writer: BufWriter<File>,
+	///Only instructions with an address in this range are written, including the end
+	range: Option<(u16, u16)>,
+	///Registers before the oldest instruction that hasn't been written
+	registers: Registers,
+	///Oldest first
+	pending: VecDeque<Entry>
+}
+
+impl Trace {
+	pub fn create(path: &str, range: Option<(u16, u16)>) -> io::Result<Self> {
+		Ok(Self {writer: BufWriter::new(File::create(path)?), range, registers: Registers::default(), pending: VecDeque::new()})
+	}
+	
+	///Called when an instruction goes to an execution unit. The registers are the CPU's right now.
+	pub fn issue(&mut self, exe: u8, address: u16, bytes: Vec<u8>, text: String, cycle: u128, registers: Registers) {
+		if self.pending.is_empty() {
+			self.registers = registers;
+		}
+		self.pending.push_back(Entry {exe, address, bytes, text, cycle, retired: None});
+	}
+	///Called when the instruction in an execution unit finishes. Writes every instruction that has no older one still running.
+	pub fn retire(&mut self, exe: u8, registers: Registers, affected: &[Storage]) -> io::Result<()> {
+		if let Some(entry) = self.pending.iter_mut().find(|entry| {entry.exe == exe && entry.retired.is_none()}) {
+			entry.retired = Some((registers, affected.to_vec()));
+		}
+		self.drain()
+	}
+	///Forgets the instructions that are still running, because the pipeline was cleared
+	pub fn discard(&mut self) -> io::Result<()> {
+		self.pending.retain(|entry| {entry.retired.is_some()});
+		self.drain()
+	}
+	///Writes what's left and flushes the file
+	pub fn finish(mut self) -> io::Result<()> {
+		self.discard()?;
+		self.writer.flush()
+	}
+	
+	///Writes the oldest instructions until one is still running
+	fn drain(&mut self) -> io::Result<()> {
+		while self.pending.front().is_some_and(|entry| {entry.retired.is_some()}) {
+			let Some(entry) = self.pending.pop_front() else {break;};
+			self.write(&entry)?;
+			if let Some((registers, affected)) = &entry.retired {
+				self.registers.commit(registers, affected);
+			}
+		}
+		Ok(())
+	}
+	fn write(&mut self, entry: &Entry) -> io::Result<()> {
+		if self.range.is_some_and(|(start, end)| {entry.address < start || entry.address > end}) {return Ok(());}
+		let bytes: String = entry.bytes.iter().map(|byte| {format!("{:02X}", byte)}).collect::<Vec<String>>().join(" ");
+		let Registers {a, x, y, p, s} = self.registers;
+		writeln!(self.writer, "{:04X}  {:<8}  {:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}", entry.address, bytes, entry.text, a, x, y, p, s, entry.cycle)
+	}
+}
\ No newline at end of file
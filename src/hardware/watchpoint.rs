@@ -0,0 +1,351 @@
+use {
+	crate::lib::parse_number,
+	std::{
+		collections::HashMap,
+		fmt::{self, Display, Formatter}
+	}
+};
+
+/**What a watchpoint fires on.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Access {
+	Read,
+	Write,
+	///A write that changes the value
+	Change
+}
+
+/**How the value is compared in a condition.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Comparison {
+	Equal,
+	NotEqual,
+	Less,
+	LessOrEqual,
+	Greater,
+	GreaterOrEqual
+}
+
+/**Checks the value read or written, like `value == $FF`.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Condition {
+	pub comparison: Comparison,
+	pub value: u8
+}
+
+impl Condition {
+	///Parses `value == $FF`. The word value is optional, and the operator is ==, !=, <, <=, >, or >=.
+	pub fn parse(text: &str) -> Result<Self, String> {
+		let text: &str = text.trim();
+		let text: &str = text.strip_prefix("value").unwrap_or(text).trim_start();
+		let (comparison, value) = [("==", Comparison::Equal), ("!=", Comparison::NotEqual), ("<=", Comparison::LessOrEqual),
+			(">=", Comparison::GreaterOrEqual), ("<", Comparison::Less), (">", Comparison::Greater)]
+			.into_iter().find_map(|(operator, comparison)| {text.strip_prefix(operator).map(|value| {(comparison, value)})})
+			.ok_or(format!("The condition should look like value == $FF, not {}", text))?;
+		return Ok(Self {comparison, value: parse_number(value.trim())?});
+	}
+	pub fn matches(&self, value: u8) -> bool {
+		match self.comparison {
+			Comparison::Equal => {value == self.value}
+			Comparison::NotEqual => {value != self.value}
+			Comparison::Less => {value < self.value}
+			Comparison::LessOrEqual => {value <= self.value}
+			Comparison::Greater => {value > self.value}
+			Comparison::GreaterOrEqual => {value >= self.value}
+		}
+	}
+}
+
+impl Display for Condition {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let operator: &str = match self.comparison {
+			Comparison::Equal => {"=="}
+			Comparison::NotEqual => {"!="}
+			Comparison::Less => {"<"}
+			Comparison::LessOrEqual => {"<="}
+			Comparison::Greater => {">"}
+			Comparison::GreaterOrEqual => {">="}
+		};
+		write!(f, "value {} 0x{:02X}", operator, self.value)
+	}
+}
+
+/**What happens when a watchpoint fires.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WatchAction {
+	///Stop the program, or go back to the debugger prompt
+	Stop,
+	///Print the hit and keep going
+	Log
+}
+
+/**Watches a range of addresses for CPU reads and writes through the cache.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Watchpoint {
+	pub start: u16,
+	///Last address watched, including it
+	pub end: u16,
+	pub access: Access,
+	pub condition: Option<Condition>,
+	pub action: WatchAction
+}
+
+impl Watchpoint {
+	pub const SYNTAX: &'static str = "<read|write|change> <ADDRESS>[-<END>] [if value <OP> <VALUE>] [stop|log]";
+	
+	/**Parses `change $007E-$0080 if value == $FF log`. The range and condition are optional, and the action defaults to stop.*/
+	pub fn parse(text: &str) -> Result<Self, String> {
+		let (text, action) = match text.trim_end().rsplit_once(char::is_whitespace) {
+			Some((rest, "stop")) => {(rest, WatchAction::Stop)}
+			Some((rest, "log")) => {(rest, WatchAction::Log)}
+			_ => {(text, WatchAction::Stop)}
+		};
+		let (text, condition) = match text.split_once(" if ") {
+			Some((rest, condition)) => {(rest, Some(Condition::parse(condition)?))}
+			None => {(text, None)}
+		};
+		let words: Vec<&str> = text.split_whitespace().collect();
+		let [access, range] = words[..] else {return Err(format!("A watchpoint should look like {}", Self::SYNTAX));};
+		let access: Access = match access {
+			"read" | "r" => {Access::Read}
+			"write" | "w" => {Access::Write}
+			"change" | "c" => {Access::Change}
+			access => {return Err(format!("Unknown access: {}. Use read, write, or change", access));}
+		};
+		let (start, end) = match range.split_once('-') {
+			Some((start, end)) => {(parse_number(start)?, parse_number(end)?)}
+			None => {
+				let address: u16 = parse_number(range)?;
+				(address, address)
+			}
+		};
+		if start > end {
+			return Err(format!("The range {} ends before it starts", range));
+		}
+		return Ok(Self {start, end, access, condition, action});
+	}
+	pub fn contains(&self, address: u16) -> bool {
+		address >= self.start && address <= self.end
+	}
+}
+
+impl Display for Watchpoint {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let access: &str = match self.access {
+			Access::Read => {"read"}
+			Access::Write => {"write"}
+			Access::Change => {"change"}
+		};
+		write!(f, "{} 0x{:04X}", access, self.start)?;
+		if self.end != self.start {
+			write!(f, "-0x{:04X}", self.end)?;
+		}
+		if let Some(condition) = self.condition {
+			write!(f, " if {}", condition)?;
+		}
+		write!(f, " {}", if self.action == WatchAction::Stop {"stop"} else {"log"})
+	}
+}
+
+/**A watchpoint that fired, with the instruction that made the access.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct WatchHit {
+	///Index of the watchpoint in the list
+	pub index: usize,
+	pub watchpoint: Watchpoint,
+	pub address: u16,
+	///Read or Write
+	pub access: Access,
+	///The value before a write, or None if it wasn't known. The same as new for reads.
+	pub old: Option<u8>,
+	pub new: u8,
+	///Address of the instruction that made the access, or the PC for fetches and interrupts
+	pub pc: u16,
+	pub cycle: u128
+}
+
+impl Display for WatchHit {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(f, "Watchpoint {} ({}): ", self.index, self.watchpoint)?;
+		match (self.access, self.old) {
+			(Access::Read, _) => {write!(f, "read 0x{:02X} at 0x{:04X}", self.new, self.address)?;}
+			(_, Some(old)) => {write!(f, "wrote 0x{:04X}: 0x{:02X} -> 0x{:02X}", self.address, old, self.new)?;}
+			(_, None) => {write!(f, "wrote 0x{:04X}: ?? -> 0x{:02X}", self.address, self.new)?;}
+		}
+		write!(f, ", PC: 0x{:04X}, Cycle: {}", self.pc, self.cycle)
+	}
+}
+
+/**The watchpoints checked by the cache. The cache tells them about every access, and the CPU adds the PC and cycle
+to the hits of its own accesses. Accesses by the host, like loading a program, only keep track of the values.*/
+#[derive(Default)]
+pub struct Watchpoints {
+	list: Vec<Watchpoint>,
+	///Last value seen at each watched address, for the old value of a write
+	values: HashMap<u16, u8>,
+	///Hits from the last access: index, address, access, old value, and new value
+	pending: Vec<(usize, u16, Access, Option<u8>, u8)>
+}
+
+impl Watchpoints {
+	///Adds a watchpoint and returns its index. The old values aren't known until the addresses are read or written.
+	pub fn add(&mut self, watchpoint: Watchpoint) -> usize {
+		self.list.push(watchpoint);
+		self.list.len() - 1
+	}
+	pub fn remove(&mut self, index: usize) -> Option<Watchpoint> {
+		if index >= self.list.len() {return None;}
+		let watchpoint: Watchpoint = self.list.remove(index);
+		self.values.retain(|address, _| {self.list.iter().any(|watchpoint| {watchpoint.contains(*address)})});
+		Some(watchpoint)
+	}
+	pub fn clear(&mut self) {
+		self.list.clear();
+		self.values.clear();
+	}
+	pub fn list(&self) -> &[Watchpoint] {
+		&self.list
+	}
+	///Whether any watchpoint covers the address
+	pub(super) fn watches(&self, address: u16) -> bool {
+		self.list.iter().any(|watchpoint| {watchpoint.contains(address)})
+	}
+	///Last value seen at the address, if it's watched and has been read or written
+	pub(super) fn value(&self, address: u16) -> Option<u8> {
+		self.values.get(&address).copied()
+	}
+	///Puts back the last value seen at the address, or forgets it
+	pub(super) fn set_value(&mut self, address: u16, value: Option<u8>) {
+		match value {
+			Some(value) => {self.values.insert(address, value);}
+			None => {self.values.remove(&address);}
+		}
+	}
+	
+	///Called by the cache when a read returns a value
+	pub(super) fn read(&mut self, address: u16, value: u8) {
+		if self.list.is_empty() {return;}
+		self.access(address, None, value, false);
+	}
+	///Called by the cache when a value is written. The old value comes from the cache line if it's there.
+	pub(super) fn write(&mut self, address: u16, old: Option<u8>, value: u8) {
+		if self.list.is_empty() {return;}
+		self.access(address, old, value, true);
+	}
+	fn access(&mut self, address: u16, old: Option<u8>, value: u8, write: bool) {
+		let watched: Vec<(usize, Watchpoint)> = self.list.iter().copied().enumerate().filter(|(_, watchpoint)| {watchpoint.contains(address)}).collect();
+		if watched.is_empty() {return;}
+		let old: Option<u8> = old.or(self.values.get(&address).copied());
+		self.values.insert(address, value);
+		for (index, watchpoint) in watched {
+			let fired: bool = match watchpoint.access {
+				Access::Read => {!write}
+				Access::Write => {write}
+				Access::Change => {write && old != Some(value)}
+			};
+			if fired && watchpoint.condition.is_none_or(|condition| {condition.matches(value)}) {
+				self.pending.push((index, address, if write {Access::Write} else {Access::Read}, if write {old} else {Some(value)}, value));
+			}
+		}
+	}
+	///Takes the hits of the last access, made by the instruction at pc
+	pub fn take_hits(&mut self, pc: u16, cycle: u128) -> Vec<WatchHit> {
+		self.pending.drain(..).map(|(index, address, access, old, new)| {
+			WatchHit {index, watchpoint: self.list[index], address, access, old, new, pc, cycle}
+		}).collect()
+	}
+	///Drops the hits of the last access, which was made by the host
+	pub fn discard_hits(&mut self) {
+		self.pending.clear();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn watchpoint(start: u16, end: u16, access: Access, condition: Option<(Comparison, u8)>, action: WatchAction) -> Watchpoint {
+		Watchpoint {start, end, access, condition: condition.map(|(comparison, value)| {Condition {comparison, value}}), action}
+	}
+	
+	#[test]
+	fn parse() {
+		use {Access::*, Comparison::*, WatchAction::*};
+		let table = [
+			("write $0300", watchpoint(0x0300, 0x0300, Write, None, Stop)),
+			("r 0x10-0x1F log", watchpoint(0x0010, 0x001F, Read, None, Log)),
+			("change $007E-$0080 if value == $FF log", watchpoint(0x007E, 0x0080, Change, Some((Equal, 0xFF)), Log)),
+			("  w 512 if value != 0 stop  ", watchpoint(0x0200, 0x0200, Write, Some((NotEqual, 0x00)), Stop)),
+			("c $00 if <= $7F", watchpoint(0x0000, 0x0000, Change, Some((LessOrEqual, 0x7F)), Stop)),
+			("read $FFFF if value>=$80", watchpoint(0xFFFF, 0xFFFF, Read, Some((GreaterOrEqual, 0x80)), Stop)),
+			("read $20 if value < 3", watchpoint(0x0020, 0x0020, Read, Some((Less, 0x03)), Stop)),
+			("read $20 if value > 3 log", watchpoint(0x0020, 0x0020, Read, Some((Greater, 0x03)), Log))
+		];
+		for (text, expected) in table {
+			assert_eq!(Watchpoint::parse(text), Ok(expected), "{}", text);
+			//what's displayed can be parsed again
+			assert_eq!(Watchpoint::parse(&expected.to_string()), Ok(expected), "{}", expected);
+		}
+		assert_eq!(watchpoint(0x007E, 0x0080, Change, Some((Equal, 0xFF)), Log).to_string(), "change 0x007E-0x0080 if value == 0xFF log");
+		
+		for text in ["", "write", "write $10 $20", "jump $10", "write $20-$10", "write $10000", "write $10 if value = 3", "write $10 if value == $100", "write $10-"] {
+			assert!(Watchpoint::parse(text).is_err(), "{}", text);
+		}
+		assert_eq!(Watchpoint::parse("modify $10"), Err(String::from("Unknown access: modify. Use read, write, or change")));
+		assert_eq!(Watchpoint::parse("write $20-$10"), Err(String::from("The range $20-$10 ends before it starts")));
+	}
+	
+	#[test]
+	fn conditions() {
+		let condition = |comparison: Comparison| {Condition {comparison, value: 0x10}};
+		let values: [u8; 3] = [0x0F, 0x10, 0x11];
+		for (comparison, matches) in [(Comparison::Equal, [false, true, false]), (Comparison::NotEqual, [true, false, true]), (Comparison::Less, [true, false, false]),
+			(Comparison::LessOrEqual, [true, true, false]), (Comparison::Greater, [false, false, true]), (Comparison::GreaterOrEqual, [false, true, true])] {
+			assert_eq!(values.map(|value| {condition(comparison).matches(value)}), matches, "{:?}", comparison);
+		}
+	}
+	
+	#[test]
+	fn fires_on_reads_writes_and_changes() {
+		let mut watchpoints: Watchpoints = Watchpoints::default();
+		assert_eq!(watchpoints.add(Watchpoint::parse("read $10-$11").unwrap()), 0);
+		assert_eq!(watchpoints.add(Watchpoint::parse("write $11").unwrap()), 1);
+		assert_eq!(watchpoints.add(Watchpoint::parse("change $11-$12 if value >= $80 log").unwrap()), 2);
+		let hits = |watchpoints: &mut Watchpoints| -> Vec<(usize, u16, Access, Option<u8>, u8)> {
+			watchpoints.take_hits(0x0200, 7).into_iter().map(|hit| {
+				assert_eq!((hit.pc, hit.cycle, hit.watchpoint), (0x0200, 7, watchpoints.list()[hit.index]));
+				(hit.index, hit.address, hit.access, hit.old, hit.new)
+			}).collect()
+		};
+		
+		watchpoints.read(0x10, 0x05);
+		assert_eq!(hits(&mut watchpoints), [(0, 0x10, Access::Read, Some(0x05), 0x05)]);
+		watchpoints.read(0x13, 0x05);
+		assert_eq!(hits(&mut watchpoints), []);
+		//the old value isn't known until the address is read or written, unless the cache has it
+		watchpoints.write(0x11, None, 0x90);
+		assert_eq!(hits(&mut watchpoints), [(1, 0x11, Access::Write, None, 0x90), (2, 0x11, Access::Write, None, 0x90)]);
+		//the same value again is a write but not a change
+		watchpoints.write(0x11, None, 0x90);
+		assert_eq!(hits(&mut watchpoints), [(1, 0x11, Access::Write, Some(0x90), 0x90)]);
+		//the value doesn't meet the condition
+		watchpoints.write(0x11, Some(0x90), 0x7F);
+		assert_eq!(hits(&mut watchpoints), [(1, 0x11, Access::Write, Some(0x90), 0x7F)]);
+		watchpoints.read(0x12, 0x00);
+		watchpoints.write(0x12, None, 0x80);
+		assert_eq!(hits(&mut watchpoints), [(2, 0x12, Access::Write, Some(0x00), 0x80)]);
+		assert_eq!(watchpoints.value(0x12), Some(0x80));
+		
+		//hits of the host's accesses are dropped
+		watchpoints.read(0x11, 0x7F);
+		watchpoints.discard_hits();
+		assert_eq!(hits(&mut watchpoints), []);
+		//removing a watchpoint forgets the addresses nothing else watches, and the others move down
+		assert_eq!(watchpoints.remove(2).map(|watchpoint| {watchpoint.start}), Some(0x11));
+		assert!(!watchpoints.watches(0x12) && watchpoints.value(0x12).is_none() && watchpoints.value(0x11) == Some(0x7F));
+		assert_eq!(watchpoints.remove(2), None);
+		watchpoints.clear();
+		watchpoints.write(0x11, None, 0x00);
+		assert_eq!(hits(&mut watchpoints), []);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,314 @@
+use std::fmt::{self, Display, Formatter};
+
+/**Bytes that go at an address.*/
+#[derive(Debug, PartialEq, Clone)]
+pub struct Segment {
+	pub address: u16,
+	pub data: Vec<u8>
+}
+
+impl Segment {
+	///Returns true if the address is one of the segment's bytes
+	pub fn contains(&self, address: u16) -> bool {
+		address >= self.address && ((address - self.address) as usize) < self.data.len()
+	}
+}
+
+/**A program made of segments at different addresses, and the address it starts at if the file has one.
+Intel HEX and S-record files are read into an Image, and exported from one.*/
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Image {
+	pub segments: Vec<Segment>,
+	pub start: Option<u16>
+}
+
+/**Problems with a program file. Lines are counted from 1.*/
+#[derive(Debug, PartialEq, Clone)]
+pub enum ImageError {
+	///The line isn't a record of the format being read
+	Syntax {line: usize},
+	///The checksum at the end of the record doesn't match its bytes
+	Checksum {line: usize, expected: u8, found: u8},
+	///The record type isn't part of the format
+	RecordType {line: usize, record_type: u8},
+	///The bytes would go past the end of the 64K address space
+	OutOfRange {address: u32, len: usize}
+}
+
+impl Display for ImageError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			ImageError::Syntax {line} => {write!(f, "line {} is not a valid record", line)}
+			ImageError::Checksum {line, expected, found} => {write!(f, "bad checksum on line {}, expected 0x{:02X} but found 0x{:02X}", line, expected, found)}
+			ImageError::RecordType {line, record_type} => {write!(f, "unknown record type {} on line {}", record_type, line)}
+			ImageError::OutOfRange {address, len} => {write!(f, "{} bytes at 0x{:X} don't fit in the 64K address space", len, address)}
+		}
+	}
+}
+
+impl std::error::Error for ImageError {}
+
+impl Image {
+	///Bytes per data record when exporting
+	const RECORD_LEN: usize = 16;
+	
+	///An image with one segment
+	pub fn from_bytes(address: u16, data: &[u8]) -> Result<Self, ImageError> {
+		let mut image: Self = Self::default();
+		image.add(address as u32, data)?;
+		return Ok(image);
+	}
+	
+	///Adds bytes at the address. They're joined to the last segment if they come right after it.
+	fn add(&mut self, address: u32, data: &[u8]) -> Result<(), ImageError> {
+		if address as usize + data.len() > 0x10000 {
+			return Err(ImageError::OutOfRange {address, len: data.len()});
+		}
+		if let Some(last) = self.segments.last_mut() {
+			if last.address as usize + last.data.len() == address as usize {
+				last.data.extend_from_slice(data);
+				return Ok(());
+			}
+		}
+		self.segments.push(Segment {address: address as u16, data: data.to_vec()});
+		return Ok(());
+	}
+	
+	/**Reads an Intel HEX file. Extended segment and linear address records are followed,
+	but every byte has to land in the 64K address space. The start address comes from a start segment or start linear address record.*/
+	pub fn from_intel_hex(text: &str) -> Result<Self, ImageError> {
+		let mut image: Self = Self::default();
+		//added to the address of data records by extended address records
+		let mut base: u32 = 0;
+		for (i, line) in text.lines().enumerate() {
+			let line_number: usize = i + 1;
+			let line: &str = line.trim();
+			if line.is_empty() {continue;}
+			let Some(record) = line.strip_prefix(':') else {return Err(ImageError::Syntax {line: line_number});};
+			let bytes: Vec<u8> = hex_bytes(record).ok_or(ImageError::Syntax {line: line_number})?;
+			if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+				return Err(ImageError::Syntax {line: line_number});
+			}
+			let (body, checksum) = bytes.split_at(bytes.len() - 1);
+			let expected: u8 = (!body.iter().fold(0u8, |sum, byte| {sum.wrapping_add(*byte)})).wrapping_add(1);
+			if expected != checksum[0] {
+				return Err(ImageError::Checksum {line: line_number, expected, found: checksum[0]});
+			}
+			let address: u32 = u16::from_be_bytes([body[1], body[2]]) as u32;
+			let data: &[u8] = &body[4..];
+			match body[3] {
+				0x00 => {image.add(base + address, data)?;}
+				0x01 => {break;}
+				0x02 if data.len() == 2 => {base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4;}
+				0x04 if data.len() == 2 => {base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16;}
+				0x03 if data.len() == 4 => {
+					//CS:IP
+					let start: u32 = ((u16::from_be_bytes([data[0], data[1]]) as u32) << 4) + u16::from_be_bytes([data[2], data[3]]) as u32;
+					image.start = Some(u16::try_from(start).map_err(|_| {ImageError::OutOfRange {address: start, len: 0}})?);
+				}
+				0x05 if data.len() == 4 => {
+					let start: u32 = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+					image.start = Some(u16::try_from(start).map_err(|_| {ImageError::OutOfRange {address: start, len: 0}})?);
+				}
+				0x02..=0x05 => {return Err(ImageError::Syntax {line: line_number});}
+				record_type => {return Err(ImageError::RecordType {line: line_number, record_type});}
+			}
+		}
+		return Ok(image);
+	}
+	
+	/**Reads a Motorola S-record file with S1, S2, or S3 data records. The header and count records are skipped once their checksums are checked.
+	The start address comes from the S7, S8, or S9 record.*/
+	pub fn from_srecord(text: &str) -> Result<Self, ImageError> {
+		let mut image: Self = Self::default();
+		for (i, line) in text.lines().enumerate() {
+			let line_number: usize = i + 1;
+			let line: &str = line.trim();
+			if line.is_empty() {continue;}
+			let Some(record) = line.strip_prefix('S') else {return Err(ImageError::Syntax {line: line_number});};
+			let mut chars = record.chars();
+			let Some(record_type) = chars.next().and_then(|c| {c.to_digit(10)}) else {return Err(ImageError::Syntax {line: line_number});};
+			let bytes: Vec<u8> = hex_bytes(chars.as_str()).ok_or(ImageError::Syntax {line: line_number})?;
+			if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+				return Err(ImageError::Syntax {line: line_number});
+			}
+			let (body, checksum) = bytes.split_at(bytes.len() - 1);
+			let expected: u8 = !body.iter().fold(0u8, |sum, byte| {sum.wrapping_add(*byte)});
+			if expected != checksum[0] {
+				return Err(ImageError::Checksum {line: line_number, expected, found: checksum[0]});
+			}
+			let address_len: usize = match record_type {
+				0 | 1 | 5 | 9 => {2}
+				2 | 6 | 8 => {3}
+				3 | 7 => {4}
+				record_type => {return Err(ImageError::RecordType {line: line_number, record_type: record_type as u8});}
+			};
+			if body.len() < address_len + 1 {
+				return Err(ImageError::Syntax {line: line_number});
+			}
+			let address: u32 = body[1..address_len + 1].iter().fold(0u32, |address, byte| {(address << 8) | *byte as u32});
+			let data: &[u8] = &body[address_len + 1..];
+			match record_type {
+				1..=3 => {image.add(address, data)?;}
+				7..=9 => {image.start = Some(u16::try_from(address).map_err(|_| {ImageError::OutOfRange {address, len: 0}})?);}
+				_ => {}
+			}
+		}
+		return Ok(image);
+	}
+	
+	/**Writes the image as Intel HEX, with 16 bytes per data record. The start address is written as a start linear address record.*/
+	pub fn to_intel_hex(&self) -> String {
+		let mut text: String = String::new();
+		let mut record = |record_type: u8, address: u16, data: &[u8]| {
+			let mut bytes: Vec<u8> = vec![data.len() as u8];
+			bytes.extend_from_slice(&address.to_be_bytes());
+			bytes.push(record_type);
+			bytes.extend_from_slice(data);
+			let checksum: u8 = (!bytes.iter().fold(0u8, |sum, byte| {sum.wrapping_add(*byte)})).wrapping_add(1);
+			bytes.push(checksum);
+			text.push(':');
+			bytes.iter().for_each(|byte| {text.push_str(format!("{:02X}", byte).as_str());});
+			text.push('\n');
+		};
+		for segment in &self.segments {
+			for (i, chunk) in segment.data.chunks(Self::RECORD_LEN).enumerate() {
+				record(0x00, segment.address.wrapping_add((i * Self::RECORD_LEN) as u16), chunk);
+			}
+		}
+		if let Some(start) = self.start {
+			record(0x05, 0x0000, &(start as u32).to_be_bytes());
+		}
+		record(0x01, 0x0000, &[]);
+		return text;
+	}
+	
+	/**Writes the image as S-records: an empty S0 header, S1 data records with 16 bytes each, an S5 count, and an S9 with the start address.*/
+	pub fn to_srecord(&self) -> String {
+		let mut text: String = String::new();
+		let mut record = |record_type: u8, address: u16, data: &[u8]| {
+			let mut bytes: Vec<u8> = vec![(data.len() + 3) as u8];
+			bytes.extend_from_slice(&address.to_be_bytes());
+			bytes.extend_from_slice(data);
+			let checksum: u8 = !bytes.iter().fold(0u8, |sum, byte| {sum.wrapping_add(*byte)});
+			bytes.push(checksum);
+			text.push_str(format!("S{}", record_type).as_str());
+			bytes.iter().for_each(|byte| {text.push_str(format!("{:02X}", byte).as_str());});
+			text.push('\n');
+		};
+		record(0, 0x0000, &[]);
+		let mut count: usize = 0;
+		for segment in &self.segments {
+			for (i, chunk) in segment.data.chunks(Self::RECORD_LEN).enumerate() {
+				record(1, segment.address.wrapping_add((i * Self::RECORD_LEN) as u16), chunk);
+				count += 1;
+			}
+		}
+		//the count record only has room for 16 bits
+		if count <= 0xFFFF {
+			record(5, count as u16, &[]);
+		}
+		record(9, self.start.unwrap_or(0x0000), &[]);
+		return text;
+	}
+}
+
+///Parses pairs of hex digits
+fn hex_bytes(text: &str) -> Option<Vec<u8>> {
+	if !text.len().is_multiple_of(2) || !text.is_ascii() {return None;}
+	(0..text.len()).step_by(2).map(|i| {u8::from_str_radix(&text[i..i + 2], 16).ok()}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn segment(address: u16, data: &[u8]) -> Segment {
+		Segment {address, data: data.to_vec()}
+	}
+	
+	#[test]
+	fn intel_hex_segments() {
+		let text: &str = "
+			:0400100001020304E2
+			:020014000506DF
+			:03010000070809E4
+			:020000020F00ED
+			:010200000AF3
+			:020000040000FA
+			:02FFFE000B0CEA
+			:00000001FF
+			this is after the end of file record
+		";
+		let image: Image = Image::from_intel_hex(text).unwrap();
+		//the second record carries on from the first, the extended segment address moves the fifth to 0xF200, and the last one ends at 0xFFFF
+		assert_eq!(image.segments, [segment(0x0010, &[1, 2, 3, 4, 5, 6]), segment(0x0100, &[7, 8, 9]), segment(0xF200, &[0x0A]), segment(0xFFFE, &[0x0B, 0x0C])]);
+		assert_eq!(image.start, None);
+		assert!(image.segments[0].contains(0x0015) && !image.segments[0].contains(0x0016) && !image.segments[0].contains(0x000F));
+	}
+	
+	#[test]
+	fn srecord_segments() {
+		let text: &str = "S00600004844521B\nS107001001020304DE\nS2060000140506DA\nS30800000100070809DE\nS5030003F9\n";
+		let image: Image = Image::from_srecord(text).unwrap();
+		assert_eq!(image.segments, [segment(0x0010, &[1, 2, 3, 4, 5, 6]), segment(0x0100, &[7, 8, 9])]);
+		assert_eq!(image.start, None);
+	}
+	
+	#[test]
+	fn start_addresses() {
+		//start linear address, and start segment address as CS:IP
+		assert_eq!(Image::from_intel_hex(":0400000500001234B1\n:00000001FF").unwrap().start, Some(0x1234));
+		assert_eq!(Image::from_intel_hex(":0400000301000034C4\n:00000001FF").unwrap().start, Some(0x1034));
+		assert_eq!(Image::from_intel_hex(":0400000500010000F6"), Err(ImageError::OutOfRange {address: 0x10000, len: 0}));
+		//16, 24, and 32 bit start addresses
+		assert_eq!(Image::from_srecord("S9031234B6").unwrap().start, Some(0x1234));
+		assert_eq!(Image::from_srecord("S80400234593").unwrap().start, Some(0x2345));
+		assert_eq!(Image::from_srecord("S7050000345670").unwrap().start, Some(0x3456));
+		assert_eq!(Image::from_srecord("S70500010000F9"), Err(ImageError::OutOfRange {address: 0x10000, len: 0}));
+	}
+	
+	#[test]
+	fn bad_records() {
+		//blank lines are counted
+		assert_eq!(Image::from_intel_hex(":020014000506DF\n\n:0400100001020304E3"), Err(ImageError::Checksum {line: 3, expected: 0xE2, found: 0xE3}));
+		assert_eq!(Image::from_srecord("S00600004844521B\nS107001001020304DF"), Err(ImageError::Checksum {line: 2, expected: 0xDE, found: 0xDF}));
+		assert_eq!(Image::from_intel_hex(":00000006FA"), Err(ImageError::RecordType {line: 1, record_type: 6}));
+		assert_eq!(Image::from_srecord("S4030000FC"), Err(ImageError::RecordType {line: 1, record_type: 4}));
+		for text in ["0400100001020304E2", ":0500100001020304E2", ":04001000010203G4E2", ":040010000102030E2", ":0"] {
+			assert_eq!(Image::from_intel_hex(text), Err(ImageError::Syntax {line: 1}), "{}", text);
+		}
+		for text in ["107001001020304DE", "S108001001020304DE", "SX07001001020304DE", "S101"] {
+			assert_eq!(Image::from_srecord(text), Err(ImageError::Syntax {line: 1}), "{}", text);
+		}
+	}
+	
+	#[test]
+	fn past_64k() {
+		assert_eq!(Image::from_bytes(0xFFFF, &[1, 2]), Err(ImageError::OutOfRange {address: 0xFFFF, len: 2}));
+		assert_eq!(Image::from_bytes(0xFFFE, &[1, 2]).unwrap().segments, [segment(0xFFFE, &[1, 2])]);
+		assert_eq!(Image::from_intel_hex(":10FFF800000102030405060708090A0B0C0D0E0F81"), Err(ImageError::OutOfRange {address: 0xFFF8, len: 16}));
+		//an extended linear address of 1 puts the data at 0x10000
+		assert_eq!(Image::from_intel_hex(":020000040001F9\n:0100000001FE"), Err(ImageError::OutOfRange {address: 0x10000, len: 1}));
+		assert_eq!(Image::from_srecord("S113FFF8000102030405060708090A0B0C0D0E0F7D"), Err(ImageError::OutOfRange {address: 0xFFF8, len: 16}));
+		assert_eq!(Image::from_srecord("S20501000001F8"), Err(ImageError::OutOfRange {address: 0x10000, len: 1}));
+	}
+	
+	#[test]
+	fn round_trip() {
+		let mut image: Image = Image {
+			segments: vec![segment(0x0200, &(0..40).collect::<Vec<u8>>()), segment(0x0300, &[0xEA]), segment(0xFFF0, &[0xFF; 16])],
+			start: Some(0x0200)
+		};
+		assert_eq!(Image::from_intel_hex(&image.to_intel_hex()).unwrap(), image);
+		assert_eq!(Image::from_srecord(&image.to_srecord()).unwrap(), image);
+		//the 40 bytes take three records of up to 16 bytes, and the other segments one each
+		assert_eq!(image.to_intel_hex().lines().filter(|line| {&line[7..9] == "00"}).count(), 5);
+		assert_eq!(image.to_srecord().lines().filter(|line| {line.starts_with("S1")}).count(), 5);
+		assert!(image.to_srecord().contains("S5030005F7\nS9030200FA\n"));
+		//the S9 record always has a start address, so only Intel HEX can leave it out
+		image.start = None;
+		assert_eq!(Image::from_intel_hex(&image.to_intel_hex()).unwrap(), image);
+		assert!(image.to_intel_hex().ends_with(":10FFF000FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF11\n:00000001FF\n"));
+	}
+}
\ No newline at end of file
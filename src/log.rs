@@ -0,0 +1,213 @@
+use {
+	crate::lib,
+	serde_json::json,
+	std::{
+		fmt,
+		fs::File,
+		io::{self, Write, LineWriter},
+		sync::{Mutex, OnceLock, atomic::{AtomicU8, AtomicU64, Ordering}}
+	}
+};
+
+/**How important a log record is. Each level includes the ones before it.*/
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Level {
+	///The emulator can't go on
+	Error = 1,
+	///A problem in the emulated program, like a fault or a stack that wrapped around
+	Warn,
+	///What the parts are doing at a glance: created, started, and the totals at the end
+	Info,
+	///Every instruction issued, system call, and interrupt taken
+	Debug,
+	///Every clock cycle of every part
+	Trace
+}
+
+impl Level {
+	pub fn parse(text: &str) -> Result<Option<Self>, String> {
+		return match text.trim().to_ascii_lowercase().as_str() {
+			"off" => {Ok(None)}
+			"error" => {Ok(Some(Self::Error))}
+			"warn" => {Ok(Some(Self::Warn))}
+			"info" => {Ok(Some(Self::Info))}
+			"debug" => {Ok(Some(Self::Debug))}
+			"trace" => {Ok(Some(Self::Trace))}
+			level => {Err(format!("Unknown log level: {}", level))}
+		};
+	}
+	fn name(self) -> &'static str {
+		match self {
+			Self::Error => {"error"}
+			Self::Warn => {"warn"}
+			Self::Info => {"info"}
+			Self::Debug => {"debug"}
+			Self::Trace => {"trace"}
+		}
+	}
+	///The word in front of the message in text lines
+	fn label(self) -> &'static str {
+		match self {
+			Self::Error => {"Error"}
+			Self::Warn => {"Warning"}
+			Self::Info => {"Message"}
+			Self::Debug => {"Debug"}
+			Self::Trace => {"Trace"}
+		}
+	}
+}
+
+/**The most detailed level logged for each component, or None if it's off.*/
+#[derive(Debug, PartialEq, Clone)]
+pub struct Filter {
+	///Level of the components that aren't named
+	default: Option<Level>,
+	components: Vec<(String, Option<Level>)>
+}
+
+impl Default for Filter {
+	fn default() -> Self {
+		return Self {
+			default: Some(Level::Info),
+			components: Vec::new()
+		};
+	}
+}
+
+impl Filter {
+	///Names of the components that log, as given to HardwareSpecs
+	pub const COMPONENTS: [&'static str; 8] = ["System", "Clock", "Cpu", "MMU", "Cache", "Memory", "Interrupt Controller", "Keyboard"];
+	
+	/**Applies comma-separated directives in order. A level on its own sets the default, and COMPONENT=LEVEL sets one component,
+	like info,cpu=debug,memory=trace. Components are matched without case, spaces, dashes, or underscores.*/
+	pub fn apply(&mut self, directives: &str) -> Result<(), String> {
+		for directive in directives.split(',').map(str::trim).filter(|directive| {!directive.is_empty()}) {
+			match directive.split_once('=') {
+				Some((component, level)) => {self.set(component, Level::parse(level)?)?;}
+				None => {self.default = Level::parse(directive)?;}
+			}
+		}
+		Ok(())
+	}
+	///Sets the level of one component, replacing the one it had
+	pub fn set(&mut self, component: &str, level: Option<Level>) -> Result<(), String> {
+		let Some(name) = Self::COMPONENTS.iter().find(|name| {same_component(name, component)}) else {
+			return Err(format!("Unknown component to log: {} (expected one of {})", component.trim(), Self::COMPONENTS.join(", ")));
+		};
+		self.components.retain(|(other, _)| {other != name});
+		self.components.push((String::from(*name), level));
+		Ok(())
+	}
+	///The most detailed level the component logs
+	pub fn level(&self, component: &str) -> Option<Level> {
+		return self.components.iter().find(|(name, _)| {same_component(name, component)}).map_or(self.default, |(_, level)| {*level});
+	}
+	///The most detailed level any component logs
+	fn max_level(&self) -> Option<Level> {
+		return self.components.iter().map(|(_, level)| {*level}).chain([self.default]).max().flatten();
+	}
+}
+
+///Whether two component names are the same, ignoring case, spaces, dashes, and underscores
+fn same_component(a: &str, b: &str) -> bool {
+	fn letters(name: &str) -> impl Iterator<Item = char> + '_ {
+		name.chars().filter(|c| {!matches!(c, ' ' | '-' | '_')}).map(|c| {c.to_ascii_lowercase()})
+	}
+	return letters(a).eq(letters(b));
+}
+
+/**How the records are written to a sink.*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+	///One line of text per record, like the console
+	Text,
+	///One JSON object per line
+	Json
+}
+
+/**Where the records go.*/
+pub enum Sink {
+	///Text on stdout, with warnings and errors on stderr
+	Console,
+	Writer(Format, Box<dyn Write + Send>)
+}
+
+impl Sink {
+	/**Creates a sink that writes to the file, or to stdout if the path is -. Each line is written as soon as it's logged.*/
+	pub fn create(path: &str, format: Format) -> io::Result<Self> {
+		let writer: Box<dyn Write + Send> = match path {
+			"-" => {Box::new(io::stdout())}
+			path => {Box::new(LineWriter::new(File::create(path)?))}
+		};
+		return Ok(Self::Writer(format, writer));
+	}
+}
+
+struct Logger {
+	filter: Filter,
+	sinks: Mutex<Vec<Sink>>
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+///The most detailed level any component logs, or 0 if logging is off, so disabled records are turned away without a lock
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+///The clock cycle records are stamped with
+static CYCLE: AtomicU64 = AtomicU64::new(0);
+
+fn logger() -> &'static Logger {
+	LOGGER.get_or_init(|| {Logger {filter: Filter::default(), sinks: Mutex::new(vec![Sink::Console])}})
+}
+
+/**Sets the filter and sinks. It can only be done once, before anything is logged, and returns false otherwise.
+With no sinks, records go to the console. With sinks, warnings and errors still go to stderr so problems are seen.*/
+pub fn init(filter: Filter, sinks: Vec<Sink>) -> bool {
+	let max_level: Option<Level> = filter.max_level();
+	let sinks: Vec<Sink> = if sinks.is_empty() {vec![Sink::Console]} else {sinks};
+	if LOGGER.set(Logger {filter, sinks: Mutex::new(sinks)}).is_err() {
+		return false;
+	}
+	MAX_LEVEL.store(max_level.map_or(0, |level| {level as u8}), Ordering::Relaxed);
+	return true;
+}
+
+///Sets the clock cycle the next records are stamped with
+pub fn set_cycle(cycle: u128) {
+	CYCLE.store(cycle as u64, Ordering::Relaxed);
+}
+
+/**Whether the component logs records at the level. Check this before building a message that's expensive to format.*/
+pub fn enabled(component: &str, level: Level) -> bool {
+	if level as u8 > MAX_LEVEL.load(Ordering::Relaxed) {
+		return false;
+	}
+	return logger().filter.level(component).is_some_and(|max| {level <= max});
+}
+
+/**Writes a record to every sink if the component logs at the level. The message is only formatted if it's written.*/
+pub fn write(id: u8, component: &str, level: Level, message: fmt::Arguments) {
+	if !enabled(component, level) {
+		return;
+	}
+	let cycle: u64 = CYCLE.load(Ordering::Relaxed);
+	let time: u128 = lib::elapsed_ms();
+	let text = || {format!("ID: {} - Name: {} - Cycle: {} - Time: {} - {}: {}", id, component, cycle, time, level.label(), message)};
+	let mut sinks = logger().sinks.lock().unwrap_or_else(|poisoned| {poisoned.into_inner()});
+	let mut console: bool = false;
+	for sink in sinks.iter_mut() {
+		//a sink that can't be written to is skipped, since there's nowhere left to report it
+		let _ = match sink {
+			Sink::Console => {
+				console = true;
+				if level <= Level::Warn {writeln!(io::stderr(), "{}", text())} else {writeln!(io::stdout(), "{}", text())}
+			}
+			Sink::Writer(Format::Text, writer) => {writeln!(writer, "{}", text())}
+			Sink::Writer(Format::Json, writer) => {
+				let record = json!({"level": level.name(), "id": id, "component": component, "cycle": cycle, "timeMs": time as u64, "message": message.to_string()});
+				writeln!(writer, "{}", record)
+			}
+		};
+	}
+	if !console && level <= Level::Warn {
+		eprintln!("{}", text());
+	}
+}
\ No newline at end of file
@@ -1,42 +1,225 @@
-use crate::system::System;
+//The code base uses explicit returns, tabs in doc comments, and 6502 mnemonics as identifiers
+#![allow(clippy::needless_return, clippy::tabs_in_doc_comments, clippy::upper_case_acronyms, clippy::module_inception, clippy::enum_variant_names)]
+
+use crate::{
+	system::System,
+	hardware::hardware::Hardware,
+	cli::Options,
+	image::Image,
+	hardware::{
+		trace::Trace,
+		profiler::{Profiler, Grouping},
+		coverage::Coverage,
+		cache::CacheConfig,
+		disassembler::Instruction
+	},
+	debugger::Debugger,
+	gdb::GdbServer,
+	dap::DapServer,
+	tui::Tui,
+	log::{Sink, Format}
+};
 
 mod system;
 mod hardware;
 mod ascii;
+mod cli;
+mod image;
+mod assembler;
+mod debugger;
+mod gdb;
+mod dap;
+mod tui;
+mod log;
+
+//Bubble-sort program, 256 bytes long. It's run when no program file is given.
+//I managed to get it all on the zeroth-page, but it still doesn't work in tsiram.com because it greatly surpasses the clock cycle limit
+const SORT_PROGRAM: &[u8] = &[
+	/*See bubbleSort.asm6502 to look at the assembly code I wrote to produce this array of bytes.
+	See README.md to check out the text-editor/assembler I made to assembly bubbleSort.asm6502*/
+	0xA2, 0x03, 0xFF, 0xE0, 0x00, 0xAD, 0xF5, 0x00, 0xA2, 0x01, 0xAC, 0xF6, 0x00, 0xFF, 0xA2, 0x03, 0xFF, 0xF2, 0x00, 0xEE, 0x0B, 0x00, 0x18, 0x6D, 0xDF, 0x00, 0xD0, 0xEC, 0xAD, 0xF5, 0x00, 0x18, 0x6D, 0xDF, 0x00, 0x8D, 0xDC, 0x00, 0xAE, 0xDC, 0x00, 0xEC, 0xD9, 0x00, 0xD0, 0x1D, 0xA2, 0x03, 0xFF, 0xE7, 0x00, 0xAD, 0xF5, 0x00, 0xA2, 0x01, 0xAC, 0xF6, 0x00, 0xFF, 0xA2, 0x03, 0xFF, 0xF2, 0x00, 0xEE, 0x39, 0x00, 0x18, 0x6D, 0xDF, 0x00, 0xD0, 0xEC, 0x00, 0xA9, 0x00, 0x8D, 0xDD, 0x00, 0x8D, 0xDB, 0x00, 0xAD, 0xDC, 0x00, 0x18, 0x6D, 0xDA, 0x00, 0xAA, 0xEC, 0xDB, 0x00, 0xD0, 0x0D, 0xAD, 0xDD, 0x00, 0x18, 0x6D, 0x4A, 0x00, 0xD0, 0x61, 0xA2, 0x03, 0xD0, 0xC3, 0xAD, 0x39, 0x00, 0x18, 0x6D, 0xDB, 0x00, 0x8D, 0x7E, 0x00, 0x8D, 0xA3, 0x00, 0x8D, 0xAD, 0x00, 0xAE, 0xF6, 0x00, 0x18, 0x6D, 0xDE, 0x00, 0x8D, 0x91, 0x00, 0x8D, 0x9D, 0x00, 0x8D, 0xA6, 0x00, 0x8D, 0xA9, 0x00, 0xEC, 0xF6, 0x00, 0xD0, 0x07, 0xA8, 0xA2, 0x01, 0xD0, 0x1A, 0xD0, 0x8A, 0xAC, 0xF6, 0x00, 0x98, 0xD0, 0x17, 0xAD, 0xF6, 0x00, 0xAC, 0xF6, 0x00, 0x8D, 0xF6, 0x00, 0x98, 0x8D, 0xF6, 0x00, 0xA9, 0x01, 0x8D, 0xDD, 0x00, 0xEE, 0xDB, 0x00, 0xD0, 0x9A, 0x18, 0x6D, 0xDF, 0x00, 0xA8, 0x8A, 0x18, 0x6D, 0xDF, 0x00, 0xAA, 0xD0, 0xD9, 0xA2, 0x01, 0xD0, 0xEA, 0xAD, 0xDA, 0x00, 0x18, 0x6D, 0xDF, 0x00, 0x8D, 0xDA, 0x00, 0xEE, 0xD9, 0x00, 0xD0, 0xC1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xFF, 0x49, 0x6E, 0x69, 0x74, 0x20, 0x20, 0x00, 0x20, 0x20, 0x53, 0x6F, 0x72, 0x74, 0x65, 0x64, 0x20, 0x20, 0x00, 0x2C, 0x20, 0x00, 0x0A, 0x08, 0x03, 0x01, 0x09, 0x07, 0x05, 0x02, 0x0A, 0x04, 0x06
+];
 
 #[tokio::main]
 async fn main() {
-	let start_address: u16 = 0x0000;
-	//Bubble-sort program, 245 bytes long
-	//I managed to get it all on the zeroth-page, but it still doesn't work in tsiram.com because it greatly surpasses the clock cycle limit
-	let sort_program: &[u8] = &[
-		/*See bubbleSort.asm6502 to look at the assembly code I wrote to produce this array of bytes.
-		See README.md to check out the text-editor/assembler I made to assembly bubbleSort.asm6502*/
-		0xA2, 0x03, 0xFF, 0xD6, 0x00, 0xAD, 0xEB, 0x00, 0xA2, 0x01, 0xAC, 0xEC, 0x00, 0xFF, 0xA2, 0x03, 0xFF, 0xE8, 0x00, 0xEE, 0x0B, 0x00, 0x6D, 0xD5, 0x00, 0xD0, 0xED, 0xAD, 0xEB, 0x00, 0x6D, 0xD5, 0x00, 0x8D, 0xD2, 0x00, 0xAE, 0xD2, 0x00, 0xEC, 0xCF, 0x00, 0xD0, 0x1C, 0xA2, 0x03, 0xFF, 0xDD, 0x00, 0xAD, 0xEB, 0x00, 0xA2, 0x01, 0xAC, 0xEC, 0x00, 0xFF, 0xA2, 0x03, 0xFF, 0xE8, 0x00, 0xEE, 0x37, 0x00, 0x6D, 0xD5, 0x00, 0xD0, 0xED, 0x00, 0xA9, 0x00, 0x8D, 0xD3, 0x00, 0x8D, 0xD1, 0x00, 0xAD, 0xD2, 0x00, 0x6D, 0xD0, 0x00, 0xAA, 0xEC, 0xD1, 0x00, 0xD0, 0x0C, 0xAD, 0xD3, 0x00, 0x6D, 0x47, 0x00, 0xD0, 0x5D, 0xA2, 0x03, 0xD0, 0xC6, 0xAD, 0x37, 0x00, 0x6D, 0xD1, 0x00, 0x8D, 0x78, 0x00, 0x8D, 0x9C, 0x00, 0x8D, 0xA6, 0x00, 0xAE, 0xEC, 0x00, 0x6D, 0xD4, 0x00, 0x8D, 0x8A, 0x00, 0x8D, 0x96, 0x00, 0x8D, 0x9F, 0x00, 0x8D, 0xA2, 0x00, 0xEC, 0xEC, 0x00, 0xD0, 0x07, 0xA8, 0xA2, 0x01, 0xD0, 0x1A, 0xD0, 0x8F, 0xAC, 0xEC, 0x00, 0x98, 0xD0, 0x17, 0xAD, 0xEC, 0x00, 0xAC, 0xEC, 0x00, 0x8D, 0xEC, 0x00, 0x98, 0x8D, 0xEC, 0x00, 0xA9, 0x01, 0x8D, 0xD3, 0x00, 0xEE, 0xD1, 0x00, 0xD0, 0x9E, 0x6D, 0xD5, 0x00, 0xA8, 0x8A, 0x6D, 0xD5, 0x00, 0xAA, 0xD0, 0xDB, 0xA2, 0x01, 0xD0, 0xEC, 0xAD, 0xD0, 0x00, 0x6D, 0xD5, 0x00, 0x8D, 0xD0, 0x00, 0xEE, 0xCF, 0x00, 0xD0, 0xC4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xFF, 0x49, 0x6E, 0x69, 0x74, 0x20, 0x20, 0x00, 0x20, 0x20, 0x53, 0x6F, 0x72, 0x74, 0x65, 0x64, 0x20, 0x20, 0x00, 0x2C, 0x20, 0x00, 0x0A, 0x08, 0x03, 0x01, 0x09, 0x07, 0x05, 0x02, 0x0A, 0x04, 0x06
-	];
+	let options: Options = match Options::parse(std::env::args().skip(1)) {
+		Ok(options) => {options}
+		Err(error) => {
+			eprintln!("{}\n\n{}", error, Options::USAGE);
+			std::process::exit(2);
+		}
+	};
+	if options.help {
+		println!("{}", Options::USAGE);
+		return;
+	}
+	let image: Option<Image> = match &options.path {
+		Some(path) => {
+			match cli::read_program(path, &options) {
+				Ok(image) => {Some(image)}
+				Err(error) => {
+					eprintln!("{}", error);
+					std::process::exit(1);
+				}
+			}
+		}
+		None => {None}
+	};
+	if image.is_none() && options.load_address as usize + SORT_PROGRAM.len() > 0x10000 {
+		eprintln!("The demo is {} bytes long, so it doesn't fit in memory at 0x{:04X}", SORT_PROGRAM.len(), options.load_address);
+		std::process::exit(1);
+	}
 	
 	let _ = lib::elapsed_ms();//initializes the timer to get the elapsed time
-	let mut system: System = System::new();
+	let sinks = [(&options.log_file, Format::Text), (&options.log_json, Format::Json)].into_iter().filter_map(|(path, format)| {
+		path.as_deref().map(|path| {Sink::create(path, format).map_err(|error| {format!("Could not create {}: {}", path, error)})})
+	}).collect::<Result<Vec<Sink>, String>>();
+	match sinks {
+		Ok(sinks) => {log::init(options.log_filter.clone(), sinks);}
+		Err(error) => {
+			eprintln!("{}", error);
+			std::process::exit(1);
+		}
+	}
+	let mut system: System = System::new(options.model);
 	
-	system.clock.specs.debug = false;
-	system.clock.cpu.specs.debug = false;
-	//memory is interleaved, so you will see it used in iterators for the rest of the program
-	system.clock.memory.iter_mut().for_each(|mem| {mem.specs.debug = false;});
+	if options.cache != CacheConfig::default() {
+		if let Err(error) = system.set_cache_config(options.cache) {
+			system.warn(format!("Could not configure the cache: {}", error).as_str());
+			std::process::exit(1);
+		}
+	}
+	system.clock.cpu.brk_halts = options.brk_halts;
+	if let Some(interval) = options.clock_interval_micro {
+		system.clock_interval_micro = interval;
+	}
+	if let Some(pulses) = options.pulses_per_sleep {
+		system.pulses_per_sleep = pulses;
+	}
+	if options.profile || options.profile_stacks.is_some() {
+		system.clock.cpu.profiler = Some(Profiler::default());
+	}
+	if options.coverage_path.is_some() || options.coverage_json.is_some() {
+		system.clock.cpu.coverage = Some(Coverage::default());
+	}
+	if let Some(path) = &options.trace_path {
+		match Trace::create(path, options.trace_range) {
+			Ok(trace) => {system.clock.cpu.trace = Some(trace);}
+			Err(error) => {
+				eprintln!("Could not create {}: {}", path, error);
+				std::process::exit(1);
+			}
+		}
+	}
 	
-	system.load_main_program(start_address, sort_program);
-	system.start().await;
+	let loaded = match &image {
+		Some(image) => {system.load_image(image)}
+		None => {system.load_main_program(options.load_address, SORT_PROGRAM)}
+	}.and_then(|_| {
+		match options.start_address {
+			Some(start_address) => {system.set_start_address(start_address)}
+			None => {Ok(())}
+		}
+	}).and_then(|_| {
+		options.watchpoints.iter().try_for_each(|watchpoint| {system.add_watchpoint(*watchpoint).map(drop)})
+	});
+	if let Err(error) = loaded {
+		system.warn(format!("Could not load the program: {}", error).as_str());
+		std::process::exit(1);
+	}
+	let result = if options.debugger {
+		system.clock.set_history_budget(options.history_kib.unwrap_or(Debugger::HISTORY_KIB) * 1024);
+		Debugger::default().run(&mut system)
+	} else if let Some(port) = options.gdb_port {
+		match GdbServer::bind(port) {
+			Ok(mut server) => {server.run(&mut system)}
+			Err(error) => {
+				system.warn(format!("Could not listen on port {}: {}", port, error).as_str());
+				std::process::exit(1);
+			}
+		}
+	} else if let Some(port) = options.dap_port {
+		match DapServer::bind(port) {
+			Ok(mut server) => {server.run(&mut system, &options)}
+			Err(error) => {
+				system.warn(format!("Could not listen on port {}: {}", port, error).as_str());
+				std::process::exit(1);
+			}
+		}
+	} else if options.tui {
+		Tui::default().run(&mut system)
+	} else {
+		system.start().await
+	};
+	//the trace and memory are written even if the program faulted, so they can be inspected
+	system.clock.cpu.finish_trace();
+	if let Some(profiler) = system.clock.cpu.profiler.take() {
+		let grouping: Grouping = match options.profile_group {
+			Some(size) => {Grouping::Ranges(size)}
+			None => {Grouping::Labels(options.path.as_deref().map(|path| {cli::read_labels(path, &options)}).unwrap_or_default())}
+		};
+		if options.profile {
+			print!("\n{}", profiler.report(&grouping));
+		}
+		if let Some(path) = &options.profile_stacks {
+			if let Err(error) = std::fs::write(path, profiler.collapsed_stacks(&grouping)) {
+				eprintln!("Could not write {}: {}", path, error);
+				std::process::exit(1);
+			}
+		}
+	}
+	if let Some(coverage) = system.clock.cpu.coverage.take() {
+		let image: Image = image.unwrap_or_else(|| {Image::from_bytes(options.load_address, SORT_PROGRAM).unwrap_or_default()});
+		let code: Vec<Instruction> = cli::read_code(options.path.as_deref(), &options, &image, &system.clock.cpu.syscalls);
+		let reports = [(&options.coverage_path, coverage.report(&code)), (&options.coverage_json, format!("{:#}\n", coverage.to_json(&code)))];
+		for (path, text) in reports {
+			match path.as_deref() {
+				Some("-") => {print!("\n{}", text);}
+				Some(path) => {
+					if let Err(error) = std::fs::write(path, text) {
+						eprintln!("Could not write {}: {}", path, error);
+						std::process::exit(1);
+					}
+				}
+				None => {}
+			}
+		}
+	}
+	if let Some(path) = &options.export_path {
+		let (start, end) = options.export_range;
+		match system.read_memory(start, end) {
+			Ok(image) => {
+				if let Err(error) = cli::write_image(path, &image) {
+					eprintln!("{}", error);
+					std::process::exit(1);
+				}
+			}
+			Err(error) => {system.warn(format!("Could not export the memory: {}", error).as_str());}
+		}
+	}
+	if let Err(error) = result {
+		system.warn(format!("{}", error).as_str());
+		std::process::exit(1);
+	}
 }
 
 mod lib {
-	use std::time::Instant;
+	use std::{sync::OnceLock, time::Instant};
 	
-	static mut START_TIME: Option<Instant> = None;
+	static START_TIME: OnceLock<Instant> = OnceLock::new();
 	
 	///Gets the elapsed ms since the program started.
 	pub fn elapsed_ms() -> u128 {
-		unsafe {
-			if START_TIME.is_none() {START_TIME = Some(Instant::now());}
-			Instant::now().duration_since(START_TIME.unwrap()).as_millis()
-		}
+		Instant::now().duration_since(*START_TIME.get_or_init(Instant::now)).as_millis()
+	}
+	
+	/**Parses a decimal number, or a hex number with a 0x or $ prefix.*/
+	pub fn parse_number<T: TryFrom<u64>>(text: &str) -> Result<T, String> {
+		let number: u64 = if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")).or(text.strip_prefix('$')) {
+			u64::from_str_radix(hex, 16)
+		} else {
+			text.parse::<u64>()
+		}.map_err(|_| {format!("Not a number: {}", text)})?;
+		T::try_from(number).map_err(|_| {format!("Out of range: {}", text)})
 	}
 }
\ No newline at end of file
@@ -3,15 +3,23 @@ use {
 	tokio::time::sleep,
 	crate::hardware::{
 		clock::Clock,
-		cpu::Cpu,
+		cache::CacheConfig,
+		cpu::{Cpu, CpuModel},
+		fault::EmulatorError,
 		hardware::{Hardware, HardwareSpecs},
-		imp::clock_listener::ClockListener
-	}
+		imp::clock_listener::ClockListener,
+		watchpoint::{Watchpoint, WatchAction, WatchHit}
+	},
+	crate::image::{Image, Segment}
 };
 
 pub struct System {
 	specs: HardwareSpecs,
-	pub clock: Clock
+	pub clock: Clock,
+	///Delay between clock pulses (in microseconds or 0.001 milliseconds)
+	pub clock_interval_micro: u64,
+	///How many clock pulses run between each sleep
+	pub pulses_per_sleep: u128
 }
 
 impl Hardware for System {
@@ -19,34 +27,106 @@ impl Hardware for System {
 }
 
 impl System {
-	/**Delay between clock pulses (in microseconds or 0.001 milliseconds)*/
-	const CLOCK_INTERVAL_MICRO: u64 = 0;//the thread.sleep command has been commented out, so there is no delay between cycles
+	/**Default delay between clock pulses (in microseconds or 0.001 milliseconds)*/
+	const CLOCK_INTERVAL_MICRO: u64 = 0;
+	/**Default number of clock pulses between each sleep*/
+	const PULSES_PER_SLEEP: u128 = 30;
 	
-	/**Instantiates a new System object.*/
-	pub fn new() -> Self {
+	/**Instantiates a new System object that emulates the given CPU model.*/
+	pub fn new(model: CpuModel) -> Self {
 		let system: Self = Self {
 			specs: HardwareSpecs::new("System"),
-			clock: Clock::new()
+			clock: Clock::new(model),
+			clock_interval_micro: Self::CLOCK_INTERVAL_MICRO,
+			pulses_per_sleep: Self::PULSES_PER_SLEEP
 		};
 		system.log("Created");
 		system
 	}
 	
-	/**Loads a set of instructions into memory and tells the cpu to start there. Must be called before System::start()*/
-	pub fn load_main_program(&mut self, address: u16, program: &[u8]) {
-		self.clock.cpu.mmu.static_load(&mut self.clock.memory, program, address);
+	/**Loads a set of instructions into memory and points the reset vector at them so the cpu starts there. Must be called before System::start()*/
+	pub fn load_main_program(&mut self, address: u16, program: &[u8]) -> Result<(), EmulatorError> {
+		self.clock.cpu.mmu.static_load(&mut self.clock.memory, program, address)?;
+		self.clock.cpu.mmu.static_load(&mut self.clock.memory, &address.to_le_bytes(), Cpu::RESET_VECTOR)?;
+		return Ok(());
+	}
+	
+	/**Loads every segment of an image into memory. The reset vector points at the image's start address if it has one,
+	otherwise at the first segment, unless the image has its own reset vector. Must be called before System::start()*/
+	pub fn load_image(&mut self, image: &Image) -> Result<(), EmulatorError> {
+		for segment in &image.segments {
+			self.clock.cpu.mmu.static_load(&mut self.clock.memory, &segment.data, segment.address)?;
+		}
+		let has_vector: bool = image.segments.iter().any(|segment| {segment.contains(Cpu::RESET_VECTOR) && segment.contains(Cpu::RESET_VECTOR + 1)});
+		match (image.start, image.segments.first()) {
+			(Some(start), _) => {self.set_start_address(start)}
+			(None, Some(segment)) if !has_vector => {self.set_start_address(segment.address)}
+			_ => {Ok(())}
+		}
+	}
+	
+	/**Reads the memory from start to end, including end, into an image that starts at the address in the reset vector.*/
+	pub fn read_memory(&mut self, start: u16, end: u16) -> Result<Image, EmulatorError> {
+		let len: usize = end.saturating_sub(start) as usize + 1;
+		let data: Vec<u8> = self.read_bytes(start, len)?;
+		let vector: Vec<u8> = self.read_bytes(Cpu::RESET_VECTOR, 2)?;
+		return Ok(Image {
+			segments: vec![Segment {address: start, data}],
+			start: Some(u16::from_le_bytes([vector[0], vector[1]]))
+		});
+	}
+	
+	/**Reads len bytes starting at start through the cache, so they're what the CPU would see. Can be called between clock cycles.*/
+	pub fn read_bytes(&mut self, start: u16, len: usize) -> Result<Vec<u8>, EmulatorError> {
+		return Ok(self.clock.cpu.mmu.static_read(&mut self.clock.memory, start, len)?);
+	}
+	
+	/**Reads len bytes starting at start the way the CPU would see them, without accessing memory or changing the cache,
+	so displays can show memory while the program runs.*/
+	pub fn peek_bytes(&self, start: u16, len: usize) -> Vec<u8> {
+		return self.clock.cpu.mmu.peek(&self.clock.memory, start, len);
+	}
+	
+	/**Writes bytes starting at address through the cache. Can be called between clock cycles.*/
+	pub fn write_bytes(&mut self, address: u16, bytes: &[u8]) -> Result<(), EmulatorError> {
+		self.clock.cpu.mmu.static_load(&mut self.clock.memory, bytes, address)?;
+		return Ok(());
 	}
 	
-	/**Starts the system and begins processing instructions until BRK.*/
-	pub async fn start(&mut self) {
-		self.clock.cpu.mmu.memory_dump(&mut self.clock.memory, 0x0000, 0x0015);
-		self.clock.cpu.mmu.cache.cache_hits = 0;
-		self.clock.cpu.mmu.cache.cache_accesses = 0;
+	/**Adds a watchpoint and returns its index. The addresses it watches are read so the old value is known for the first write.*/
+	pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> Result<usize, EmulatorError> {
+		let index: usize = self.clock.cpu.mmu.cache.watchpoints.add(watchpoint);
+		self.read_bytes(watchpoint.start, (watchpoint.end - watchpoint.start) as usize + 1)?;
+		return Ok(index);
+	}
+	
+	/**Gives the cache a new number of lines, line size, and mapping. The lines in it are written back to memory first.*/
+	pub fn set_cache_config(&mut self, config: CacheConfig) -> Result<(), EmulatorError> {
+		self.clock.cpu.mmu.configure_cache(&mut self.clock.memory, config)?;
+		return Ok(());
+	}
+	
+	/**Points the reset vector somewhere other than the start of the program loaded by System::load_main_program().*/
+	pub fn set_start_address(&mut self, address: u16) -> Result<(), EmulatorError> {
+		self.clock.cpu.mmu.static_load(&mut self.clock.memory, &address.to_le_bytes(), Cpu::RESET_VECTOR)?;
+		return Ok(());
+	}
+	
+	/**Starts the system and begins processing instructions until BRK, STP, or a watchpoint that stops.
+	Watchpoints that log are printed as they're hit. Returns an error if the CPU halted on a fault or the memory bus failed; the registers and memory are left as they were for inspection.*/
+	pub async fn start(&mut self) -> Result<(), EmulatorError> {
+		self.clock.cpu.mmu.memory_dump(&mut self.clock.memory, 0x0000, 0x0015, self.clock.cpu.model, &self.clock.cpu.syscalls)?;
+		self.clock.cpu.mmu.cache.reset_counters();
 		self.log("The delay between cycles has been greatly reduced to speed up the program.");
 		
 		self.log("Program Output:\n===================================================================================");
-		while self.clock.cpu.nv_bdizc & Cpu::BREAK_FLAG != Cpu::BREAK_FLAG {
-			self.clock.pulse();
+		while !self.clock.cpu.halted() {
+			let hits: Vec<WatchHit> = self.cycle()?;
+			hits.iter().for_each(|hit| {self.log(hit.to_string().as_str());});
+			if hits.iter().any(|hit| {hit.watchpoint.action == WatchAction::Stop}) {
+				self.log("Stopped by a watchpoint");
+				break;
+			}
 			
 			/* ATTENTION!!!!!
 			If sleep is commented out, the program will run almost instantly.
@@ -54,25 +134,44 @@ impl System {
 			If the interval is more than 0, I can't imagine how long it would take.
 			*/
 			
-			//I'm going to make it sleep for 0 second every 30 pulses, so it will only take a few seconds to run.
-			if self.clock.cpu.cpu_clock_counter % 30 == 0 {
-				sleep(Duration::from_micros(Self::CLOCK_INTERVAL_MICRO)).await; //uncomment to include the delay between cycles
+			//By default it sleeps for 0 seconds every 30 pulses, so it will only take a few seconds to run.
+			if self.clock.cpu.cpu_clock_counter.is_multiple_of(self.pulses_per_sleep.max(1)) {
+				sleep(Duration::from_micros(self.clock_interval_micro)).await;
 			}
 		}
 		println!("\n===================================================================================");
-		self.clock.cpu.specs.debug = true;
 		self.clock.cpu.log(format!("Total CPU clock cycles: {}", self.clock.cpu.cpu_clock_counter).as_str());
 		self.clock.cpu.log(format!("Total CPU instructions executed: {}", self.clock.cpu.instruction_counter).as_str());
 		self.clock.cpu.log(format!("Instructions per clock cycle: {}", self.clock.cpu.instruction_counter as f32 / self.clock.cpu.cpu_clock_counter as f32).as_str());
 		self.clock.cpu.mmu.cache.log(format!("Total cache hits: {}", self.clock.cpu.mmu.cache.cache_hits).as_str());
 		self.clock.cpu.mmu.cache.log(format!("Total cache accesses: {}", self.clock.cpu.mmu.cache.cache_accesses).as_str());
 		self.clock.cpu.mmu.cache.log(format!("Cache hit ratio: {}", self.clock.cpu.mmu.cache.cache_hits as f32 / self.clock.cpu.mmu.cache.cache_accesses as f32).as_str());
+		for (policy, evictions) in self.clock.cpu.mmu.cache.evictions() {
+			self.clock.cpu.mmu.cache.log(format!("Cache evictions ({}): {}", policy, evictions).as_str());
+		}
+		
+		return match self.clock.cpu.fault {
+			Some(fault) => {Err(EmulatorError::Cpu(fault))}
+			None => {Ok(())}
+		};
+	}
+	
+	/**Runs one clock cycle and returns the watchpoints hit in it. Returns an error if a memory module failed.
+	A CPU fault halts the CPU instead, which Cpu::halted() reports.*/
+	pub fn cycle(&mut self) -> Result<Vec<WatchHit>, EmulatorError> {
+		self.clock.pulse();
+		if let Some(error) = self.clock.memory.iter().find_map(|mem| {mem.fault}) {
+			return Err(EmulatorError::Memory(error));
+		}
+		return Ok(self.clock.cpu.take_watch_hits());
 	}
 	
-	/**Resets the RAM and sets the program counter back to the reset vector.*/
-	fn restart(&mut self) {
+	/**Resets the RAM and sets the program counter back to the reset vector. The history is cleared, since it can't go back past it.*/
+	pub fn restart(&mut self) -> Result<(), EmulatorError> {
+		self.clock.cpu.mmu.flush_cache(&mut self.clock.memory)?;
 		self.clock.memory.iter_mut().for_each(|mem| {mem.reset();});
-		self.clock.cpu.pc = 0x0000;
-		self.clock.cpu.clear_pipeline();
+		self.clock.history.clear();
+		self.clock.cpu.reset();
+		return Ok(());
 	}
 }
\ No newline at end of file
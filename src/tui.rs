@@ -0,0 +1,370 @@
+use {
+	crate::{
+		hardware::{
+			fault::EmulatorError,
+			hardware::Hardware,
+			watchpoint::{WatchAction, WatchHit},
+			cache::CacheConfig
+		},
+		system::System
+	},
+	crossterm::{
+		cursor::{Hide, MoveTo, Show},
+		event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+		execute, queue,
+		style::Print,
+		terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen}
+	},
+	std::{
+		io::{self, Stdout, Write},
+		sync::mpsc,
+		time::{Duration, Instant}
+	}
+};
+
+/**A full-screen dashboard that runs the System and shows the registers, the pipeline, the cache, memory, and what the
+program printed, redrawn several times a second. It starts paused. The dashboard reads the keys itself, so the keyboard
+doesn't listen to the console and the input system calls don't get any keys.*/
+pub struct Tui {
+	paused: bool,
+	///First address of the memory view
+	memory_start: u16,
+	///What the program printed, keeping only the end
+	output: String,
+	///Why the program stopped, or the result of the last key
+	status: String,
+	///Cycles run and time spent running them since the speed was last measured
+	sample: (u128, Duration),
+	///Speed of the last measurement, kept while paused
+	cycles_per_second: f64
+}
+
+impl Default for Tui {
+	fn default() -> Self {
+		Self {
+			paused: true,
+			memory_start: 0x0000,
+			output: String::new(),
+			status: String::from("Paused"),
+			sample: (0, Duration::ZERO),
+			cycles_per_second: 0.0
+		}
+	}
+}
+
+impl Tui {
+	const KEYS: &'static str = "space run/pause  s step  c cycle  up/down/PgUp/PgDn scroll memory  g memory at PC  q quit";
+	///Time between redraws. The program runs for this long between them.
+	const FRAME: Duration = Duration::from_millis(50);
+	///Time spent running between measurements of the cycles per second
+	const SAMPLE: Duration = Duration::from_millis(500);
+	///Width of the left panels, the CPU and pipeline
+	const LEFT_WIDTH: usize = 62;
+	///Width of the right panels, the cache and output
+	const RIGHT_WIDTH: usize = 52;
+	///Rows of 16 bytes in the memory view
+	const MEMORY_ROWS: usize = 8;
+	///Lines of program output shown
+	const OUTPUT_LINES: usize = 8;
+	///Most cache lines shown, the ones used last
+	const CACHE_ROWS: usize = 16;
+	///Most bytes of a cache line shown
+	const CACHE_BYTES: usize = 8;
+	///Bytes of program output kept
+	const OUTPUT_LIMIT: usize = 0x4000;
+	
+	/**Takes over the terminal until q is pressed. Returns an error if the memory bus fails. Problems with the terminal
+	are printed once it's been given back, and end the dashboard without an error.*/
+	pub fn run(&mut self, system: &mut System) -> Result<(), EmulatorError> {
+		let (sender, receiver) = mpsc::channel();
+		system.clock.cpu.syscalls.output.redirect(sender);
+		system.clock.cpu.set_keyboard_listening(false);
+		//loading the program went through the cache, which shouldn't count toward the hit ratio
+		system.clock.cpu.mmu.cache.reset_counters();
+		let mut screen: Screen = match Screen::open() {
+			Ok(screen) => {screen}
+			Err(error) => {
+				system.warn(format!("Could not open the dashboard: {}", error).as_str());
+				return Ok(());
+			}
+		};
+		loop {
+			let frame_end: Instant = Instant::now() + Self::FRAME;
+			if !self.paused {
+				let (cycles, start) = (system.clock.cpu.cpu_clock_counter, Instant::now());
+				self.run_until(system, |_| {Instant::now() >= frame_end})?;
+				self.measure(system.clock.cpu.cpu_clock_counter - cycles, start.elapsed());
+			}
+			for text in receiver.try_iter() {
+				self.print(&text);
+			}
+			let result: Result<bool, Error> = screen.draw(&self.lines(system)).map_err(Error::Io).and_then(|_| {
+				self.keys(system, frame_end.saturating_duration_since(Instant::now()))
+			});
+			match result {
+				Ok(true) => {}
+				Ok(false) => {return Ok(());}
+				Err(Error::Emulator(error)) => {return Err(error);}
+				Err(Error::Io(error)) => {
+					drop(screen);
+					system.warn(format!("Lost the terminal: {}", error).as_str());
+					return Ok(());
+				}
+			}
+		}
+	}
+	
+	/**Handles the keys pressed until the wait is over, or the first one if the program is paused. Returns Ok(false) to quit.*/
+	fn keys(&mut self, system: &mut System, wait: Duration) -> Result<bool, Error> {
+		let wait: Duration = if self.paused {Self::FRAME} else {wait};
+		if !event::poll(wait)? {return Ok(true);}
+		while event::poll(Duration::ZERO)? {
+			let Event::Key(KeyEvent {code, modifiers, kind: KeyEventKind::Press, ..}) = event::read()? else {continue;};
+			match code {
+				KeyCode::Char('q') | KeyCode::Esc => {return Ok(false);}
+				KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {return Ok(false);}
+				KeyCode::Char(' ') => {
+					if system.clock.cpu.halted() {
+						self.status = String::from("The CPU has halted");
+					} else {
+						self.paused = !self.paused;
+						self.status = String::from(if self.paused {"Paused"} else {"Running"});
+					}
+				}
+				KeyCode::Char('s') => {
+					self.paused = true;
+					let target: u128 = system.clock.cpu.instruction_counter + 1;
+					self.status = format!("Stepped to instruction {}", target);
+					self.run_until(system, |system| {system.clock.cpu.instruction_counter >= target})?;
+				}
+				KeyCode::Char('c') => {
+					self.paused = true;
+					let target: u128 = system.clock.cpu.cpu_clock_counter + 1;
+					self.status = format!("Ran cycle {}", target);
+					self.run_until(system, |system| {system.clock.cpu.cpu_clock_counter >= target})?;
+				}
+				KeyCode::Up => {self.memory_start = self.memory_start.wrapping_sub(16);}
+				KeyCode::Down => {self.memory_start = self.memory_start.wrapping_add(16);}
+				KeyCode::PageUp => {self.memory_start = self.memory_start.wrapping_sub((Self::MEMORY_ROWS * 16) as u16);}
+				KeyCode::PageDown => {self.memory_start = self.memory_start.wrapping_add((Self::MEMORY_ROWS * 16) as u16);}
+				KeyCode::Char('g') => {self.memory_start = system.clock.cpu.current_address() & 0xFFF0;}
+				_ => {}
+			}
+		}
+		return Ok(true);
+	}
+	
+	/**Runs clock cycles until done returns true, the CPU halts, or a watchpoint that stops is hit, which pauses the dashboard.
+	Watchpoint hits go to the output. The clock interval is slept every few pulses, like System::start.*/
+	fn run_until(&mut self, system: &mut System, done: impl Fn(&System) -> bool) -> Result<(), EmulatorError> {
+		if system.clock.cpu.halted() {
+			self.paused = true;
+			self.status = String::from("The CPU has halted");
+			return Ok(());
+		}
+		while !system.clock.cpu.halted() && !done(system) {
+			let hits: Vec<WatchHit> = system.cycle()?;
+			hits.iter().for_each(|hit| {self.print(format!("\n{}\n", hit).as_str());});
+			if let Some(hit) = hits.iter().find(|hit| {hit.watchpoint.action == WatchAction::Stop}) {
+				self.paused = true;
+				self.status = format!("Stopped by {}", hit.watchpoint);
+				return Ok(());
+			}
+			if system.clock_interval_micro > 0 && system.clock.cpu.cpu_clock_counter.is_multiple_of(system.pulses_per_sleep.max(1)) {
+				std::thread::sleep(Duration::from_micros(system.clock_interval_micro));
+			}
+		}
+		if system.clock.cpu.halted() {
+			self.paused = true;
+			self.status = match system.clock.cpu.fault {
+				Some(fault) => {format!("The CPU halted on {}", fault)}
+				None => {String::from("The CPU halted")}
+			};
+		}
+		return Ok(());
+	}
+	
+	///Adds to the program output, dropping the start once there's too much
+	fn print(&mut self, text: &str) {
+		self.output.push_str(text);
+		if self.output.len() > Self::OUTPUT_LIMIT {
+			let mut cut: usize = self.output.len() - Self::OUTPUT_LIMIT;
+			while !self.output.is_char_boundary(cut) {
+				cut += 1;
+			}
+			self.output.drain(..cut);
+		}
+	}
+	
+	///Adds a frame's cycles to the speed, which is measured again once enough time was spent running or the program stopped
+	fn measure(&mut self, cycles: u128, time: Duration) {
+		self.sample = (self.sample.0 + cycles, self.sample.1 + time);
+		let (cycles, time) = self.sample;
+		if time >= Self::SAMPLE || (self.paused && !time.is_zero()) {
+			self.cycles_per_second = cycles as f64 / time.as_secs_f64();
+			self.sample = (0, Duration::ZERO);
+		}
+	}
+	
+	/**Every line of the screen: the CPU and pipeline next to the cache and output, then memory and the keys.*/
+	fn lines(&self, system: &System) -> Vec<String> {
+		let cpu = &system.clock.cpu;
+		let cache = &cpu.mmu.cache;
+		let state: &str = if cpu.halted() {"HALTED"} else if self.paused {"PAUSED"} else {"RUNNING"};
+		let mut lines: Vec<String> = vec![format!(" 6502 {} - {}", state, self.status), String::new()];
+		
+		let flags: String = "NV-BDIZC".chars().enumerate().map(|(i, flag)| {
+			if cpu.nv_bdizc & (0x80 >> i) != 0 {flag} else {flag.to_ascii_lowercase()}
+		}).collect();
+		let mut left: Vec<String> = vec![
+			heading("CPU", Self::LEFT_WIDTH),
+			format!("PC 0x{:04X}   A 0x{:02X}   X 0x{:02X}   Y 0x{:02X}   S 0x{:02X}", cpu.pc, cpu.a, cpu.x, cpu.y, cpu.s),
+			format!("Status {} (0b{:08b})", flags, cpu.nv_bdizc),
+			format!("Cycles {}   Instructions {}", cpu.cpu_clock_counter, cpu.instruction_counter),
+			String::new(),
+			heading("Pipeline", Self::LEFT_WIDTH)
+		];
+		for (stage, instruction) in cpu.pipeline() {
+			let busy: &str = match (stage.starts_with("Execution"), &instruction) {
+				(false, _) => {""}
+				(true, Some(_)) => {"busy "}
+				(true, None) => {"idle "}
+			};
+			match instruction {
+				Some((address, text)) => {left.push(format!("{:<17}{}0x{:04X}: {}", stage, busy, address, text));}
+				None => {left.push(format!("{:<17}{}empty", stage, busy));}
+			}
+		}
+		left.push(format!("{:<17}{}", "Memory bus", cpu.memory_user()));
+		left.push(String::new());
+		left.push(heading("Performance", Self::LEFT_WIDTH));
+		let ipc: f64 = if cpu.cpu_clock_counter == 0 {0.0} else {cpu.instruction_counter as f64 / cpu.cpu_clock_counter as f64};
+		let hit_ratio: f64 = if cache.cache_accesses == 0 {0.0} else {cache.cache_hits as f64 * 100.0 / cache.cache_accesses as f64};
+		left.push(format!("Cycles/s {:.0}   IPC {:.3}   Cache hit ratio {:.1}%", self.cycles_per_second, ipc, hit_ratio));
+		left.push(format!("Cache misses {}   Evictions {}", cache.cache_misses, cache.cache_evictions));
+		
+		let config: CacheConfig = cache.config();
+		let mut right: Vec<String> = vec![
+			heading("Cache", Self::RIGHT_WIDTH),
+			format!("{} lines of {} bytes, {}", config.lines, config.line_size, config.mapping),
+			format!("Replacement: {}", config.policy),
+			String::from("Age  Tag     Addresses  D  Data")
+		];
+		let cache_lines = cache.lines();
+		for i in 0..cache.capacity().min(Self::CACHE_ROWS) {
+			match cache_lines.get(i) {
+				Some(line) => {
+					let mut data: Vec<String> = line.data.iter().take(Self::CACHE_BYTES).map(|byte| {format!("{:02X}", byte)}).collect();
+					if line.data.len() > Self::CACHE_BYTES {
+						data.push(String::from(".."));
+					}
+					right.push(format!("{:>3}  0x{:04X}  {:04X}-{:04X}  {}  {}", line.age, line.tag, line.address, line.address.wrapping_add(line.data.len() as u16 - 1),
+						if line.dirty {'*'} else {' '}, data.join(" ")));
+				}
+				None => {right.push(String::from("  -"));}
+			}
+		}
+		if cache.capacity() > Self::CACHE_ROWS {
+			right.push(format!("  {} of {} lines in use", cache_lines.len(), cache.capacity()));
+		}
+		right.push(String::new());
+		right.push(heading("Output", Self::RIGHT_WIDTH));
+		right.extend(wrap(&self.output, Self::RIGHT_WIDTH, Self::OUTPUT_LINES));
+		
+		for i in 0..left.len().max(right.len()) {
+			let left_line: &str = left.get(i).map_or("", |line| {line.as_str()});
+			lines.push(format!("{:<width$}  {}", clip(left_line, Self::LEFT_WIDTH), right.get(i).map_or("", |line| {line.as_str()}), width = Self::LEFT_WIDTH));
+		}
+		
+		lines.push(String::new());
+		lines.push(heading("Memory", Self::LEFT_WIDTH));
+		let len: usize = (Self::MEMORY_ROWS * 16).min(0x10000 - self.memory_start as usize);
+		let bytes: Vec<u8> = system.peek_bytes(self.memory_start, len);
+		let current: u16 = cpu.current_address();
+		for (i, row) in bytes.chunks(16).enumerate() {
+			let start: u16 = self.memory_start.wrapping_add((i * 16) as u16);
+			let hex: Vec<String> = row.iter().enumerate().map(|(j, byte)| {
+				let marker: char = if start.wrapping_add(j as u16) == current {'>'} else {' '};
+				format!("{}{:02X}", marker, byte)
+			}).collect();
+			let text: String = row.iter().map(|&byte| {if byte.is_ascii_graphic() || byte == b' ' {byte as char} else {'.'}}).collect();
+			lines.push(format!("{:04X} {:<48}  {}", start, hex.join(""), text));
+		}
+		lines.push(String::new());
+		lines.push(String::from(Self::KEYS));
+		return lines;
+	}
+}
+
+///A panel's title followed by a rule across the width
+fn heading(title: &str, width: usize) -> String {
+	let rule: usize = width.saturating_sub(title.len() + 3).max(8);
+	format!("- {} {}", title, "-".repeat(rule))
+}
+
+///The line cut to the width, in characters
+fn clip(line: &str, width: usize) -> String {
+	line.chars().take(width).collect()
+}
+
+///The last lines of the text, with long lines broken at the width
+fn wrap(text: &str, width: usize, count: usize) -> Vec<String> {
+	let mut lines: Vec<String> = Vec::new();
+	for line in text.split('\n') {
+		let chars: Vec<char> = line.chars().filter(|c| {!c.is_control()}).collect();
+		if chars.is_empty() {
+			lines.push(String::new());
+		}
+		lines.extend(chars.chunks(width).map(|chunk| {chunk.iter().collect::<String>()}));
+	}
+	let skip: usize = lines.len().saturating_sub(count);
+	return lines.into_iter().skip(skip).collect();
+}
+
+///The terminal in raw mode on the alternate screen. It's put back the way it was when this is dropped.
+struct Screen {
+	stdout: Stdout
+}
+
+impl Screen {
+	fn open() -> io::Result<Self> {
+		terminal::enable_raw_mode()?;
+		let mut stdout: Stdout = io::stdout();
+		if let Err(error) = execute!(stdout, EnterAlternateScreen, Hide) {
+			let _ = terminal::disable_raw_mode();
+			return Err(error);
+		}
+		return Ok(Self {stdout});
+	}
+	
+	///Draws the lines from the top, cut to the size of the terminal
+	fn draw(&mut self, lines: &[String]) -> io::Result<()> {
+		let (width, height) = terminal::size()?;
+		for (row, line) in lines.iter().take(height as usize).enumerate() {
+			queue!(self.stdout, MoveTo(0, row as u16), Print(clip(line, width as usize)), Clear(ClearType::UntilNewLine))?;
+		}
+		queue!(self.stdout, Clear(ClearType::FromCursorDown))?;
+		self.stdout.flush()
+	}
+}
+
+impl Drop for Screen {
+	fn drop(&mut self) {
+		let _ = execute!(self.stdout, Show, LeaveAlternateScreen);
+		let _ = terminal::disable_raw_mode();
+	}
+}
+
+///A terminal that can't be used ends the dashboard, and so does a memory bus failure
+enum Error {
+	Io(io::Error),
+	Emulator(EmulatorError)
+}
+
+impl From<io::Error> for Error {
+	fn from(error: io::Error) -> Self {Error::Io(error)}
+}
+
+impl From<EmulatorError> for Error {
+	fn from(error: EmulatorError) -> Self {Error::Emulator(error)}
+}
\ No newline at end of file